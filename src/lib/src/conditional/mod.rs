@@ -0,0 +1,228 @@
+//! Conditional formats, which change how the cells of a range look depending
+//! on their values.
+
+use sheet::{Coord, FormulaAtom};
+use style::{Style, Rgb};
+
+/// A rule for the cells from `from` to `to`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConditionalFormat {
+    pub from: Coord,
+    pub to: Coord,
+    pub rule: Rule,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    /// Changes the style of the cells whose value meets the condition.
+    Highlight(Condition, StylePatch),
+    /// Colors the background of each number in the range from `low` for the
+    /// smallest one to `high` for the largest, through `mid` if any.
+    ColorScale{low: Rgb, mid: Option<Rgb>, high: Rgb},
+    /// Draws a bar in each cell, as long as its number is relative to the
+    /// largest one in the range.
+    DataBar(Rgb),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Compare(CompareOp, FormulaAtom),
+    /// Between the two values, both included.
+    Between(FormulaAtom, FormulaAtom),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+    Equal,
+    NotEqual,
+}
+
+/// The parts of a style that a rule changes, `None` for those it leaves
+/// alone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StylePatch {
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub color: Option<Rgb>,
+    pub background: Option<Rgb>,
+}
+
+/// How a cell looks once its conditional formats are applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Look {
+    pub style: Style,
+    /// The length of the data bar, from 0 to 1, and its color.
+    pub bar: Option<(f64, Rgb)>,
+}
+
+impl ConditionalFormat {
+    pub fn contains(&self, Coord(col, row): Coord) -> bool {
+        let (Coord(col_from, row_from), Coord(col_to, row_to)) = (self.from, self.to);
+        col >= col_from && col <= col_to && row >= row_from && row <= row_to
+    }
+
+    /// Whether the look of a cell depends on the values of the others in
+    /// the range.
+    pub fn is_relative(&self) -> bool {
+        match self.rule {
+            Rule::Highlight(..) => false,
+            Rule::ColorScale{..} | Rule::DataBar(_) => true,
+        }
+    }
+
+    /// Applies the rule to the look of a cell with value `value`, given the
+    /// `bounds` of the numbers in the whole range.
+    pub fn apply(&self, value: &FormulaAtom, (min, max): (f64, f64), look: &mut Look) {
+        match self.rule {
+            Rule::Highlight(ref condition, ref patch) => if condition.matches(value) {
+                patch.apply(&mut look.style);
+            },
+            Rule::ColorScale{low, mid, high} => if let Some(x) = number(value) {
+                let t = if max > min { (x - min) / (max - min) } else { 0.5 };
+                look.style.background = Some(match mid {
+                    Some(mid) if t < 0.5 => mix(low, mid, t * 2.0),
+                    Some(mid) => mix(mid, high, t * 2.0 - 1.0),
+                    None => mix(low, high, t),
+                });
+            },
+            Rule::DataBar(color) => if let Some(x) = number(value) {
+                let (min, max) = (min.min(0.0), max.max(0.0));
+                let t = if max > min { (x - min) / (max - min) } else { 0.0 };
+                look.bar = Some((t, color));
+            },
+        }
+    }
+}
+
+impl Condition {
+    pub fn matches(&self, value: &FormulaAtom) -> bool {
+        match *self {
+            Condition::Compare(op, ref x) => match compare(value, x) {
+                Some(ord) => op.holds(ord),
+                None => op == CompareOp::NotEqual,
+            },
+            Condition::Between(ref low, ref high) => {
+                match (compare(value, low), compare(value, high)) {
+                    (Some(a), Some(b)) => CompareOp::GreaterEq.holds(a) && CompareOp::LessEq.holds(b),
+                    _ => false,
+                }
+            },
+        }
+    }
+}
+
+impl CompareOp {
+    pub fn holds(&self, ord: ::std::cmp::Ordering) -> bool {
+        use std::cmp::Ordering::{Less, Equal, Greater};
+
+        match *self {
+            CompareOp::Less => ord == Less,
+            CompareOp::LessEq => ord != Greater,
+            CompareOp::Greater => ord == Greater,
+            CompareOp::GreaterEq => ord != Less,
+            CompareOp::Equal => ord == Equal,
+            CompareOp::NotEqual => ord != Equal,
+        }
+    }
+}
+
+impl StylePatch {
+    pub fn new() -> StylePatch {
+        StylePatch{bold: None, italic: None, color: None, background: None}
+    }
+
+    pub fn apply(&self, style: &mut Style) {
+        if let Some(x) = self.bold { style.bold = x; }
+        if let Some(x) = self.italic { style.italic = x; }
+        if let Some(x) = self.color { style.color = Some(x); }
+        if let Some(x) = self.background { style.background = Some(x); }
+    }
+}
+
+/// Compares numbers with numbers and strings with strings, ignoring case.
+/// `None` for anything else.
+pub fn compare(a: &FormulaAtom, b: &FormulaAtom) -> Option<::std::cmp::Ordering> {
+    match (number(a), number(b), a, b) {
+        (Some(x), Some(y), _, _) => x.partial_cmp(&y),
+        (_, _, &FormulaAtom::String(ref x), &FormulaAtom::String(ref y)) => {
+            Some(x.to_lowercase().cmp(&y.to_lowercase()))
+        },
+        _ => None,
+    }
+}
+
+pub fn number(atom: &FormulaAtom) -> Option<f64> {
+    match *atom {
+        FormulaAtom::Number(x) | FormulaAtom::Date(x) | FormulaAtom::Duration(x) => Some(x),
+        FormulaAtom::Decimal(ref x) => Some(x.to_f64()),
+        _ => None,
+    }
+}
+
+/// The smallest and the largest of some numbers.
+pub fn bounds(xs: &[f64]) -> (f64, f64) {
+    xs.iter().fold((::std::f64::INFINITY, ::std::f64::NEG_INFINITY), |(min, max), &x| {
+        (min.min(x), max.max(x))
+    })
+}
+
+/// The color at `t`, from 0 to 1, of the way from `a` to `b`.
+fn mix(Rgb(r1, g1, b1): Rgb, Rgb(r2, g2, b2): Rgb, t: f64) -> Rgb {
+    let component = |x: u8, y: u8| (x as f64 + (y as f64 - x as f64) * t).round() as u8;
+    Rgb(component(r1, r2), component(g1, g2), component(b1, b2))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sheet::{Coord, FormulaAtom};
+    use style::{Style, Rgb};
+
+    fn look() -> Look {
+        Look{style: Style::new(), bar: None}
+    }
+
+    #[test]
+    fn test_highlight() {
+        let red = StylePatch{color: Some(Rgb(255, 0, 0)), ..StylePatch::new()};
+        let cf = ConditionalFormat{from: Coord(0, 0), to: Coord(0, 9), rule: Rule::Highlight(
+            Condition::Compare(CompareOp::Less, FormulaAtom::Number(0.0)), red)};
+        let mut l = look();
+        cf.apply(&FormulaAtom::Number(-1.0), bounds(&[]), &mut l);
+        assert_eq!(Some(Rgb(255, 0, 0)), l.style.color);
+        l = look();
+        cf.apply(&FormulaAtom::Number(1.0), bounds(&[]), &mut l);
+        assert_eq!(None, l.style.color);
+        cf.apply(&FormulaAtom::String("x".to_string()), bounds(&[]), &mut l);
+        assert_eq!(None, l.style.color);
+
+        let between = Condition::Between(FormulaAtom::String("b".to_string()), FormulaAtom::String("d".to_string()));
+        assert!(between.matches(&FormulaAtom::String("C".to_string())));
+        assert!(!between.matches(&FormulaAtom::String("e".to_string())));
+    }
+
+    #[test]
+    fn test_scales() {
+        let range = [0.0, 5.0, 10.0];
+        let scale = ConditionalFormat{from: Coord(0, 0), to: Coord(0, 2), rule: Rule::ColorScale{
+            low: Rgb(0, 0, 0), mid: Some(Rgb(255, 255, 255)), high: Rgb(255, 0, 0)}};
+        let background = |x| {
+            let mut l = look();
+            scale.apply(&FormulaAtom::Number(x), bounds(&range), &mut l);
+            l.style.background.unwrap()
+        };
+        assert_eq!(Rgb(0, 0, 0), background(0.0));
+        assert_eq!(Rgb(128, 128, 128), background(2.5));
+        assert_eq!(Rgb(255, 255, 255), background(5.0));
+        assert_eq!(Rgb(255, 0, 0), background(10.0));
+
+        let bar = ConditionalFormat{rule: Rule::DataBar(Rgb(0, 0, 255)), ..scale.clone()};
+        let mut l = look();
+        bar.apply(&FormulaAtom::Number(5.0), bounds(&range), &mut l);
+        assert_eq!(Some((0.5, Rgb(0, 0, 255))), l.bar);
+    }
+}
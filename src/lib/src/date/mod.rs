@@ -0,0 +1,233 @@
+//! Dates as serial numbers: the days since 1899-12-30, with the time of the
+//! day as the fractional part. This matches the serial numbers of other
+//! spreadsheet applications from 1900-03-01 on.
+
+/// Days from 1899-12-30 to 1970-01-01.
+const UNIX_EPOCH: i64 = 25569;
+const SECONDS_PER_DAY: f64 = 86400.0;
+/// The years before and after year 0 that dates can be in.
+pub const MAX_YEAR: i64 = 1000000000;
+/// The serial numbers of dates in the years up to `MAX_YEAR`.
+pub const MAX_SERIAL: i64 = MAX_YEAR * 366;
+
+/// The serial number of a day of the proleptic Gregorian calendar.
+pub fn serial_from_ymd(year: i64, month: u32, day: u32) -> i64 {
+    // See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468 + UNIX_EPOCH
+}
+
+/// The year, month and day of a serial number.
+pub fn ymd_from_serial(serial: i64) -> (i64, u32, u32) {
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = serial - UNIX_EPOCH + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Like `serial_from_ymd`, but months and days out of range carry over to the
+/// next or previous years and months, so `(2015, 14, 0)` is 2016-01-31.
+/// `None` if the date is more than `MAX_YEAR` years away.
+pub fn serial_from_ymd_lenient(year: i64, month: i64, day: i64) -> Option<i64> {
+    let months = match year.checked_mul(12).and_then(|x| x.checked_add(month - 1)) {
+        Some(months) => months,
+        None => { return None; },
+    };
+    let (year, month) = (div_floor(months, 12), months - div_floor(months, 12) * 12 + 1);
+    if year.abs() > MAX_YEAR {
+        return None;
+    }
+    match serial_from_ymd(year, month as u32, 1).checked_add(day - 1) {
+        Some(serial) if serial.abs() <= MAX_SERIAL => Some(serial),
+        _ => None,
+    }
+}
+
+pub fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 => if is_leap_year(year) { 29 } else { 28 },
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The day of the week, from 0 for Sunday to 6 for Saturday.
+pub fn weekday(serial: i64) -> u32 {
+    // 1899-12-30 was a Saturday.
+    ((serial + 6) % 7 + 7) as u32 % 7
+}
+
+/// The serial number of the current local date and time.
+pub fn now() -> f64 {
+    let tm = ::time::now();
+    let day = serial_from_ymd(tm.tm_year as i64 + 1900, tm.tm_mon as u32 + 1, tm.tm_mday as u32);
+    let seconds = tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec;
+    day as f64 + seconds as f64 / SECONDS_PER_DAY
+}
+
+/// Parses `YYYY-MM-DD`, optionally followed by a time of the day as
+/// `THH:MM` or `THH:MM:SS`. A space can be used instead of the `T`.
+pub fn parse_iso(s: &str) -> Option<f64> {
+    let (date, time) = match s.find(|c: char| c == 'T' || c == ' ') {
+        Some(idx) => (&s[..idx], Some(&s[idx+1..])),
+        None => (s, None),
+    };
+
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let (year, month, day) = match (parts[0].parse(), parts[1].parse(), parts[2].parse()) {
+        (Ok(y), Ok(m), Ok(d)) => (y, m, d),
+        _ => { return None; },
+    };
+    if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let seconds = match time {
+        Some(time) => match parse_hms(time) {
+            Some(x) if x < SECONDS_PER_DAY as i64 => x,
+            _ => { return None; },
+        },
+        None => 0,
+    };
+
+    Some(serial_from_ymd(year, month, day) as f64 + seconds as f64 / SECONDS_PER_DAY)
+}
+
+/// Parses a duration written as `H:MM` or `H:MM:SS`, with any number of
+/// hours, into days.
+pub fn parse_duration(s: &str) -> Option<f64> {
+    let (negative, s) = if s.starts_with("-") { (true, &s[1..]) } else { (false, s) };
+    parse_hms(s).map(|x| {
+        let days = x as f64 / SECONDS_PER_DAY;
+        if negative { -days } else { days }
+    })
+}
+
+fn parse_hms(s: &str) -> Option<i64> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return None;
+    }
+
+    let mut ret: i64 = 0;
+    for (i, part) in parts.iter().enumerate() {
+        let x: i64 = match part.parse() {
+            Ok(x) => x,
+            Err(_) => { return None; },
+        };
+        if i > 0 && (part.len() != 2 || x > 59) {
+            return None;
+        }
+        // Hours can have any number of digits.
+        ret = match ret.checked_mul(60).and_then(|y| y.checked_add(x)) {
+            Some(y) => y,
+            None => { return None; },
+        };
+    }
+    if parts.len() == 2 {
+        ret.checked_mul(60)
+    } else {
+        Some(ret)
+    }
+}
+
+/// Splits a serial number into the day and the second of the day, rounded
+/// to the nearest second.
+pub fn split_serial(serial: f64) -> (i64, i64) {
+    let seconds = (serial * SECONDS_PER_DAY).round() as i64;
+    let day = div_floor(seconds, SECONDS_PER_DAY as i64);
+    (day, seconds - day * SECONDS_PER_DAY as i64)
+}
+
+/// Formats a serial number as `YYYY-MM-DD`, adding `THH:MM:SS` if it isn't
+/// midnight.
+pub fn format_iso(serial: f64) -> String {
+    let (day, time) = split_serial(serial);
+    let (year, month, day) = ymd_from_serial(day);
+
+    let mut ret = format!("{:04}-{:02}-{:02}", year, month, day);
+    if time != 0 {
+        ret.push_str(format!("T{:02}:{:02}:{:02}", time / 3600, time / 60 % 60, time % 60).as_str());
+    }
+    ret
+}
+
+/// Formats a number of days as `H:MM:SS`.
+pub fn format_duration(days: f64) -> String {
+    let seconds = (days * SECONDS_PER_DAY).round() as i64;
+    let sign = if seconds < 0 { "-" } else { "" };
+    let seconds = seconds.abs();
+    format!("{}{}:{:02}:{:02}", sign, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn div_floor(a: i64, b: i64) -> i64 {
+    let d = a / b;
+    if (a % b != 0) && ((a < 0) != (b < 0)) { d - 1 } else { d }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_serial() {
+        assert_eq!(0, serial_from_ymd(1899, 12, 30));
+        assert_eq!(61, serial_from_ymd(1900, 3, 1));
+        assert_eq!(42005, serial_from_ymd(2015, 1, 1));
+        assert_eq!(45306, serial_from_ymd(2024, 1, 15));
+        assert_eq!((2024, 1, 15), ymd_from_serial(45306));
+        assert_eq!((2000, 2, 29), ymd_from_serial(serial_from_ymd(2000, 2, 29)));
+        assert_eq!((1850, 7, 4), ymd_from_serial(serial_from_ymd(1850, 7, 4)));
+        assert_eq!(Some(serial_from_ymd(2016, 1, 31)), serial_from_ymd_lenient(2015, 14, 0));
+        assert_eq!(Some(serial_from_ymd(2014, 12, 1)), serial_from_ymd_lenient(2015, 0, 1));
+        assert_eq!(None, serial_from_ymd_lenient(::std::i64::MAX / 2, 1, 1));
+        assert_eq!(None, serial_from_ymd_lenient(2015, 1, ::std::i64::MAX));
+    }
+
+    #[test]
+    fn test_weekday() {
+        assert_eq!(6, weekday(0));
+        assert_eq!(1, weekday(serial_from_ymd(2024, 1, 15)));
+        assert_eq!(0, weekday(-6));
+    }
+
+    #[test]
+    fn test_iso() {
+        assert_eq!(Some(45306.0), parse_iso("2024-01-15"));
+        assert_eq!(Some(45306.4375), parse_iso("2024-01-15T10:30"));
+        assert_eq!(Some(45306.4375), parse_iso("2024-01-15 10:30:00"));
+        assert_eq!(None, parse_iso("2023-02-29"));
+        assert_eq!(None, parse_iso("2024-01-15T24:00"));
+        assert_eq!("2024-01-15", format_iso(45306.0));
+        assert_eq!("2024-01-15T10:30:00", format_iso(45306.4375));
+    }
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(Some(1.5), parse_duration("36:00"));
+        assert_eq!(Some(-0.25), parse_duration("-6:00:00"));
+        assert_eq!(None, parse_duration("1:60"));
+        assert_eq!(None, parse_duration("999999999999999999:00"));
+        assert_eq!("36:00:00", format_duration(1.5));
+        assert_eq!("-0:01:30", format_duration(-90.0 / 86400.0));
+    }
+}
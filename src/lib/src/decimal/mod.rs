@@ -0,0 +1,262 @@
+//! Exact decimal numbers, for the sheets where `add(0.1, 0.2)` has to be
+//! `0.3`. Addition, subtraction and multiplication are exact; quotients are
+//! rounded to a given number of decimal places.
+
+use ::num::{BigInt, Zero, One, Signed, Integer, FromPrimitive};
+
+/// The largest exponent `Decimal::parse` accepts, so that `1e999999999`
+/// doesn't take all the memory.
+const MAX_EXPONENT: i32 = 1000;
+
+/// A number written as `digits / 10^scale`.
+#[derive(Clone, Debug)]
+pub struct Decimal {
+    digits: BigInt,
+    scale: u32,
+}
+
+/// How to round the digits that don't fit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    /// To the nearest, and to the even neighbour on ties. Also known as
+    /// banker's rounding.
+    HalfEven,
+    /// To the nearest, and away from zero on ties.
+    HalfUp,
+    /// Towards zero, truncating.
+    Down,
+    /// Away from zero.
+    Up,
+    /// Towards negative infinity.
+    Floor,
+    /// Towards positive infinity.
+    Ceiling,
+}
+
+impl Decimal {
+    pub fn from_integer(x: i64) -> Decimal {
+        Decimal{digits: BigInt::from_i64(x).unwrap(), scale: 0}
+    }
+
+    /// Parses a number like `-12.345` or `+1.5e-3`, with any number of
+    /// digits.
+    pub fn parse(s: &str) -> Option<Decimal> {
+        let (s, exp) = match s.find(|c: char| c == 'e' || c == 'E') {
+            Some(idx) => match s[idx+1..].trim_left_matches('+').parse::<i32>() {
+                Ok(exp) if exp.abs() <= MAX_EXPONENT => (&s[..idx], exp),
+                _ => { return None; },
+            },
+            None => (s, 0),
+        };
+        let (negative, s) = if s.starts_with("-") {
+            (true, &s[1..])
+        } else {
+            (false, s.trim_left_matches('+'))
+        };
+        let (int, frac) = match s.find('.') {
+            Some(idx) => (&s[..idx], &s[idx+1..]),
+            None => (s, ""),
+        };
+        if int.len() + frac.len() == 0 || !(int.bytes().chain(frac.bytes()).all(|b| b >= b'0' && b <= b'9')) {
+            return None;
+        }
+
+        let mut digits: BigInt = match format!("{}{}", int, frac).parse() {
+            Ok(x) => x,
+            Err(_) => { return None; },
+        };
+        if negative {
+            digits = -digits;
+        }
+
+        let scale = frac.len() as i32 - exp;
+        if scale >= 0 {
+            Some(Decimal{digits: digits, scale: scale as u32})
+        } else {
+            Some(Decimal{digits: digits * pow10(-scale as u32), scale: 0})
+        }
+    }
+
+    /// The decimal with the fewest digits that converts back to `x`, so
+    /// `0.1` becomes exactly `0.1`. `None` for infinities and NaN.
+    pub fn from_f64(x: f64) -> Option<Decimal> {
+        if x.is_finite() {
+            Decimal::parse(format!("{}", x).as_str())
+        } else {
+            None
+        }
+    }
+
+    /// The nearest float, which is what functions that only work with floats
+    /// get.
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.digits.is_negative()
+    }
+
+    pub fn abs(&self) -> Decimal {
+        Decimal{digits: self.digits.abs(), scale: self.scale}
+    }
+
+    pub fn add(&self, other: &Decimal) -> Decimal {
+        let scale = ::std::cmp::max(self.scale, other.scale);
+        Decimal{digits: self.rescaled(scale) + other.rescaled(scale), scale: scale}
+    }
+
+    pub fn sub(&self, other: &Decimal) -> Decimal {
+        let scale = ::std::cmp::max(self.scale, other.scale);
+        Decimal{digits: self.rescaled(scale) - other.rescaled(scale), scale: scale}
+    }
+
+    pub fn mul(&self, other: &Decimal) -> Decimal {
+        Decimal{digits: &self.digits * &other.digits, scale: self.scale + other.scale}
+    }
+
+    /// The quotient rounded to `scale` decimal places, without trailing
+    /// zeros. `None` when dividing by zero.
+    pub fn div(&self, other: &Decimal, scale: u32, rounding: Rounding) -> Option<Decimal> {
+        if other.is_zero() {
+            return None;
+        }
+        // self / other = (a / 10^sa) / (b / 10^sb) = (a * 10^(scale + sb) / (b * 10^sa)) / 10^scale
+        let num = &self.digits * pow10(scale + other.scale);
+        let den = &other.digits * pow10(self.scale);
+        let (num, den) = if den.is_negative() { (-num, -den) } else { (num, den) };
+        Some(Decimal{digits: div_rounded(&num, &den, rounding), scale: scale}.trimmed())
+    }
+
+    /// Rounds to `scale` decimal places. Numbers with fewer decimal places
+    /// are left as they are.
+    pub fn round(&self, scale: u32, rounding: Rounding) -> Decimal {
+        if scale >= self.scale {
+            return self.clone();
+        }
+        Decimal{digits: div_rounded(&self.digits, &pow10(self.scale - scale), rounding), scale: scale}
+    }
+
+    /// The same number, with the trailing zeros after the point removed.
+    pub fn trimmed(&self) -> Decimal {
+        let ten = BigInt::from_u32(10).unwrap();
+        let mut ret = self.clone();
+        while ret.scale > 0 && ret.digits.is_multiple_of(&ten) {
+            ret.digits = ret.digits / &ten;
+            ret.scale -= 1;
+        }
+        ret
+    }
+
+    fn rescaled(&self, scale: u32) -> BigInt {
+        &self.digits * pow10(scale - self.scale)
+    }
+}
+
+/// Equal values are equal whatever their scale, so `1.50 == 1.5`.
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        let scale = ::std::cmp::max(self.scale, other.scale);
+        self.rescaled(scale) == other.rescaled(scale)
+    }
+}
+
+impl ::std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        let mut digits = self.digits.abs().to_string();
+        while digits.len() <= self.scale as usize {
+            digits.insert(0, '0');
+        }
+        if self.digits.is_negative() {
+            try!(write!(f, "-"));
+        }
+        let point = digits.len() - self.scale as usize;
+        if self.scale == 0 {
+            write!(f, "{}", digits)
+        } else {
+            write!(f, "{}.{}", &digits[..point], &digits[point..])
+        }
+    }
+}
+
+fn pow10(n: u32) -> BigInt {
+    ::num::pow(BigInt::from_u32(10).unwrap(), n as usize)
+}
+
+/// `num / den` rounded to an integer. `den` has to be positive.
+fn div_rounded(num: &BigInt, den: &BigInt, rounding: Rounding) -> BigInt {
+    // Division truncates, so the remainder has the sign of `num`.
+    let q = num / den;
+    let r = num - &q * den;
+    if r.is_zero() {
+        return q;
+    }
+
+    let away = if num.is_negative() { &q - BigInt::one() } else { &q + BigInt::one() };
+    let twice = r.abs() * BigInt::from_u32(2).unwrap();
+    let round_away = match rounding {
+        Rounding::Down => false,
+        Rounding::Up => true,
+        Rounding::Floor => num.is_negative(),
+        Rounding::Ceiling => num.is_positive(),
+        Rounding::HalfUp => twice >= *den,
+        Rounding::HalfEven => twice > *den || (twice == *den && q.is_odd()),
+    };
+    if round_away { away } else { q }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        Decimal::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!("12.345", dec("12.345").to_string());
+        assert_eq!("-0.05", dec("-.05").to_string());
+        assert_eq!("1.50", dec("1.50").to_string());
+        assert_eq!("123456789012345678901234567890.1", dec("123456789012345678901234567890.1").to_string());
+        assert_eq!(None, Decimal::parse("1.2.3"));
+        assert_eq!(None, Decimal::parse("-"));
+        assert_eq!("1000000", dec("1e6").to_string());
+        assert_eq!("0.0015", dec("+1.5E-3").to_string());
+        assert_eq!("-120", dec("-1.2e+2").to_string());
+        assert_eq!(None, Decimal::parse("1e"));
+        assert_eq!(None, Decimal::parse("1e99999"));
+        assert_eq!(dec("0.1"), Decimal::from_f64(0.1).unwrap());
+        assert_eq!(0.25, dec("0.25").to_f64());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!("0.3", dec("0.1").add(&dec("0.2")).to_string());
+        assert_eq!("-1.90", dec("0.10").sub(&dec("2")).to_string());
+        assert_eq!("0.0050", dec("0.05").mul(&dec("0.10")).to_string());
+        assert_eq!(dec("1.5"), dec("1.50"));
+        assert_eq!("0.3333", dec("1").div(&dec("3"), 4, Rounding::HalfEven).unwrap().to_string());
+        assert_eq!("0.25", dec("1").div(&dec("4"), 4, Rounding::HalfEven).unwrap().to_string());
+        assert_eq!("-2", dec("1").div(&dec("-0.5"), 4, Rounding::HalfEven).unwrap().to_string());
+        assert_eq!(None, dec("1").div(&dec("0.0"), 4, Rounding::HalfEven));
+    }
+
+    #[test]
+    fn test_rounding() {
+        let modes = [Rounding::HalfEven, Rounding::HalfUp, Rounding::Down, Rounding::Up,
+                     Rounding::Floor, Rounding::Ceiling];
+        let round = |s: &str| -> Vec<String> {
+            modes.iter().map(|&mode| dec(s).round(0, mode).to_string()).collect()
+        };
+        assert_eq!(vec!["2", "3", "2", "3", "2", "3"], round("2.5"));
+        assert_eq!(vec!["-2", "-3", "-2", "-3", "-3", "-2"], round("-2.5"));
+        assert_eq!(vec!["4", "4", "3", "4", "3", "4"], round("3.5"));
+        assert_eq!(vec!["3", "3", "3", "4", "3", "4"], round("3.49"));
+        assert_eq!("1.23", dec("1.23").round(4, Rounding::Down).to_string());
+    }
+}
@@ -0,0 +1,523 @@
+//! The dependency graph: which cells each formula reads, and from that which
+//! cells have to be sent again to the selections when others change.
+//!
+//! What a formula reads is worked out from the formula alone, without
+//! evaluating it, so a lookup depends on every cell of the range it looks in,
+//! and `if` on both of its branches. References built as the formula is
+//! evaluated, by `offset` and `indirect`, could be to any cell, so formulas
+//! with them depend on every cell; they are resolved again, and checked for
+//! cycles, whenever they are evaluated.
+//!
+//! The areas arrays spill over are kept by each sheet and worked out again
+//! here for the cells that change, so that reading a cell doesn't evaluate
+//! every formula that may give an array. Cells that read spilled cells
+//! depend on the cell whose formula gives the array through them.
+//!
+//! The same graph orders the formulas evaluated to send many cells at once,
+//! so that each is evaluated once, and those that don't read each other on
+//! different threads.
+//!
+//! Books keep the cells that read each cell once worked out, and change
+//! them along with the formula of a cell. They're worked out again from
+//! scratch after anything that may change what every formula reads, like
+//! moving lines or defining names.
+
+use ::std::collections::{HashMap, HashSet};
+use sheet::{Book, Context, Coord, Formula, FormulaOp};
+
+/// A cell of a book, with the position of its sheet.
+pub type Cell = (usize, Coord);
+
+/// What a formula reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Precedents {
+    /// Rectangles of cells, from their top left to their bottom right cell,
+    /// in the sheet with the given name or else in the formula's own sheet.
+    pub areas: Vec<(Option<String>, Coord, Coord)>,
+    /// Defined names, which read what their targets read. Names bound by
+    /// `let` and lambdas are in here too, as they look the same.
+    pub names: Vec<String>,
+    /// Whether it builds references with `offset` or `indirect`.
+    pub dynamic: bool,
+}
+
+impl Precedents {
+    pub fn of(formula: &Formula) -> Precedents {
+        let mut ret = Precedents{areas: vec![], names: vec![], dynamic: false};
+        ret.add(formula, None);
+        ret
+    }
+
+    fn add(&mut self, formula: &Formula, sheet: Option<&str>) {
+        match *formula {
+            Formula::Ref(coord, _) => {
+                self.areas.push((sheet.map(|x| x.to_string()), coord, coord));
+            },
+            Formula::Range(from, _, to, _) => {
+                self.areas.push((sheet.map(|x| x.to_string()), from, to));
+            },
+            Formula::SheetRef(ref name, ref inner) => self.add(inner, Some(name)),
+            Formula::Name(ref name) => self.add_name(name),
+            Formula::Op(ref op, ref args) => {
+                match *op {
+                    FormulaOp::Custom(ref name) => self.add_name(name),
+                    FormulaOp::Offset | FormulaOp::Indirect => { self.dynamic = true; },
+                    _ => {},
+                }
+                for arg in args {
+                    self.add(arg, sheet);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn add_name(&mut self, name: &str) {
+        if !self.names.iter().any(|x| x == name) {
+            self.names.push(name.to_string());
+        }
+    }
+}
+
+/// The cells of a book with formulas, by the cells they read.
+pub struct Dependents {
+    /// Cells read on their own, with the cells that read them.
+    cells: HashMap<Cell, Vec<Cell>>,
+    /// The cells each cell reads on its own, to take it out of `cells`.
+    reads: HashMap<Cell, Vec<Cell>>,
+    /// The ranges each cell reads, by sheet.
+    ranges: HashMap<Cell, Vec<(usize, Coord, Coord)>>,
+    /// Cells that may read any cell.
+    dynamic: HashSet<Cell>,
+}
+
+impl Dependents {
+    pub fn new(book: &Book) -> Dependents {
+        let mut ret = Dependents{cells: HashMap::new(), reads: HashMap::new(), ranges: HashMap::new(),
+                                 dynamic: HashSet::new()};
+        for sheet in 0 .. book.sheet_count() {
+            for (coord, precedents) in book.sheet_at(sheet).precedents() {
+                ret.add((sheet, coord), book, sheet, precedents);
+            }
+        }
+        ret
+    }
+
+    fn add(&mut self, reader: Cell, book: &Book, sheet: usize, precedents: &Precedents) {
+        let mut areas = vec![];
+        if !read_areas(book, sheet, precedents, &mut HashSet::new(), &mut areas) {
+            self.dynamic.insert(reader);
+        }
+        for (idx, from, to) in areas {
+            if from == to {
+                self.cells.entry((idx, from)).or_insert(vec![]).push(reader);
+                self.reads.entry(reader).or_insert(vec![]).push((idx, from));
+            } else {
+                self.ranges.entry(reader).or_insert(vec![]).push((idx, from, to));
+            }
+        }
+    }
+
+    fn remove(&mut self, reader: Cell) {
+        for cell in self.reads.remove(&reader).unwrap_or(vec![]) {
+            let empty = match self.cells.get_mut(&cell) {
+                Some(readers) => {
+                    readers.retain(|&other| other != reader);
+                    readers.is_empty()
+                },
+                None => false,
+            };
+            if empty {
+                self.cells.remove(&cell);
+            }
+        }
+        self.ranges.remove(&reader);
+        self.dynamic.remove(&reader);
+    }
+
+    /// The cells that read any of `areas`, given as a sheet and two
+    /// corners, directly or through other cells, each once.
+    pub fn of(&self, areas: &[(usize, Coord, Coord)]) -> Vec<Cell> {
+        let mut todo = vec![];
+        for &(sheet, from, to) in areas {
+            todo.extend(self.readers(sheet, from, to));
+        }
+
+        let mut found = HashSet::new();
+        let mut ret = vec![];
+        while let Some(cell) = todo.pop() {
+            if found.insert(cell) {
+                ret.push(cell);
+                let (sheet, coord) = cell;
+                todo.extend(self.readers(sheet, coord, coord));
+            }
+        }
+        ret
+    }
+
+    /// The cells that read any cell of an area themselves, or may.
+    fn readers(&self, sheet: usize, from: Coord, to: Coord) -> Vec<Cell> {
+        let mut ret: Vec<Cell> = self.dynamic.iter().cloned().collect();
+        if from == to {
+            if let Some(readers) = self.cells.get(&(sheet, from)) {
+                ret.extend(readers.iter().cloned());
+            }
+        } else {
+            for (&(idx, coord), readers) in self.cells.iter() {
+                if idx == sheet && overlap((coord, coord), (from, to)) {
+                    ret.extend(readers.iter().cloned());
+                }
+            }
+        }
+        for (&reader, ranges) in self.ranges.iter() {
+            if ranges.iter().any(|&(idx, range_from, range_to)| idx == sheet && overlap((range_from, range_to), (from, to))) {
+                ret.push(reader);
+            }
+        }
+        ret
+    }
+}
+
+/// Changes the dependents a book keeps, if worked out, after the formula of
+/// `cell` changed.
+pub fn changed(book: &Book, cell: Cell) {
+    let mut dependents = book.dependents().lock().unwrap();
+    if let Some(ref mut dependents) = *dependents {
+        let (sheet, coord) = cell;
+        dependents.remove(cell);
+        if let Some(precedents) = book.sheet_at(sheet).precedents_of(coord) {
+            dependents.add(cell, book, sheet, precedents);
+        }
+    }
+}
+
+/// Adds the areas a formula of the sheet at `sheet` reads to `ret`, by the
+/// position of their sheet, with those of the names it uses. Returns whether
+/// that's all it reads, that is, unless it builds references.
+fn read_areas(book: &Book, sheet: usize, precedents: &Precedents, names: &mut HashSet<String>,
+              ret: &mut Vec<(usize, Coord, Coord)>) -> bool {
+    let mut known = !precedents.dynamic;
+    for &(ref name, from, to) in &precedents.areas {
+        let idx = match *name {
+            Some(ref name) => match book.sheet_index(name) {
+                Some(idx) => idx,
+                None => { continue; },
+            },
+            None => sheet,
+        };
+        ret.push((idx, from, to));
+    }
+    // A name is evaluated in the sheet of the formula that uses it.
+    for name in &precedents.names {
+        if names.insert(name.clone()) {
+            if let Some(target) = book.name(name) {
+                known = read_areas(book, sheet, &Precedents::of(target), names, ret) && known;
+            }
+        }
+    }
+    known
+}
+
+/// The cells whose formulas are read to evaluate `cells`, directly or
+/// through other cells, in levels: the cells of a level only read cells of
+/// earlier levels, so those of a level can be evaluated at once, each once.
+/// Spilled cells are read through the cell whose formula gives the array.
+/// Cells on cycles, cells that build references as they're evaluated, and
+/// the cells that read any of those are left out, as what they give depends
+/// on what's being evaluated when they're read.
+pub fn levels(book: &Book, cells: &[Cell]) -> Vec<Vec<Cell>> {
+    // What each cell with a formula reads, or `None` if it may read any cell.
+    let mut reads: HashMap<Cell, Option<Vec<Cell>>> = HashMap::new();
+    let mut todo: Vec<Cell> = cells.iter().flat_map(|&(sheet, coord)| formulas_in(book, sheet, coord, coord)).collect();
+    while let Some(cell) = todo.pop() {
+        if reads.contains_key(&cell) {
+            continue;
+        }
+        let (sheet, coord) = cell;
+        let mut areas = vec![];
+        let known = match book.sheet_at(sheet).precedents_of(coord) {
+            Some(precedents) => read_areas(book, sheet, precedents, &mut HashSet::new(), &mut areas),
+            None => true,
+        };
+        if !known {
+            reads.insert(cell, None);
+            continue;
+        }
+        let mut read = HashSet::new();
+        for (idx, from, to) in areas {
+            read.extend(formulas_in(book, idx, from, to));
+        }
+        let read: Vec<Cell> = read.into_iter().collect();
+        todo.extend(read.iter().cloned());
+        reads.insert(cell, Some(read));
+    }
+
+    // How many cells each waits for, and the cells waiting for each.
+    let mut waiting: HashMap<Cell, usize> = HashMap::new();
+    let mut readers: HashMap<Cell, Vec<Cell>> = HashMap::new();
+    for (&cell, read) in reads.iter() {
+        match *read {
+            Some(ref read) => {
+                waiting.insert(cell, read.len());
+                for &other in read {
+                    readers.entry(other).or_insert(vec![]).push(cell);
+                }
+            },
+            None => { waiting.insert(cell, 1); },
+        }
+    }
+
+    let mut ret = vec![];
+    let mut level: Vec<Cell> = waiting.iter().filter(|&(_, &n)| n == 0).map(|(&cell, _)| cell).collect();
+    while !level.is_empty() {
+        let mut next = vec![];
+        for cell in &level {
+            for reader in readers.get(cell).into_iter().flat_map(|x| x.iter()) {
+                let n = waiting.get_mut(reader).unwrap();
+                *n -= 1;
+                if *n == 0 {
+                    next.push(*reader);
+                }
+            }
+        }
+        ret.push(level);
+        level = next;
+    }
+    ret
+}
+
+/// The cells with formulas in an area, and those whose arrays spill over it,
+/// which may be the same.
+fn formulas_in(book: &Book, sheet: usize, from: Coord, to: Coord) -> Vec<Cell> {
+    let sheet_ref = book.sheet_at(sheet);
+    let anchors = sheet_ref.spills_over(from, to).into_iter().map(|(anchor, _)| anchor);
+    sheet_ref.formula_cells(from, to).into_iter().chain(anchors).map(|coord| (sheet, coord)).collect()
+}
+
+/// Works out again the areas arrays spill over after the cells of `areas`
+/// changed, returning the areas to send again to the selections: those
+/// areas, the cells that depend on them, the areas spilled over by any of
+/// those cells, before and after, and the ranges whose look depends on the
+/// value of any of those cells.
+pub fn update(book: &Book, areas: &[(usize, Coord, Coord)]) -> Vec<(usize, Coord, Coord)> {
+    let mut kept = book.dependents().lock().unwrap();
+    if kept.is_none() {
+        *kept = Some(Dependents::new(book));
+    }
+    let dependents = kept.as_ref().unwrap();
+    let mut ret = areas.to_vec();
+    let mut seen: HashSet<(usize, Coord, Coord)> = areas.iter().cloned().collect();
+    let mut todo = areas.to_vec();
+    // Spilled cells change with the cells their arrays are calculated from,
+    // and may be read by others in turn, so this goes on until no area is
+    // left that hasn't been looked at.
+    while !todo.is_empty() {
+        let mut next = vec![];
+        for &(sheet, from, to) in &todo {
+            // Whether these arrays can spill may have changed.
+            next.extend(book.sheet_at(sheet).spills_over(from, to).into_iter().map(|(from, to)| (sheet, from, to)));
+        }
+        let cells = todo.iter().filter(|&&(_, from, to)| from == to).map(|&(sheet, coord, _)| (sheet, coord));
+        for (sheet, coord) in cells.chain(dependents.of(&todo).into_iter()).collect::<Vec<_>>() {
+            let sent = ret.iter().any(|&(idx, from, to)| idx == sheet && overlap((coord, coord), (from, to)));
+            if !sent {
+                ret.push((sheet, coord, coord));
+            }
+            let sheet_ref = book.sheet_at(sheet);
+            for (from, to) in sheet_ref.restyled_by(coord) {
+                if !ret.contains(&(sheet, from, to)) {
+                    ret.push((sheet, from, to));
+                }
+            }
+            next.extend(sheet_ref.update_spill(coord, Context{book: book, sheet: sheet, cache: None}).into_iter()
+                        .chain(sheet_ref.spill_area(coord).into_iter())
+                        .map(|(from, to)| (sheet, from, to)));
+        }
+
+        todo = vec![];
+        for area in next {
+            if seen.insert(area) {
+                ret.push(area);
+                todo.push(area);
+            }
+        }
+    }
+    ret
+}
+
+/// Works out the areas all arrays spill over from scratch, as after lines
+/// move or anything that may change every value.
+pub fn refresh_spills(book: &Book) {
+    let mut anchors = vec![];
+    for sheet in 0 .. book.sheet_count() {
+        book.sheet_at(sheet).clear_spills();
+        anchors.extend(book.sheet_at(sheet).spill_anchors().into_iter().map(|coord| (sheet, coord)));
+    }
+    // Arrays can be calculated from cells other arrays spill over, which
+    // are empty until those are worked out, so this goes on until nothing
+    // changes, at most once more than there are arrays.
+    for _ in 0 .. anchors.len() + 1 {
+        let mut changed = false;
+        for &(sheet, anchor) in &anchors {
+            if !book.sheet_at(sheet).update_spill(anchor, Context{book: book, sheet: sheet, cache: None}).is_empty() {
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Whether two areas, given by their top left and bottom right cells, have
+/// cells in common.
+pub fn overlap((Coord(a_col_from, a_row_from), Coord(a_col_to, a_row_to)): (Coord, Coord),
+               (Coord(b_col_from, b_row_from), Coord(b_col_to, b_row_to)): (Coord, Coord)) -> bool {
+    a_col_from <= b_col_to && b_col_from <= a_col_to && a_row_from <= b_row_to && b_row_from <= a_row_to
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sheet::{Book, Anchor, Coord, Formula, FormulaAtom, FormulaOp};
+    use workbook::Workbook;
+
+    fn cell(col: usize, row: usize) -> Formula {
+        Formula::Ref(Coord(col, row), Anchor(false, false))
+    }
+
+    #[test]
+    fn test_precedents() {
+        let formula = Formula::Op(FormulaOp::VLookup, vec![
+            cell(0, 0),
+            Formula::SheetRef("Data".to_string(), Box::new(Formula::Range(Coord(0, 0), Anchor(false, false),
+                                                                          Coord(1, 9), Anchor(false, false)))),
+            Formula::Name("Column".to_string()),
+        ]);
+        let precedents = Precedents::of(&formula);
+        assert_eq!(vec![(None, Coord(0, 0), Coord(0, 0)), (Some("Data".to_string()), Coord(0, 0), Coord(1, 9))],
+                   precedents.areas);
+        assert_eq!(vec!["Column".to_string()], precedents.names);
+        assert!(!precedents.dynamic);
+        assert!(Precedents::of(&Formula::Op(FormulaOp::Indirect, vec![cell(0, 0)])).dynamic);
+    }
+
+    #[test]
+    fn test_dependents() {
+        let mut book = Workbook::new();
+        book.add_sheet("Data").unwrap();
+        book.define_name("Total", Formula::SheetRef("Data".to_string(), Box::new(cell(0, 5)))).unwrap();
+        // Data!A6 sums Data!A1:A5, Sheet1!A1 reads it through a name and
+        // Sheet1!B1 reads Sheet1!A1.
+        book.set(1, Coord(0, 5), Formula::Op(FormulaOp::Add, vec![
+            Formula::Range(Coord(0, 0), Anchor(false, false), Coord(0, 4), Anchor(false, false)),
+        ]));
+        book.set(0, Coord(0, 0), Formula::Name("Total".to_string()));
+        book.set(0, Coord(1, 0), Formula::Op(FormulaOp::Add, vec![cell(0, 0)]));
+
+        let dependents = Dependents::new(&book);
+        let found = dependents.of(&[(1, Coord(0, 2), Coord(0, 2))]);
+        assert_eq!(3, found.len());
+        for cell in &[(1, Coord(0, 5)), (0, Coord(0, 0)), (0, Coord(1, 0))] {
+            assert!(found.contains(cell));
+        }
+        assert_eq!(vec![(0, Coord(1, 0))], dependents.of(&[(0, Coord(0, 0), Coord(0, 0))]));
+        assert!(dependents.of(&[(1, Coord(0, 6), Coord(3, 9))]).is_empty());
+
+        // A reference built at runtime may be to any cell.
+        book.set(0, Coord(2, 0), Formula::Op(FormulaOp::Indirect, vec![
+            Formula::Atom(FormulaAtom::String("Data!D9".to_string())),
+        ]));
+        assert_eq!(vec![(0, Coord(2, 0))], Dependents::new(&book).of(&[(1, Coord(3, 8), Coord(3, 8))]));
+    }
+
+    #[test]
+    fn test_kept_dependents() {
+        let mut book = Workbook::new();
+        // Sheet1!B1 reads Sheet1!A1, and Sheet1!C1 sums Sheet1!B1:B2.
+        book.set(0, Coord(1, 0), cell(0, 0));
+        book.set(0, Coord(2, 0), Formula::Op(FormulaOp::Add, vec![
+            Formula::Range(Coord(1, 0), Anchor(false, false), Coord(1, 1), Anchor(false, false)),
+        ]));
+        assert!(book.dependents().lock().unwrap().is_some());
+
+        // Changing a formula changes the kept dependents along with it.
+        book.set(0, Coord(1, 0), cell(3, 0));
+        book.set(0, Coord(2, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        let kept = book.dependents().lock().unwrap();
+        let dependents = kept.as_ref().unwrap();
+        assert!(dependents.of(&[(0, Coord(0, 0), Coord(0, 0))]).is_empty());
+        assert_eq!(vec![(0, Coord(1, 0))], dependents.of(&[(0, Coord(3, 0), Coord(3, 0))]));
+        assert!(dependents.of(&[(0, Coord(1, 1), Coord(1, 1))]).is_empty());
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut book = Workbook::new();
+        book.add_sheet("Data").unwrap();
+        book.define_name("Total", Formula::SheetRef("Data".to_string(), Box::new(cell(0, 5)))).unwrap();
+        // Data!A6 sums Data!A1:A5, which Data!A2 and Data!A4 are in, and
+        // Sheet1!A1 reads it through a name.
+        book.set(1, Coord(0, 1), Formula::Atom(FormulaAtom::Number(1.0)));
+        book.set(1, Coord(0, 3), Formula::Op(FormulaOp::Add, vec![cell(0, 1)]));
+        book.set(1, Coord(0, 5), Formula::Op(FormulaOp::Add, vec![
+            Formula::Range(Coord(0, 0), Anchor(false, false), Coord(0, 4), Anchor(false, false)),
+        ]));
+        book.set(0, Coord(0, 0), Formula::Name("Total".to_string()));
+        // Sheet1!B1 and Sheet1!C1 read each other, and Sheet1!D1 reads them.
+        book.set(0, Coord(1, 0), cell(2, 0));
+        book.set(0, Coord(2, 0), cell(1, 0));
+        book.set(0, Coord(3, 0), Formula::Op(FormulaOp::Add, vec![cell(1, 0), cell(0, 0)]));
+
+        let found = levels(&book, &[(0, Coord(0, 0)), (0, Coord(3, 0)), (0, Coord(4, 0))]);
+        assert_eq!(vec![vec![(1, Coord(0, 1))], vec![(1, Coord(0, 3))], vec![(1, Coord(0, 5))], vec![(0, Coord(0, 0))]],
+                   found);
+
+        // A reference built at runtime may be to any cell.
+        book.set(1, Coord(0, 3), Formula::Op(FormulaOp::Indirect, vec![
+            Formula::Atom(FormulaAtom::String("A2".to_string())),
+        ]));
+        assert_eq!(vec![vec![(1, Coord(0, 1))]], levels(&book, &[(0, Coord(0, 0))]));
+    }
+
+    #[test]
+    fn test_array_functions() {
+        let mut book = Workbook::new();
+        book.add_sheet("Data").unwrap();
+        for (row, &x) in [3.0, 1.0, 2.0].iter().enumerate() {
+            book.set(1, Coord(0, row), Formula::Atom(FormulaAtom::Number(x)));
+        }
+        // Sheet1!A1 sorts Data!A1:A3, and Sheet1!B1 reads the last number.
+        book.set(0, Coord(0, 0), Formula::Op(FormulaOp::Sort, vec![
+            Formula::SheetRef("Data".to_string(), Box::new(Formula::Range(Coord(0, 0), Anchor(false, false),
+                                                                          Coord(0, 2), Anchor(false, false)))),
+        ]));
+        book.set(0, Coord(1, 0), Formula::Op(FormulaOp::Add, vec![cell(0, 2)]));
+        assert_eq!(vec![(Coord(0, 0), Coord(0, 2))], book.spill_areas(0));
+        let selection = book.select(0, Coord(0, 0), Coord(1, 3));
+        while let Ok(_) = selection.values.try_recv() {}
+
+        // The whole array and what reads it are sent again.
+        book.set(1, Coord(0, 1), Formula::Atom(FormulaAtom::Number(5.0)));
+        let mut sent = vec![];
+        while let Ok((coord, value)) = selection.values.try_recv() {
+            if !sent.iter().any(|&(other, _)| other == coord) {
+                sent.push((coord, value.ok().unwrap()));
+            }
+        }
+        assert_eq!(4, sent.len());
+        for &(coord, x) in &[(Coord(0, 0), 2.0), (Coord(0, 1), 3.0), (Coord(0, 2), 5.0), (Coord(1, 0), 5.0)] {
+            assert!(sent.contains(&(coord, Box::new(FormulaAtom::Number(x)))));
+        }
+
+        // Growing the range it sorts spills it further.
+        book.set(0, Coord(0, 0), Formula::Op(FormulaOp::Sort, vec![
+            Formula::SheetRef("Data".to_string(), Box::new(Formula::Range(Coord(0, 0), Anchor(false, false),
+                                                                          Coord(0, 3), Anchor(false, false)))),
+        ]));
+        assert_eq!(vec![(Coord(0, 0), Coord(0, 3))], book.spill_areas(0));
+        book.set(1, Coord(0, 3), Formula::Atom(FormulaAtom::Number(4.0)));
+        assert_eq!(FormulaAtom::Number(5.0), *book.value(0, Coord(0, 3)).ok().unwrap());
+        assert_eq!(FormulaAtom::Number(4.0), *book.value(0, Coord(1, 0)).ok().unwrap());
+    }
+}
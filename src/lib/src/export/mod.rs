@@ -0,0 +1,547 @@
+//! Workbooks exported for other spreadsheet applications, as Office Open XML
+//! (`.xlsx`) and OpenDocument (`.ods`) files.
+//!
+//! Cells are exported with their values and styles, and in `.xlsx` files
+//! with their number formats too. Formulas aren't, as other applications
+//! name their functions differently, nor are validations and conditional
+//! formats.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use date;
+use format::{format_atom, format_error};
+use sheet::{Coord, FormulaAtom, Value, cells_between};
+use style::{Border, Borders, HAlign, Rgb, Style, VAlign};
+use workbook::Workbook;
+
+/// The text size of cells that don't set one, in points.
+const DEFAULT_FONT_SIZE: u32 = 11;
+
+pub fn export_xlsx(book: &Workbook, out: &mut Write) -> io::Result<()> {
+    let names = book.sheet_names();
+    let mut xfs = vec![(None, Style::new())];
+    let mut files = vec![];
+
+    for idx in 0..names.len() {
+        let mut xml = format!("{}<worksheet xmlns=\"{}\"><sheetData>", XML_HEADER, XLSX_MAIN);
+        let mut last_row = None;
+        for (coord, value) in cells(book, idx) {
+            let Coord(_, row) = coord;
+            if last_row != Some(row) {
+                if last_row.is_some() {
+                    xml.push_str("</row>");
+                }
+                xml.push_str(&format!("<row r=\"{}\">", row + 1));
+                last_row = Some(row);
+            }
+
+            let code = match (book.format(idx, coord), &value) {
+                (Some(format), _) => Some(format.code().to_string()),
+                (None, &Ok(ref x)) => default_code(x).map(|x| x.to_string()),
+                _ => None,
+            };
+            let style = book.style(idx, coord).cloned().unwrap_or(Style::new());
+            let key = (code, style);
+            let xf = match xfs.iter().position(|x| *x == key) {
+                Some(xf) => xf,
+                None => { xfs.push(key); xfs.len() - 1 },
+            };
+
+            let r = coord.format_natural();
+            xml.push_str(&match value {
+                Ok(x) => match *x {
+                    FormulaAtom::Empty => format!("<c r=\"{}\" s=\"{}\"/>", r, xf),
+                    FormulaAtom::Number(x) | FormulaAtom::Date(x) | FormulaAtom::Duration(x) if x.is_finite() => {
+                        format!("<c r=\"{}\" s=\"{}\"><v>{}</v></c>", r, xf, x)
+                    },
+                    FormulaAtom::Number(_) | FormulaAtom::Date(_) | FormulaAtom::Duration(_) => {
+                        format!("<c r=\"{}\" s=\"{}\" t=\"e\"><v>#NUM!</v></c>", r, xf)
+                    },
+                    FormulaAtom::Decimal(ref x) => format!("<c r=\"{}\" s=\"{}\"><v>{}</v></c>", r, xf, x),
+                    ref x => format!("<c r=\"{}\" s=\"{}\" t=\"inlineStr\"><is><t xml:space=\"preserve\">{}</t></is></c>",
+                                     r, xf, escape(&format_atom(x, None, false).text)),
+                },
+                Err(e) => format!("<c r=\"{}\" s=\"{}\" t=\"e\"><v>{}</v></c>", r, xf, escape(format_error(&e))),
+            });
+        }
+        if last_row.is_some() {
+            xml.push_str("</row>");
+        }
+        xml.push_str("</sheetData></worksheet>");
+        files.push((format!("xl/worksheets/sheet{}.xml", idx + 1), xml.into_bytes()));
+    }
+
+    let mut types = format!("{}<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\
+        <Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\
+        <Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+        <Override PartName=\"/xl/workbook.xml\" \
+        ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml\"/>\
+        <Override PartName=\"/xl/styles.xml\" \
+        ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml\"/>", XML_HEADER);
+    let mut workbook = format!("{}<workbook xmlns=\"{}\" xmlns:r=\"{}\"><sheets>", XML_HEADER, XLSX_MAIN, XLSX_RELATIONSHIPS);
+    let mut relationships = format!("{}<Relationships xmlns=\"{}\">", XML_HEADER, PACKAGE_RELATIONSHIPS);
+    for (idx, name) in names.iter().enumerate() {
+        types.push_str(&format!("<Override PartName=\"/xl/worksheets/sheet{}.xml\" \
+            ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>", idx + 1));
+        workbook.push_str(&format!("<sheet name=\"{}\" sheetId=\"{}\" r:id=\"rId{}\"/>", escape(name), idx + 1, idx + 1));
+        relationships.push_str(&format!("<Relationship Id=\"rId{}\" Type=\"{}/worksheet\" \
+            Target=\"worksheets/sheet{}.xml\"/>", idx + 1, XLSX_RELATIONSHIPS, idx + 1));
+    }
+    types.push_str("</Types>");
+    workbook.push_str("</sheets></workbook>");
+    relationships.push_str(&format!("<Relationship Id=\"rId{}\" Type=\"{}/styles\" Target=\"styles.xml\"/>\
+        </Relationships>", names.len() + 1, XLSX_RELATIONSHIPS));
+    let root = format!("{}<Relationships xmlns=\"{}\"><Relationship Id=\"rId1\" Type=\"{}/officeDocument\" \
+        Target=\"xl/workbook.xml\"/></Relationships>", XML_HEADER, PACKAGE_RELATIONSHIPS, XLSX_RELATIONSHIPS);
+
+    files.insert(0, ("[Content_Types].xml".to_string(), types.into_bytes()));
+    files.insert(1, ("_rels/.rels".to_string(), root.into_bytes()));
+    files.insert(2, ("xl/workbook.xml".to_string(), workbook.into_bytes()));
+    files.insert(3, ("xl/_rels/workbook.xml.rels".to_string(), relationships.into_bytes()));
+    files.insert(4, ("xl/styles.xml".to_string(), xlsx_styles(&xfs).into_bytes()));
+    write_zip(&files, out)
+}
+
+pub fn export_ods(book: &Workbook, out: &mut Write) -> io::Result<()> {
+    let mut styles: Vec<Style> = vec![];
+    let mut tables = String::new();
+
+    for (idx, name) in book.sheet_names().iter().enumerate() {
+        tables.push_str(&format!("<table:table table:name=\"{}\">", escape(name)));
+        let (mut row, mut col) = (0, 0);
+        let mut open = false;
+        for (coord, value) in cells(book, idx) {
+            let Coord(cell_col, cell_row) = coord;
+            if !open || cell_row != row {
+                if open {
+                    tables.push_str("</table:table-row>");
+                    row += 1;
+                }
+                if cell_row > row {
+                    tables.push_str(&format!("<table:table-row table:number-rows-repeated=\"{}\">\
+                        <table:table-cell/></table:table-row>", cell_row - row));
+                }
+                tables.push_str("<table:table-row>");
+                row = cell_row;
+                col = 0;
+                open = true;
+            }
+            if cell_col > col {
+                tables.push_str(&format!("<table:table-cell table:number-columns-repeated=\"{}\"/>", cell_col - col));
+            }
+            col = cell_col + 1;
+
+            let style = match book.style(idx, coord) {
+                Some(style) => {
+                    let position = styles.iter().position(|x| x == style);
+                    let n = match position {
+                        Some(n) => n,
+                        None => { styles.push(style.clone()); styles.len() - 1 },
+                    };
+                    format!(" table:style-name=\"ce{}\"", n + 1)
+                },
+                None => "".to_string(),
+            };
+            let text = match value {
+                Ok(ref x) => format_atom(x, book.format(idx, coord), false).text,
+                Err(ref e) => format_error(e).to_string(),
+            };
+            let attributes = match value {
+                Ok(x) => match *x {
+                    FormulaAtom::Empty => "".to_string(),
+                    FormulaAtom::Number(x) if x.is_finite() => format!(" office:value-type=\"float\" office:value=\"{}\"", x),
+                    FormulaAtom::Decimal(ref x) => format!(" office:value-type=\"float\" office:value=\"{}\"", x),
+                    FormulaAtom::Date(x) if x.is_finite() => {
+                        format!(" office:value-type=\"date\" office:date-value=\"{}\"", date::format_iso(x))
+                    },
+                    FormulaAtom::Duration(x) if x.is_finite() => {
+                        format!(" office:value-type=\"time\" office:time-value=\"{}\"", iso_duration(x))
+                    },
+                    _ => " office:value-type=\"string\"".to_string(),
+                },
+                Err(_) => " office:value-type=\"string\"".to_string(),
+            };
+            if text.is_empty() {
+                tables.push_str(&format!("<table:table-cell{}{}/>", style, attributes));
+            } else {
+                tables.push_str(&format!("<table:table-cell{}{}><text:p>{}</text:p></table:table-cell>",
+                                         style, attributes, escape(&text)));
+            }
+        }
+        if open {
+            tables.push_str("</table:table-row>");
+        } else {
+            // Tables need a row, even if empty.
+            tables.push_str("<table:table-row><table:table-cell/></table:table-row>");
+        }
+        tables.push_str("</table:table>");
+    }
+
+    let mut automatic = String::new();
+    for (n, style) in styles.iter().enumerate() {
+        automatic.push_str(&format!("<style:style style:name=\"ce{}\" style:family=\"table-cell\">{}</style:style>",
+                                    n + 1, ods_style(style)));
+    }
+    let content = format!("{}<office:document-content xmlns:office=\"{}:office:1.0\" xmlns:style=\"{}:style:1.0\" \
+        xmlns:text=\"{}:text:1.0\" xmlns:table=\"{}:table:1.0\" xmlns:fo=\"{}:xsl-fo-compatible:1.0\" \
+        office:version=\"1.2\"><office:automatic-styles>{}</office:automatic-styles>\
+        <office:body><office:spreadsheet>{}</office:spreadsheet></office:body></office:document-content>",
+        XML_HEADER, ODF, ODF, ODF, ODF, ODF, automatic, tables);
+    let manifest = format!("{}<manifest:manifest xmlns:manifest=\"{}:manifest:1.0\" manifest:version=\"1.2\">\
+        <manifest:file-entry manifest:full-path=\"/\" manifest:media-type=\"{}\"/>\
+        <manifest:file-entry manifest:full-path=\"content.xml\" manifest:media-type=\"text/xml\"/>\
+        </manifest:manifest>", XML_HEADER, ODF, ODS_MIME);
+
+    // The media type has to be the first file.
+    write_zip(&[("mimetype".to_string(), ODS_MIME.as_bytes().to_vec()),
+                ("META-INF/manifest.xml".to_string(), manifest.into_bytes()),
+                ("content.xml".to_string(), content.into_bytes())], out)
+}
+
+const XML_HEADER: &'static str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n";
+const XLSX_MAIN: &'static str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+const XLSX_RELATIONSHIPS: &'static str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const PACKAGE_RELATIONSHIPS: &'static str = "http://schemas.openxmlformats.org/package/2006/relationships";
+const ODF: &'static str = "urn:oasis:names:tc:opendocument:xmlns";
+const ODS_MIME: &'static str = "application/vnd.oasis.opendocument.spreadsheet";
+
+/// The cells of a sheet with a value, a style or a number format, row by
+/// row, with their values.
+fn cells(book: &Workbook, sheet: usize) -> Vec<(Coord, Value)> {
+    let mut coords = BTreeSet::new();
+    for (Coord(col, row), _) in book.sheet(sheet).formulas() {
+        coords.insert((row, col));
+    }
+    for (from, to) in book.spill_areas(sheet) {
+        for Coord(col, row) in cells_between(from, to) {
+            coords.insert((row, col));
+        }
+    }
+    for (Coord(col, row), _) in book.sheet(sheet).styles() {
+        coords.insert((row, col));
+    }
+    for (Coord(col, row), _) in book.sheet(sheet).formats() {
+        coords.insert((row, col));
+    }
+    coords.into_iter().map(|(row, col)| (Coord(col, row), book.value(sheet, Coord(col, row)))).collect()
+}
+
+/// The format code for dates and durations without a number format, which
+/// are plain numbers in `.xlsx` files.
+fn default_code(atom: &FormulaAtom) -> Option<&'static str> {
+    match *atom {
+        FormulaAtom::Date(x) if x.fract() == 0.0 => Some("yyyy-mm-dd"),
+        FormulaAtom::Date(_) => Some("yyyy-mm-dd hh:mm:ss"),
+        FormulaAtom::Duration(_) => Some("[h]:mm:ss"),
+        _ => None,
+    }
+}
+
+/// The `styles.xml` of an `.xlsx` file, with the formats after the first
+/// one, which is the default, as custom number formats, fonts, fills and
+/// borders.
+fn xlsx_styles(xfs: &[(Option<String>, Style)]) -> String {
+    let mut codes: Vec<&str> = vec![];
+    let mut fonts = vec![xlsx_font(&Style::new())];
+    let mut fills = vec!["<fill><patternFill patternType=\"none\"/></fill>".to_string(),
+                         "<fill><patternFill patternType=\"gray125\"/></fill>".to_string()];
+    let mut borders = vec![xlsx_borders(&Borders::none())];
+    let mut cell_xfs = String::new();
+
+    for &(ref code, ref style) in xfs {
+        let num_fmt = match *code {
+            Some(ref code) => {
+                let position = codes.iter().position(|x| *x == code.as_str());
+                164 + match position {
+                    Some(n) => n,
+                    None => { codes.push(code.as_str()); codes.len() - 1 },
+                }
+            },
+            None => 0,
+        };
+        let font = index_of(&mut fonts, xlsx_font(style));
+        let fill = match style.background {
+            Some(color) => index_of(&mut fills, format!("<fill><patternFill patternType=\"solid\">\
+                <fgColor rgb=\"{}\"/></patternFill></fill>", argb(color))),
+            None => 0,
+        };
+        let border = index_of(&mut borders, xlsx_borders(&style.borders));
+
+        let mut alignment = String::new();
+        match style.h_align {
+            HAlign::General => {},
+            HAlign::Left => alignment.push_str(" horizontal=\"left\""),
+            HAlign::Center => alignment.push_str(" horizontal=\"center\""),
+            HAlign::Right => alignment.push_str(" horizontal=\"right\""),
+        }
+        match style.v_align {
+            VAlign::Top => alignment.push_str(" vertical=\"top\""),
+            VAlign::Middle => alignment.push_str(" vertical=\"center\""),
+            VAlign::Bottom => {},
+        }
+        if style.wrap {
+            alignment.push_str(" wrapText=\"1\"");
+        }
+        cell_xfs.push_str(&format!("<xf numFmtId=\"{}\" fontId=\"{}\" fillId=\"{}\" borderId=\"{}\" xfId=\"0\" \
+            applyNumberFormat=\"1\" applyFont=\"1\" applyFill=\"1\" applyBorder=\"1\" applyAlignment=\"1\">\
+            <alignment{}/></xf>", num_fmt, font, fill, border, alignment));
+    }
+
+    let num_fmts: String = codes.iter().enumerate().map(|(n, code)| {
+        format!("<numFmt numFmtId=\"{}\" formatCode=\"{}\"/>", 164 + n, escape(code))
+    }).collect::<Vec<_>>().concat();
+    format!("{}<styleSheet xmlns=\"{}\"><numFmts count=\"{}\">{}</numFmts><fonts count=\"{}\">{}</fonts>\
+        <fills count=\"{}\">{}</fills><borders count=\"{}\">{}</borders>\
+        <cellStyleXfs count=\"1\"><xf numFmtId=\"0\" fontId=\"0\" fillId=\"0\" borderId=\"0\"/></cellStyleXfs>\
+        <cellXfs count=\"{}\">{}</cellXfs></styleSheet>",
+        XML_HEADER, XLSX_MAIN, codes.len(), num_fmts, fonts.len(), fonts.concat(), fills.len(), fills.concat(),
+        borders.len(), borders.concat(), xfs.len(), cell_xfs)
+}
+
+fn xlsx_font(style: &Style) -> String {
+    let mut ret = "<font>".to_string();
+    if style.bold {
+        ret.push_str("<b/>");
+    }
+    if style.italic {
+        ret.push_str("<i/>");
+    }
+    ret.push_str(&format!("<sz val=\"{}\"/>", style.font_size.unwrap_or(DEFAULT_FONT_SIZE)));
+    if let Some(color) = style.color {
+        ret.push_str(&format!("<color rgb=\"{}\"/>", argb(color)));
+    }
+    ret.push_str("<name val=\"Calibri\"/></font>");
+    ret
+}
+
+fn xlsx_borders(borders: &Borders) -> String {
+    let side = |name: &str, border: Option<Border>| match border {
+        Some(border) => format!("<{} style=\"{}\"><color rgb=\"{}\"/></{}>",
+                                name, line_style(border), argb(border.color), name),
+        None => format!("<{}/>", name),
+    };
+    format!("<border>{}{}{}{}<diagonal/></border>", side("left", borders.left), side("right", borders.right),
+            side("top", borders.top), side("bottom", borders.bottom))
+}
+
+/// The style of a border line in `.xlsx` files, which only come in a few
+/// widths.
+fn line_style(border: Border) -> &'static str {
+    match border.width {
+        0 | 1 => "thin",
+        2 => "medium",
+        _ => "thick",
+    }
+}
+
+fn argb(color: Rgb) -> String {
+    format!("FF{}", &color.to_hex()[1..])
+}
+
+/// The properties of a cell style in an `.ods` file.
+fn ods_style(style: &Style) -> String {
+    let mut cell = String::new();
+    if let Some(color) = style.background {
+        cell.push_str(&format!(" fo:background-color=\"{}\"", color.to_hex()));
+    }
+    let sides = [("top", style.borders.top), ("bottom", style.borders.bottom),
+                 ("left", style.borders.left), ("right", style.borders.right)];
+    for &(name, border) in sides.iter() {
+        if let Some(border) = border {
+            cell.push_str(&format!(" fo:border-{}=\"{}pt solid {}\"", name, border.width as f64 * 0.75, border.color.to_hex()));
+        }
+    }
+    cell.push_str(match style.v_align {
+        VAlign::Top => " style:vertical-align=\"top\"",
+        VAlign::Middle => " style:vertical-align=\"middle\"",
+        VAlign::Bottom => " style:vertical-align=\"bottom\"",
+    });
+    if style.wrap {
+        cell.push_str(" fo:wrap-option=\"wrap\"");
+    }
+
+    let paragraph = match style.h_align {
+        HAlign::General => "",
+        HAlign::Left => " fo:text-align=\"start\"",
+        HAlign::Center => " fo:text-align=\"center\"",
+        HAlign::Right => " fo:text-align=\"end\"",
+    };
+
+    let mut text = String::new();
+    if style.bold {
+        text.push_str(" fo:font-weight=\"bold\"");
+    }
+    if style.italic {
+        text.push_str(" fo:font-style=\"italic\"");
+    }
+    if let Some(size) = style.font_size {
+        text.push_str(&format!(" fo:font-size=\"{}pt\"", size));
+    }
+    if let Some(color) = style.color {
+        text.push_str(&format!(" fo:color=\"{}\"", color.to_hex()));
+    }
+    format!("<style:table-cell-properties{}/><style:paragraph-properties{}/><style:text-properties{}/>",
+            cell, paragraph, text)
+}
+
+/// A number of days as an ISO 8601 duration, like `PT36H00M00S`.
+fn iso_duration(days: f64) -> String {
+    let seconds = (days * 86400.0).round() as i64;
+    let sign = if seconds < 0 { "-" } else { "" };
+    let seconds = seconds.abs();
+    format!("{}PT{}H{:02}M{:02}S", sign, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// The position of `item` in `items`, added at the end if it isn't there.
+fn index_of(items: &mut Vec<String>, item: String) -> usize {
+    match items.iter().position(|x| *x == item) {
+        Some(n) => n,
+        None => { items.push(item); items.len() - 1 },
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;").replace("'", "&apos;")
+}
+
+/// Writes a zip archive with the files stored as they are, without
+/// compressing them.
+fn write_zip(files: &[(String, Vec<u8>)], out: &mut Write) -> io::Result<()> {
+    let mut archive = vec![];
+    let mut directory = vec![];
+    for &(ref name, ref data) in files {
+        let offset = archive.len();
+        let crc = crc32(data);
+        // The headers of files and of the directory share most fields.
+        let mut common = vec![];
+        push_u16(&mut common, 20);
+        push_u16(&mut common, 0);
+        push_u16(&mut common, 0);
+        // At midnight on 1980-01-01, the earliest time zip files have.
+        push_u16(&mut common, 0);
+        push_u16(&mut common, 0x21);
+        push_u32(&mut common, crc);
+        push_u32(&mut common, data.len() as u32);
+        push_u32(&mut common, data.len() as u32);
+        push_u16(&mut common, name.len() as u16);
+        push_u16(&mut common, 0);
+
+        push_u32(&mut archive, 0x04034b50);
+        archive.extend(common.iter().cloned());
+        archive.extend(name.bytes());
+        archive.extend(data.iter().cloned());
+
+        push_u32(&mut directory, 0x02014b50);
+        push_u16(&mut directory, 20);
+        directory.extend(common.iter().cloned());
+        push_u16(&mut directory, 0);
+        push_u16(&mut directory, 0);
+        push_u16(&mut directory, 0);
+        push_u32(&mut directory, 0);
+        push_u32(&mut directory, offset as u32);
+        directory.extend(name.bytes());
+    }
+
+    let directory_offset = archive.len();
+    archive.extend(directory.iter().cloned());
+    push_u32(&mut archive, 0x06054b50);
+    push_u16(&mut archive, 0);
+    push_u16(&mut archive, 0);
+    push_u16(&mut archive, files.len() as u16);
+    push_u16(&mut archive, files.len() as u16);
+    push_u32(&mut archive, directory.len() as u32);
+    push_u32(&mut archive, directory_offset as u32);
+    push_u16(&mut archive, 0);
+    out.write_all(&archive)
+}
+
+fn push_u16(buf: &mut Vec<u8>, x: u16) {
+    buf.push(x as u8);
+    buf.push((x >> 8) as u8);
+}
+
+fn push_u32(buf: &mut Vec<u8>, x: u32) {
+    push_u16(buf, x as u16);
+    push_u16(buf, (x >> 16) as u16);
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::crc32;
+    use format::NumberFormat;
+    use parser::parse_formula;
+    use sheet::Coord;
+    use style::{Border, Borders, HAlign, Rgb, Style};
+    use workbook::Workbook;
+
+    fn contains(haystack: &[u8], needle: &str) -> bool {
+        haystack.windows(needle.len()).any(|x| x == needle.as_bytes())
+    }
+
+    fn book() -> Workbook {
+        let mut book = Workbook::new();
+        book.set(0, Coord(0, 0), parse_formula("1234.5").ok().unwrap());
+        book.set(0, Coord(1, 0), parse_formula("\"a < b\"").ok().unwrap());
+        book.set(0, Coord(0, 2), parse_formula("2024-01-15").ok().unwrap());
+        book.set(0, Coord(1, 2), parse_formula("add(missing, 1)").ok().unwrap());
+        book.set_format(0, Coord(0, 0), Coord(0, 0), Some(NumberFormat::parse("#,##0.00").unwrap()));
+        let mut style = Style::new();
+        style.bold = true;
+        style.background = Some(Rgb(255, 255, 0));
+        style.h_align = HAlign::Center;
+        style.borders = Borders::all(Border{width: 2, color: Rgb(0, 0, 255)});
+        book.set_style(0, Coord(1, 0), Coord(1, 0), style);
+        book
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0xCBF43926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_xlsx() {
+        let mut out = vec![];
+        export_xlsx(&book(), &mut out).unwrap();
+        assert!(out.starts_with(b"PK\x03\x04"));
+        assert!(contains(&out, "<row r=\"1\"><c r=\"A1\" s=\"1\"><v>1234.5</v></c>\
+                                <c r=\"B1\" s=\"2\" t=\"inlineStr\"><is><t xml:space=\"preserve\">a &lt; b</t></is></c></row>\
+                                <row r=\"3\"><c r=\"A3\" s=\"3\"><v>45306</v></c><c r=\"B3\" s=\"0\" t=\"e\"><v>#NAME?</v></c></row>"));
+        assert!(contains(&out, "<numFmt numFmtId=\"164\" formatCode=\"#,##0.00\"/><numFmt numFmtId=\"165\" formatCode=\"yyyy-mm-dd\"/>"));
+        assert!(contains(&out, "<font><b/><sz val=\"11\"/><name val=\"Calibri\"/></font>"));
+        assert!(contains(&out, "<patternFill patternType=\"solid\"><fgColor rgb=\"FFFFFF00\"/>"));
+        assert!(contains(&out, "<left style=\"medium\"><color rgb=\"FF0000FF\"/></left>"));
+        assert!(contains(&out, "<alignment horizontal=\"center\"/>"));
+    }
+
+    #[test]
+    fn test_ods() {
+        let mut out = vec![];
+        export_ods(&book(), &mut out).unwrap();
+        assert!(contains(&out, "mimetypeapplication/vnd.oasis.opendocument.spreadsheet"));
+        assert!(contains(&out, "<table:table table:name=\"Sheet1\"><table:table-row>\
+            <table:table-cell office:value-type=\"float\" office:value=\"1234.5\"><text:p>1,234.50</text:p></table:table-cell>\
+            <table:table-cell table:style-name=\"ce1\" office:value-type=\"string\"><text:p>a &lt; b</text:p></table:table-cell>\
+            </table:table-row><table:table-row table:number-rows-repeated=\"1\"><table:table-cell/></table:table-row>\
+            <table:table-row><table:table-cell office:value-type=\"date\" office:date-value=\"2024-01-15\">\
+            <text:p>2024-01-15</text:p></table:table-cell>\
+            <table:table-cell office:value-type=\"string\"><text:p>#NAME?</text:p></table:table-cell></table:table-row>\
+            </table:table>"));
+        assert!(contains(&out, "<style:table-cell-properties fo:background-color=\"#FFFF00\" \
+            fo:border-top=\"1.5pt solid #0000FF\""));
+        assert!(contains(&out, "<style:paragraph-properties fo:text-align=\"center\"/>\
+            <style:text-properties fo:font-weight=\"bold\"/>"));
+    }
+}
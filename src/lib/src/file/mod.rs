@@ -0,0 +1,603 @@
+//! Workbooks saved as text, with formulas the way they're typed:
+//!
+//! ```text
+//! rate = 0.05
+//! [Sheet1]
+//! A1 = 100
+//! A2 = mul(A1, add(1, rate))
+//! A2 format #,##0.00
+//! A2 style bold color #C00000 border-bottom 1 #000000
+//! A1:A9 validate reject number 0 1000
+//! A1:A9 highlight italic background #FFFF00 if > 500
+//! A1:A9 scale #FFFFFF #00B050
+//! B1:B9 bar #4472C4
+//! ```
+//!
+//! Defined names come first, then every sheet, with its name in brackets
+//! followed by its cells, then the number formats and styles of its cells,
+//! its validations and its conditional formats. Validations are kept as
+//! `reject` or `warn`, then `number`, `date` or `length` with the smallest
+//! and largest values allowed, `list` with an array of the values, or
+//! `custom` with the formula. Conditional formats are kept as `highlight`
+//! with the changes to the style, then `if` and a comparison with a value,
+//! or `between` and an array of the two bounds, as `scale` with two or
+//! three colors, or as `bar` with one.
+//!
+//! The values of a sheet, as shown with their number formats, can also be
+//! exported as CSV.
+
+use std::cmp::max;
+use std::io::{self, Read, Write};
+use conditional::{CompareOp, Condition, ConditionalFormat, Rule, StylePatch};
+use format::{NumberFormat, format_atom, format_error};
+use parser::{format_formula, parse_formula};
+use sheet::{Coord, Formula, FormulaAtom};
+use style::{Border, HAlign, Rgb, Style, VAlign};
+use validation::{Validation, ValidationMode, ValidationRule};
+use workbook::{Workbook, WorkbookErr};
+
+#[derive(Debug)]
+pub enum FileErr {
+    Io(io::Error),
+    /// A line, counted from 1, that isn't a sheet, a name or a cell.
+    Syntax(usize),
+    Workbook(WorkbookErr),
+}
+
+pub fn save(book: &Workbook, out: &mut Write) -> io::Result<()> {
+    for (name, formula) in book.names() {
+        try!(writeln!(out, "{} = {}", name, try!(line(formula))));
+    }
+    for (idx, name) in book.sheet_names().iter().enumerate() {
+        try!(writeln!(out, "[{}]", name));
+        let sheet = book.sheet(idx);
+        for (coord, formula) in sheet.formulas() {
+            try!(writeln!(out, "{} = {}", coord.format_natural(), try!(line(formula))));
+        }
+        for (coord, format) in sheet.formats() {
+            try!(writeln!(out, "{} format {}", coord.format_natural(), format.code()));
+        }
+        for (coord, style) in sheet.styles() {
+            try!(writeln!(out, "{} style {}", coord.format_natural(), style_words(style).join(" ")));
+        }
+        for validation in sheet.validations() {
+            try!(writeln!(out, "{} validate {}", area(validation.from, validation.to), try!(validation_words(validation))));
+        }
+        for cf in sheet.conditional_formats() {
+            try!(writeln!(out, "{} {}", area(cf.from, cf.to), try!(rule_words(&cf.rule))));
+        }
+    }
+    Ok(())
+}
+
+pub fn load(input: &mut Read) -> Result<Workbook, FileErr> {
+    let mut text = String::new();
+    try!(input.read_to_string(&mut text).map_err(FileErr::Io));
+
+    let mut book = Workbook::new();
+    let mut sheet = None;
+    // Names are defined once the sheets have their names, as references in
+    // them are kept by the name of the sheet, and validations are added
+    // once the cells are set, so that they don't reject them.
+    let mut names = vec![];
+    let mut validations = vec![];
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim_right_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if line.starts_with("[") && line.ends_with("]") {
+            let name = &line[1 .. line.len()-1];
+            sheet = Some(match sheet {
+                None => { try!(book.rename_sheet(0, name).map_err(FileErr::Workbook)); 0 },
+                Some(_) => try!(book.add_sheet(name).map_err(FileErr::Workbook)),
+            });
+            continue;
+        }
+
+        let syntax = FileErr::Syntax(idx + 1);
+        if let Some(sheet) = sheet {
+            if let Some((from, to, keyword, rest)) = attribute(line) {
+                match keyword {
+                    "format" => match NumberFormat::parse(rest) {
+                        Ok(format) => book.set_format(sheet, from, to, Some(format)),
+                        Err(_) => { return Err(syntax); },
+                    },
+                    "style" => match parse_style(rest) {
+                        Some(style) => book.set_style(sheet, from, to, style),
+                        None => { return Err(syntax); },
+                    },
+                    "validate" => match parse_validation(rest) {
+                        Some((rule, mode)) => validations.push((sheet, Validation{from: from, to: to, rule: rule, mode: mode})),
+                        None => { return Err(syntax); },
+                    },
+                    _ => match parse_rule(keyword, rest) {
+                        Some(rule) => { book.add_conditional_format(sheet, ConditionalFormat{from: from, to: to, rule: rule}); },
+                        None => { return Err(syntax); },
+                    },
+                }
+                continue;
+            }
+        }
+
+        let (target, formula) = match line.find('=') {
+            Some(eq) => (line[..eq].trim(), &line[eq+1..]),
+            None => { return Err(syntax); },
+        };
+        let formula = match parse_formula(formula.trim()) {
+            Ok(x) => x,
+            Err(_) => { return Err(syntax); },
+        };
+        match sheet {
+            None => names.push((target.to_string(), formula)),
+            Some(idx) => match Coord::parse(target) {
+                Some(coord) => { book.set(idx, coord, formula); },
+                None => { return Err(syntax); },
+            },
+        }
+    }
+    for (sheet, validation) in validations {
+        try!(book.add_validation(sheet, validation).map_err(FileErr::Workbook));
+    }
+    for (name, formula) in names {
+        try!(book.define_name(&name, formula).map_err(FileErr::Workbook));
+    }
+    Ok(book)
+}
+
+/// Writes the values of a sheet as CSV, from A1 to the last row and column
+/// with a value, formatted the way they're shown.
+pub fn export_csv(book: &Workbook, sheet: usize, out: &mut Write) -> io::Result<()> {
+    let (mut cols, mut rows) = (0, 0);
+    for (Coord(col, row), _) in book.sheet(sheet).formulas() {
+        cols = max(cols, col + 1);
+        rows = max(rows, row + 1);
+    }
+    for (_, Coord(col, row)) in book.spill_areas(sheet) {
+        cols = max(cols, col + 1);
+        rows = max(rows, row + 1);
+    }
+
+    for row in 0..rows {
+        let fields: Vec<String> = (0..cols).map(|col| {
+            let coord = Coord(col, row);
+            let text = match book.value(sheet, coord) {
+                Ok(x) => format_atom(&x, book.format(sheet, coord), false).text,
+                Err(e) => format_error(&e).to_string(),
+            };
+            csv_field(text)
+        }).collect();
+        try!(write!(out, "{}\r\n", fields.join(",")));
+    }
+    Ok(())
+}
+
+/// Quotes a field that has commas, quotes or line breaks in it.
+fn csv_field(text: String) -> String {
+    if text.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", text.replace("\"", "\"\""))
+    } else {
+        text
+    }
+}
+
+/// A cell, or a range from its top left to its bottom right cell.
+fn area(from: Coord, to: Coord) -> String {
+    if from == to {
+        from.format_natural()
+    } else {
+        format!("{}:{}", from.format_natural(), to.format_natural())
+    }
+}
+
+/// The cells, the keyword and the rest of a line that gives cells a format,
+/// a style, a validation or a conditional format.
+fn attribute(line: &str) -> Option<(Coord, Coord, &str, &str)> {
+    let (target, rest) = split_word(line.trim());
+    let (keyword, rest) = split_word(rest);
+    match keyword {
+        "format" | "style" | "validate" | "highlight" | "scale" | "bar" => {},
+        _ => { return None; },
+    }
+    let (from, to) = match target.find(':') {
+        Some(colon) => (Coord::parse(&target[..colon]), Coord::parse(&target[colon+1..])),
+        None => (Coord::parse(target), Coord::parse(target)),
+    };
+    match (from, to) {
+        (Some(from), Some(to)) => {
+            let (from, to) = ::sheet::corners(from, to);
+            Some((from, to, keyword, rest))
+        },
+        _ => None,
+    }
+}
+
+/// The first word of a text and what follows it.
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(' ') {
+        Some(space) => (&text[..space], text[space+1..].trim_left()),
+        None => (text, ""),
+    }
+}
+
+/// The words a style is saved as, for what differs from `Style::new()`.
+fn style_words(style: &Style) -> Vec<String> {
+    let mut ret = vec![];
+    if style.bold {
+        ret.push("bold".to_string());
+    }
+    if style.italic {
+        ret.push("italic".to_string());
+    }
+    if let Some(size) = style.font_size {
+        ret.push(format!("size {}", size));
+    }
+    if let Some(color) = style.color {
+        ret.push(format!("color {}", color.to_hex()));
+    }
+    if let Some(color) = style.background {
+        ret.push(format!("background {}", color.to_hex()));
+    }
+    match style.h_align {
+        HAlign::General => {},
+        HAlign::Left => ret.push("align left".to_string()),
+        HAlign::Center => ret.push("align center".to_string()),
+        HAlign::Right => ret.push("align right".to_string()),
+    }
+    match style.v_align {
+        VAlign::Top => ret.push("valign top".to_string()),
+        VAlign::Middle => ret.push("valign middle".to_string()),
+        VAlign::Bottom => {},
+    }
+    let borders = &style.borders;
+    for &(side, border) in &[("top", borders.top), ("bottom", borders.bottom), ("left", borders.left), ("right", borders.right)] {
+        if let Some(border) = border {
+            ret.push(format!("border-{} {} {}", side, border.width, border.color.to_hex()));
+        }
+    }
+    if style.wrap {
+        ret.push("wrap".to_string());
+    }
+    ret
+}
+
+fn parse_style(text: &str) -> Option<Style> {
+    let mut style = Style::new();
+    let mut words = text.split_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "bold" => { style.bold = true; },
+            "italic" => { style.italic = true; },
+            "wrap" => { style.wrap = true; },
+            "size" => match words.next().and_then(|x| x.parse().ok()) {
+                Some(size) => { style.font_size = Some(size); },
+                None => { return None; },
+            },
+            "color" => match words.next().and_then(Rgb::parse_hex) {
+                Some(color) => { style.color = Some(color); },
+                None => { return None; },
+            },
+            "background" => match words.next().and_then(Rgb::parse_hex) {
+                Some(color) => { style.background = Some(color); },
+                None => { return None; },
+            },
+            "align" => match words.next() {
+                Some("left") => { style.h_align = HAlign::Left; },
+                Some("center") => { style.h_align = HAlign::Center; },
+                Some("right") => { style.h_align = HAlign::Right; },
+                _ => { return None; },
+            },
+            "valign" => match words.next() {
+                Some("top") => { style.v_align = VAlign::Top; },
+                Some("middle") => { style.v_align = VAlign::Middle; },
+                _ => { return None; },
+            },
+            "border-top" | "border-bottom" | "border-left" | "border-right" => {
+                let border = match (words.next().and_then(|x| x.parse().ok()), words.next().and_then(Rgb::parse_hex)) {
+                    (Some(width), Some(color)) => Some(Border{width: width, color: color}),
+                    _ => { return None; },
+                };
+                match word {
+                    "border-top" => { style.borders.top = border; },
+                    "border-bottom" => { style.borders.bottom = border; },
+                    "border-left" => { style.borders.left = border; },
+                    _ => { style.borders.right = border; },
+                }
+            },
+            _ => { return None; },
+        }
+    }
+    Some(style)
+}
+
+fn validation_words(validation: &Validation) -> io::Result<String> {
+    let mode = match validation.mode {
+        ValidationMode::Reject => "reject",
+        ValidationMode::Warn => "warn",
+    };
+    let rule = match validation.rule {
+        ValidationRule::NumberBetween(min, max) => format!("number {} {}", min, max),
+        ValidationRule::DateBetween(min, max) => format!("date {} {}", min, max),
+        ValidationRule::TextLength(min, max) => format!("length {} {}", min, max),
+        ValidationRule::List(ref values) => format!("list {}", try!(array(values))),
+        ValidationRule::Custom(ref formula) => format!("custom {}", try!(line(formula))),
+    };
+    Ok(format!("{} {}", mode, rule))
+}
+
+fn parse_validation(text: &str) -> Option<(ValidationRule, ValidationMode)> {
+    let (mode, rest) = split_word(text);
+    let mode = match mode {
+        "reject" => ValidationMode::Reject,
+        "warn" => ValidationMode::Warn,
+        _ => { return None; },
+    };
+    let (kind, rest) = split_word(rest);
+    let rule = match kind {
+        "number" | "date" => {
+            let (min, max) = split_word(rest);
+            match (min.parse(), max.parse()) {
+                (Ok(min), Ok(max)) if kind == "number" => ValidationRule::NumberBetween(min, max),
+                (Ok(min), Ok(max)) => ValidationRule::DateBetween(min, max),
+                _ => { return None; },
+            }
+        },
+        "length" => {
+            let (min, max) = split_word(rest);
+            match (min.parse(), max.parse()) {
+                (Ok(min), Ok(max)) => ValidationRule::TextLength(min, max),
+                _ => { return None; },
+            }
+        },
+        "list" => match parse_formula(rest) {
+            Ok(Formula::Atom(FormulaAtom::Array(table))) => ValidationRule::List(table.cells.into_iter().map(|x| *x).collect()),
+            _ => { return None; },
+        },
+        "custom" => match parse_formula(rest) {
+            Ok(formula) => ValidationRule::Custom(formula),
+            Err(_) => { return None; },
+        },
+        _ => { return None; },
+    };
+    Some((rule, mode))
+}
+
+/// The keyword and the rest of the line a conditional format is saved as.
+fn rule_words(rule: &Rule) -> io::Result<String> {
+    Ok(match *rule {
+        Rule::Highlight(ref condition, ref patch) => {
+            let mut words = vec!["highlight".to_string()];
+            match patch.bold {
+                Some(true) => words.push("bold".to_string()),
+                Some(false) => words.push("not-bold".to_string()),
+                None => {},
+            }
+            match patch.italic {
+                Some(true) => words.push("italic".to_string()),
+                Some(false) => words.push("not-italic".to_string()),
+                None => {},
+            }
+            if let Some(color) = patch.color {
+                words.push(format!("color {}", color.to_hex()));
+            }
+            if let Some(color) = patch.background {
+                words.push(format!("background {}", color.to_hex()));
+            }
+            words.push("if".to_string());
+            words.push(match *condition {
+                Condition::Compare(op, ref value) => format!("{} {}", compare_op(op), try!(line(&Formula::Atom(value.clone())))),
+                Condition::Between(ref min, ref max) => format!("between {}", try!(array(&[min.clone(), max.clone()]))),
+            });
+            words.join(" ")
+        },
+        Rule::ColorScale{low, mid: Some(mid), high} => format!("scale {} {} {}", low.to_hex(), mid.to_hex(), high.to_hex()),
+        Rule::ColorScale{low, mid: None, high} => format!("scale {} {}", low.to_hex(), high.to_hex()),
+        Rule::DataBar(color) => format!("bar {}", color.to_hex()),
+    })
+}
+
+fn parse_rule(keyword: &str, text: &str) -> Option<Rule> {
+    match keyword {
+        "highlight" => {
+            let mut patch = StylePatch::new();
+            let mut rest = text;
+            loop {
+                let (word, after) = split_word(rest);
+                rest = after;
+                match word {
+                    "if" => { break; },
+                    "bold" => { patch.bold = Some(true); },
+                    "not-bold" => { patch.bold = Some(false); },
+                    "italic" => { patch.italic = Some(true); },
+                    "not-italic" => { patch.italic = Some(false); },
+                    "color" | "background" => {
+                        let (hex, after) = split_word(rest);
+                        rest = after;
+                        let color = match Rgb::parse_hex(hex) {
+                            Some(x) => Some(x),
+                            None => { return None; },
+                        };
+                        if word == "color" { patch.color = color; } else { patch.background = color; }
+                    },
+                    _ => { return None; },
+                }
+            }
+            let (op, value) = split_word(rest);
+            let condition = match (op, parse_formula(value)) {
+                ("between", Ok(Formula::Atom(FormulaAtom::Array(ref table)))) if table.cells.len() == 2 => {
+                    Condition::Between((*table.cells[0]).clone(), (*table.cells[1]).clone())
+                },
+                (_, Ok(Formula::Atom(value))) => match parse_compare_op(op) {
+                    Some(op) => Condition::Compare(op, value),
+                    None => { return None; },
+                },
+                _ => { return None; },
+            };
+            Some(Rule::Highlight(condition, patch))
+        },
+        "scale" => {
+            let colors: Option<Vec<Rgb>> = text.split_whitespace().map(Rgb::parse_hex).collect();
+            match colors {
+                Some(ref x) if x.len() == 2 => Some(Rule::ColorScale{low: x[0], mid: None, high: x[1]}),
+                Some(ref x) if x.len() == 3 => Some(Rule::ColorScale{low: x[0], mid: Some(x[1]), high: x[2]}),
+                _ => None,
+            }
+        },
+        _ => Rgb::parse_hex(text.trim()).map(Rule::DataBar),
+    }
+}
+
+fn compare_op(op: CompareOp) -> &'static str {
+    match op {
+        CompareOp::Less => "<",
+        CompareOp::LessEq => "<=",
+        CompareOp::Greater => ">",
+        CompareOp::GreaterEq => ">=",
+        CompareOp::Equal => "=",
+        CompareOp::NotEqual => "<>",
+    }
+}
+
+fn parse_compare_op(op: &str) -> Option<CompareOp> {
+    [CompareOp::Less, CompareOp::LessEq, CompareOp::Greater, CompareOp::GreaterEq, CompareOp::Equal, CompareOp::NotEqual]
+        .iter().cloned().find(|&x| compare_op(x) == op)
+}
+
+/// Values as an array literal, on a line.
+fn array(values: &[FormulaAtom]) -> io::Result<String> {
+    let mut words = vec![];
+    for value in values {
+        words.push(try!(line(&Formula::Atom(value.clone()))));
+    }
+    Ok(format!("{{{}}}", words.join(", ")))
+}
+
+/// The formula as typed, which has to fit in a line.
+fn line(formula: &Formula) -> io::Result<String> {
+    let text = format_formula(formula);
+    if text.contains('\n') {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "a string with a line break"))
+    } else {
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser::parse_formula;
+    use sheet::{Coord, Formula, FormulaAtom};
+    use workbook::Workbook;
+
+    #[test]
+    fn test_save_and_load() {
+        let mut book = Workbook::new();
+        book.add_sheet("Prices 2").unwrap();
+        book.define_name("rate", parse_formula("0.5").ok().unwrap()).unwrap();
+        book.set(0, Coord(0, 0), parse_formula("100").ok().unwrap());
+        book.set(0, Coord(1, 0), parse_formula("\"a = b\"").ok().unwrap());
+        book.set(0, Coord(0, 1), parse_formula("mul($A$1, add(1, rate))").ok().unwrap());
+        book.set(1, Coord(2, 4), parse_formula("add('Sheet1'!A2, 1)").ok().unwrap());
+
+        let mut saved = vec![];
+        save(&book, &mut saved).unwrap();
+        let text = String::from_utf8(saved).unwrap();
+        assert_eq!("rate = 0.5\n[Sheet1]\nA1 = 100\nB1 = \"a = b\"\nA2 = mul($A$1, add(1, rate))\n\
+                    [Prices 2]\nC5 = add(Sheet1!A2, 1)\n", text);
+
+        let loaded = load(&mut text.as_bytes()).unwrap();
+        assert_eq!(book.sheet_names(), loaded.sheet_names());
+        assert_eq!(FormulaAtom::Number(151.0), *loaded.value(1, Coord(2, 4)).ok().unwrap());
+        assert_eq!(FormulaAtom::String("a = b".to_string()), *loaded.value(0, Coord(1, 0)).ok().unwrap());
+
+        assert!(match load(&mut "[Sheet1]\nA1 = 1\nA2 1".as_bytes()) { Err(FileErr::Syntax(3)) => true, _ => false });
+        assert!(match load(&mut "[A]\n[A]".as_bytes()) { Err(FileErr::Workbook(_)) => true, _ => false });
+        book.set(0, Coord(0, 0), parse_formula("\"two\nlines\"").ok().unwrap());
+        assert!(save(&book, &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_names_after_sheets() {
+        // The first sheet is renamed from `Sheet1` once its header is read,
+        // which mustn't move names to it from the sheet now called so.
+        let text = "total = Sheet1!A1\nfirst = A1\n[Main]\nA1 = 1\n[Sheet1]\nA1 = 2\n";
+        let loaded = load(&mut text.as_bytes()).unwrap();
+        assert_eq!(vec![("first", &parse_formula("Main!A1").ok().unwrap()), ("total", &parse_formula("Sheet1!A1").ok().unwrap())],
+                   loaded.names());
+        let mut saved = vec![];
+        save(&loaded, &mut saved).unwrap();
+        assert_eq!("first = Main!A1\ntotal = Sheet1!A1\n[Main]\nA1 = 1\n[Sheet1]\nA1 = 2\n", String::from_utf8(saved).unwrap());
+    }
+
+    #[test]
+    fn test_save_and_load_looks() {
+        use conditional::{CompareOp, Condition, ConditionalFormat, Rule, StylePatch};
+        use format::NumberFormat;
+        use style::{Border, Borders, HAlign, Rgb, Style};
+        use validation::{Validation, ValidationMode, ValidationRule};
+
+        let mut book = Workbook::new();
+        book.set(0, Coord(0, 0), parse_formula("5000").ok().unwrap());
+        book.set_format(0, Coord(0, 0), Coord(0, 1), Some(NumberFormat::parse("#,##0.00 \"kg\"").unwrap()));
+        let mut style = Style::new();
+        style.bold = true;
+        style.font_size = Some(14);
+        style.h_align = HAlign::Center;
+        style.borders = Borders{bottom: Some(Border{width: 2, color: Rgb(0, 0, 0)}), ..Borders::none()};
+        book.set_style(0, Coord(1, 0), Coord(1, 0), style);
+        // Set before the cell, as the file adds validations after the cells
+        // whatever their order.
+        book.add_validation(0, Validation{from: Coord(0, 0), to: Coord(0, 9), rule: ValidationRule::NumberBetween(0.0, 1000.5),
+                                          mode: ValidationMode::Reject}).unwrap();
+        book.add_validation(0, Validation{from: Coord(2, 0), to: Coord(2, 9), mode: ValidationMode::Warn,
+                                          rule: ValidationRule::List(vec![FormulaAtom::String("a, b".to_string()), FormulaAtom::Number(3.0)])}).unwrap();
+        let patch = StylePatch{italic: Some(false), background: Some(Rgb(255, 255, 0)), ..StylePatch::new()};
+        book.add_conditional_format(0, ConditionalFormat{from: Coord(0, 0), to: Coord(0, 9),
+                                                         rule: Rule::Highlight(Condition::Compare(CompareOp::GreaterEq, FormulaAtom::Number(-5.0)), patch)});
+        book.add_conditional_format(0, ConditionalFormat{from: Coord(0, 0), to: Coord(1, 9),
+                                                         rule: Rule::Highlight(Condition::Between(FormulaAtom::Number(1.0), FormulaAtom::Number(2.0)), StylePatch::new())});
+        book.add_conditional_format(0, ConditionalFormat{from: Coord(1, 0), to: Coord(1, 9),
+                                                         rule: Rule::ColorScale{low: Rgb(255, 255, 255), mid: None, high: Rgb(0, 176, 80)}});
+        book.add_conditional_format(0, ConditionalFormat{from: Coord(1, 0), to: Coord(1, 9), rule: Rule::DataBar(Rgb(68, 114, 196))});
+
+        let mut saved = vec![];
+        save(&book, &mut saved).unwrap();
+        let text = String::from_utf8(saved).unwrap();
+        assert_eq!("[Sheet1]\nA1 = 5000\nA1 format #,##0.00 \"kg\"\nA2 format #,##0.00 \"kg\"\n\
+                    B1 style bold size 14 align center border-bottom 2 #000000\n\
+                    A1:A10 validate reject number 0 1000.5\nC1:C10 validate warn list {\"a, b\", 3}\n\
+                    A1:A10 highlight not-italic background #FFFF00 if >= -5\nA1:B10 highlight if between {1, 2}\n\
+                    B1:B10 scale #FFFFFF #00B050\nB1:B10 bar #4472C4\n", text);
+
+        let loaded = load(&mut text.as_bytes()).unwrap();
+        assert_eq!(FormulaAtom::Number(5000.0), *loaded.value(0, Coord(0, 0)).ok().unwrap());
+        assert_eq!(book.format(0, Coord(0, 1)), loaded.format(0, Coord(0, 1)));
+        assert_eq!(book.style(0, Coord(1, 0)), loaded.style(0, Coord(1, 0)));
+        assert_eq!(book.validations(0), loaded.validations(0));
+        assert_eq!(book.conditional_formats(0), loaded.conditional_formats(0));
+
+        for line in &["A1 style bold size", "A1 format [Red", "A1:A2 validate reject number 1", "A1 validate maybe list {1}",
+                      "A1 highlight if ~ 3", "A1 scale #FFFFFF", "A1 bar blue",
+                      "A1 bar #1é234", "A1 style color #+1+2+3"] {
+            let text = format!("[Sheet1]\n{}\n", line);
+            assert!(match load(&mut text.as_bytes()) { Err(FileErr::Syntax(2)) => true, _ => false }, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_export_csv() {
+        use format::NumberFormat;
+        let mut book = Workbook::new();
+        book.set(0, Coord(0, 0), parse_formula("1234.5").ok().unwrap());
+        book.set(0, Coord(1, 0), Formula::Atom(FormulaAtom::String("say \"hi\", ok".to_string())));
+        book.set(0, Coord(0, 1), parse_formula("add(missing, 1)").ok().unwrap());
+        book.set(0, Coord(2, 2), parse_formula("{1, 2}").ok().unwrap());
+        book.set_format(0, Coord(0, 0), Coord(0, 0), Some(NumberFormat::parse("#,##0.00").unwrap()));
+
+        let mut out = vec![];
+        export_csv(&book, 0, &mut out).unwrap();
+        assert_eq!("\"1,234.50\",\"say \"\"hi\"\", ok\",,\r\n#NAME?,,,\r\n,,1,2\r\n",
+                   String::from_utf8(out).unwrap());
+    }
+}
@@ -0,0 +1,601 @@
+//! Number formats, which decide how the value of a cell is shown without
+//! changing it. They use the usual format codes, like `#,##0.00`, `0%`,
+//! `$#,##0`, `0.00E+00`, `yyyy-mm-dd hh:mm`, `[h]:mm` or `0.00;[Red]-0.00`.
+
+use sheet::{Formula, FormulaAtom, FormulaErr};
+use decimal::{Decimal, Rounding};
+use date;
+
+const MONTHS: [&'static str; 12] = ["January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December"];
+const WEEKDAYS: [&'static str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday",
+    "Friday", "Saturday"];
+
+/// The colors a format code can ask for, like `[Red]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Black,
+    Blue,
+    Cyan,
+    Green,
+    Magenta,
+    Red,
+    White,
+    Yellow,
+}
+
+/// A value ready to be shown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formatted {
+    pub text: String,
+    pub color: Option<Color>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatErr {
+    /// A `[...]` that is neither a color nor an elapsed time.
+    Bracket(String),
+    /// A `"` without its closing one.
+    Unclosed,
+    /// Format codes have up to four sections: for positive numbers, negative
+    /// numbers, zero and text.
+    TooManySections,
+}
+
+/// A parsed format code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberFormat {
+    code: String,
+    sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Section {
+    color: Option<Color>,
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(String),
+    /// The value formatted as if there were no format.
+    General,
+    /// `0`, `#` or `?`.
+    Digit(char),
+    Point,
+    Comma,
+    Percent,
+    /// `E+` or `E-`; only the first shows the sign of positive exponents.
+    Exponent(bool),
+    /// `@`, where strings go.
+    Text,
+    Date(DatePart),
+}
+
+/// A part of a date or time, with the number of letters it was written with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DatePart {
+    Year(usize),
+    Month(usize),
+    Day(usize),
+    Hour(usize),
+    Minute(usize),
+    Second(usize),
+    AmPm,
+    /// `[h]`, `[m]` and `[s]`: the whole duration in hours, minutes or seconds.
+    ElapsedHours,
+    ElapsedMinutes,
+    ElapsedSeconds,
+}
+
+impl NumberFormat {
+    pub fn parse(code: &str) -> Result<NumberFormat, FormatErr> {
+        let mut sections = vec![];
+        for s in try!(split_sections(code)) {
+            sections.push(try!(parse_section(s.as_str())));
+        }
+        if sections.len() > 4 {
+            return Err(FormatErr::TooManySections);
+        }
+        Ok(NumberFormat{code: code.to_string(), sections: sections})
+    }
+
+    /// The format code this was parsed from.
+    pub fn code(&self) -> &str {
+        self.code.as_str()
+    }
+
+    /// Formats a value. Strings are quoted if `quote_strings`, unless the
+    /// format has a section for text.
+    pub fn format(&self, atom: &FormulaAtom, quote_strings: bool) -> Formatted {
+        match *atom {
+            FormulaAtom::Empty => Formatted{text: "".to_string(), color: None},
+            FormulaAtom::String(ref x) => match self.sections.iter().find(|s| s.tokens.contains(&Token::Text)) {
+                Some(section) => section.format_text(x),
+                None => format_default(atom, quote_strings),
+            },
+            FormulaAtom::Number(x) | FormulaAtom::Date(x) | FormulaAtom::Duration(x) => {
+                match Decimal::from_f64(x) {
+                    Some(exact) => self.format_number(atom, &exact),
+                    None => format_default(atom, quote_strings),
+                }
+            },
+            FormulaAtom::Decimal(ref x) => self.format_number(atom, x),
+            FormulaAtom::Array(_) | FormulaAtom::Lambda(..) => format_default(atom, quote_strings),
+            FormulaAtom::Error(ref e) => Formatted{text: format_error(e).to_string(), color: None},
+        }
+    }
+
+    /// Picks the section for the sign of the number, which shows its
+    /// absolute value unless it is the only section.
+    fn format_number(&self, atom: &FormulaAtom, x: &Decimal) -> Formatted {
+        let numeric: Vec<&Section> = self.sections.iter().filter(|s| {
+            !s.tokens.contains(&Token::Text)
+        }).collect();
+        if numeric.len() == 0 {
+            return format_default(atom, false);
+        }
+
+        let (section, signed) = if x.is_negative() && numeric.len() >= 2 {
+            (numeric[1], false)
+        } else if x.is_zero() && numeric.len() >= 3 {
+            (numeric[2], false)
+        } else {
+            (numeric[0], true)
+        };
+
+        let text = if section.is_date() && !section.is_elapsed() {
+            section.format_date(x.to_f64())
+        } else {
+            let text = if section.is_date() {
+                section.format_date(x.abs().to_f64())
+            } else {
+                section.format_number(atom, &x.abs())
+            };
+            let zero = text.chars().all(|c| !(c >= '1' && c <= '9'));
+            if signed && x.is_negative() && !zero { format!("-{}", text) } else { text }
+        };
+        Formatted{text: text, color: section.color}
+    }
+}
+
+/// Formats a value as if it had no format.
+pub fn format_default(atom: &FormulaAtom, quote_strings: bool) -> Formatted {
+    let text = match *atom {
+        FormulaAtom::String(ref x) if !quote_strings => x.clone(),
+        ref x => ::parser::format_formula(&Formula::Atom(x.clone())),
+    };
+    Formatted{text: text, color: None}
+}
+
+/// How an error is shown in place of a value, like `#N/A`.
+pub fn format_error(e: &FormulaErr) -> &'static str {
+    match *e {
+        FormulaErr::Ref(_) | FormulaErr::InvalidRef => "#REF!",
+        FormulaErr::Type(_) | FormulaErr::Arity(_) => "#VALUE!",
+        FormulaErr::Num | FormulaErr::CallDepth => "#NUM!",
+        FormulaErr::Name(_) | FormulaErr::NameCycle(_) => "#NAME?",
+        FormulaErr::NA => "#N/A",
+        FormulaErr::Spill => "#SPILL!",
+    }
+}
+
+/// Formats a value with a format, or without one if `None`.
+pub fn format_atom(atom: &FormulaAtom, format: Option<&NumberFormat>, quote_strings: bool) -> Formatted {
+    match format {
+        Some(format) => format.format(atom, quote_strings),
+        None => format_default(atom, quote_strings),
+    }
+}
+
+impl Section {
+    fn is_date(&self) -> bool {
+        self.tokens.iter().any(|t| match *t { Token::Date(_) => true, _ => false })
+    }
+
+    fn is_elapsed(&self) -> bool {
+        self.tokens.iter().any(|t| match *t {
+            Token::Date(DatePart::ElapsedHours) | Token::Date(DatePart::ElapsedMinutes) |
+            Token::Date(DatePart::ElapsedSeconds) => true,
+            _ => false,
+        })
+    }
+
+    fn format_text(&self, s: &str) -> Formatted {
+        let mut text = String::new();
+        for token in &self.tokens {
+            match *token {
+                Token::Literal(ref x) => text.push_str(x.as_str()),
+                Token::Text | Token::General => text.push_str(s),
+                _ => {},
+            }
+        }
+        Formatted{text: text, color: self.color}
+    }
+
+    /// Formats a number that isn't negative.
+    fn format_number(&self, atom: &FormulaAtom, x: &Decimal) -> String {
+        // Count the placeholders of the integer part, the fraction and the
+        // exponent.
+        let (mut int_min, mut frac_min, mut frac_max, mut exp_min) = (0, 0, 0, 0);
+        let (mut in_frac, mut in_exp, mut grouped, mut percent) = (false, false, false, false);
+        for token in &self.tokens {
+            match *token {
+                Token::Digit(c) if in_exp => { if c == '0' { exp_min += 1; } },
+                Token::Digit(c) if in_frac => {
+                    frac_max += 1;
+                    if c == '0' { frac_min += 1; }
+                },
+                Token::Digit(c) => { if c == '0' { int_min += 1; } },
+                Token::Point => { in_frac = true; },
+                Token::Comma if !in_frac && !in_exp => { grouped = true; },
+                Token::Exponent(_) => { in_exp = true; },
+                Token::Percent => { percent = true; },
+                _ => {},
+            }
+        }
+
+        let mut x = if percent { x.mul(&Decimal::from_integer(100)) } else { x.clone() };
+        let mut exp = 0;
+        if in_exp && !x.is_zero() {
+            let unscaled = x.clone();
+            exp = exponent(&unscaled);
+            x = scaled(&unscaled, exp).round(frac_max, Rounding::HalfUp);
+            if exponent(&x) > 0 {
+                exp += 1;
+                x = scaled(&unscaled, exp);
+            }
+        }
+
+        let rounded = x.round(frac_max, Rounding::HalfUp).to_string();
+        let (int, frac) = match rounded.find('.') {
+            Some(idx) => (rounded[..idx].to_string(), rounded[idx+1..].to_string()),
+            None => (rounded.clone(), "".to_string()),
+        };
+        let mut int = if int == "0" { "".to_string() } else { int };
+        while int.len() < int_min {
+            int.insert(0, '0');
+        }
+        if grouped {
+            int = group_thousands(int.as_str());
+        }
+        let mut frac = frac;
+        while frac.len() < frac_max as usize {
+            frac.push('0');
+        }
+        while frac.len() > frac_min && frac.ends_with("0") {
+            frac.pop();
+        }
+        let mut exp_digits = format!("{}", exp.abs());
+        while exp_digits.len() < exp_min {
+            exp_digits.insert(0, '0');
+        }
+
+        let mut ret = String::new();
+        let (mut int_done, mut frac_done, mut in_frac, mut in_exp) = (false, false, false, false);
+        for token in &self.tokens {
+            match *token {
+                Token::Literal(ref s) => ret.push_str(s.as_str()),
+                Token::General => ret.push_str(format_default(atom, false).text.as_str()),
+                Token::Digit(_) if in_exp => {},
+                Token::Digit(_) if in_frac => if !frac_done {
+                    ret.push_str(frac.as_str());
+                    frac_done = true;
+                },
+                Token::Digit(_) => if !int_done {
+                    ret.push_str(int.as_str());
+                    int_done = true;
+                },
+                Token::Point => {
+                    ret.push('.');
+                    in_frac = true;
+                },
+                Token::Exponent(plus) => {
+                    ret.push('E');
+                    if exp < 0 {
+                        ret.push('-');
+                    } else if plus {
+                        ret.push('+');
+                    }
+                    ret.push_str(exp_digits.as_str());
+                    in_exp = true;
+                },
+                Token::Percent => ret.push('%'),
+                Token::Comma | Token::Text | Token::Date(_) => {},
+            }
+        }
+        ret
+    }
+
+    fn format_date(&self, serial: f64) -> String {
+        let (day, seconds) = date::split_serial(serial);
+        let (year, month, day_of_month) = date::ymd_from_serial(day);
+        let weekday = date::weekday(day) as usize;
+        let total_seconds = day * 86400 + seconds;
+        let am_pm = self.tokens.contains(&Token::Date(DatePart::AmPm));
+        let hour = seconds / 3600;
+
+        let pad = |x: i64, n: usize| if n >= 2 { format!("{:02}", x) } else { format!("{}", x) };
+        let mut ret = String::new();
+        for token in &self.tokens {
+            let part = match *token {
+                Token::Literal(ref s) => s.clone(),
+                Token::Digit(c) => c.to_string(),
+                Token::Point => ".".to_string(),
+                Token::Comma => ",".to_string(),
+                Token::Percent => "%".to_string(),
+                Token::Date(DatePart::Year(n)) => {
+                    if n <= 2 { format!("{:02}", year % 100) } else { format!("{:04}", year) }
+                },
+                Token::Date(DatePart::Month(n)) => match n {
+                    1 | 2 => pad(month as i64, n),
+                    3 => MONTHS[month as usize - 1][..3].to_string(),
+                    _ => MONTHS[month as usize - 1].to_string(),
+                },
+                Token::Date(DatePart::Day(n)) => match n {
+                    1 | 2 => pad(day_of_month as i64, n),
+                    3 => WEEKDAYS[weekday][..3].to_string(),
+                    _ => WEEKDAYS[weekday].to_string(),
+                },
+                Token::Date(DatePart::Hour(n)) => {
+                    let h = if am_pm { (hour + 11) % 12 + 1 } else { hour };
+                    pad(h, n)
+                },
+                Token::Date(DatePart::Minute(n)) => pad(seconds / 60 % 60, n),
+                Token::Date(DatePart::Second(n)) => pad(seconds % 60, n),
+                Token::Date(DatePart::AmPm) => (if hour < 12 { "AM" } else { "PM" }).to_string(),
+                Token::Date(DatePart::ElapsedHours) => format!("{}", total_seconds / 3600),
+                Token::Date(DatePart::ElapsedMinutes) => format!("{}", total_seconds / 60),
+                Token::Date(DatePart::ElapsedSeconds) => format!("{}", total_seconds),
+                Token::General | Token::Exponent(_) | Token::Text => "".to_string(),
+            };
+            ret.push_str(part.as_str());
+        }
+        ret
+    }
+}
+
+/// The power of ten of the first digit of `x`, which isn't zero, so `2` for
+/// `123.4` and `-2` for `0.05`.
+fn exponent(x: &Decimal) -> i32 {
+    let digits = x.abs().to_string();
+    let point = digits.find('.').unwrap_or(digits.len());
+    let first = digits.find(|c: char| c >= '1' && c <= '9').unwrap_or(point);
+    if first < point { (point - first) as i32 - 1 } else { point as i32 - first as i32 }
+}
+
+/// `x / 10^exp` exactly, or `x` if the exponent is beyond what decimals
+/// take.
+fn scaled(x: &Decimal, exp: i32) -> Decimal {
+    Decimal::parse(format!("{}e{}", x, -exp).as_str()).unwrap_or(x.clone())
+}
+
+fn group_thousands(digits: &str) -> String {
+    let mut ret = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            ret.push(',');
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+/// Splits a format code at the `;` that aren't quoted or escaped.
+fn split_sections(code: &str) -> Result<Vec<String>, FormatErr> {
+    let mut ret = vec![String::new()];
+    let mut chars = code.chars();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            ';' if !quoted => { ret.push(String::new()); continue; },
+            '"' => { quoted = !quoted; },
+            '\\' if !quoted => if let Some(next) = chars.next() {
+                ret.last_mut().unwrap().push(c);
+                ret.last_mut().unwrap().push(next);
+                continue;
+            },
+            _ => {},
+        }
+        ret.last_mut().unwrap().push(c);
+    }
+    if quoted { Err(FormatErr::Unclosed) } else { Ok(ret) }
+}
+
+fn parse_section(s: &str) -> Result<Section, FormatErr> {
+    let chars: Vec<char> = s.chars().collect();
+    let lower = s.to_lowercase();
+    let mut color = None;
+    let mut tokens = vec![];
+    let mut literal = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = lower.chars().skip(i).collect();
+        let token = match c {
+            '"' => {
+                let end = match chars[i+1..].iter().position(|&c| c == '"') {
+                    Some(x) => i + 1 + x,
+                    None => { return Err(FormatErr::Unclosed); },
+                };
+                literal.extend(chars[i+1..end].iter().cloned());
+                i = end + 1;
+                continue;
+            },
+            '\\' | '_' if i + 1 < chars.len() => {
+                literal.push(if c == '_' { ' ' } else { chars[i + 1] });
+                i += 2;
+                continue;
+            },
+            '*' => {
+                // Repeating a character to fill the cell isn't supported.
+                i += 2;
+                continue;
+            },
+            '[' => {
+                let end = match chars[i..].iter().position(|&c| c == ']') {
+                    Some(x) => i + x,
+                    None => { return Err(FormatErr::Bracket(chars[i..].iter().cloned().collect())); },
+                };
+                let inside: String = chars[i+1..end].iter().cloned().collect();
+                i = end + 1;
+                match inside.to_lowercase().as_str() {
+                    "black" => { color = Some(Color::Black); continue; },
+                    "blue" => { color = Some(Color::Blue); continue; },
+                    "cyan" => { color = Some(Color::Cyan); continue; },
+                    "green" => { color = Some(Color::Green); continue; },
+                    "magenta" => { color = Some(Color::Magenta); continue; },
+                    "red" => { color = Some(Color::Red); continue; },
+                    "white" => { color = Some(Color::White); continue; },
+                    "yellow" => { color = Some(Color::Yellow); continue; },
+                    "h" | "hh" => Token::Date(DatePart::ElapsedHours),
+                    "m" | "mm" => Token::Date(DatePart::ElapsedMinutes),
+                    "s" | "ss" => Token::Date(DatePart::ElapsedSeconds),
+                    _ => { return Err(FormatErr::Bracket(inside)); },
+                }
+            },
+            _ if rest.starts_with("general") => {
+                i += 7;
+                Token::General
+            },
+            _ if rest.starts_with("am/pm") => {
+                i += 5;
+                Token::Date(DatePart::AmPm)
+            },
+            'e' | 'E' if i + 1 < chars.len() && (chars[i + 1] == '+' || chars[i + 1] == '-') => {
+                i += 2;
+                Token::Exponent(chars[i - 1] == '+')
+            },
+            'y' | 'Y' | 'm' | 'M' | 'd' | 'D' | 'h' | 'H' | 's' | 'S' => {
+                let lc = lower.chars().nth(i).unwrap();
+                let n = lower.chars().skip(i).take_while(|&x| x == lc).count();
+                i += n;
+                Token::Date(match lc {
+                    'y' => DatePart::Year(n),
+                    'm' => DatePart::Month(n),
+                    'd' => DatePart::Day(n),
+                    'h' => DatePart::Hour(n),
+                    _ => DatePart::Second(n),
+                })
+            },
+            '0' | '#' | '?' => { i += 1; Token::Digit(c) },
+            '.' => { i += 1; Token::Point },
+            ',' => { i += 1; Token::Comma },
+            '%' => { i += 1; Token::Percent },
+            '@' => { i += 1; Token::Text },
+            _ => {
+                literal.push(c);
+                i += 1;
+                continue;
+            },
+        };
+
+        if literal.len() > 0 {
+            tokens.push(Token::Literal(::std::mem::replace(&mut literal, String::new())));
+        }
+        tokens.push(token);
+    }
+    if literal.len() > 0 {
+        tokens.push(Token::Literal(literal));
+    }
+
+    resolve_minutes(&mut tokens);
+    Ok(Section{color: color, tokens: tokens})
+}
+
+/// `m` and `mm` are minutes rather than months right after hours or right
+/// before seconds.
+fn resolve_minutes(tokens: &mut Vec<Token>) {
+    let parts: Vec<(usize, DatePart)> = tokens.iter().enumerate().filter_map(|(i, t)| match *t {
+        Token::Date(part) => Some((i, part)),
+        _ => None,
+    }).collect();
+
+    for (j, &(i, part)) in parts.iter().enumerate() {
+        if let DatePart::Month(n) = part {
+            let after_hours = j > 0 && match parts[j - 1].1 {
+                DatePart::Hour(_) | DatePart::ElapsedHours => true,
+                _ => false,
+            };
+            let before_seconds = j + 1 < parts.len() && match parts[j + 1].1 {
+                DatePart::Second(_) | DatePart::ElapsedSeconds => true,
+                _ => false,
+            };
+            if n <= 2 && (after_hours || before_seconds) {
+                tokens[i] = Token::Date(DatePart::Minute(n));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sheet::FormulaAtom;
+    use decimal::Decimal;
+
+    fn format(code: &str, atom: FormulaAtom) -> String {
+        NumberFormat::parse(code).unwrap().format(&atom, false).text
+    }
+
+    fn number(code: &str, x: f64) -> String {
+        format(code, FormulaAtom::Number(x))
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!("1234.57", number("0.00", 1234.567));
+        assert_eq!("1,234.57", number("#,##0.00", 1234.567));
+        assert_eq!("-1,234,568", number("#,##0", -1234567.8));
+        assert_eq!("0.13", number("0.00", 0.125));
+        assert_eq!("1.01", number("0.00", 1.005));
+        assert_eq!(".5", number("#.##", 0.5));
+        assert_eq!("007", number("000", 7.0));
+        assert_eq!("12.5%", number("0.0%", 0.125));
+        assert_eq!("$1,000.00", number("$#,##0.00", 1000.0));
+        assert_eq!("-$5.00", number("$#,##0.00", -5.0));
+        assert_eq!("12.00 €", number("0.00 \"€\"", 12.0));
+        assert_eq!("1.23E+04", number("0.00E+00", 12345.0));
+        assert_eq!("1.00E+01", number("0.00E+00", 9.999));
+        assert_eq!("5.0E-3", number("0.0E+0", 0.005));
+        assert_eq!("5.0E-324", number("0.0E+0", 5e-324));
+        assert_eq!("1.8E+308", number("0.0E+0", 1.797e308));
+        assert_eq!("1.0E+400", format("0.0E+0", FormulaAtom::Decimal(Decimal::parse("1e400").unwrap())));
+        assert_eq!("0.1", format("0.0", FormulaAtom::Decimal(Decimal::parse("0.05").unwrap())));
+        assert_eq!("x = 3", number("\"x = \"General", 3.0));
+    }
+
+    #[test]
+    fn test_sections() {
+        let f = NumberFormat::parse("0.00;[Red]-0.00;\"zero\";\"<\"@\">\"").unwrap();
+        assert_eq!(Formatted{text: "1.50".to_string(), color: None}, f.format(&FormulaAtom::Number(1.5), false));
+        assert_eq!(Formatted{text: "-1.50".to_string(), color: Some(Color::Red)}, f.format(&FormulaAtom::Number(-1.5), false));
+        assert_eq!("zero", f.format(&FormulaAtom::Number(0.0), false).text);
+        assert_eq!("<foo>", f.format(&FormulaAtom::String("foo".to_string()), true).text);
+        assert_eq!("(2)", number("0;(0)", -2.0));
+        assert_eq!("\"foo\"", NumberFormat::parse("0").unwrap().format(&FormulaAtom::String("foo".to_string()), true).text);
+        assert_eq!(Err(FormatErr::Bracket("Purple".to_string())), NumberFormat::parse("[Purple]0"));
+        assert_eq!(Err(FormatErr::Unclosed), NumberFormat::parse("0\"x"));
+        assert_eq!(Err(FormatErr::Unclosed), NumberFormat::parse("0_\"x\""));
+        assert_eq!(Err(FormatErr::Unclosed), NumberFormat::parse("*\"x\""));
+        assert_eq!(Err(FormatErr::TooManySections), NumberFormat::parse("0;0;0;@;0"));
+    }
+
+    #[test]
+    fn test_dates() {
+        let date = |code, x| format(code, FormulaAtom::Date(x));
+        // 2024-01-15T14:05:09, a Monday.
+        let x = 45306.0 + (14.0 * 3600.0 + 5.0 * 60.0 + 9.0) / 86400.0;
+        assert_eq!("2024-01-15 14:05:09", date("yyyy-mm-dd hh:mm:ss", x));
+        assert_eq!("15/1/24", date("d/m/yy", x));
+        assert_eq!("Mon, Jan 15", date("ddd, mmm d", x));
+        assert_eq!("Monday 15 January 2024", date("dddd d mmmm yyyy", x));
+        assert_eq!("2:05 PM", date("h:mm AM/PM", x));
+        assert_eq!("5:09", date("m:ss", x));
+        assert_eq!("36:30", format("[h]:mm", FormulaAtom::Duration(1.5 + 0.5 / 24.0)));
+        assert_eq!("-1:30", format("[h]:mm", FormulaAtom::Duration(-1.5 / 24.0)));
+        assert_eq!("45306", format("0", FormulaAtom::Date(45306.0)));
+        assert_eq!("1900-01-01", number("yyyy-mm-dd", 2.0));
+    }
+}
@@ -0,0 +1,250 @@
+//! Sums, counts and averages of the cells that meet criteria, like
+//! `sumif(A1:A9, ">10")`.
+//!
+//! A criterion is a value to be equal to, or a string starting with `=`,
+//! `<>`, `<`, `<=`, `>` or `>=` followed by the value to compare with. Strings
+//! are compared ignoring case, and `*` stands for any characters and `?` for
+//! any one character, unless escaped with `~`.
+
+use conditional::{compare, CompareOp};
+use date;
+use functions::{arity, check_arity, number, scalar};
+use sheet::{FormulaAtom, FormulaErr, FormulaOp, Table, Value};
+
+pub fn call(op: &FormulaOp, args: &[Table]) -> Value {
+    // The range the values come from, and the ranges that have to meet
+    // each criterion.
+    let (values, conditions) = match *op {
+        // sumif(range, criterion, values = range), and so on.
+        FormulaOp::SumIf | FormulaOp::CountIf | FormulaOp::AverageIf => {
+            let max = if *op == FormulaOp::CountIf { 2 } else { 3 };
+            try!(check_arity(args, 2, max));
+            let values = if args.len() > 2 { &args[2] } else { &args[0] };
+            (values, &args[..2])
+        },
+        // countifs(range, criterion, ...)
+        FormulaOp::CountIfs => {
+            try!(check_arity(args, 2, ::std::usize::MAX));
+            if args.len() % 2 != 0 {
+                return Err(arity(args.len() + 1));
+            }
+            (&args[0], args)
+        },
+        // sumifs(values, range, criterion, ...)
+        FormulaOp::SumIfs | FormulaOp::AverageIfs => {
+            try!(check_arity(args, 3, ::std::usize::MAX));
+            if args.len() % 2 != 1 {
+                return Err(arity(args.len() + 1));
+            }
+            (&args[0], &args[1..])
+        },
+        _ => unreachable!(),
+    };
+
+    let mut criteria = Vec::with_capacity(conditions.len() / 2);
+    for pair in conditions.chunks(2) {
+        if (pair[0].cols, pair[0].rows) != (values.cols, values.rows) {
+            return Err(FormulaErr::Type("Range"));
+        }
+        criteria.push((&pair[0], Criterion::parse(try!(scalar(&pair[1])))));
+    }
+
+    let (mut sum, mut count) = (0.0, 0);
+    for (idx, value) in values.cells.iter().enumerate() {
+        if criteria.iter().all(|&(range, ref criterion)| criterion.matches(&range.cells[idx])) {
+            match *op {
+                FormulaOp::CountIf | FormulaOp::CountIfs => { count += 1; },
+                _ => if let Ok(x) = number(value) {
+                    sum += x;
+                    count += 1;
+                },
+            }
+        }
+    }
+
+    Ok(Box::new(FormulaAtom::Number(match *op {
+        FormulaOp::CountIf | FormulaOp::CountIfs => count as f64,
+        FormulaOp::SumIf | FormulaOp::SumIfs => sum,
+        _ => if count == 0 {
+            return Err(FormulaErr::Num);
+        } else {
+            sum / count as f64
+        },
+    })))
+}
+
+#[derive(Debug, PartialEq)]
+enum Criterion {
+    Compare(CompareOp, FormulaAtom),
+    /// A string with wildcards, lower case, which the value has to match or,
+    /// if negated, not match.
+    Pattern(Vec<Wildcard>, bool),
+}
+
+/// A part of a pattern: `*`, `?` or a character to match as is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Wildcard {
+    Any,
+    One,
+    Char(char),
+}
+
+impl Criterion {
+    fn parse(atom: &FormulaAtom) -> Criterion {
+        let s = match *atom {
+            FormulaAtom::String(ref s) => s.as_str(),
+            FormulaAtom::Empty => "",
+            ref x => { return Criterion::Compare(CompareOp::Equal, x.clone()); },
+        };
+
+        let ops = [("<>", CompareOp::NotEqual), ("<=", CompareOp::LessEq), (">=", CompareOp::GreaterEq),
+                   ("<", CompareOp::Less), (">", CompareOp::Greater), ("=", CompareOp::Equal)];
+        let (op, rest) = match ops.iter().find(|&&(prefix, _)| s.starts_with(prefix)) {
+            Some(&(prefix, op)) => (op, &s[prefix.len()..]),
+            None => (CompareOp::Equal, s),
+        };
+
+        // Rust also parses `inf` and `NaN`, which are strings here.
+        let number = rest.trim().parse::<f64>().ok().and_then(|x| if x.is_finite() { Some(x) } else { None });
+        if let Some(x) = number {
+            Criterion::Compare(op, FormulaAtom::Number(x))
+        } else if let Some(x) = date::parse_iso(rest.trim()) {
+            Criterion::Compare(op, FormulaAtom::Date(x))
+        } else if op == CompareOp::Equal || op == CompareOp::NotEqual {
+            Criterion::Pattern(wildcards(rest.to_lowercase().as_str()), op == CompareOp::NotEqual)
+        } else {
+            Criterion::Compare(op, FormulaAtom::String(rest.to_string()))
+        }
+    }
+
+    fn matches(&self, value: &FormulaAtom) -> bool {
+        match *self {
+            Criterion::Compare(op, ref x) => match compare(value, x) {
+                Some(ord) => op.holds(ord),
+                None => op == CompareOp::NotEqual,
+            },
+            Criterion::Pattern(ref pattern, negated) => {
+                let found = match *value {
+                    FormulaAtom::String(ref s) => {
+                        let s: Vec<char> = s.to_lowercase().chars().collect();
+                        wildcard_match(pattern, &s)
+                    },
+                    FormulaAtom::Empty => pattern.len() == 0,
+                    _ => false,
+                };
+                found != negated
+            },
+        }
+    }
+}
+
+/// Reads the wildcards of a pattern, where `~` makes the character after it
+/// match as is.
+fn wildcards(pattern: &str) -> Vec<Wildcard> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut ret = Vec::with_capacity(chars.len());
+    let mut i = 0;
+    while i < chars.len() {
+        ret.push(match chars[i] {
+            '*' => Wildcard::Any,
+            '?' => Wildcard::One,
+            '~' if i + 1 < chars.len() => {
+                i += 1;
+                Wildcard::Char(chars[i])
+            },
+            c => Wildcard::Char(c),
+        });
+        i += 1;
+    }
+    ret
+}
+
+/// Matches greedily, going back only to the last `*` seen, which takes one
+/// more character each time. That's enough, as whatever an earlier `*` could
+/// take instead the last one can too, so it takes time proportional to the
+/// lengths of the pattern and the string multiplied at worst.
+fn wildcard_match(pattern: &[Wildcard], s: &[char]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // Where the pattern goes on after the last `*`, and where in `s` it was
+    // last tried from.
+    let mut star: Option<(usize, usize)> = None;
+    while i < s.len() {
+        match pattern.get(p) {
+            Some(&Wildcard::Any) => {
+                p += 1;
+                star = Some((p, i));
+            },
+            Some(&Wildcard::One) => {
+                p += 1;
+                i += 1;
+            },
+            Some(&Wildcard::Char(c)) if c == s[i] => {
+                p += 1;
+                i += 1;
+            },
+            _ => match star {
+                Some((after, from)) => {
+                    p = after;
+                    i = from + 1;
+                    star = Some((after, from + 1));
+                },
+                None => { return false; },
+            },
+        }
+    }
+    pattern[p..].iter().all(|&w| w == Wildcard::Any)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{call, Criterion};
+    use sheet::{FormulaAtom, FormulaErr, FormulaOp, Table};
+
+    #[test]
+    fn test_criteria() {
+        let matches = |criterion: &str, value: FormulaAtom| {
+            Criterion::parse(&FormulaAtom::String(criterion.to_string())).matches(&value)
+        };
+        let string = |x: &str| FormulaAtom::String(x.to_string());
+        assert!(matches(">10", FormulaAtom::Number(11.0)));
+        assert!(!matches(">10", FormulaAtom::Number(10.0)));
+        assert!(!matches(">10", string("20")));
+        assert!(matches("<=2024-01-31", FormulaAtom::Date(45300.0)));
+        assert!(matches("5", FormulaAtom::Number(5.0)));
+        assert!(matches("<>x", string("y")));
+        assert!(matches("<>x", FormulaAtom::Empty));
+        assert!(!matches("<>x", string("X")));
+        assert!(matches("ap*", string("Apple")));
+        assert!(matches("?pple", string("apple")));
+        assert!(!matches("?pple", string("pple")));
+        assert!(matches("what~?", string("what?")));
+        assert!(!matches("what~?", string("whatx")));
+        assert!(matches("", FormulaAtom::Empty));
+        assert!(matches("<>", string("a")));
+        assert!(matches(">b", string("C")));
+        assert!(matches("*a*b*", string("xxaxxbxx")));
+        assert!(!matches("*a*b*", string("xxbxxaxx")));
+        assert!(matches("a~*", string("a*")));
+        assert!(matches("a~", string("a~")));
+        assert!(!matches("*", FormulaAtom::Empty));
+
+        // Would take ages if every `*` tried every split.
+        let long: String = (0 .. 200).map(|_| 'a').collect();
+        let mut pattern = String::new();
+        for _ in 0 .. 30 {
+            pattern.push_str("*a");
+        }
+        pattern.push_str("*b");
+        assert!(!matches(pattern.as_str(), string(long.as_str())));
+    }
+
+    #[test]
+    fn test_many_arguments() {
+        let tables: Vec<Table> = (0 .. 301).map(|_| {
+            Table{cols: 1, rows: 1, cells: vec![Box::new(FormulaAtom::Number(1.0))]}
+        }).collect();
+        assert_eq!(Some(FormulaErr::Arity(255)), call(&FormulaOp::CountIfs, &tables).err());
+        assert_eq!(Some(FormulaErr::Arity(255)), call(&FormulaOp::SumIfs, &tables[1..]).err());
+        assert_eq!(FormulaAtom::Number(1.0), *call(&FormulaOp::SumIfs, &tables).ok().unwrap());
+    }
+}
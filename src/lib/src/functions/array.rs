@@ -0,0 +1,178 @@
+//! Functions that give arrays, which spill over the cells next to the one
+//! whose formula calls them. The areas they spill over are worked out again
+//! by `deps::update` whenever the cells they read change.
+//!
+//! Rows or columns are compared like in lookups: numbers with numbers and
+//! strings with strings, ignoring case.
+
+use std::cmp::Ordering;
+use conditional::compare;
+use functions::{check_arity, number, scalar};
+use sheet::{FormulaAtom, FormulaErr, FormulaOp, Table, Value};
+
+/// The most cells `sequence` makes, so that a typo can't fill the memory.
+const MAX_CELLS: f64 = 1048576.0;
+
+pub fn call(op: &FormulaOp, args: &[Table]) -> Value {
+    let arg = |idx: usize, default: f64| {
+        if args.len() > idx { number(try!(scalar(&args[idx]))) } else { Ok(default) }
+    };
+
+    match *op {
+        // filter(array, include, if_empty), keeping the rows, or the columns
+        // if `include` is a row, for which `include` is a number other than
+        // zero. Without `if_empty`, filtering everything out gives `#N/A`.
+        FormulaOp::Filter => {
+            try!(check_arity(args, 2, 3));
+            let (array, include) = (&args[0], &args[1]);
+            let by_col = if include.cols == 1 && include.rows == array.rows {
+                false
+            } else if include.rows == 1 && include.cols == array.cols {
+                true
+            } else {
+                return Err(FormulaErr::Type("Range"));
+            };
+            let mut kept = vec![];
+            for (line, keep) in lines(array, by_col).into_iter().zip(include.cells.iter()) {
+                let keep = match **keep {
+                    FormulaAtom::Empty => false,
+                    ref x => try!(number(x)) != 0.0,
+                };
+                if keep {
+                    kept.push(line);
+                }
+            }
+            match kept.len() {
+                0 if args.len() > 2 => Ok(Box::new(try!(scalar(&args[2])).clone())),
+                0 => Err(FormulaErr::NA),
+                _ => Ok(array_of(kept, by_col)),
+            }
+        },
+        // sort(array, index = 1, order = 1, by_col = 0) sorts the rows, or the
+        // columns, by their value at `index`, ascending for an order of 1 and
+        // descending for -1. Rows with equal values keep their order.
+        FormulaOp::Sort => {
+            try!(check_arity(args, 1, 4));
+            let by_col = try!(arg(3, 0.0)) != 0.0;
+            let mut lines = lines(&args[0], by_col);
+            let idx = try!(arg(1, 1.0)).trunc();
+            if idx < 1.0 || idx as usize > lines[0].len() {
+                return Err(FormulaErr::InvalidRef);
+            }
+            let idx = idx as usize - 1;
+            let descending = match try!(arg(2, 1.0)) {
+                x if x == 1.0 => false,
+                x if x == -1.0 => true,
+                _ => { return Err(FormulaErr::Type("Sort order")); },
+            };
+            lines.sort_by(|a, b| {
+                let ord = sort_order(&a[idx], &b[idx]);
+                if descending { ord.reverse() } else { ord }
+            });
+            Ok(array_of(lines, by_col))
+        },
+        // unique(array, by_col = 0, exactly_once = 0) keeps the first of equal
+        // rows, or columns, or only the rows that aren't repeated if
+        // `exactly_once`.
+        FormulaOp::Unique => {
+            try!(check_arity(args, 1, 3));
+            let by_col = try!(arg(1, 0.0)) != 0.0;
+            let exactly_once = try!(arg(2, 0.0)) != 0.0;
+            let mut seen: Vec<(Vec<FormulaAtom>, usize)> = vec![];
+            for line in lines(&args[0], by_col) {
+                match seen.iter().position(|&(ref x, _)| same_line(x, &line)) {
+                    Some(idx) => { seen[idx].1 += 1; },
+                    None => { seen.push((line, 1)); },
+                }
+            }
+            let kept: Vec<_> = seen.into_iter().filter(|&(_, count)| !exactly_once || count == 1)
+                .map(|(line, _)| line).collect();
+            if kept.len() == 0 {
+                return Err(FormulaErr::NA);
+            }
+            Ok(array_of(kept, by_col))
+        },
+        // sequence(rows, columns = 1, start = 1, step = 1), filled row by row.
+        FormulaOp::Sequence => {
+            try!(check_arity(args, 1, 4));
+            let (rows, cols) = (try!(arg(0, 1.0)).trunc(), try!(arg(1, 1.0)).trunc());
+            let (start, step) = (try!(arg(2, 1.0)), try!(arg(3, 1.0)));
+            if rows < 1.0 || cols < 1.0 || rows * cols > MAX_CELLS {
+                return Err(FormulaErr::Num);
+            }
+            let cells = (0 .. (rows * cols) as usize).map(|i| {
+                Box::new(FormulaAtom::Number(start + step * i as f64))
+            }).collect();
+            Ok(Box::new(FormulaAtom::Array(Table{cols: cols as usize, rows: rows as usize, cells: cells})))
+        },
+        FormulaOp::Transpose => {
+            try!(check_arity(args, 1, 1));
+            Ok(array_of(lines(&args[0], false), true))
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// The rows of a table, or its columns if `by_col`.
+fn lines(table: &Table, by_col: bool) -> Vec<Vec<FormulaAtom>> {
+    let (count, len) = if by_col { (table.cols, table.rows) } else { (table.rows, table.cols) };
+    (0 .. count).map(|i| (0 .. len).map(|j| {
+        if by_col { table.get(i, j).clone() } else { table.get(j, i).clone() }
+    }).collect()).collect()
+}
+
+/// The array made of rows, or of columns if `by_col`, all as long.
+fn array_of(lines: Vec<Vec<FormulaAtom>>, by_col: bool) -> Box<FormulaAtom> {
+    let len = lines[0].len();
+    let table = if by_col {
+        let mut cells = Vec::with_capacity(len * lines.len());
+        for j in 0 .. len {
+            for line in &lines {
+                cells.push(Box::new(line[j].clone()));
+            }
+        }
+        Table{cols: lines.len(), rows: len, cells: cells}
+    } else {
+        let rows = lines.len();
+        let cells = lines.into_iter().flat_map(|line| line.into_iter().map(Box::new)).collect();
+        Table{cols: len, rows: rows, cells: cells}
+    };
+    Box::new(FormulaAtom::Array(table))
+}
+
+/// Numbers come first, then strings, then anything else, and empty values
+/// last.
+fn sort_order(a: &FormulaAtom, b: &FormulaAtom) -> Ordering {
+    let rank = |x: &FormulaAtom| match *x {
+        FormulaAtom::String(_) => 1,
+        FormulaAtom::Empty => 3,
+        _ if ::conditional::number(x).is_some() => 0,
+        _ => 2,
+    };
+    match compare(a, b) {
+        Some(ord) => ord,
+        None => rank(a).cmp(&rank(b)),
+    }
+}
+
+fn same_line(a: &[FormulaAtom], b: &[FormulaAtom]) -> bool {
+    a.iter().zip(b.iter()).all(|(x, y)| x == y || compare(x, y) == Some(Ordering::Equal))
+}
+
+#[cfg(test)]
+mod test {
+    use super::sort_order;
+    use std::cmp::Ordering;
+    use sheet::FormulaAtom;
+
+    #[test]
+    fn test_sort_order() {
+        let string = |x: &str| FormulaAtom::String(x.to_string());
+        let mut xs = vec![FormulaAtom::Empty, string("b"), FormulaAtom::Number(2.0), string("A"),
+                          FormulaAtom::Number(-1.0)];
+        xs.sort_by(sort_order);
+        assert_eq!(vec![FormulaAtom::Number(-1.0), FormulaAtom::Number(2.0), string("A"), string("b"),
+                        FormulaAtom::Empty], xs);
+        assert_eq!(Ordering::Equal, sort_order(&string("a"), &string("A")));
+    }
+}
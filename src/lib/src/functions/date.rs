@@ -0,0 +1,148 @@
+use date::{self, serial_from_ymd, serial_from_ymd_lenient, ymd_from_serial, days_in_month};
+use functions::{check_arity, number, string};
+use sheet::{FormulaAtom, FormulaErr, FormulaOp, Value};
+
+pub fn call(op: &FormulaOp, atoms: &[Box<FormulaAtom>]) -> Value {
+    let ret = match *op {
+        FormulaOp::Today => {
+            try!(check_arity(atoms, 0, 0));
+            FormulaAtom::Date(date::now().floor())
+        },
+        FormulaOp::Now => {
+            try!(check_arity(atoms, 0, 0));
+            FormulaAtom::Date(date::now())
+        },
+        FormulaOp::Date => {
+            try!(check_arity(atoms, 3, 3));
+            let year = try!(number(&atoms[0])).trunc() as i64;
+            // Like in other spreadsheet applications, `date(15, 1, 1)` is in 1915.
+            let year = if year >= 0 && year < 1900 { year + 1900 } else { year };
+            let month = try!(number(&atoms[1])).trunc() as i64;
+            let day = try!(number(&atoms[2])).trunc() as i64;
+            FormulaAtom::Date(try!(serial_from_ymd_lenient(year, month, day).ok_or(FormulaErr::Num)) as f64)
+        },
+        FormulaOp::Year | FormulaOp::Month | FormulaOp::Day => {
+            try!(check_arity(atoms, 1, 1));
+            let (year, month, day) = ymd_from_serial(try!(day_serial(&atoms[0])));
+            FormulaAtom::Number(match *op {
+                FormulaOp::Year => year as f64,
+                FormulaOp::Month => month as f64,
+                _ => day as f64,
+            })
+        },
+        FormulaOp::Weekday => {
+            try!(check_arity(atoms, 1, 2));
+            let weekday = date::weekday(try!(day_serial(&atoms[0]))) as f64;
+            let kind = if atoms.len() > 1 { try!(number(&atoms[1])) } else { 1.0 };
+            FormulaAtom::Number(match kind as i64 {
+                1 => weekday + 1.0,
+                2 => (weekday + 6.0) % 7.0 + 1.0,
+                3 => (weekday + 6.0) % 7.0,
+                _ => { return Err(FormulaErr::Num); },
+            })
+        },
+        FormulaOp::DateDif => {
+            try!(check_arity(atoms, 3, 3));
+            let start = try!(day_serial(&atoms[0]));
+            let end = try!(day_serial(&atoms[1]));
+            let unit = try!(string(&atoms[2])).to_uppercase();
+            FormulaAtom::Number(try!(date_dif(start, end, unit.as_str())) as f64)
+        },
+        FormulaOp::EDate | FormulaOp::EOMonth => {
+            try!(check_arity(atoms, 2, 2));
+            let (year, month, day) = ymd_from_serial(try!(day_serial(&atoms[0])));
+            let months = try!(number(&atoms[1])).trunc() as i64;
+            let serial = (month as i64).checked_add(months)
+                .and_then(|month| serial_from_ymd_lenient(year, month, 1));
+            let (year, month, _) = ymd_from_serial(try!(serial.ok_or(FormulaErr::Num)));
+            let last = days_in_month(year, month);
+            let day = if *op == FormulaOp::EOMonth || day > last { last } else { day };
+            FormulaAtom::Date(serial_from_ymd(year, month, day) as f64)
+        },
+        FormulaOp::NetworkDays => {
+            try!(check_arity(atoms, 2, ::std::usize::MAX));
+            let start = try!(day_serial(&atoms[0]));
+            let end = try!(day_serial(&atoms[1]));
+            let mut holidays = Vec::with_capacity(atoms.len() - 2);
+            for atom in &atoms[2..] {
+                holidays.push(try!(day_serial(atom)));
+            }
+            FormulaAtom::Number(network_days(start, end, &holidays) as f64)
+        },
+        _ => unreachable!(),
+    };
+    Ok(Box::new(ret))
+}
+
+/// The day of a date, or of a number taken as a serial date.
+fn day_serial(atom: &FormulaAtom) -> Result<i64, FormulaErr> {
+    match *atom {
+        FormulaAtom::Date(x) | FormulaAtom::Number(x) => {
+            if x.is_finite() && x.abs() <= date::MAX_SERIAL as f64 {
+                Ok(x.floor() as i64)
+            } else {
+                Err(FormulaErr::Num)
+            }
+        },
+        _ => Err(FormulaErr::Type("Date")),
+    }
+}
+
+/// The complete years (`Y`), months (`M`) or days (`D`) from `start` to
+/// `end`. `MD`, `YM` and `YD` ignore the months and years, the years, and the
+/// years respectively.
+fn date_dif(start: i64, end: i64, unit: &str) -> Result<i64, FormulaErr> {
+    if start > end {
+        return Err(FormulaErr::Num);
+    }
+
+    let (y1, m1, d1) = ymd_from_serial(start);
+    let (y2, m2, d2) = ymd_from_serial(end);
+    let months = (y2 - y1) * 12 + m2 as i64 - m1 as i64 - if d2 < d1 { 1 } else { 0 };
+
+    Ok(match unit {
+        "Y" => months / 12,
+        "M" => months,
+        "D" => end - start,
+        "YM" => months % 12,
+        "MD" => {
+            if d2 >= d1 {
+                (d2 - d1) as i64
+            } else {
+                let (y, m) = if m2 == 1 { (y2 - 1, 12) } else { (y2, m2 - 1) };
+                ::std::cmp::max(days_in_month(y, m) as i64 - d1 as i64, 0) + d2 as i64
+            }
+        },
+        "YD" => {
+            let y = if (m2, d2) >= (m1, d1) { y2 } else { y2 - 1 };
+            let day = ::std::cmp::min(d1, days_in_month(y, m1));
+            end - serial_from_ymd(y, m1, day)
+        },
+        _ => { return Err(FormulaErr::Num); },
+    })
+}
+
+/// The working days, Monday to Friday, from `start` to `end` both included,
+/// that aren't `holidays`. Negative if `end` is before `start`.
+fn network_days(start: i64, end: i64, holidays: &[i64]) -> i64 {
+    if start > end {
+        return -network_days(end, start, holidays);
+    }
+    let is_workday = |day: i64| {
+        let weekday = date::weekday(day);
+        weekday != 0 && weekday != 6
+    };
+
+    // Every whole week has five working days; only the rest is counted.
+    let days = end - start + 1;
+    let rest = start + days / 7 * 7;
+    let mut count = days / 7 * 5 + (rest .. end+1).filter(|&day| is_workday(day)).count() as i64;
+
+    let mut holidays: Vec<i64> = holidays.iter().cloned()
+        .filter(|&day| day >= start && day <= end && is_workday(day))
+        .collect();
+    holidays.sort();
+    holidays.dedup();
+    count -= holidays.len() as i64;
+    count
+}
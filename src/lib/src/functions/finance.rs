@@ -0,0 +1,161 @@
+//! Time value of money. Like in other spreadsheet applications, money paid
+//! out is negative and money received is positive, and `kind` 0 means
+//! payments at the end of each period and 1 at the beginning.
+
+use functions::{check_arity, number, scalar, line};
+use sheet::{FormulaAtom, FormulaErr, FormulaOp, Table, Value};
+
+/// How many steps `rate` and `irr` take before giving up.
+const MAX_ITERATIONS: usize = 100;
+/// How close two steps have to be for `rate` and `irr` to stop.
+const TOLERANCE: f64 = 1e-10;
+
+/// The functions that take their arguments with ranges expanded.
+pub fn call(op: &FormulaOp, atoms: &[Box<FormulaAtom>]) -> Value {
+    let arg = |idx: usize, default: f64| if atoms.len() > idx { number(&atoms[idx]) } else { Ok(default) };
+
+    let ret = match *op {
+        // pmt(rate, periods, present, future = 0, kind = 0)
+        FormulaOp::Pmt => {
+            try!(check_arity(atoms, 3, 5));
+            let (rate, n, pv) = (try!(arg(0, 0.0)), try!(arg(1, 0.0)), try!(arg(2, 0.0)));
+            let (fv, kind) = (try!(arg(3, 0.0)), try!(arg(4, 0.0)));
+            if n == 0.0 {
+                return Err(FormulaErr::Num);
+            }
+            if rate == 0.0 {
+                -(pv + fv) / n
+            } else {
+                let growth = (1.0 + rate).powf(n);
+                -(fv + pv * growth) * rate / ((1.0 + rate * kind) * (growth - 1.0))
+            }
+        },
+        // fv(rate, periods, payment, present = 0, kind = 0)
+        FormulaOp::Fv => {
+            try!(check_arity(atoms, 3, 5));
+            let (rate, n, pmt) = (try!(arg(0, 0.0)), try!(arg(1, 0.0)), try!(arg(2, 0.0)));
+            let (pv, kind) = (try!(arg(3, 0.0)), try!(arg(4, 0.0)));
+            -(pv * (1.0 + rate).powf(n) + pmt * annuity(rate, n, kind))
+        },
+        // pv(rate, periods, payment, future = 0, kind = 0)
+        FormulaOp::Pv => {
+            try!(check_arity(atoms, 3, 5));
+            let (rate, n, pmt) = (try!(arg(0, 0.0)), try!(arg(1, 0.0)), try!(arg(2, 0.0)));
+            let (fv, kind) = (try!(arg(3, 0.0)), try!(arg(4, 0.0)));
+            -(fv + pmt * annuity(rate, n, kind)) / (1.0 + rate).powf(n)
+        },
+        // rate(periods, payment, present, future = 0, kind = 0, guess = 0.1)
+        FormulaOp::Rate => {
+            try!(check_arity(atoms, 3, 6));
+            let (n, pmt, pv) = (try!(arg(0, 0.0)), try!(arg(1, 0.0)), try!(arg(2, 0.0)));
+            let (fv, kind, guess) = (try!(arg(3, 0.0)), try!(arg(4, 0.0)), try!(arg(5, 0.1)));
+            let balance = |rate: f64| pv * (1.0 + rate).powf(n) + pmt * annuity(rate, n, kind) + fv;
+            try!(solve(balance, guess).ok_or(FormulaErr::Num))
+        },
+        // npv(rate, values...), with the first value one period away.
+        FormulaOp::Npv => {
+            try!(check_arity(atoms, 2, ::std::usize::MAX));
+            let rate = try!(arg(0, 0.0));
+            let mut values = Vec::with_capacity(atoms.len() - 1);
+            for atom in &atoms[1..] {
+                values.push(try!(number(atom)));
+            }
+            if rate == -1.0 {
+                return Err(FormulaErr::Num);
+            }
+            present_value(rate, &values) / (1.0 + rate)
+        },
+        _ => unreachable!(),
+    };
+
+    if ret.is_finite() {
+        Ok(Box::new(FormulaAtom::Number(ret)))
+    } else {
+        Err(FormulaErr::Num)
+    }
+}
+
+/// The functions that take ranges as tables, to tell them apart from the
+/// other arguments.
+pub fn call_tables(op: &FormulaOp, args: &[Table]) -> Value {
+    let ret = match *op {
+        // irr(values, guess = 0.1), ignoring the cells that aren't numbers.
+        FormulaOp::Irr => {
+            try!(check_arity(args, 1, 2));
+            let values: Vec<f64> = try!(line(&args[0])).into_iter().filter_map(|x| number(x).ok()).collect();
+            let guess = if args.len() > 1 { try!(number(try!(scalar(&args[1])))) } else { 0.1 };
+            if !values.iter().any(|&x| x > 0.0) || !values.iter().any(|&x| x < 0.0) {
+                return Err(FormulaErr::Num);
+            }
+            try!(solve(|rate| present_value(rate, &values), guess).ok_or(FormulaErr::Num))
+        },
+        // xnpv(rate, values, dates), discounting each value by the years
+        // from the first date, counted as 365 days.
+        FormulaOp::XNpv => {
+            try!(check_arity(args, 3, 3));
+            let rate = try!(number(try!(scalar(&args[0]))));
+            let (values, dates) = (try!(line(&args[1])), try!(line(&args[2])));
+            if values.len() != dates.len() || values.len() == 0 {
+                return Err(FormulaErr::Num);
+            }
+            let start = try!(date(dates[0]));
+            let mut ret = 0.0;
+            for (value, day) in values.iter().zip(dates.iter()) {
+                let years = (try!(date(day)) - start) / 365.0;
+                ret += try!(number(value)) / (1.0 + rate).powf(years);
+            }
+            ret
+        },
+        _ => unreachable!(),
+    };
+
+    if ret.is_finite() {
+        Ok(Box::new(FormulaAtom::Number(ret)))
+    } else {
+        Err(FormulaErr::Num)
+    }
+}
+
+/// What paying 1 every period for `n` periods adds up to at the end.
+fn annuity(rate: f64, n: f64, kind: f64) -> f64 {
+    if rate == 0.0 {
+        n
+    } else {
+        (1.0 + rate * kind) * ((1.0 + rate).powf(n) - 1.0) / rate
+    }
+}
+
+/// The values discounted to the time of the first one, one period apart.
+fn present_value(rate: f64, values: &[f64]) -> f64 {
+    values.iter().enumerate().fold(0.0, |acc, (i, &x)| acc + x / (1.0 + rate).powi(i as i32))
+}
+
+fn date(atom: &FormulaAtom) -> Result<f64, FormulaErr> {
+    match *atom {
+        FormulaAtom::Date(x) | FormulaAtom::Number(x) => Ok(x.floor()),
+        _ => Err(FormulaErr::Type("Date")),
+    }
+}
+
+/// The rate near `guess` where `f` is zero, found with Newton's method.
+/// `None` if it doesn't converge, or wanders to rates of -100% or less.
+fn solve<F: Fn(f64) -> f64>(f: F, guess: f64) -> Option<f64> {
+    let h = 1e-7;
+    let mut x = guess;
+    for _ in 0 .. MAX_ITERATIONS {
+        let y = f(x);
+        let slope = (f(x + h) - f(x - h)) / (2.0 * h);
+        if !y.is_finite() || !slope.is_finite() || slope == 0.0 {
+            return None;
+        }
+        let next = x - y / slope;
+        if next <= -1.0 {
+            return None;
+        }
+        if (next - x).abs() < TOLERANCE {
+            return Some(next);
+        }
+        x = next;
+    }
+    None
+}
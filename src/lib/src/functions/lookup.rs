@@ -0,0 +1,140 @@
+//! Lookups, which take their arguments as tables so ranges keep their shape.
+//!
+//! Matches compare numbers with numbers and strings with strings, ignoring
+//! case, and skip cells whose values are errors. A match mode of 0 finds
+//! the exact value; -1 falls back to the largest smaller value and 1 to the
+//! smallest larger one.
+
+use std::cmp::Ordering;
+use conditional::compare;
+use functions::{check_arity, number, scalar, line};
+use sheet::{FormulaAtom, FormulaErr, FormulaOp, Table, Value};
+
+pub fn call(op: &FormulaOp, args: &[Table]) -> Value {
+    let ret = match *op {
+        // vlookup(value, table, column, approximate = 1)
+        FormulaOp::VLookup | FormulaOp::HLookup => {
+            try!(check_arity(args, 3, 4));
+            let value = try!(scalar(&args[0]));
+            let table = &args[1];
+            let idx = try!(position(&args[2]));
+            let mode = if args.len() > 3 && try!(number(try!(scalar(&args[3])))) == 0.0 { 0 } else { -1 };
+            if *op == FormulaOp::VLookup {
+                if idx >= table.cols {
+                    return Err(FormulaErr::InvalidRef);
+                }
+                let keys: Vec<_> = (0 .. table.rows).map(|row| table.get(0, row)).collect();
+                let row = try!(find(value, &keys, mode).ok_or(FormulaErr::NA));
+                table.get(idx, row).clone()
+            } else {
+                if idx >= table.rows {
+                    return Err(FormulaErr::InvalidRef);
+                }
+                let keys: Vec<_> = (0 .. table.cols).map(|col| table.get(col, 0)).collect();
+                let col = try!(find(value, &keys, mode).ok_or(FormulaErr::NA));
+                table.get(col, idx).clone()
+            }
+        },
+        // index(table, row, column), where a single row or column only needs
+        // one position.
+        FormulaOp::Index => {
+            try!(check_arity(args, 2, 3));
+            let table = &args[0];
+            let (col, row) = if args.len() > 2 {
+                (try!(position(&args[2])), try!(position(&args[1])))
+            } else if table.rows == 1 {
+                (try!(position(&args[1])), 0)
+            } else {
+                (0, try!(position(&args[1])))
+            };
+            if col >= table.cols || row >= table.rows {
+                return Err(FormulaErr::InvalidRef);
+            }
+            table.get(col, row).clone()
+        },
+        // match(value, line, kind = 1), where kind 1 finds the largest value
+        // not above `value`, 0 the exact one and -1 the smallest value not
+        // below it.
+        FormulaOp::Match => {
+            try!(check_arity(args, 2, 3));
+            let value = try!(scalar(&args[0]));
+            let keys = try!(line(&args[1]));
+            let kind = if args.len() > 2 { try!(number(try!(scalar(&args[2])))) } else { 1.0 };
+            let mode = if kind > 0.0 { -1 } else if kind < 0.0 { 1 } else { 0 };
+            let idx = try!(find(value, &keys, mode).ok_or(FormulaErr::NA));
+            FormulaAtom::Number(idx as f64 + 1.0)
+        },
+        // xlookup(value, keys, results, if_not_found, mode = 0)
+        FormulaOp::XLookup => {
+            try!(check_arity(args, 3, 5));
+            let value = try!(scalar(&args[0]));
+            let keys = try!(line(&args[1]));
+            let results = try!(line(&args[2]));
+            if keys.len() != results.len() {
+                return Err(FormulaErr::Type("Range"));
+            }
+            let mode = if args.len() > 4 { try!(number(try!(scalar(&args[4])))) } else { 0.0 };
+            let mode = if mode < 0.0 { -1 } else if mode > 0.0 { 1 } else { 0 };
+            match find(value, &keys, mode) {
+                Some(idx) => results[idx].clone(),
+                None if args.len() > 3 => try!(scalar(&args[3])).clone(),
+                None => { return Err(FormulaErr::NA); },
+            }
+        },
+        _ => unreachable!(),
+    };
+    match ret {
+        FormulaAtom::Error(e) => Err(e),
+        x => Ok(Box::new(x)),
+    }
+}
+
+/// A position counted from 1, as an index from 0.
+fn position(table: &Table) -> Result<usize, FormulaErr> {
+    let x = try!(number(try!(scalar(table)))).trunc();
+    if x < 1.0 {
+        Err(FormulaErr::Type("Position"))
+    } else {
+        Ok(x as usize - 1)
+    }
+}
+
+/// Where `value` is in `keys`, with the given match mode. Exact matches win,
+/// and the first one of equal keys is taken. The keys don't have to be
+/// sorted.
+fn find(value: &FormulaAtom, keys: &[&FormulaAtom], mode: i8) -> Option<usize> {
+    let wanted = if mode < 0 { Ordering::Less } else { Ordering::Greater };
+    let mut best: Option<usize> = None;
+    for (idx, key) in keys.iter().enumerate() {
+        match compare(key, value) {
+            Some(Ordering::Equal) => { return Some(idx); },
+            Some(ord) if mode != 0 && ord == wanted => {
+                best = match best {
+                    Some(b) if compare(key, keys[b]) != Some(wanted.reverse()) => Some(b),
+                    _ => Some(idx),
+                };
+            },
+            _ => {},
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod test {
+    use super::find;
+    use sheet::FormulaAtom;
+
+    #[test]
+    fn test_find() {
+        let keys = [FormulaAtom::Number(30.0), FormulaAtom::Empty, FormulaAtom::Number(10.0),
+                    FormulaAtom::String("b".to_string()), FormulaAtom::Number(20.0)];
+        let keys: Vec<_> = keys.iter().collect();
+        assert_eq!(Some(2), find(&FormulaAtom::Number(10.0), &keys, 0));
+        assert_eq!(None, find(&FormulaAtom::Number(15.0), &keys, 0));
+        assert_eq!(Some(2), find(&FormulaAtom::Number(15.0), &keys, -1));
+        assert_eq!(Some(4), find(&FormulaAtom::Number(15.0), &keys, 1));
+        assert_eq!(None, find(&FormulaAtom::Number(5.0), &keys, -1));
+        assert_eq!(Some(3), find(&FormulaAtom::String("B".to_string()), &keys, 0));
+    }
+}
@@ -0,0 +1,182 @@
+//! Rounding, arithmetic, logarithms, trigonometry and random numbers.
+//!
+//! Angles are in radians. Results that aren't finite numbers, like
+//! `sqrt(-1)` or `power(10, 400)`, are `#NUM!`, and `rand` and
+//! `randbetween` draw from the book's `Random`.
+
+use std::sync::Mutex;
+use std::f64::consts::PI;
+use decimal::{Decimal, Rounding};
+use functions::{check_arity, number};
+use sheet::{FormulaAtom, FormulaErr, FormulaOp, Value};
+
+pub fn call(op: &FormulaOp, atoms: &[Box<FormulaAtom>], random: &Random) -> Value {
+    let arg = |idx: usize| number(&atoms[idx]);
+    // The number of digits for `round` and the like, 0 when not given.
+    let digits = || if atoms.len() > 1 { arg(1).map(|x| x.trunc() as i32) } else { Ok(0) };
+
+    let ret = match *op {
+        FormulaOp::Abs | FormulaOp::Sign | FormulaOp::Int | FormulaOp::Sqrt | FormulaOp::Exp |
+        FormulaOp::Ln | FormulaOp::Log10 | FormulaOp::Sin | FormulaOp::Cos | FormulaOp::Tan |
+        FormulaOp::Asin | FormulaOp::Acos | FormulaOp::Atan => {
+            try!(check_arity(atoms, 1, 1));
+            let x = try!(arg(0));
+            match *op {
+                FormulaOp::Abs => x.abs(),
+                FormulaOp::Sign => if x > 0.0 { 1.0 } else if x < 0.0 { -1.0 } else { 0.0 },
+                FormulaOp::Int => x.floor(),
+                FormulaOp::Sqrt if x >= 0.0 => x.sqrt(),
+                FormulaOp::Exp => x.exp(),
+                FormulaOp::Ln if x > 0.0 => x.ln(),
+                FormulaOp::Log10 if x > 0.0 => x.log10(),
+                FormulaOp::Sin => x.sin(),
+                FormulaOp::Cos => x.cos(),
+                FormulaOp::Tan => x.tan(),
+                FormulaOp::Asin if x.abs() <= 1.0 => x.asin(),
+                FormulaOp::Acos if x.abs() <= 1.0 => x.acos(),
+                FormulaOp::Atan => x.atan(),
+                _ => { return Err(FormulaErr::Num); },
+            }
+        },
+        FormulaOp::Round | FormulaOp::RoundUp | FormulaOp::RoundDown | FormulaOp::Trunc => {
+            try!(check_arity(atoms, 1, 2));
+            let rounding = match *op {
+                FormulaOp::Round => Rounding::HalfUp,
+                FormulaOp::RoundUp => Rounding::Up,
+                _ => Rounding::Down,
+            };
+            try!(round(try!(arg(0)), try!(digits()), rounding))
+        },
+        // floor(x, significance = 1) and ceiling(x, significance = 1) round
+        // to a multiple of the significance, which must have the sign of `x`.
+        FormulaOp::Floor | FormulaOp::Ceiling => {
+            try!(check_arity(atoms, 1, 2));
+            let x = try!(arg(0));
+            let significance = if atoms.len() > 1 { try!(arg(1)) } else { 1.0 };
+            if x == 0.0 {
+                0.0
+            } else if significance == 0.0 || (x > 0.0 && significance < 0.0) {
+                return Err(FormulaErr::Num);
+            } else if *op == FormulaOp::Floor {
+                (x / significance).floor() * significance
+            } else {
+                (x / significance).ceil() * significance
+            }
+        },
+        // The remainder has the sign of the divisor, so `mod(-1, 3)` is 2.
+        FormulaOp::Mod => {
+            try!(check_arity(atoms, 2, 2));
+            let (x, d) = (try!(arg(0)), try!(arg(1)));
+            if d == 0.0 {
+                return Err(FormulaErr::Num);
+            }
+            x - d * (x / d).floor()
+        },
+        FormulaOp::Power => {
+            try!(check_arity(atoms, 2, 2));
+            try!(arg(0)).powf(try!(arg(1)))
+        },
+        // log(x, base = 10)
+        FormulaOp::Log => {
+            try!(check_arity(atoms, 1, 2));
+            let x = try!(arg(0));
+            let base = if atoms.len() > 1 { try!(arg(1)) } else { 10.0 };
+            if x <= 0.0 || base <= 0.0 || base == 1.0 {
+                return Err(FormulaErr::Num);
+            }
+            x.ln() / base.ln()
+        },
+        // atan2(x, y) is the angle of the point (x, y).
+        FormulaOp::Atan2 => {
+            try!(check_arity(atoms, 2, 2));
+            let (x, y) = (try!(arg(0)), try!(arg(1)));
+            if x == 0.0 && y == 0.0 {
+                return Err(FormulaErr::Num);
+            }
+            y.atan2(x)
+        },
+        FormulaOp::Pi => {
+            try!(check_arity(atoms, 0, 0));
+            PI
+        },
+        FormulaOp::Rand => {
+            try!(check_arity(atoms, 0, 0));
+            random.next()
+        },
+        // randbetween(low, high), both included.
+        FormulaOp::RandBetween => {
+            try!(check_arity(atoms, 2, 2));
+            let (low, high) = (try!(arg(0)).ceil(), try!(arg(1)).floor());
+            if low > high {
+                return Err(FormulaErr::Num);
+            }
+            low + (random.next() * (high - low + 1.0)).floor()
+        },
+        _ => unreachable!(),
+    };
+
+    if ret.is_finite() {
+        Ok(Box::new(FormulaAtom::Number(ret)))
+    } else {
+        Err(FormulaErr::Num)
+    }
+}
+
+/// `x` rounded to `digits` decimal places, or to tens, hundreds and so on
+/// for negative `digits`. It goes through decimals, so `round(2.675, 2)` is
+/// `2.68` like it's written, even if the nearest float is a bit smaller.
+fn round(x: f64, digits: i32, rounding: Rounding) -> Result<f64, FormulaErr> {
+    let digits = ::std::cmp::max(::std::cmp::min(digits, 300), -300);
+    let x = try!(Decimal::from_f64(x).ok_or(FormulaErr::Num));
+    if digits >= 0 {
+        Ok(x.round(digits as u32, rounding).to_f64())
+    } else {
+        let unit = Decimal::parse(format!("1e{}", -digits).as_str()).unwrap();
+        Ok(x.div(&unit, 0, rounding).unwrap().mul(&unit).to_f64())
+    }
+}
+
+/// The numbers `rand` and `randbetween` draw from: a xorshift generator,
+/// which is fast and good enough for spreadsheets but not for cryptography.
+/// Sheets seeded alike draw the same numbers.
+pub struct Random {
+    state: Mutex<u64>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        // Xorshift gets stuck at zero.
+        Random{state: Mutex::new(if seed == 0 { 0x9E3779B97F4A7C15 } else { seed })}
+    }
+
+    /// Seeded with the clock.
+    pub fn from_time() -> Random {
+        Random::new(::time::precise_time_ns())
+    }
+
+    /// A number from 0 included to 1 excluded.
+    pub fn next(&self) -> f64 {
+        let mut state = self.state.lock().unwrap();
+        let mut x = *state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        *state = x;
+        (x.wrapping_mul(0x2545F4914F6CDD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_random() {
+        let (a, b) = (Random::new(42), Random::new(42));
+        let xs: Vec<f64> = (0 .. 100).map(|_| a.next()).collect();
+        let ys: Vec<f64> = (0 .. 100).map(|_| b.next()).collect();
+        assert_eq!(xs, ys);
+        assert!(xs.iter().all(|&x| x >= 0.0 && x < 1.0));
+        assert!(xs[0] != Random::new(43).next());
+    }
+}
@@ -0,0 +1,71 @@
+//! The functions formulas can call, besides the basic arithmetic in `sheet`.
+//! They take their arguments already evaluated, with ranges expanded, or as
+//! tables when they need ranges to keep their shape.
+
+use sheet::{FormulaAtom, FormulaErr, Table};
+
+pub mod aggregate;
+pub mod array;
+pub mod date;
+pub mod finance;
+pub mod lookup;
+pub mod math;
+pub mod reference;
+pub mod registry;
+
+/// Checks that there are from `min` to `max` arguments.
+pub fn check_arity<T>(atoms: &[T], min: usize, max: usize) -> Result<(), FormulaErr> {
+    if atoms.len() < min {
+        Err(arity(min))
+    } else if atoms.len() > max {
+        Err(arity(max))
+    } else {
+        Ok(())
+    }
+}
+
+/// The error for a call that needed `n` arguments, or more than an error can
+/// tell.
+pub fn arity(n: usize) -> FormulaErr {
+    if n > ::std::u8::MAX as usize {
+        FormulaErr::Arity(::std::u8::MAX)
+    } else {
+        FormulaErr::Arity(n as u8)
+    }
+}
+
+pub fn number(atom: &FormulaAtom) -> Result<f64, FormulaErr> {
+    match *atom {
+        FormulaAtom::Number(x) => Ok(x),
+        FormulaAtom::Decimal(ref x) => Ok(x.to_f64()),
+        _ => Err(FormulaErr::Type("Number")),
+    }
+}
+
+pub fn string(atom: &FormulaAtom) -> Result<&str, FormulaErr> {
+    match *atom {
+        FormulaAtom::String(ref x) => Ok(x.as_str()),
+        _ => Err(FormulaErr::Type("String")),
+    }
+}
+
+/// The value of a table with a single cell, or its error if it has one.
+pub fn scalar(table: &Table) -> Result<&FormulaAtom, FormulaErr> {
+    if table.cols == 1 && table.rows == 1 {
+        match *table.get(0, 0) {
+            FormulaAtom::Error(ref e) => Err(e.clone()),
+            ref x => Ok(x),
+        }
+    } else {
+        Err(FormulaErr::Type("Value"))
+    }
+}
+
+/// The values of a table with a single row or column.
+pub fn line(table: &Table) -> Result<Vec<&FormulaAtom>, FormulaErr> {
+    if table.cols == 1 || table.rows == 1 {
+        Ok(table.cells.iter().map(|x| &**x).collect())
+    } else {
+        Err(FormulaErr::Type("Range"))
+    }
+}
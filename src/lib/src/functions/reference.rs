@@ -0,0 +1,205 @@
+//! Functions that build references from values, and that tell where
+//! references point.
+//!
+//! `offset` and `indirect` stand for cells, so the sheet resolves them into a
+//! `Formula::Ref` or `Formula::Range` with `resolve` before reading them. They
+//! are resolved again every time they are evaluated, and have to stay inside
+//! the grid and below `MAX_CELLS` cells.
+
+use functions::{check_arity, number};
+use parser::format_sheet_name;
+use sheet::{Anchor, Coord, Formula, FormulaAtom, FormulaErr, FormulaOp, Value};
+
+/// How many rows and columns references built at runtime can reach, as in
+/// other spreadsheets.
+pub const MAX_ROWS: usize = 1048576;
+pub const MAX_COLS: usize = 16384;
+
+/// The most cells a reference built at runtime can have, as each of them is
+/// read.
+pub const MAX_CELLS: usize = 1048576;
+
+/// `address(row, column, kind = 1, a1 = 1, sheet)`, where `kind` 1 makes
+/// both parts absolute, 2 only the row, 3 only the column and 4 neither.
+/// There's only the A1 notation, so `a1` is ignored.
+pub fn call(op: &FormulaOp, atoms: &[Box<FormulaAtom>]) -> Value {
+    match *op {
+        FormulaOp::Address => {
+            try!(check_arity(atoms, 2, 5));
+            let row = try!(position(&atoms[0]));
+            let col = try!(position(&atoms[1]));
+            let kind = if atoms.len() > 2 { try!(number(&atoms[2])) as i64 } else { 1 };
+            let anchor = match kind {
+                1 => Anchor(true, true),
+                2 => Anchor(false, true),
+                3 => Anchor(true, false),
+                4 => Anchor(false, false),
+                _ => { return Err(FormulaErr::Type("Address kind")); },
+            };
+            let mut ret = String::new();
+            if atoms.len() > 4 {
+                match *atoms[4] {
+                    FormulaAtom::String(ref name) => {
+                        ret.push_str(format_sheet_name(name).as_str());
+                        ret.push('!');
+                    },
+                    FormulaAtom::Empty => {},
+                    _ => { return Err(FormulaErr::Type("String")); },
+                }
+            }
+            ret.push_str(Coord(col, row).format_anchored(anchor).as_str());
+            Ok(Box::new(FormulaAtom::String(ret)))
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// Resolves `offset(reference, rows, columns, height, width)` given its
+/// already resolved reference and the values of the other arguments.
+/// `height` and `width` default to those of the reference.
+pub fn offset(reference: &Formula, atoms: &[Box<FormulaAtom>]) -> Result<Formula, FormulaErr> {
+    try!(check_arity(atoms, 2, 4));
+    if let Formula::SheetRef(ref name, ref inner) = *reference {
+        return offset(inner, atoms).map(|x| Formula::SheetRef(name.clone(), Box::new(x)));
+    }
+
+    let (Coord(col_from, row_from), Coord(col_to, row_to)) = try!(bounds(reference));
+    if row_to >= MAX_ROWS || col_to >= MAX_COLS {
+        return Err(FormulaErr::InvalidRef);
+    }
+    let rows = try!(integer(&atoms[0]));
+    let cols = try!(integer(&atoms[1]));
+    let height = if atoms.len() > 2 { try!(integer(&atoms[2])) } else { (row_to - row_from + 1) as i64 };
+    let width = if atoms.len() > 3 { try!(integer(&atoms[3])) } else { (col_to - col_from + 1) as i64 };
+
+    // Every number here is well below `i64::MAX`, so none of this overflows.
+    let row = row_from as i64 + rows;
+    let col = col_from as i64 + cols;
+    if row < 0 || col < 0 || height < 1 || width < 1 ||
+       row + height > MAX_ROWS as i64 || col + width > MAX_COLS as i64 {
+        return Err(FormulaErr::InvalidRef);
+    }
+    let from = Coord(col as usize, row as usize);
+    let to = Coord((col + width - 1) as usize, (row + height - 1) as usize);
+    try!(check_area(from, to));
+    Ok(if from == to {
+        Formula::Ref(from, Anchor(false, false))
+    } else {
+        Formula::Range(from, Anchor(false, false), to, Anchor(false, false))
+    })
+}
+
+/// Parses the text of `indirect`, like `B2`, `$A$1:C3` or `'Q1 sales'!A1`.
+pub fn indirect(s: &str) -> Result<Formula, FormulaErr> {
+    let s = s.trim();
+    let (sheet, local) = match s.rfind('!') {
+        Some(idx) => {
+            let name = &s[..idx];
+            let name = if name.len() > 1 && name.starts_with("'") && name.ends_with("'") {
+                &name[1..name.len()-1]
+            } else {
+                name
+            };
+            (Some(name), &s[idx+1..])
+        },
+        None => (None, s),
+    };
+
+    let mut corners = local.split(':').map(|x| Coord::parse_anchored(x));
+    let formula = match (corners.next(), corners.next(), corners.next()) {
+        (Some(Some((coord, anchor))), None, None) => Formula::Ref(coord, anchor),
+        (Some(Some((a, a_anchor))), Some(Some((b, b_anchor))), None) => Formula::range(a, a_anchor, b, b_anchor),
+        _ => { return Err(FormulaErr::InvalidRef); },
+    };
+    let (from, to) = try!(bounds(&formula));
+    try!(check_area(from, to));
+    Ok(match sheet {
+        Some(name) if name.len() > 0 => Formula::SheetRef(name.to_string(), Box::new(formula)),
+        Some(_) => { return Err(FormulaErr::InvalidRef); },
+        None => formula,
+    })
+}
+
+/// The top left and bottom right cells of a resolved reference.
+pub fn bounds(reference: &Formula) -> Result<(Coord, Coord), FormulaErr> {
+    match *reference {
+        Formula::Ref(coord, _) => Ok((coord, coord)),
+        Formula::Range(from, _, to, _) => Ok((from, to)),
+        Formula::SheetRef(_, ref inner) => bounds(inner),
+        _ => Err(FormulaErr::Type("Reference")),
+    }
+}
+
+/// Fails with `#REF!` if an area goes past the last row or column, or with
+/// `#NUM!` if it has more than `MAX_CELLS` cells.
+fn check_area(Coord(col_from, row_from): Coord, Coord(col_to, row_to): Coord) -> Result<(), FormulaErr> {
+    if row_to >= MAX_ROWS || col_to >= MAX_COLS {
+        Err(FormulaErr::InvalidRef)
+    } else if (row_to - row_from + 1) * (col_to - col_from + 1) > MAX_CELLS {
+        Err(FormulaErr::Num)
+    } else {
+        Ok(())
+    }
+}
+
+/// A whole number of rows or columns, which can't be further than the size
+/// of the grid.
+fn integer(atom: &FormulaAtom) -> Result<i64, FormulaErr> {
+    let x = try!(number(atom)).trunc();
+    if x.is_finite() && x.abs() <= (MAX_ROWS + MAX_COLS) as f64 {
+        Ok(x as i64)
+    } else {
+        Err(FormulaErr::InvalidRef)
+    }
+}
+
+/// A row or column number, counted from 1, as an index from 0.
+fn position(atom: &FormulaAtom) -> Result<usize, FormulaErr> {
+    let x = try!(number(atom)).trunc();
+    if x < 1.0 {
+        Err(FormulaErr::Type("Position"))
+    } else {
+        Ok(x as usize - 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sheet::{Anchor, Coord, Formula, FormulaAtom, FormulaErr};
+
+    #[test]
+    fn test_indirect() {
+        let r = |coord| Formula::Ref(coord, Anchor(false, false));
+        assert_eq!(r(Coord(1, 1)), indirect("B2").ok().unwrap());
+        assert_eq!(Formula::Range(Coord(0, 0), Anchor(true, true), Coord(2, 2), Anchor(false, false)),
+                   indirect("$A$1:C3").ok().unwrap());
+        assert_eq!(Formula::SheetRef("Q1 sales".to_string(), Box::new(r(Coord(0, 0)))),
+                   indirect("'Q1 sales'!A1").ok().unwrap());
+        assert!(indirect("A0").is_err());
+        assert!(indirect("!A1").is_err());
+        assert!(indirect("A1:B2:C3").is_err());
+        assert_eq!(Some(FormulaErr::InvalidRef), indirect("A99999999").err());
+        assert_eq!(Some(FormulaErr::Num), indirect("A1:Z1000000").err());
+    }
+
+    #[test]
+    fn test_offset() {
+        let n = |x: f64| Box::new(FormulaAtom::Number(x));
+        let base = Formula::Range(Coord(1, 1), Anchor(true, true), Coord(2, 3), Anchor(true, true));
+        assert_eq!(Formula::Range(Coord(0, 3), Anchor(false, false), Coord(1, 5), Anchor(false, false)),
+                   offset(&base, &[n(2.0), n(-1.0)]).ok().unwrap());
+        assert_eq!(Formula::Ref(Coord(1, 1), Anchor(false, false)),
+                   offset(&base, &[n(0.0), n(0.0), n(1.0), n(1.0)]).ok().unwrap());
+        assert!(offset(&base, &[n(-2.0), n(0.0)]).is_err());
+
+        let ok = |atoms: &[Box<FormulaAtom>]| offset(&base, atoms).ok();
+        let err = |atoms: &[Box<FormulaAtom>]| offset(&base, atoms).err().unwrap();
+        assert_eq!(FormulaErr::InvalidRef, err(&[n(1e300), n(0.0)]));
+        assert_eq!(FormulaErr::InvalidRef, err(&[n(0.0), n(0.0), n(1.0), n(::std::f64::INFINITY)]));
+        assert_eq!(FormulaErr::InvalidRef, err(&[n(0.0), n(0.0), n(1.0), n(MAX_COLS as f64)]));
+        assert_eq!(FormulaErr::Num, err(&[n(0.0), n(0.0), n(MAX_ROWS as f64 - 1.0), n(2.0)]));
+        assert_eq!(Some(Formula::Range(Coord(1, 1), Anchor(false, false), Coord(1, MAX_ROWS - 1), Anchor(false, false))),
+                   ok(&[n(0.0), n(0.0), n(MAX_ROWS as f64 - 1.0), n(1.0)]));
+    }
+}
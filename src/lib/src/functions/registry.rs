@@ -0,0 +1,156 @@
+//! Functions that applications define in Rust, for formulas to call by name
+//! like `margin(B2, C2)`.
+//!
+//! Formulas can call any name. The parser asks a registry which operation
+//! each name stands for, and the built-in functions are what every registry
+//! has to begin with, so a registered function can't replace one. Other
+//! names are looked up in the book's registry when the formula is
+//! evaluated, and give `#NAME?` if they aren't there.
+
+use std::collections::HashMap;
+use sheet::{FormulaAtom, FormulaOp, Value};
+
+pub struct Function {
+    pub name: String,
+    /// The fewest and most arguments it takes.
+    pub min_args: usize,
+    pub max_args: usize,
+    /// Whether it can give a different result for the same arguments, like
+    /// `rand()`. See `Sheet::refresh_volatile`.
+    pub volatile: bool,
+    /// Gets the values of the arguments, with ranges expanded. It can be
+    /// called from several threads at once.
+    pub call: Box<Fn(&[Box<FormulaAtom>]) -> Value + Send + Sync>,
+}
+
+impl Function {
+    /// A function that isn't volatile.
+    pub fn new<F>(name: &str, min_args: usize, max_args: usize, call: F) -> Function
+        where F: Fn(&[Box<FormulaAtom>]) -> Value + Send + Sync + 'static
+    {
+        Function{
+            name: name.to_string(),
+            min_args: min_args,
+            max_args: max_args,
+            volatile: false,
+            call: Box::new(call),
+        }
+    }
+}
+
+pub trait FunctionRegistry: Sync {
+    /// The function formulas call `name`, if any.
+    fn function(&self, name: &str) -> Option<&Function>;
+
+    /// The operation a call to `name` stands for: a built-in function, or
+    /// else a function of the registry. Registries can give built-in
+    /// functions more names, like in other languages.
+    fn op(&self, name: &str) -> Option<FormulaOp> {
+        builtin(name).or_else(|| self.function(name).map(|f| FormulaOp::Custom(f.name.clone())))
+    }
+}
+
+/// The registry books start with, with just the built-in functions, where
+/// others are added one by one.
+pub struct Registry {
+    functions: HashMap<String, Function>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry{functions: HashMap::new()}
+    }
+
+    /// Adds a function, replacing any other with the same name.
+    pub fn register(&mut self, function: Function) {
+        self.functions.insert(function.name.clone(), function);
+    }
+}
+
+impl FunctionRegistry for Registry {
+    fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+}
+
+/// The built-in function called `name`, if any.
+pub fn builtin(name: &str) -> Option<FormulaOp> {
+    Some(match name {
+        "address" => FormulaOp::Address,
+        "add" => FormulaOp::Add,
+        "sub" => FormulaOp::Sub,
+        "mul" => FormulaOp::Mul,
+        "div" => FormulaOp::Div,
+        "avg" => FormulaOp::Avg,
+        "today" => FormulaOp::Today,
+        "now" => FormulaOp::Now,
+        "datedif" => FormulaOp::DateDif,
+        "date" => FormulaOp::Date,
+        "year" => FormulaOp::Year,
+        "month" => FormulaOp::Month,
+        "day" => FormulaOp::Day,
+        "weekday" => FormulaOp::Weekday,
+        "edate" => FormulaOp::EDate,
+        "eomonth" => FormulaOp::EOMonth,
+        "networkdays" => FormulaOp::NetworkDays,
+        "vlookup" => FormulaOp::VLookup,
+        "hlookup" => FormulaOp::HLookup,
+        "index" => FormulaOp::Index,
+        "match" => FormulaOp::Match,
+        "xlookup" => FormulaOp::XLookup,
+        "offset" => FormulaOp::Offset,
+        "indirect" => FormulaOp::Indirect,
+        "rows" => FormulaOp::Rows,
+        "row" => FormulaOp::Row,
+        "columns" => FormulaOp::Columns,
+        "column" => FormulaOp::Column,
+        "abs" => FormulaOp::Abs,
+        "sign" => FormulaOp::Sign,
+        "int" => FormulaOp::Int,
+        "trunc" => FormulaOp::Trunc,
+        "roundup" => FormulaOp::RoundUp,
+        "rounddown" => FormulaOp::RoundDown,
+        "round" => FormulaOp::Round,
+        "floor" => FormulaOp::Floor,
+        "ceiling" => FormulaOp::Ceiling,
+        "mod" => FormulaOp::Mod,
+        "power" => FormulaOp::Power,
+        "sqrt" => FormulaOp::Sqrt,
+        "exp" => FormulaOp::Exp,
+        "ln" => FormulaOp::Ln,
+        "log10" => FormulaOp::Log10,
+        "log" => FormulaOp::Log,
+        "pi" => FormulaOp::Pi,
+        "sin" => FormulaOp::Sin,
+        "cos" => FormulaOp::Cos,
+        "tan" => FormulaOp::Tan,
+        "asin" => FormulaOp::Asin,
+        "acos" => FormulaOp::Acos,
+        "atan2" => FormulaOp::Atan2,
+        "atan" => FormulaOp::Atan,
+        "randbetween" => FormulaOp::RandBetween,
+        "rand" => FormulaOp::Rand,
+        "pmt" => FormulaOp::Pmt,
+        "fv" => FormulaOp::Fv,
+        "pv" => FormulaOp::Pv,
+        "rate" => FormulaOp::Rate,
+        "npv" => FormulaOp::Npv,
+        "irr" => FormulaOp::Irr,
+        "xnpv" => FormulaOp::XNpv,
+        "sumifs" => FormulaOp::SumIfs,
+        "sumif" => FormulaOp::SumIf,
+        "countifs" => FormulaOp::CountIfs,
+        "countif" => FormulaOp::CountIf,
+        "averageifs" => FormulaOp::AverageIfs,
+        "averageif" => FormulaOp::AverageIf,
+        "filter" => FormulaOp::Filter,
+        "sort" => FormulaOp::Sort,
+        "unique" => FormulaOp::Unique,
+        "sequence" => FormulaOp::Sequence,
+        "transpose" => FormulaOp::Transpose,
+        "if" => FormulaOp::If,
+        "let" => FormulaOp::Let,
+        "lambda" => FormulaOp::Lambda,
+        _ => { return None; },
+    })
+}
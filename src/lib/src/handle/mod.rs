@@ -0,0 +1,409 @@
+//! A workbook running on its own thread. Front-ends send it commands through
+//! a `WorkbookHandle` and get the replies over channels when they're ready,
+//! so they never hold a lock on the workbook, nor wait for it to
+//! recalculate unless they choose to.
+
+use std::io;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+use conditional::Look;
+use export;
+use file::{self, FileErr};
+use format::NumberFormat;
+use parser::NumberLocale;
+use sheet::{Coord, Formula, FormulaAtom, FillDirection, Value, Subscription, cells_between, corners};
+use validation::{Validation, ValidationRule, Validity};
+use workbook::{Workbook, WorkbookErr};
+
+/// How many changes `undo` can take back.
+const MAX_UNDO: usize = 100;
+
+/// The number format, look and, for cells with a list validation, the values
+/// to pick from of a cell.
+pub type CellLook = (Option<NumberFormat>, Look, Vec<FormulaAtom>);
+
+/// Sends commands to a workbook on its own thread. Clones send to the same
+/// workbook, which stops once they're all dropped.
+#[derive(Clone)]
+pub struct WorkbookHandle {
+    commands: Sender<Command>,
+    next_selection: Arc<AtomicUsize>,
+}
+
+enum Command {
+    Set(usize, Coord, Formula, Sender<Result<Validity, WorkbookErr>>),
+    Fill(usize, Coord, Coord, FillDirection, Sender<Result<(), WorkbookErr>>),
+    AddSheet(String, Sender<Result<usize, WorkbookErr>>),
+    Select(usize, usize, Coord, Coord, bool, Sender<(Coord, Value)>),
+    Unselect(usize),
+    MoveSelection(usize, Coord, Coord),
+    Undo(Sender<bool>),
+    Load(PathBuf, Sender<Result<(), FileErr>>),
+    Save(PathBuf, Sender<io::Result<()>>),
+    ExportCsv(usize, PathBuf, Sender<io::Result<()>>),
+    Export(PathBuf, Sender<io::Result<()>>),
+    Value(usize, Coord, Sender<Result<Value, WorkbookErr>>),
+    Looks(usize, Vec<Coord>, Sender<Result<Vec<CellLook>, WorkbookErr>>),
+    SheetNames(Sender<Vec<String>>),
+    NumberLocale(Sender<NumberLocale>),
+    With(Box<FnMut(&mut Workbook) + Send>),
+}
+
+impl WorkbookHandle {
+    pub fn new(book: Workbook) -> WorkbookHandle {
+        let (tx, rx) = channel();
+        ::std::thread::spawn(move || {
+            let mut actor = Actor{book: book, selections: vec![], undo: vec![]};
+            for command in rx.iter() {
+                actor.handle(command);
+                actor.forward();
+            }
+        });
+        WorkbookHandle{commands: tx, next_selection: Arc::new(AtomicUsize::new(0))}
+    }
+
+    /// Sets the formula of a cell, like `Workbook::set`.
+    pub fn set(&self, sheet: usize, coord: Coord, formula: Formula) -> Receiver<Result<Validity, WorkbookErr>> {
+        self.request(|tx| Command::Set(sheet, coord, formula, tx))
+    }
+
+    pub fn fill(&self, sheet: usize, from: Coord, to: Coord, direction: FillDirection) -> Receiver<Result<(), WorkbookErr>> {
+        self.request(|tx| Command::Fill(sheet, from, to, direction, tx))
+    }
+
+    pub fn add_sheet(&self, name: &str) -> Receiver<Result<usize, WorkbookErr>> {
+        self.request(|tx| Command::AddSheet(name.to_string(), tx))
+    }
+
+    /// Like `Workbook::select`, along with an ID to stop receiving values
+    /// with `unselect`, or to move the selection with `move_selection`.
+    pub fn select(&self, sheet: usize, from: Coord, to: Coord) -> (usize, Receiver<(Coord, Value)>) {
+        let id = self.next_selection.fetch_add(1, Ordering::SeqCst);
+        (id, self.request(|tx| Command::Select(id, sheet, from, to, false, tx)))
+    }
+
+    /// Like `select`, for a selection that stays where it is when lines are
+    /// inserted or deleted. See `Workbook::select_fixed`.
+    pub fn select_fixed(&self, sheet: usize, from: Coord, to: Coord) -> (usize, Receiver<(Coord, Value)>) {
+        let id = self.next_selection.fetch_add(1, Ordering::SeqCst);
+        (id, self.request(|tx| Command::Select(id, sheet, from, to, true, tx)))
+    }
+
+    pub fn unselect(&self, id: usize) {
+        self.send(Command::Unselect(id));
+    }
+
+    /// See `Workbook::move_selection`.
+    pub fn move_selection(&self, id: usize, from: Coord, to: Coord) {
+        self.send(Command::MoveSelection(id, from, to));
+    }
+
+    /// Takes back the last change made with `set` or `fill`, if any, replying
+    /// whether there was one.
+    pub fn undo(&self) -> Receiver<bool> {
+        self.request(|tx| Command::Undo(tx))
+    }
+
+    /// Replaces the sheets and names of the workbook with those saved in a
+    /// file. See the `file` module.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Receiver<Result<(), FileErr>> {
+        let path = path.as_ref().to_path_buf();
+        self.request(|tx| Command::Load(path, tx))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Receiver<io::Result<()>> {
+        let path = path.as_ref().to_path_buf();
+        self.request(|tx| Command::Save(path, tx))
+    }
+
+    /// Writes the values of a sheet to a CSV file, see `file::export_csv`.
+    pub fn export_csv<P: AsRef<Path>>(&self, sheet: usize, path: P) -> Receiver<io::Result<()>> {
+        let path = path.as_ref().to_path_buf();
+        self.request(|tx| Command::ExportCsv(sheet, path, tx))
+    }
+
+    /// Exports the workbook as an `.xlsx` or an `.ods` file, going by the
+    /// extension of `path`. See the `export` module.
+    pub fn export<P: AsRef<Path>>(&self, path: P) -> Receiver<io::Result<()>> {
+        let path = path.as_ref().to_path_buf();
+        self.request(|tx| Command::Export(path, tx))
+    }
+
+    pub fn value(&self, sheet: usize, coord: Coord) -> Receiver<Result<Value, WorkbookErr>> {
+        self.request(|tx| Command::Value(sheet, coord, tx))
+    }
+
+    /// The looks of several cells, in the same order.
+    pub fn looks(&self, sheet: usize, coords: Vec<Coord>) -> Receiver<Result<Vec<CellLook>, WorkbookErr>> {
+        self.request(|tx| Command::Looks(sheet, coords, tx))
+    }
+
+    pub fn sheet_names(&self) -> Receiver<Vec<String>> {
+        self.request(|tx| Command::SheetNames(tx))
+    }
+
+    /// How numbers typed on their own in a cell are written, for reading
+    /// them with `parser::parse_formula_in`.
+    pub fn number_locale(&self) -> Receiver<NumberLocale> {
+        self.request(|tx| Command::NumberLocale(tx))
+    }
+
+    /// Runs `f` on the workbook's thread, for whatever the other commands
+    /// don't cover. Neither its changes nor those before can be undone.
+    pub fn with<F>(&self, f: F)
+        where F: FnMut(&mut Workbook) + Send + 'static
+    {
+        self.send(Command::With(Box::new(f)));
+    }
+
+    fn request<T, F>(&self, command: F) -> Receiver<T>
+        where F: FnOnce(Sender<T>) -> Command
+    {
+        let (tx, rx) = channel();
+        self.send(command(tx));
+        rx
+    }
+
+    fn send(&self, command: Command) {
+        // If the workbook's thread is gone, so are the senders for the
+        // replies, which front-ends find out about when receiving.
+        let _ = self.commands.send(command);
+    }
+}
+
+struct Actor {
+    book: Workbook,
+    /// The selections by ID, with the values the workbook sends them and
+    /// where to pass those on to.
+    selections: Vec<(usize, Subscription, Sender<(Coord, Value)>)>,
+    /// The formulas that changes replaced, oldest change first.
+    undo: Vec<Vec<(usize, Coord, Formula)>>,
+}
+
+impl Actor {
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Set(sheet, coord, formula, reply) => {
+                let _ = reply.send(self.check_sheet(sheet).map(|_| {
+                    let old = self.formulas(sheet, coord, coord);
+                    let validity = self.book.set(sheet, coord, formula);
+                    if let Validity::Rejected(_) = validity {} else {
+                        self.push_undo(old);
+                    }
+                    validity
+                }));
+            },
+            Command::Fill(sheet, from, to, direction, reply) => {
+                let _ = reply.send(self.check_sheet(sheet).map(|_| {
+                    let old = self.formulas(sheet, from, to);
+                    self.book.fill(sheet, from, to, direction);
+                    self.push_undo(old);
+                }));
+            },
+            Command::AddSheet(name, reply) => {
+                let _ = reply.send(self.book.add_sheet(name.as_str()));
+            },
+            // A sheet that isn't there drops `tx`, so no values come.
+            Command::Select(id, sheet, from, to, fixed, tx) => if sheet < self.book.len() {
+                let subscription = if fixed {
+                    self.book.select_fixed(sheet, from, to)
+                } else {
+                    self.book.select(sheet, from, to)
+                };
+                self.selections.push((id, subscription, tx));
+            },
+            Command::Unselect(id) => {
+                if let Some(idx) = self.selections.iter().position(|&(other, _, _)| other == id) {
+                    self.selections.remove(idx);
+                }
+            },
+            Command::MoveSelection(id, from, to) => {
+                if let Some(&(_, ref subscription, _)) = self.selections.iter().find(|&&(other, _, _)| other == id) {
+                    self.book.move_selection(subscription.id, from, to);
+                }
+            },
+            Command::Undo(reply) => {
+                let change = self.undo.pop();
+                let _ = reply.send(change.is_some());
+                for (sheet, coord, formula) in change.unwrap_or(vec![]) {
+                    if sheet < self.book.len() {
+                        self.book.set(sheet, coord, formula);
+                    }
+                }
+            },
+            Command::Load(path, reply) => {
+                let loaded = File::open(&path).map_err(FileErr::Io).and_then(|mut f| file::load(&mut f));
+                let _ = reply.send(loaded.map(|book| {
+                    self.book.replace_contents(book);
+                    self.undo.clear();
+                }));
+            },
+            Command::Save(path, reply) => {
+                let _ = reply.send(File::create(&path).and_then(|mut f| file::save(&self.book, &mut f)));
+            },
+            Command::Export(path, reply) => {
+                let extension = path.extension().and_then(|x| x.to_str()).map(|x| x.to_lowercase());
+                let book = &self.book;
+                let _ = reply.send(match extension.as_ref().map(|x| x.as_str()) {
+                    Some("xlsx") => File::create(&path).and_then(|mut f| export::export_xlsx(book, &mut f)),
+                    Some("ods") => File::create(&path).and_then(|mut f| export::export_ods(book, &mut f)),
+                    _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "not an .xlsx nor an .ods file")),
+                });
+            },
+            Command::ExportCsv(sheet, path, reply) => {
+                let _ = reply.send(match self.check_sheet(sheet) {
+                    Ok(()) => File::create(&path).and_then(|mut f| file::export_csv(&self.book, sheet, &mut f)),
+                    Err(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "no such sheet")),
+                });
+            },
+            Command::Value(sheet, coord, reply) => {
+                let _ = reply.send(self.check_sheet(sheet).map(|_| self.book.value(sheet, coord)));
+            },
+            Command::Looks(sheet, coords, reply) => {
+                let _ = reply.send(self.check_sheet(sheet).map(|_| {
+                    let looks = self.book.looks(sheet, &coords);
+                    coords.into_iter().zip(looks.into_iter()).map(|(coord, look)| {
+                        let choices = match self.book.validation(sheet, coord) {
+                            Some(&Validation{rule: ValidationRule::List(ref xs), ..}) => xs.clone(),
+                            _ => vec![],
+                        };
+                        (self.book.format(sheet, coord).cloned(), look, choices)
+                    }).collect()
+                }));
+            },
+            Command::SheetNames(reply) => {
+                let _ = reply.send(self.book.sheet_names());
+            },
+            Command::NumberLocale(reply) => {
+                let _ = reply.send(self.book.number_locale());
+            },
+            Command::With(mut f) => {
+                f(&mut self.book);
+                self.undo.clear();
+            },
+        }
+    }
+
+    /// Passes on the values the workbook sent to the selections, dropping
+    /// the selections no one receives from anymore, and those the workbook
+    /// dropped, like the selections of a sheet that's gone.
+    fn forward(&mut self) {
+        self.selections.retain(|&(_, ref subscription, ref tx)| {
+            loop {
+                match subscription.values.try_recv() {
+                    Ok(x) => if let Err(_) = tx.send(x) {
+                        return false;
+                    },
+                    Err(TryRecvError::Empty) => { return true; },
+                    Err(TryRecvError::Disconnected) => { return false; },
+                }
+            }
+        });
+    }
+
+    fn check_sheet(&self, sheet: usize) -> Result<(), WorkbookErr> {
+        if sheet < self.book.len() { Ok(()) } else { Err(WorkbookErr::NoSuchSheet(sheet)) }
+    }
+
+    /// The formulas of the cells from `from` to `to`, empty ones included.
+    fn formulas(&self, sheet: usize, from: Coord, to: Coord) -> Vec<(usize, Coord, Formula)> {
+        if sheet >= self.book.len() {
+            return vec![];
+        }
+        let (from, to) = corners(from, to);
+        cells_between(from, to).into_iter().map(|coord| {
+            let formula = self.book.formula(sheet, coord).cloned().unwrap_or(Formula::Atom(FormulaAtom::Empty));
+            (sheet, coord, formula)
+        }).collect()
+    }
+
+    fn push_undo(&mut self, change: Vec<(usize, Coord, Formula)>) {
+        if self.undo.len() == MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.undo.push(change);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser::parse_formula;
+    use sheet::{Coord, Formula, FormulaAtom, FillDirection};
+    use workbook::{Workbook, WorkbookErr};
+
+    #[test]
+    fn test_handle() {
+        let book = WorkbookHandle::new(Workbook::new());
+        let (id, rx) = book.select(0, Coord(0, 0), Coord(0, 2));
+        for row in 0 .. 3 {
+            assert_eq!((Coord(0, row), FormulaAtom::Empty), {
+                let (coord, value) = rx.recv().unwrap();
+                (coord, *value.ok().unwrap())
+            });
+        }
+
+        book.set(0, Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0))).recv().unwrap().unwrap();
+        book.set(0, Coord(0, 1), parse_formula("add(A1, 1)").ok().unwrap()).recv().unwrap().unwrap();
+        book.fill(0, Coord(0, 1), Coord(0, 2), FillDirection::Down).recv().unwrap().unwrap();
+        let number = |x: f64| FormulaAtom::Number(x);
+        assert_eq!(number(3.0), *book.value(0, Coord(0, 2)).recv().unwrap().unwrap().ok().unwrap());
+        let mut received = vec![];
+        while let Ok((coord, value)) = rx.try_recv() {
+            received.push((coord, *value.ok().unwrap()));
+        }
+        assert_eq!(vec![(Coord(0, 0), number(1.0)), (Coord(0, 1), number(2.0)), (Coord(0, 1), number(2.0)),
+                        (Coord(0, 2), number(3.0))], received);
+
+        assert!(book.undo().recv().unwrap());
+        assert_eq!(FormulaAtom::Empty, *book.value(0, Coord(0, 2)).recv().unwrap().unwrap().ok().unwrap());
+        assert!(book.undo().recv().unwrap());
+        assert!(book.undo().recv().unwrap());
+        assert!(!book.undo().recv().unwrap());
+        assert_eq!(FormulaAtom::Empty, *book.value(0, Coord(0, 0)).recv().unwrap().unwrap().ok().unwrap());
+
+        // The values sent before unselecting are still there, with A3 sent
+        // again when undoing the fill sets A2, and then no more come.
+        book.unselect(id);
+        assert_eq!(Ok(1), book.add_sheet("Sheet2").recv().unwrap());
+        assert_eq!(5, rx.iter().count());
+        assert_eq!(vec!["Sheet1".to_string(), "Sheet2".to_string()], book.sheet_names().recv().unwrap());
+
+        let (_, rx) = book.select(2, Coord(0, 0), Coord(0, 0));
+        assert!(rx.recv().is_err());
+        assert_eq!(Err(WorkbookErr::NoSuchSheet(2)), book.set(2, Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0))).recv().unwrap());
+        assert_eq!(Err(WorkbookErr::NoSuchSheet(2)), book.fill(2, Coord(0, 0), Coord(0, 1), FillDirection::Down).recv().unwrap());
+        assert!(book.value(2, Coord(0, 0)).recv().unwrap().is_err());
+        assert!(book.looks(2, vec![Coord(0, 0)]).recv().unwrap().is_err());
+        assert!(!book.undo().recv().unwrap());
+
+        book.set(0, Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0))).recv().unwrap().unwrap();
+        book.with(|book| { book.delete_rows(0, 0, 1); });
+        assert!(!book.undo().recv().unwrap());
+    }
+
+    #[test]
+    fn test_load() {
+        let path = ::std::env::temp_dir().join("handle-test-load.txt");
+        let mut one = Workbook::new();
+        one.set(0, Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        file::save(&one, &mut File::create(&path).unwrap()).unwrap();
+
+        let book = WorkbookHandle::new(Workbook::new());
+        assert_eq!(Ok(1), book.add_sheet("Sheet2").recv().unwrap());
+        let (_, first) = book.select(0, Coord(0, 0), Coord(0, 0));
+        let (_, second) = book.select(1, Coord(0, 0), Coord(0, 0));
+        assert_eq!(FormulaAtom::Empty, *first.recv().unwrap().1.ok().unwrap());
+        assert_eq!(FormulaAtom::Empty, *second.recv().unwrap().1.ok().unwrap());
+
+        // The selection of the sheet the file doesn't have is dropped.
+        assert!(book.load(&path).recv().unwrap().is_ok());
+        let _ = ::std::fs::remove_file(&path);
+        assert_eq!(FormulaAtom::Number(1.0), *first.recv().unwrap().1.ok().unwrap());
+        assert!(second.recv().is_err());
+        assert_eq!(vec!["Sheet1".to_string()], book.sheet_names().recv().unwrap());
+    }
+}
@@ -9,8 +9,24 @@ extern crate glutin_window;
 extern crate opengl_graphics;
 extern crate conrod;
 extern crate event;
+extern crate input;
+extern crate time;
+extern crate num;
 
 pub mod ui;
 pub mod sheet;
+pub mod deps;
+pub mod workbook;
 pub mod parser;
+pub mod date;
+pub mod decimal;
+pub mod format;
+pub mod style;
+pub mod conditional;
+pub mod validation;
+pub mod functions;
+pub mod script;
+pub mod file;
+pub mod export;
+pub mod handle;
 
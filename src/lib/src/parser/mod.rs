@@ -1,16 +1,27 @@
 #![plugin(peg_syntax_ext)]
 
-use sheet::{Formula, FormulaAtom};
+use sheet::{Formula, FormulaAtom, FormulaOp};
+use decimal::Decimal;
+use functions::registry::{FunctionRegistry, Registry};
 
 peg! grammar(r#"
-use sheet::{Formula, FormulaAtom, FormulaOp, Coord};
+use sheet::{Formula, FormulaAtom, FormulaOp, Coord, Anchor};
+use date;
+use super::{number_atom, array_atom};
 
 #[pub]
 formula -> Formula
-    = string
+    = array
+    / string
+    / datetime
+    / duration
     / number
-    / ref
-    / op
+    / sheet_ref
+    / local_ref
+    / invalid_ref
+    / apply
+    / call
+    / name
 
 string -> Formula
     = "\"" s:inside_str "\"" { s }
@@ -18,44 +29,273 @@ string -> Formula
 inside_str -> Formula
     = [^"]* { Formula::Atom(FormulaAtom::String(match_str.to_string())) }
 
+datetime -> Formula
+    = [0-9][0-9][0-9][0-9] "-" [0-9][0-9] "-" [0-9][0-9] ([T ] [0-9][0-9] ":" [0-9][0-9] (":" [0-9][0-9])?)? {
+        match date::parse_iso(match_str) {
+            Some(x) => Formula::Atom(FormulaAtom::Date(x)),
+            None => Formula::Atom(FormulaAtom::String(match_str.to_string())),
+        }
+    }
+
+duration -> Formula
+    = "-"? [0-9]+ ":" [0-9][0-9] (":" [0-9][0-9])? {
+        match date::parse_duration(match_str) {
+            Some(x) => Formula::Atom(FormulaAtom::Duration(x)),
+            None => Formula::Atom(FormulaAtom::String(match_str.to_string())),
+        }
+    }
+
+array -> Formula
+    = "{" [ \t]* rows:array_row ++ (";" [ \t]*) [ \t]* "}" { array_atom(rows) }
+
+array_row -> Vec<Formula>
+    = constant ++ arg_delim
+
+constant -> Formula
+    = string
+    / datetime
+    / duration
+    / number
+
 number -> Formula
-    = "-"? [0-9]* "." [0-9]+ { Formula::Atom(FormulaAtom::Number(match_str.parse().unwrap())) }
+    = [+-]? ([0-9]+ ("." [0-9]*)? / "." [0-9]+) ([eE] [+-]? [0-9]+)? { Formula::Atom(number_atom(match_str)) }
+
+sheet_ref -> Formula
+    = s:sheet_name "!" r:local_ref { Formula::SheetRef(s, Box::new(r)) }
+
+sheet_name -> String
+    = "'" s:quoted_sheet_name "'" { s }
+    / [A-Za-z_][A-Za-z0-9_]* { match_str.to_string() }
+
+quoted_sheet_name -> String
+    = [^'!]+ { match_str.to_string() }
+
+local_ref -> Formula
+    = range
+    / ref
+
+range -> Formula
+    = a:anchored_coord ":" b:anchored_coord { Formula::range(a.0, a.1, b.0, b.1) }
 
 ref -> Formula
-    = [A-Z]+[1-9][0-9]* { Formula::Ref(Coord::parse(match_str).unwrap()) }
+    = a:anchored_coord !name_char { Formula::Ref(a.0, a.1) }
+
+name -> Formula
+    = !(anchored_coord !name_char) [A-Za-z_] name_char* { Formula::Name(match_str.to_string()) }
+
+name_char -> ()
+    = [A-Za-z0-9_.] { }
+
+anchored_coord -> (Coord, Anchor)
+    = "$"? [A-Z]+ "$"? [1-9][0-9]* { Coord::parse_anchored(match_str).unwrap() }
+
+invalid_ref -> Formula
+    = "#REF!" { Formula::InvalidRef }
+
+apply -> Formula
+    = f:lambda "(" args:formula ** arg_delim ")" {
+        let mut args = args;
+        args.insert(0, f);
+        Formula::Op(FormulaOp::Apply, args)
+    }
+
+lambda -> Formula
+    = "lambda(" args:formula ** arg_delim ")" { Formula::Op(FormulaOp::Lambda, args) }
 
-op -> Formula
-    = o:op_name "(" args:formula ** arg_delim ")" {
-        Formula::Op(o, args)
+call -> Formula
+    = n:function_name "(" args:formula ** arg_delim ")" {
+        Formula::Op(FormulaOp::Custom(n), args)
     }
 
+function_name -> String
+    = [A-Za-z_] name_char* { match_str.to_string() }
+
 arg_delim -> ()
     = "," [ \t]* { }
-
-op_name -> FormulaOp
-    = "add" { FormulaOp::Add }
-    / "sub" { FormulaOp::Sub }
-    / "mul" { FormulaOp::Div }
-    / "div" { FormulaOp::Div }
-    / "avg" { FormulaOp::Avg }
 "#);
 
-pub fn parse_formula(s: &str) -> Result<Formula, grammar::ParseError> {
+pub use self::grammar::ParseError;
+
+/// How numbers typed on their own in a cell are written. Numbers inside
+/// formulas always use `.` for decimals and no thousands separators, as
+/// `,` separates arguments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NumberLocale {
+    pub decimal_sep: char,
+    pub thousands_sep: Option<char>,
+}
+
+impl NumberLocale {
+    /// `1,234.5`
+    pub fn english() -> NumberLocale {
+        NumberLocale{decimal_sep: '.', thousands_sep: Some(',')}
+    }
+
+    /// `1.234,5`
+    pub fn decimal_comma() -> NumberLocale {
+        NumberLocale{decimal_sep: ',', thousands_sep: Some('.')}
+    }
+}
+
+pub fn parse_formula(s: &str) -> Result<Formula, ParseError> {
+    parse_formula_in(s, &NumberLocale::english())
+}
+
+/// Like `parse_formula`, reading a cell that is just a number, like
+/// `1.234,5`, the way `locale` writes them.
+pub fn parse_formula_in(s: &str, locale: &NumberLocale) -> Result<Formula, ParseError> {
+    parse_formula_with(s, locale, &Registry::new())
+}
+
+/// Like `parse_formula_in`, asking `functions` what the functions formulas
+/// call stand for. Names it doesn't know are kept as calls by name.
+pub fn parse_formula_with(s: &str, locale: &NumberLocale, functions: &FunctionRegistry) -> Result<Formula, ParseError> {
     if s.len() == 0 {
         Ok(Formula::Atom(FormulaAtom::Empty))
+    } else if let Some(x) = parse_local_number(s, locale) {
+        Ok(Formula::Atom(x))
     } else {
-        grammar::formula(s)
+        grammar::formula(s).map(|f| resolve_calls(f, functions))
+    }
+}
+
+/// Replaces the calls by name in a formula with the operations they stand
+/// for in `functions`.
+fn resolve_calls(formula: Formula, functions: &FunctionRegistry) -> Formula {
+    match formula {
+        Formula::Op(op, args) => {
+            let op = match op {
+                FormulaOp::Custom(name) => functions.op(&name).unwrap_or(FormulaOp::Custom(name)),
+                op => op,
+            };
+            Formula::Op(op, args.into_iter().map(|x| resolve_calls(x, functions)).collect())
+        },
+        Formula::SheetRef(name, inner) => Formula::SheetRef(name, Box::new(resolve_calls(*inner, functions))),
+        x => x,
+    }
+}
+
+/// Parses a number with separators, like `-1,234,567.89`.
+fn parse_local_number(s: &str, locale: &NumberLocale) -> Option<FormulaAtom> {
+    let (sign, s) = match s.chars().next() {
+        Some(c) if c == '-' || c == '+' => (&s[..1], &s[1..]),
+        _ => ("", s),
+    };
+    let (int, frac) = match s.find(locale.decimal_sep) {
+        Some(idx) => (&s[..idx], &s[idx + locale.decimal_sep.len_utf8()..]),
+        None => (s, ""),
+    };
+    let digits = |x: &str| x.chars().all(|c| c >= '0' && c <= '9');
+
+    let groups: Vec<&str> = match locale.thousands_sep {
+        Some(sep) => int.split(sep).collect(),
+        None => vec![int],
+    };
+    let grouped = groups.iter().enumerate().all(|(i, group)| {
+        digits(group) && if i == 0 {
+            groups.len() == 1 || (group.len() >= 1 && group.len() <= 3)
+        } else {
+            group.len() == 3
+        }
+    });
+    if !grouped || !digits(frac) || int.len() + frac.len() == 0 {
+        return None;
+    }
+
+    let canonical = format!("{}{}.{}", sign, groups.concat(), frac);
+    Some(number_atom(canonical.trim_right_matches('.')))
+}
+
+/// A number literal is a float, unless it has more digits than a float can
+/// hold, in which case it is kept as an exact decimal.
+fn number_atom(s: &str) -> FormulaAtom {
+    let x: f64 = s.parse().unwrap();
+    match Decimal::parse(s) {
+        Some(ref exact) if Decimal::from_f64(x).as_ref() != Some(exact) => FormulaAtom::Decimal(exact.clone()),
+        _ => FormulaAtom::Number(x),
+    }
+}
+
+/// An array literal from its rows, where rows shorter than the longest are
+/// padded with empty values.
+fn array_atom(rows: Vec<Vec<Formula>>) -> Formula {
+    use sheet::Table;
+
+    let cols = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut cells = Vec::with_capacity(cols * rows.len());
+    for row in &rows {
+        for idx in 0 .. cols {
+            cells.push(Box::new(match row.get(idx) {
+                Some(&Formula::Atom(ref x)) => x.clone(),
+                _ => FormulaAtom::Empty,
+            }));
+        }
+    }
+    Formula::Atom(FormulaAtom::Array(Table{cols: cols, rows: rows.len(), cells: cells}))
+}
+
+/// Formats a float the shortest way that reads back as the same float, with
+/// an exponent if it's very large or very small.
+fn format_number(x: f64) -> String {
+    let abs = x.abs();
+    if abs >= 1e21 || (abs < 1e-7 && abs != 0.0) {
+        format!("{:e}", x)
+    } else {
+        format!("{}", x)
+    }
+}
+
+/// Formats a sheet name the way it has to be written in a reference, quoted
+/// unless it is a plain identifier.
+pub fn format_sheet_name(name: &str) -> String {
+    let plain = name.chars().enumerate().all(|(i, c)| match c {
+        'A' ... 'Z' | 'a' ... 'z' | '_' => true,
+        '0' ... '9' => i > 0,
+        _ => false,
+    });
+    if plain && name.len() > 0 {
+        name.to_string()
+    } else {
+        format!("'{}'", name)
     }
 }
 
 pub fn format_formula(f: &Formula) -> String {
-    use sheet::{FormulaAtom, FormulaOp};
 
     match *f {
-        Formula::Atom(FormulaAtom::Number(ref x)) => format!("{}", *x),
+        Formula::Atom(FormulaAtom::Number(ref x)) => format_number(*x),
+        Formula::Atom(FormulaAtom::Decimal(ref x)) => x.to_string(),
         Formula::Atom(FormulaAtom::String(ref x)) => format!("\"{}\"", *x),
+        Formula::Atom(FormulaAtom::Date(x)) => ::date::format_iso(x),
+        Formula::Atom(FormulaAtom::Duration(x)) => ::date::format_duration(x),
         Formula::Atom(FormulaAtom::Empty) => "".to_string(),
-        Formula::Ref(ref coord) => coord.format_natural(),
+        Formula::Atom(FormulaAtom::Error(ref e)) => ::format::format_error(e).to_string(),
+        Formula::Atom(FormulaAtom::Array(ref table)) => {
+            let rows: Vec<String> = table.cells.chunks(table.cols).map(|row| {
+                let row: Vec<String> = row.iter().map(|x| format_formula(&Formula::Atom((**x).clone()))).collect();
+                row.join(", ")
+            }).collect();
+            format!("{{{}}}", rows.join("; "))
+        },
+        Formula::Ref(ref coord, anchor) => coord.format_anchored(anchor),
+        Formula::Range(ref from, from_anchor, ref to, to_anchor) => {
+            format!("{}:{}", from.format_anchored(from_anchor), to.format_anchored(to_anchor))
+        },
+        Formula::SheetRef(ref name, ref inner) => {
+            format!("{}!{}", format_sheet_name(name), format_formula(inner))
+        },
+        Formula::Name(ref name) => name.clone(),
+        Formula::InvalidRef => "#REF!".to_string(),
+        Formula::Atom(FormulaAtom::Lambda(ref params, ref body)) => {
+            let mut args: Vec<Formula> = params.iter().map(|x| Formula::Name(x.clone())).collect();
+            args.push((**body).clone());
+            format_formula(&Formula::Op(FormulaOp::Lambda, args))
+        },
+        // Written like a call to a function named after the lambda.
+        Formula::Op(FormulaOp::Apply, ref args) => {
+            let (callee, args) = args.split_first().unwrap();
+            format_formula(&Formula::Op(FormulaOp::Custom(format_formula(callee)), args.to_vec()))
+        },
         Formula::Op(ref op, ref args) => {
             let mut ret = match *op {
                 FormulaOp::Add => "add",
@@ -63,13 +303,86 @@ pub fn format_formula(f: &Formula) -> String {
                 FormulaOp::Mul => "mul",
                 FormulaOp::Div => "div",
                 FormulaOp::Avg => "avg",
+                FormulaOp::Today => "today",
+                FormulaOp::Now => "now",
+                FormulaOp::Date => "date",
+                FormulaOp::Year => "year",
+                FormulaOp::Month => "month",
+                FormulaOp::Day => "day",
+                FormulaOp::Weekday => "weekday",
+                FormulaOp::DateDif => "datedif",
+                FormulaOp::EDate => "edate",
+                FormulaOp::EOMonth => "eomonth",
+                FormulaOp::NetworkDays => "networkdays",
+                FormulaOp::VLookup => "vlookup",
+                FormulaOp::HLookup => "hlookup",
+                FormulaOp::Index => "index",
+                FormulaOp::Match => "match",
+                FormulaOp::XLookup => "xlookup",
+                FormulaOp::Offset => "offset",
+                FormulaOp::Indirect => "indirect",
+                FormulaOp::Row => "row",
+                FormulaOp::Column => "column",
+                FormulaOp::Rows => "rows",
+                FormulaOp::Columns => "columns",
+                FormulaOp::Address => "address",
+                FormulaOp::Abs => "abs",
+                FormulaOp::Sign => "sign",
+                FormulaOp::Int => "int",
+                FormulaOp::Trunc => "trunc",
+                FormulaOp::RoundUp => "roundup",
+                FormulaOp::RoundDown => "rounddown",
+                FormulaOp::Round => "round",
+                FormulaOp::Floor => "floor",
+                FormulaOp::Ceiling => "ceiling",
+                FormulaOp::Mod => "mod",
+                FormulaOp::Power => "power",
+                FormulaOp::Sqrt => "sqrt",
+                FormulaOp::Exp => "exp",
+                FormulaOp::Ln => "ln",
+                FormulaOp::Log10 => "log10",
+                FormulaOp::Log => "log",
+                FormulaOp::Pi => "pi",
+                FormulaOp::Sin => "sin",
+                FormulaOp::Cos => "cos",
+                FormulaOp::Tan => "tan",
+                FormulaOp::Asin => "asin",
+                FormulaOp::Acos => "acos",
+                FormulaOp::Atan2 => "atan2",
+                FormulaOp::Atan => "atan",
+                FormulaOp::RandBetween => "randbetween",
+                FormulaOp::Rand => "rand",
+                FormulaOp::Pmt => "pmt",
+                FormulaOp::Fv => "fv",
+                FormulaOp::Pv => "pv",
+                FormulaOp::Rate => "rate",
+                FormulaOp::Npv => "npv",
+                FormulaOp::Irr => "irr",
+                FormulaOp::XNpv => "xnpv",
+                FormulaOp::SumIf => "sumif",
+                FormulaOp::CountIf => "countif",
+                FormulaOp::AverageIf => "averageif",
+                FormulaOp::SumIfs => "sumifs",
+                FormulaOp::CountIfs => "countifs",
+                FormulaOp::AverageIfs => "averageifs",
+                FormulaOp::Filter => "filter",
+                FormulaOp::Sort => "sort",
+                FormulaOp::Unique => "unique",
+                FormulaOp::Sequence => "sequence",
+                FormulaOp::Transpose => "transpose",
+                FormulaOp::If => "if",
+                FormulaOp::Let => "let",
+                FormulaOp::Lambda => "lambda",
+                FormulaOp::Apply => unreachable!(),
+                FormulaOp::Custom(ref name) => name.as_str(),
             }.to_string();
             ret.push_str("(");
-            let first = true;
+            let mut first = true;
             for arg in args {
                 if !first {
                     ret.push_str(", ");
                 }
+                first = false;
                 ret.push_str(format_formula(arg).as_str());
             }
             ret.push_str(")");
@@ -81,7 +394,7 @@ pub fn format_formula(f: &Formula) -> String {
 #[cfg(test)]
 mod test {
     use super::*;
-    use sheet::{Formula, Sheet, FormulaAtom, FormulaOp, Coord};
+    use sheet::{Formula, Sheet, FormulaAtom, FormulaOp, FormulaErr, Coord, Anchor, Context, Table};
     
     #[test]
     fn test_string() {
@@ -107,15 +420,676 @@ mod test {
         } else { panic!(); };
     }
 
+    #[test]
+    fn test_number_syntax() {
+        let number = |s: &str| match parse_formula(s) {
+            Ok(Formula::Atom(FormulaAtom::Number(x))) => x,
+            x => panic!("{} parsed as {:?}", s, x),
+        };
+        assert_eq!(42.0, number("42"));
+        assert_eq!(3.0, number("+3"));
+        assert_eq!(1e6, number("1e6"));
+        assert_eq!(-2.5e-3, number("-2.5E-3"));
+        assert_eq!(0.5, number(".5"));
+        assert_eq!(1234567.5, number("1,234,567.5"));
+        assert!(parse_formula("1,23").is_err());
+        assert!(parse_formula("1e").is_err());
+
+        let comma = NumberLocale::decimal_comma();
+        assert_eq!(Ok(Formula::Atom(FormulaAtom::Number(-1234.5))), parse_formula_in("-1.234,5", &comma));
+        assert_eq!(Ok(Formula::Atom(FormulaAtom::Number(0.25))), parse_formula_in("0,25", &comma));
+
+        assert_eq!("42", format_formula(&Formula::Atom(FormulaAtom::Number(42.0))));
+        assert_eq!("1e21", format_formula(&Formula::Atom(FormulaAtom::Number(1e21))));
+        assert_eq!("1.5e-8", format_formula(&Formula::Atom(FormulaAtom::Number(1.5e-8))));
+        assert_eq!("add(1, 2.5)", format_formula(&parse_formula("add(1,+2.5)").ok().unwrap()));
+    }
+
+    #[test]
+    fn test_long_number() {
+        let r = parse_formula("0.1234567890123456789").ok().unwrap();
+        if let Formula::Atom(FormulaAtom::Decimal(ref x)) = r {
+            assert_eq!("0.1234567890123456789", x.to_string());
+            assert_eq!("0.1234567890123456789", format_formula(&r).as_str());
+        } else { panic!(); };
+    }
+
+    #[test]
+    fn test_date() {
+        let mut r = parse_formula("2024-01-15").ok().unwrap();
+        assert_eq!(Formula::Atom(FormulaAtom::Date(45306.0)), r);
+        assert_eq!("2024-01-15", format_formula(&r).as_str());
+
+        r = parse_formula("2024-01-15T10:30").ok().unwrap();
+        assert_eq!(Formula::Atom(FormulaAtom::Date(45306.4375)), r);
+        assert_eq!("2024-01-15T10:30:00", format_formula(&r).as_str());
+
+        r = parse_formula("2023-02-29").ok().unwrap();
+        assert_eq!(Formula::Atom(FormulaAtom::String("2023-02-29".to_string())), r);
+
+        r = parse_formula("-36:30").ok().unwrap();
+        assert_eq!(Formula::Atom(FormulaAtom::Duration(-1.5208333333333333)), r);
+        assert_eq!("-36:30:00", format_formula(&r).as_str());
+    }
+
+    #[test]
+    fn test_date_functions() {
+        let mut sheet = Sheet::new();
+        let mut check = |formula: &str, expected: FormulaAtom| {
+            sheet.set(Coord(0, 0), parse_formula(formula).ok().unwrap());
+            assert_eq!(expected, *sheet.value(Coord(0, 0)).ok().unwrap());
+        };
+        check("date(2024, 14, 0)", FormulaAtom::Date(45688.0));
+        check("year(2024-03-05T12:00)", FormulaAtom::Number(2024.0));
+        check("month(2024-03-05)", FormulaAtom::Number(3.0));
+        check("day(2024-03-05)", FormulaAtom::Number(5.0));
+        check("weekday(2024-01-15)", FormulaAtom::Number(2.0));
+        check("weekday(2024-01-14, 2)", FormulaAtom::Number(7.0));
+        check("datedif(2020-02-29, 2024-02-28, \"Y\")", FormulaAtom::Number(3.0));
+        check("datedif(2024-01-31, 2024-03-01, \"M\")", FormulaAtom::Number(1.0));
+        check("datedif(2024-01-31, 2024-03-01, \"MD\")", FormulaAtom::Number(1.0));
+        check("datedif(2023-11-20, 2024-02-10, \"YM\")", FormulaAtom::Number(2.0));
+        check("datedif(2023-11-20, 2024-02-10, \"YD\")", FormulaAtom::Number(82.0));
+        check("edate(2024-01-31, 1)", FormulaAtom::Date(45351.0));
+        check("eomonth(2024-01-15, -2)", FormulaAtom::Date(45260.0));
+        check("networkdays(2024-01-01, 2024-01-14, 2024-01-01)", FormulaAtom::Number(9.0));
+        check("sub(2024-01-02, 2024-01-01T12:00)", FormulaAtom::Duration(0.5));
+        check("add(2024-01-01, 36:00)", FormulaAtom::Date(45293.5));
+        check("networkdays(2024-01-31, 2024-01-01)", FormulaAtom::Number(-23.0));
+        check("networkdays(0, 7000000, 1, 2, 2)", FormulaAtom::Number(4999999.0));
+
+        let mut errors = Sheet::new();
+        for formula in &["date(1e18, 1, 1)", "edate(0, 1e18)", "networkdays(0, 1e15)"] {
+            errors.set(Coord(0, 0), parse_formula(formula).ok().unwrap());
+            match errors.value(Coord(0, 0)) {
+                Err(FormulaErr::Num) => {},
+                x => panic!("{}: {:?}", formula, x),
+            }
+        }
+    }
+
+    #[test]
+    fn test_lookup_functions() {
+        let mut sheet = Sheet::new();
+        let rows = [("apple", 3.0), ("Banana", 5.0), ("cherry", 8.0)];
+        for (row, &(name, price)) in rows.iter().enumerate() {
+            sheet.set(Coord(0, row), Formula::Atom(FormulaAtom::String(name.to_string())));
+            sheet.set(Coord(1, row), Formula::Atom(FormulaAtom::Number(price)));
+        }
+        // Cells whose values are errors are skipped, unless they're found.
+        sheet.set(Coord(2, 1), parse_formula("add(missing, 1)").ok().unwrap());
+        let mut check = |formula: &str, expected: Option<FormulaAtom>| {
+            sheet.set(Coord(5, 0), parse_formula(formula).ok().unwrap());
+            match sheet.value(Coord(5, 0)) {
+                Ok(x) => assert_eq!(expected, Some(*x)),
+                Err(FormulaErr::NA) => assert_eq!(expected, None),
+                Err(x) => panic!("{:?}", x),
+            }
+        };
+        let string = |x: &str| Some(FormulaAtom::String(x.to_string()));
+        check("vlookup(\"banana\", A1:C3, 2, 0)", Some(FormulaAtom::Number(5.0)));
+        check("match(1, C1:C3, 0)", None);
+        check("vlookup(\"kiwi\", A1:B3, 2, 0)", None);
+        check("vlookup(6, B1:B3, 1)", Some(FormulaAtom::Number(5.0)));
+        check("vlookup(1, B1:B3, 1)", None);
+        check("hlookup(3, A2:B2, 1, 0)", None);
+        check("hlookup(5, A2:B2, 1, 0)", Some(FormulaAtom::Number(5.0)));
+        check("index(A1:B3, 3, 1)", string("cherry"));
+        check("index(B1:B3, 2)", Some(FormulaAtom::Number(5.0)));
+        check("match(\"CHERRY\", A1:A3, 0)", Some(FormulaAtom::Number(3.0)));
+        check("match(7, B1:B3)", Some(FormulaAtom::Number(2.0)));
+        check("xlookup(8, B1:B3, A1:A3)", string("cherry"));
+        check("xlookup(7, B1:B3, A1:A3, \"none\")", string("none"));
+        check("xlookup(7, B1:B3, A1:A3, \"none\", 1)", string("cherry"));
+        check("index(A1:B3, match(\"apple\", A1:A3, 0), 2)", Some(FormulaAtom::Number(3.0)));
+
+
+        let mut errors = Sheet::new();
+        errors.set(Coord(0, 0), parse_formula("add(missing, 1)").ok().unwrap());
+        errors.set(Coord(5, 0), parse_formula("index(A1:B3, 4, 1)").ok().unwrap());
+        assert!(match errors.value(Coord(5, 0)) { Err(FormulaErr::InvalidRef) => true, _ => false });
+        errors.set(Coord(5, 0), parse_formula("index(A1:B3, 1, 1)").ok().unwrap());
+        assert!(match errors.value(Coord(5, 0)) { Err(FormulaErr::Name(_)) => true, _ => false });
+    }
+
+    #[test]
+    fn test_reference_functions() {
+        let mut sheet = Sheet::new();
+        for row in 0 .. 4 {
+            sheet.set(Coord(0, row), Formula::Atom(FormulaAtom::Number(row as f64 + 1.0)));
+        }
+        sheet.set(Coord(1, 0), Formula::Atom(FormulaAtom::String("A3".to_string())));
+        {
+            let mut check = |formula: &str, expected: FormulaAtom| {
+                sheet.set(Coord(5, 0), parse_formula(formula).ok().unwrap());
+                assert_eq!(expected, *sheet.value(Coord(5, 0)).ok().unwrap());
+            };
+            check("offset(A1, 2, 0)", FormulaAtom::Number(3.0));
+            check("avg(offset(A1, 1, 0, 3))", FormulaAtom::Number(3.0));
+            check("indirect(B1)", FormulaAtom::Number(3.0));
+            check("avg(indirect(\"A1:A2\"))", FormulaAtom::Number(1.5));
+            check("row(B3)", FormulaAtom::Number(3.0));
+            check("column(B3)", FormulaAtom::Number(2.0));
+            check("rows(offset(A1, 0, 0, 4, 2))", FormulaAtom::Number(4.0));
+            check("columns(A1:C2)", FormulaAtom::Number(3.0));
+            check("address(3, 28)", FormulaAtom::String("$AB$3".to_string()));
+            check("address(3, 2, 4, 1, \"Q1 sales\")", FormulaAtom::String("'Q1 sales'!B3".to_string()));
+            check("vlookup(3, offset(A1, 0, 0, 4), 1, 0)", FormulaAtom::Number(3.0));
+        }
+
+        // A reference built at runtime can still make a cycle.
+        sheet.set(Coord(1, 0), Formula::Atom(FormulaAtom::String("F1".to_string())));
+        sheet.set(Coord(5, 0), parse_formula("indirect(B1)").ok().unwrap());
+        assert!(match sheet.value(Coord(5, 0)) { Err(FormulaErr::Ref(_)) => true, _ => false });
+        sheet.set(Coord(5, 0), parse_formula("offset(A1, -1, 0)").ok().unwrap());
+        assert!(match sheet.value(Coord(5, 0)) { Err(FormulaErr::InvalidRef) => true, _ => false });
+        sheet.set(Coord(5, 0), parse_formula("avg(offset(A1, 0, 0, 1e9, 1e9))").ok().unwrap());
+        assert!(match sheet.value(Coord(5, 0)) { Err(FormulaErr::InvalidRef) => true, _ => false });
+        sheet.set(Coord(5, 0), parse_formula("avg(offset(A1, 0, 0, 100000, 100))").ok().unwrap());
+        assert!(match sheet.value(Coord(5, 0)) { Err(FormulaErr::Num) => true, _ => false });
+    }
+
+    #[test]
+    fn test_math_functions() {
+        let mut sheet = Sheet::new();
+        let mut value = |formula: &str| {
+            sheet.set(Coord(0, 0), parse_formula(formula).ok().unwrap());
+            sheet.value(Coord(0, 0)).map(|x| match *x {
+                FormulaAtom::Number(x) => x,
+                ref x => panic!("{:?}", x),
+            })
+        };
+        assert_eq!(2.68, value("round(2.675, 2)").ok().unwrap());
+        assert_eq!(-3.0, value("round(-2.5)").ok().unwrap());
+        assert_eq!(1300.0, value("roundup(1201, -2)").ok().unwrap());
+        assert_eq!(-1.23, value("rounddown(-1.239, 2)").ok().unwrap());
+        assert_eq!(-2.0, value("trunc(-2.7)").ok().unwrap());
+        assert_eq!(-3.0, value("int(-2.7)").ok().unwrap());
+        assert_eq!(2.0, value("mod(-1, 3)").ok().unwrap());
+        assert_eq!(4.5, value("floor(5.2, 1.5)").ok().unwrap());
+        assert_eq!(6.0, value("ceiling(5.2, 1.5)").ok().unwrap());
+        assert_eq!(1024.0, value("power(2, 10)").ok().unwrap());
+        assert_eq!(3.0, value("log(8, 2)").ok().unwrap());
+        assert_eq!(2.0, value("log10(100)").ok().unwrap());
+        assert_eq!(-1.0, value("sign(-0.5)").ok().unwrap());
+        assert_eq!(1.0, value("abs(sub(0, 1))").ok().unwrap());
+        assert_eq!(::std::f64::consts::PI / 4.0, value("atan2(1, 1)").ok().unwrap());
+        assert_eq!(0.0, value("sin(0)").ok().unwrap());
+        assert!(value("abs(pi())").ok().unwrap() > 3.14);
+        for formula in &["sqrt(-1)", "ln(0)", "asin(2)", "mod(1, 0)", "power(0, -1)", "exp(1000)",
+                         "floor(1, -1)", "randbetween(2, 1)"] {
+            assert!(match value(formula) { Err(FormulaErr::Num) => true, _ => false }, "{}", formula);
+        }
+    }
+
+    #[test]
+    fn test_random_functions() {
+        let draw = |seed: u64| -> Vec<f64> {
+            let mut sheet = Sheet::new();
+            sheet.seed_random(seed);
+            sheet.set(Coord(0, 0), parse_formula("randbetween(1, 6)").ok().unwrap());
+            (0 .. 20).map(|_| match *sheet.value(Coord(0, 0)).ok().unwrap() {
+                FormulaAtom::Number(x) => x,
+                _ => panic!(),
+            }).collect()
+        };
+        let rolls = draw(7);
+        assert_eq!(rolls, draw(7));
+        assert!(rolls.iter().all(|&x| x >= 1.0 && x <= 6.0 && x == x.trunc()));
+    }
+
+    #[test]
+    fn test_finance_functions() {
+        let mut sheet = Sheet::new();
+        let flows = [-70000.0, 12000.0, 15000.0, 18000.0, 21000.0, 26000.0];
+        for (row, &x) in flows.iter().enumerate() {
+            sheet.set(Coord(0, row), Formula::Atom(FormulaAtom::Number(x)));
+        }
+        let dated = [("2008-01-01", -10000.0), ("2008-03-01", 2750.0), ("2008-10-30", 4250.0),
+                     ("2009-02-15", 3250.0), ("2009-04-01", 2750.0)];
+        for (row, &(day, x)) in dated.iter().enumerate() {
+            sheet.set(Coord(1, row), parse_formula(day).ok().unwrap());
+            sheet.set(Coord(2, row), Formula::Atom(FormulaAtom::Number(x)));
+        }
+        let mut value = |formula: &str| {
+            sheet.set(Coord(5, 0), parse_formula(formula).ok().unwrap());
+            sheet.value(Coord(5, 0)).map(|x| match *x {
+                FormulaAtom::Number(x) => (x * 100.0).round() / 100.0,
+                ref x => panic!("{:?}", x),
+            })
+        };
+        assert_eq!(-1037.03, value("pmt(div(0.08, 12), 10, 10000)").ok().unwrap());
+        assert_eq!(-1000.0, value("pmt(0, 10, 10000)").ok().unwrap());
+        assert_eq!(2581.4, value("fv(div(0.06, 12), 10, -200, -500, 1)").ok().unwrap());
+        assert_eq!(-59777.15, value("pv(div(0.08, 12), 240, 500)").ok().unwrap());
+        assert_eq!(1188.44, value("npv(0.1, -10000, 3000, 4200, 6800)").ok().unwrap());
+        assert_eq!(8.66, value("mul(irr(A1:A6), 100)").ok().unwrap());
+        assert_eq!(-2.12, value("mul(irr(A1:A5), 100)").ok().unwrap());
+        assert_eq!(0.77, value("mul(rate(48, -200, 8000), 100)").ok().unwrap());
+        assert_eq!(2086.65, value("xnpv(0.09, C1:C5, B1:B5)").ok().unwrap());
+
+        assert!(match value("irr(A2:A6)") { Err(FormulaErr::Num) => true, _ => false });
+        assert!(match value("xnpv(0.09, C1:C5, B1:B4)") { Err(FormulaErr::Num) => true, _ => false });
+    }
+
+    #[test]
+    fn test_conditional_aggregates() {
+        let mut sheet = Sheet::new();
+        let rows = [("north", "apples", 10.0), ("south", "pears", 20.0), ("north", "pears", 30.0),
+                    ("east", "apricots", 40.0)];
+        for (row, &(region, fruit, amount)) in rows.iter().enumerate() {
+            sheet.set(Coord(0, row), Formula::Atom(FormulaAtom::String(region.to_string())));
+            sheet.set(Coord(1, row), Formula::Atom(FormulaAtom::String(fruit.to_string())));
+            sheet.set(Coord(2, row), Formula::Atom(FormulaAtom::Number(amount)));
+        }
+        let mut value = |formula: &str| {
+            sheet.set(Coord(5, 0), parse_formula(formula).ok().unwrap());
+            sheet.value(Coord(5, 0))
+        };
+        let number = |x: f64| FormulaAtom::Number(x);
+        assert_eq!(number(40.0), *value("sumif(A1:A4, \"North\", C1:C4)").ok().unwrap());
+        assert_eq!(number(90.0), *value("sumif(C1:C4, \">=20\")").ok().unwrap());
+        assert_eq!(number(2.0), *value("countif(B1:B4, \"ap*\")").ok().unwrap());
+        assert_eq!(number(3.0), *value("countif(A1:A4, \"<>east\")").ok().unwrap());
+        assert_eq!(number(25.0), *value("averageif(B1:B4, \"pears\", C1:C4)").ok().unwrap());
+        assert_eq!(number(30.0), *value("sumifs(C1:C4, A1:A4, \"north\", B1:B4, \"p?ars\")").ok().unwrap());
+        assert_eq!(number(1.0), *value("countifs(A1:A4, \"north\", C1:C4, \"<20\")").ok().unwrap());
+        assert_eq!(number(30.0), *value("averageifs(C1:C4, C1:C4, \">10\", B1:B4, \"<>apples\")").ok().unwrap());
+
+        assert!(match value("sumif(A1:A4, \"north\", C1:C3)") { Err(FormulaErr::Type(_)) => true, _ => false });
+        assert!(match value("sumifs(C1:C4, A1:A3, \"north\")") { Err(FormulaErr::Type(_)) => true, _ => false });
+        assert!(match value("averageif(A1:A4, \"west\", C1:C4)") { Err(FormulaErr::Num) => true, _ => false });
+    }
+
+    #[test]
+    fn test_array() {
+        let f = parse_formula("{1, 2, 3; \"a\"}").ok().unwrap();
+        let n = |x: f64| Box::new(FormulaAtom::Number(x));
+        let empty = Box::new(FormulaAtom::Empty);
+        assert_eq!(Formula::Atom(FormulaAtom::Array(Table{cols: 3, rows: 2, cells: vec![
+            n(1.0), n(2.0), n(3.0), Box::new(FormulaAtom::String("a".to_string())), empty.clone(), empty,
+        ]})), f);
+        assert_eq!("{1, 2, 3; \"a\", , }", format_formula(&f).as_str());
+        assert!(parse_formula("{}").is_err());
+        assert!(parse_formula("{A1}").is_err());
+    }
+
+    #[test]
+    fn test_spill() {
+        let mut sheet = Sheet::new();
+        for row in 0 .. 3 {
+            sheet.set(Coord(0, row), Formula::Atom(FormulaAtom::Number(row as f64 + 1.0)));
+        }
+        sheet.set(Coord(1, 0), parse_formula("mul(A1:A3, {10, 100})").ok().unwrap());
+        let number = |x: f64| FormulaAtom::Number(x);
+        assert_eq!(number(10.0), *sheet.value(Coord(1, 0)).ok().unwrap());
+        assert_eq!(number(200.0), *sheet.value(Coord(2, 1)).ok().unwrap());
+        assert_eq!(number(30.0), *sheet.value(Coord(1, 2)).ok().unwrap());
+        assert_eq!(vec![(Coord(1, 0), Coord(2, 2))], sheet.spill_areas());
+
+        // Ranges and single arguments see the spilled values.
+        sheet.set(Coord(4, 0), parse_formula("add(B1:C3)").ok().unwrap());
+        assert_eq!(number(660.0), *sheet.value(Coord(4, 0)).ok().unwrap());
+        sheet.set(Coord(4, 1), parse_formula("add(C2, 1)").ok().unwrap());
+        assert_eq!(number(201.0), *sheet.value(Coord(4, 1)).ok().unwrap());
+
+        sheet.set(Coord(2, 2), Formula::Atom(FormulaAtom::Number(0.0)));
+        assert!(match sheet.value(Coord(1, 0)) { Err(FormulaErr::Spill) => true, _ => false });
+        assert_eq!(FormulaAtom::Empty, *sheet.value(Coord(2, 1)).ok().unwrap());
+        assert!(match sheet.value(Coord(4, 0)) { Err(FormulaErr::Spill) => true, _ => false });
+
+        sheet.set(Coord(3, 0), parse_formula("add(A1:A3, {1, 2; 3, 4})").ok().unwrap());
+        assert!(match sheet.value(Coord(3, 0)) { Err(FormulaErr::Type(_)) => true, _ => false });
+        sheet.set(Coord(3, 0), parse_formula("add(A1:A3, {1; 2; 3})").ok().unwrap());
+        assert_eq!(number(6.0), *sheet.value(Coord(3, 2)).ok().unwrap());
+
+        // Ranges go cell by cell only with ranges of the same shape, and
+        // are otherwise added up with the other arguments.
+        sheet.set(Coord(5, 0), parse_formula("add(A1:A3, 1)").ok().unwrap());
+        assert_eq!(number(7.0), *sheet.value(Coord(5, 0)).ok().unwrap());
+        assert_eq!(FormulaAtom::Empty, *sheet.value(Coord(5, 1)).ok().unwrap());
+        sheet.set(Coord(6, 0), parse_formula("add(A1:A2, A1:A3)").ok().unwrap());
+        assert_eq!(number(9.0), *sheet.value(Coord(6, 0)).ok().unwrap());
+        sheet.set(Coord(7, 0), parse_formula("mul(A1:A3, A1:A3)").ok().unwrap());
+        assert_eq!(number(9.0), *sheet.value(Coord(7, 2)).ok().unwrap());
+    }
+
+    #[test]
+    fn test_array_functions() {
+        let mut sheet = Sheet::new();
+        let rows = [("pears", 3.0, 1.0), ("apples", 5.0, 0.0), ("Pears", 2.0, 1.0), ("figs", 5.0, 1.0)];
+        for (row, &(fruit, amount, keep)) in rows.iter().enumerate() {
+            sheet.set(Coord(0, row), Formula::Atom(FormulaAtom::String(fruit.to_string())));
+            sheet.set(Coord(1, row), Formula::Atom(FormulaAtom::Number(amount)));
+            sheet.set(Coord(2, row), Formula::Atom(FormulaAtom::Number(keep)));
+        }
+        // The values the formula spills, written as an array literal.
+        let mut array = |formula: &str| {
+            let f = parse_formula(formula).ok().unwrap();
+            assert_eq!(formula, format_formula(&f).as_str());
+            sheet.set(Coord(5, 0), f);
+            let first = try!(sheet.value(Coord(5, 0)));
+            let areas = sheet.spill_areas();
+            let Coord(cols, rows) = match areas.first() {
+                Some(&(_, to)) => to,
+                None => { return Ok(format_formula(&Formula::Atom((*first).clone()))); },
+            };
+            let mut cells = vec![];
+            for row in 0 .. rows + 1 {
+                for col in 5 .. cols + 1 {
+                    cells.push(sheet.value(Coord(col, row)).ok().unwrap());
+                }
+            }
+            let table = Table{cols: cols - 4, rows: rows + 1, cells: cells};
+            Ok(format_formula(&Formula::Atom(FormulaAtom::Array(table))))
+        };
+        assert_eq!("{\"pears\", 3; \"Pears\", 2; \"figs\", 5}", array("filter(A1:B4, C1:C4)").ok().unwrap());
+        assert_eq!("{\"pears\"; \"figs\"}", array("filter(A1:A4, {1; 0; 0; 1})").ok().unwrap());
+        assert_eq!("\"none\"", array("filter(A1:A4, {0; 0; 0; 0}, \"none\")").ok().unwrap());
+        assert!(match array("filter(A1:A4, C1:C3)") { Err(FormulaErr::Type(_)) => true, _ => false });
+        assert_eq!("{\"Pears\", 2; \"pears\", 3; \"apples\", 5; \"figs\", 5}", array("sort(A1:B4, 2)").ok().unwrap());
+        assert_eq!("{\"pears\"; \"Pears\"; \"figs\"; \"apples\"}", array("sort(A1:A4, 1, -1)").ok().unwrap());
+        assert_eq!("{\"pears\"; \"apples\"; \"figs\"}", array("unique(A1:A4)").ok().unwrap());
+        assert_eq!("{\"apples\"; \"figs\"}", array("unique(A1:A4, 0, 1)").ok().unwrap());
+        assert_eq!("{1, 0, 1, 1}", array("transpose(C1:C4)").ok().unwrap());
+        assert_eq!("{10, 15, 20; 25, 30, 35}", array("sequence(2, 3, 10, 5)").ok().unwrap());
+        assert_eq!("{1; 0}", array("sort(unique(C1:C4), 1, -1)").ok().unwrap());
+
+        // The results spill, and ranges see them.
+        sheet.set(Coord(5, 0), parse_formula("sequence(3)").ok().unwrap());
+        sheet.set(Coord(6, 0), parse_formula("add(F1:F3)").ok().unwrap());
+        assert_eq!(FormulaAtom::Number(6.0), *sheet.value(Coord(6, 0)).ok().unwrap());
+    }
+
+    #[test]
+    fn test_custom_functions() {
+        use functions::registry::{Function, Registry};
+
+        let mut registry = Registry::new();
+        registry.register(Function::new("log2", 1, 1, |atoms| match *atoms[0] {
+            FormulaAtom::Number(x) => Ok(Box::new(FormulaAtom::Number(x.log2()))),
+            _ => Err(FormulaErr::Type("Number")),
+        }));
+        registry.register(Function::new("Count_Text", 0, ::std::usize::MAX, |atoms| {
+            let n = atoms.iter().filter(|x| match ***x { FormulaAtom::String(_) => true, _ => false }).count();
+            Ok(Box::new(FormulaAtom::Number(n as f64)))
+        }));
+        let mut sheet = Sheet::new();
+        sheet.set_functions(Box::new(registry));
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::String("a".to_string())));
+        sheet.set(Coord(0, 1), Formula::Atom(FormulaAtom::Number(8.0)));
+
+        let mut value = |formula: &str| {
+            let f = parse_formula(formula).ok().unwrap();
+            assert_eq!(formula, format_formula(&f).as_str());
+            sheet.set(Coord(5, 0), f);
+            sheet.value(Coord(5, 0))
+        };
+        assert_eq!(FormulaAtom::Number(3.0), *value("log2(A2)").ok().unwrap());
+        assert_eq!(FormulaAtom::Number(4.0), *value("add(log2(16), 0)").ok().unwrap());
+        assert_eq!(FormulaAtom::Number(2.0), *value("Count_Text(A1:A2, \"b\")").ok().unwrap());
+        assert!(match value("log2(A1)") { Err(FormulaErr::Type(_)) => true, _ => false });
+        assert!(match value("log2(1, 2)") { Err(FormulaErr::Arity(1)) => true, _ => false });
+        assert!(match value("log3(8)") { Err(FormulaErr::Name(ref x)) => x == "log3", _ => false });
+        // Built-in functions come first.
+        assert_eq!(Formula::Op(FormulaOp::Log, vec![Formula::Atom(FormulaAtom::Number(8.0))]),
+                   parse_formula("log(8)").ok().unwrap());
+    }
+
+    #[test]
+    fn test_function_names() {
+        use functions::registry::{Function, FunctionRegistry, builtin};
+
+        // Built-in functions under other names.
+        struct German;
+        impl FunctionRegistry for German {
+            fn function(&self, _: &str) -> Option<&Function> {
+                None
+            }
+
+            fn op(&self, name: &str) -> Option<FormulaOp> {
+                match name {
+                    "summe" => Some(FormulaOp::Add),
+                    "wenn" => Some(FormulaOp::If),
+                    _ => builtin(name),
+                }
+            }
+        }
+        let german = |s: &str| parse_formula_with(s, &NumberLocale::english(), &German).ok().unwrap();
+        assert_eq!(parse_formula("if(A1, add(1, B2), 0)").ok().unwrap(), german("wenn(A1, summe(1, B2), 0)"));
+        assert_eq!(parse_formula("Sheet2!A1").ok().unwrap(), german("Sheet2!A1"));
+        assert_eq!(Formula::Op(FormulaOp::Custom("summe".to_string()), vec![]), parse_formula("summe()").ok().unwrap());
+    }
+
+    #[test]
+    fn test_volatile_functions() {
+        use functions::registry::{Function, Registry};
+
+        let mut registry = Registry::new();
+        let mut tick = Function::new("tick", 0, 0, |_| Ok(Box::new(FormulaAtom::Number(1.0))));
+        tick.volatile = true;
+        registry.register(tick);
+        let mut sheet = Sheet::new();
+        sheet.set_functions(Box::new(registry));
+        sheet.set(Coord(0, 0), parse_formula("add(tick(), 1)").ok().unwrap());
+        sheet.set(Coord(0, 1), parse_formula("add(A1, 1)").ok().unwrap());
+        sheet.set(Coord(1, 0), parse_formula("sequence(2, 1, rand())").ok().unwrap());
+        assert_eq!(vec![(Coord(0, 0), Coord(0, 0)), (Coord(1, 0), Coord(1, 0)), (Coord(1, 0), Coord(1, 1))], {
+            let mut areas = sheet.volatile_areas(Context{book: &sheet, sheet: 0, cache: None});
+            areas.sort_by(|&(Coord(c1, r1), Coord(c2, r2)), &(Coord(c3, r3), Coord(c4, r4))| {
+                (c1, r1, c2, r2).cmp(&(c3, r3, c4, r4))
+            });
+            areas
+        });
+
+        let selection = sheet.select(Coord(0, 0), Coord(0, 1));
+        let rx = &selection.values;
+        assert_eq!(Coord(0, 0), rx.recv().unwrap().0);
+        assert_eq!(Coord(0, 1), rx.recv().unwrap().0);
+        // A2 reads A1, so it's sent again too.
+        sheet.refresh_volatile();
+        assert_eq!(Coord(0, 0), rx.recv().unwrap().0);
+        assert_eq!(Coord(0, 1), rx.recv().unwrap().0);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_parallel_values() {
+        use sheet::{Book, cells_between};
+
+        let mut sheet = Sheet::new();
+        for row in 0 .. 100 {
+            let n = row + 1;
+            sheet.set(Coord(0, row), Formula::Atom(FormulaAtom::Number(n as f64)));
+            let sum = if row == 0 { "A1".to_string() } else { format!("add(A{}, B{})", n, n - 1) };
+            sheet.set(Coord(1, row), parse_formula(&sum).ok().unwrap());
+            sheet.set(Coord(2, row), parse_formula(&format!("if(sub(A{}, 50), div(B{}, sub(A{}, 50)), C{})", n, n, n, n)).ok().unwrap());
+        }
+        sheet.set(Coord(3, 0), parse_formula("sort(A1:A100, 1, -1)").ok().unwrap());
+        // E1 reads what D1 spills over, F1 and G1 read each other, and H1
+        // reads them.
+        sheet.set(Coord(4, 0), parse_formula("add(D1:D100)").ok().unwrap());
+        sheet.set(Coord(5, 0), parse_formula("add(G1, 1)").ok().unwrap());
+        sheet.set(Coord(6, 0), parse_formula("add(F1, 1)").ok().unwrap());
+        sheet.set(Coord(7, 0), parse_formula("add(F1, E1)").ok().unwrap());
+
+        let coords = cells_between(Coord(0, 0), Coord(7, 99));
+        let serial: Vec<_> = coords.iter().map(|&coord| sheet.value(coord)).collect();
+        sheet.set_threads(1);
+        let scheduled = sheet.values_in(&coords, Context{book: &sheet, sheet: 0, cache: None});
+        assert_eq!(format!("{:?}", serial), format!("{:?}", scheduled));
+        sheet.set_threads(8);
+        assert_eq!(8, sheet.threads());
+        let parallel = sheet.values_in(&coords, Context{book: &sheet, sheet: 0, cache: None});
+        assert_eq!(format!("{:?}", serial), format!("{:?}", parallel));
+        assert_eq!(FormulaAtom::Number(5050.0), **serial[400].as_ref().ok().unwrap());
+        assert_eq!(FormulaAtom::Number(5050.0), **serial[199].as_ref().ok().unwrap());
+        assert!(serial[249].is_err());
+        assert_eq!(FormulaAtom::Number(1.0), **serial[399].as_ref().ok().unwrap());
+
+        // What rand draws depends on the order cells are evaluated in.
+        sheet.set(Coord(8, 0), parse_formula("rand()").ok().unwrap());
+        assert_eq!(1, sheet.threads());
+    }
+
+    #[test]
+    fn test_parallel_calls() {
+        use sheet::cells_between;
+
+        // Calls go deep, so this needs more stack than tests get.
+        let test = ::std::thread::Builder::new().stack_size(64 * 1024 * 1024).spawn(|| {
+            let mut sheet = Sheet::new();
+            for row in 0 .. 40 {
+                sheet.set(Coord(0, row), Formula::Atom(FormulaAtom::Number(row as f64)));
+            }
+            // A1 calls a lambda 60 deep, and so does B1 before reading A1,
+            // which it then can't.
+            sheet.set(Coord(0, 0), parse_formula("let(f, lambda(g, n, if(n, g(g, sub(n, 1)), A40)), f(f, 60))").ok().unwrap());
+            sheet.set(Coord(1, 0), parse_formula("let(f, lambda(g, n, if(n, g(g, sub(n, 1)), A1)), f(f, 60))").ok().unwrap());
+
+            let coords = cells_between(Coord(0, 0), Coord(1, 39));
+            let values = sheet.values_in(&coords, Context{book: &sheet, sheet: 0, cache: None});
+            assert_eq!(FormulaAtom::Number(39.0), **values[0].as_ref().ok().unwrap());
+            assert!(values[40].is_err());
+            let serial: Vec<_> = coords.iter().map(|&coord| sheet.value(coord)).collect();
+            assert_eq!(format!("{:?}", serial), format!("{:?}", values));
+        });
+        assert!(test.unwrap().join().is_ok());
+    }
+
+    #[test]
+    fn test_let_and_lambda() {
+        let mut sheet = Sheet::new();
+        for row in 0 .. 3 {
+            sheet.set(Coord(0, row), Formula::Atom(FormulaAtom::Number(row as f64 + 1.0)));
+        }
+        let mut value = |formula: &str| {
+            let f = parse_formula(formula).ok().unwrap();
+            assert_eq!(formula, format_formula(&f).as_str());
+            sheet.set(Coord(5, 0), f);
+            sheet.value(Coord(5, 0))
+        };
+        let number = |x: f64| FormulaAtom::Number(x);
+        assert_eq!(number(6.0), *value("let(x, 2, y, mul(x, 3), y)").ok().unwrap());
+        assert_eq!(number(12.0), *value("let(r, A1:A3, mul(add(r), 2))").ok().unwrap());
+        assert_eq!(number(7.0), *value("let(x, 1, add(let(x, 5, x), x, x))").ok().unwrap());
+        assert_eq!(number(8.0), *value("lambda(x, mul(x, 2))(4)").ok().unwrap());
+        assert_eq!(number(13.0), *value("let(k, 10, f, lambda(x, add(x, k)), k, 100, f(3))").ok().unwrap());
+        assert_eq!(number(3.0), *value("let(twice, lambda(f, x, f(f(x))), twice(lambda(x, add(x, 1)), 1))").ok().unwrap());
+        assert_eq!(number(1.0), *value("if(sub(A2, 2), 0, 1)").ok().unwrap());
+        assert_eq!(FormulaAtom::Empty, *value("if(0, 1)").ok().unwrap());
+
+        assert!(match value("let(x, 1, y)") { Err(FormulaErr::Name(ref x)) => x == "y", _ => false });
+        assert!(match value("let(A1, 1, 2)") { Err(FormulaErr::Type(_)) => true, _ => false });
+        assert!(match value("let(x, 1)") { Err(FormulaErr::Arity(_)) => true, _ => false });
+        assert!(match value("lambda(x, x, 1)(1, 2)") { Err(FormulaErr::Type(_)) => true, _ => false });
+        assert!(match value("lambda(x, x)(1, 2)") { Err(FormulaErr::Arity(1)) => true, _ => false });
+        assert!(match value("let(f, 1, f(2))") { Err(FormulaErr::Type(_)) => true, _ => false });
+
+        // A lambda's body sees the names bound outside it, unless its
+        // parameters bind them again.
+        assert_eq!(number(1.0), *value("let(x, 1, lambda(y, x)(0))").ok().unwrap());
+        assert_eq!(number(5.0), *value("let(x, 1, lambda(x, x)(5))").ok().unwrap());
+
+        let may_spill = |formula: &str| parse_formula(formula).ok().unwrap().may_spill();
+        assert!(!may_spill("let(x, 2, mul(x, 3))"));
+        assert!(!may_spill("lambda(x, mul(x, 2))(4)"));
+        assert!(may_spill("let(x, sequence(3), x)"));
+        assert!(may_spill("let(r, A1:A3, mul(r, 2))"));
+        assert!(may_spill("lambda(n, sequence(n))(2)"));
+        assert!(may_spill("let(f, lambda(n, sequence(n)), f(2))"));
+    }
+
     #[test]
     fn test_ref() {
         let mut r = parse_formula("C4").ok().unwrap();
-        assert_eq!(Formula::Ref(Coord(2, 3)), r);
+        assert_eq!(Formula::Ref(Coord(2, 3), Anchor(false, false)), r);
         assert_eq!("C4", format_formula(&r).as_str());
 
         r = parse_formula("AAB45").ok().unwrap();
-        assert_eq!(Formula::Ref(Coord(703, 44)), r);
-        // assert_eq!("AAB45", format_formula(&r).as_str());
+        assert_eq!(Formula::Ref(Coord(703, 44), Anchor(false, false)), r);
+        assert_eq!("AAB45", format_formula(&r).as_str());
+    }
+
+    #[test]
+    fn test_anchored_ref() {
+        let mut r = parse_formula("$C$4").ok().unwrap();
+        assert_eq!(Formula::Ref(Coord(2, 3), Anchor(true, true)), r);
+        assert_eq!("$C$4", format_formula(&r).as_str());
+
+        r = parse_formula("C$4").ok().unwrap();
+        assert_eq!(Formula::Ref(Coord(2, 3), Anchor(false, true)), r);
+        assert_eq!("C$4", format_formula(&r).as_str());
+
+        r = parse_formula("$C4").ok().unwrap();
+        assert_eq!(Formula::Ref(Coord(2, 3), Anchor(true, false)), r);
+        assert_eq!("$C4", format_formula(&r).as_str());
+
+        assert!(parse_formula("$$C4").is_err());
+        assert!(parse_formula("C4$").is_err());
+    }
+
+    #[test]
+    fn test_invalid_ref() {
+        let r = parse_formula("add(#REF!, $A1)").ok().unwrap();
+        assert_eq!(Formula::Op(FormulaOp::Add, vec![
+            Formula::InvalidRef,
+            Formula::Ref(Coord(0, 0), Anchor(true, false)),
+        ]), r);
+        assert_eq!("add(#REF!, $A1)", format_formula(&r).as_str());
+    }
+
+    #[test]
+    fn test_range() {
+        let mut r = parse_formula("A1:$B$3").ok().unwrap();
+        assert_eq!(Formula::Range(Coord(0, 0), Anchor(false, false), Coord(1, 2), Anchor(true, true)), r);
+        assert_eq!("A1:$B$3", format_formula(&r).as_str());
+
+        r = parse_formula("B$1:$A3").ok().unwrap();
+        assert_eq!("$A$1:B3", format_formula(&r).as_str());
+
+        let formula = parse_formula("avg(A1:B2, 3.0)").ok().unwrap();
+        let mut sheet = Sheet::new();
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        sheet.set(Coord(1, 1), Formula::Atom(FormulaAtom::Number(2.0)));
+        sheet.set(Coord(5, 5), formula);
+        assert_eq!(FormulaAtom::Number(2.0), *sheet.value(Coord(5, 5)).ok().unwrap());
+    }
+
+    #[test]
+    fn test_sheet_ref() {
+        let mut r = parse_formula("Sheet2!B$3").ok().unwrap();
+        assert_eq!(Formula::SheetRef("Sheet2".to_string(), Box::new(
+            Formula::Ref(Coord(1, 2), Anchor(false, true)))), r);
+        assert_eq!("Sheet2!B$3", format_formula(&r).as_str());
+
+        r = parse_formula("avg('Q1 sales'!A1:A3, 1.0)").ok().unwrap();
+        assert_eq!(Formula::Op(FormulaOp::Avg, vec![
+            Formula::SheetRef("Q1 sales".to_string(), Box::new(
+                Formula::Range(Coord(0, 0), Anchor(false, false), Coord(0, 2), Anchor(false, false)))),
+            Formula::Atom(FormulaAtom::Number(1.0)),
+        ]), r);
+        assert_eq!("avg('Q1 sales'!A1:A3, 1)", format_formula(&r).as_str());
+
+        assert!(parse_formula("Sheet2!").is_err());
+        assert!(parse_formula("''!A1").is_err());
+    }
+
+    #[test]
+    fn test_name() {
+        let mut r = parse_formula("TaxRate").ok().unwrap();
+        assert_eq!(Formula::Name("TaxRate".to_string()), r);
+        assert_eq!("TaxRate", format_formula(&r).as_str());
+
+        r = parse_formula("add(A1x, sales.q1, A1, add)").ok().unwrap();
+        assert_eq!(Formula::Op(FormulaOp::Add, vec![
+            Formula::Name("A1x".to_string()),
+            Formula::Name("sales.q1".to_string()),
+            Formula::Ref(Coord(0, 0), Anchor(false, false)),
+            Formula::Name("add".to_string()),
+        ]), r);
+
+        assert!(parse_formula("1abc").is_err());
     }
 
     #[test]
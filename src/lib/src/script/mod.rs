@@ -0,0 +1,755 @@
+//! Scripts for automating work on a sheet, like cleaning up data or filling
+//! in reports:
+//!
+//! ```text
+//! # Trims the names in the first column and totals the second.
+//! let total = 0
+//! for row in first_row .. last_row + 1 {
+//!     set(cell(1, row), trim(value(cell(1, row))))
+//!     total = total + value(cell(2, row))
+//! }
+//! set(cell(2, last_row + 1), total)
+//! ```
+//!
+//! Values are those of cells: numbers, strings, dates and so on, where
+//! comparisons give 1 or 0, and 0 and empty values are false. `+` joins
+//! strings. `for` counts from the first number up to, but not including,
+//! the second. `first_row`, `last_row`, `first_col` and `last_col` are the
+//! bounds of the selection the script runs on, counted from 1.
+//!
+//! Scripts are sandboxed: they only reach the sheet through a `Host`, and
+//! stop with an error once they take more steps or time than their
+//! `Limits` allow, or build a string longer than `MAX_STRING_LEN`.
+
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use conditional::compare;
+use format::format_default;
+use parser::{NumberLocale, parse_formula_in};
+use sheet::{Anchor, Coord, FillDirection, Formula, FormulaAtom, FormulaErr, Value};
+use validation::Validity;
+use workbook::{Workbook, WorkbookErr};
+
+peg! grammar(r#"
+use super::{Stmt, Expr, BinOp, UnOp, chain, unary};
+use sheet::FormulaAtom;
+
+#[pub]
+script -> Vec<Stmt>
+    = ws s:statements ws { s }
+
+statements -> Vec<Stmt>
+    = statement ** sep
+
+sep -> ()
+    = sp [\n;] ws { }
+
+sp -> ()
+    = [ \t]* ("#" [^\n]*)? { }
+
+ws -> ()
+    = (sp [\n;])* sp { }
+
+block -> Vec<Stmt>
+    = "{" ws s:statements ws "}" { s }
+
+statement -> Stmt
+    = "let" !ident_char sp n:ident sp "=" sp e:expr { Stmt::Let(n, e) }
+    / if_statement
+    / "while" !ident_char sp c:expr sp b:block { Stmt::While(c, b) }
+    / "for" !ident_char sp n:ident sp "in" !ident_char sp a:expr sp ".." sp b:expr sp body:block {
+        Stmt::For(n, a, b, body)
+    }
+    / n:ident sp "=" !"=" sp e:expr { Stmt::Assign(n, e) }
+    / e:expr { Stmt::Expr(e) }
+
+if_statement -> Stmt
+    = "if" !ident_char sp c:expr sp t:block rest:else_if* e:else_part? {
+        let mut branches = vec![(c, t)];
+        branches.extend(rest);
+        Stmt::If(branches, e.unwrap_or(vec![]))
+    }
+
+else_if -> (Expr, Vec<Stmt>)
+    = ws "else" !ident_char sp "if" !ident_char sp c:expr sp b:block { (c, b) }
+
+else_part -> Vec<Stmt>
+    = ws "else" !ident_char sp b:block { b }
+
+expr -> Expr
+    = l:conjunction rest:disjunct* { chain(l, rest) }
+
+disjunct -> (BinOp, Expr)
+    = sp "or" !ident_char sp e:conjunction { (BinOp::Or, e) }
+
+conjunction -> Expr
+    = l:comparison rest:conjunct* { chain(l, rest) }
+
+conjunct -> (BinOp, Expr)
+    = sp "and" !ident_char sp e:comparison { (BinOp::And, e) }
+
+comparison -> Expr
+    = l:sum rest:compared* { chain(l, rest) }
+
+compared -> (BinOp, Expr)
+    = sp o:compare_op sp e:sum { (o, e) }
+
+compare_op -> BinOp
+    = "==" { BinOp::Eq }
+    / "!=" { BinOp::Ne }
+    / "<=" { BinOp::Le }
+    / ">=" { BinOp::Ge }
+    / "<" { BinOp::Lt }
+    / ">" { BinOp::Gt }
+
+sum -> Expr
+    = l:product rest:summand* { chain(l, rest) }
+
+summand -> (BinOp, Expr)
+    = sp o:sum_op sp e:product { (o, e) }
+
+sum_op -> BinOp
+    = "+" { BinOp::Add }
+    / "-" { BinOp::Sub }
+
+product -> Expr
+    = l:unary rest:factor* { chain(l, rest) }
+
+factor -> (BinOp, Expr)
+    = sp o:product_op sp e:unary { (o, e) }
+
+product_op -> BinOp
+    = "*" { BinOp::Mul }
+    / "/" { BinOp::Div }
+    / "%" { BinOp::Mod }
+
+unary -> Expr
+    = ops:unary_op* e:primary { unary(ops, e) }
+
+unary_op -> UnOp
+    = "-" sp { UnOp::Neg }
+    / "not" !ident_char sp { UnOp::Not }
+
+primary -> Expr
+    = number
+    / string
+    / "(" sp e:expr sp ")" { e }
+    / call
+    / n:ident { Expr::Var(n) }
+
+call -> Expr
+    = n:ident "(" sp args:expr ** comma sp ")" { Expr::Call(n, args) }
+
+comma -> ()
+    = sp "," sp { }
+
+number -> Expr
+    = [0-9]+ ("." [0-9]+)? { Expr::Atom(FormulaAtom::Number(match_str.parse().unwrap())) }
+
+string -> Expr
+    = "\"" s:inside_str "\"" { s }
+
+inside_str -> Expr
+    = [^"\n]* { Expr::Atom(FormulaAtom::String(match_str.to_string())) }
+
+ident -> String
+    = !keyword [A-Za-z_] ident_char* { match_str.to_string() }
+
+keyword -> ()
+    = ("let" / "if" / "else" / "while" / "for" / "in" / "and" / "or" / "not") !ident_char { }
+
+ident_char -> ()
+    = [A-Za-z0-9_] { }
+"#);
+
+/// How deep parentheses and blocks can nest.
+const MAX_NESTING: usize = 64;
+
+/// The longest string, in bytes, that scripts can build.
+pub const MAX_STRING_LEN: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    /// Declares a variable in the current block.
+    Let(String, Expr),
+    /// Changes a declared variable.
+    Assign(String, Expr),
+    /// The conditions of an `if` and its `else if`s, with their blocks, and
+    /// the block of its `else`.
+    If(Vec<(Expr, Vec<Stmt>)>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    For(String, Expr, Expr, Vec<Stmt>),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Atom(FormulaAtom),
+    Var(String),
+    Call(String, Vec<Expr>),
+    /// Operators before an expression, applied from the innermost out.
+    Unary(Vec<UnOp>, Box<Expr>),
+    /// Operations with the same precedence, applied from the left.
+    Binary(Box<Expr>, Vec<(BinOp, Expr)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp {
+    Neg, Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add, Sub, Mul, Div, Mod,
+    Eq, Ne, Lt, Le, Gt, Ge,
+    And, Or,
+}
+
+#[derive(Debug)]
+pub enum ScriptErr {
+    Syntax(String),
+    /// Parentheses or blocks nested more than `MAX_NESTING` deep.
+    TooDeep,
+    /// A variable or function that doesn't exist.
+    Undefined(String),
+    Type(&'static str),
+    /// A function called with the wrong number of arguments.
+    Arity(String),
+    /// A string that isn't a cell, like `A0`.
+    BadCell(String),
+    /// Division by zero, or another result that isn't a number.
+    Num,
+    /// A string longer than `MAX_STRING_LEN`.
+    TooLong,
+    /// A cell whose value is an error.
+    Formula(FormulaErr),
+    Workbook(WorkbookErr),
+    /// More steps than the limits allow.
+    Steps,
+    /// More time than the limits allow.
+    Time,
+}
+
+/// How far a script can go before it's stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Statements, expressions and loop iterations evaluated, and cells
+    /// written.
+    pub steps: u64,
+    pub millis: u64,
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits{steps: 1000000, millis: 5000}
+    }
+}
+
+/// What scripts work on: a sheet, and the cells selected in it.
+pub trait Host {
+    fn value(&self, coord: Coord) -> Value;
+    fn set(&mut self, coord: Coord, formula: Formula) -> Validity;
+    fn fill(&mut self, from: Coord, to: Coord, direction: FillDirection);
+    fn add_sheet(&mut self, name: &str) -> Result<usize, WorkbookErr>;
+    /// The area the array given by the formula of a cell spills over, if
+    /// any.
+    fn spill_area(&self, coord: Coord) -> Option<(Coord, Coord)>;
+    /// How numbers on their own are written in formulas set by the script.
+    fn number_locale(&self) -> NumberLocale;
+    /// The top left and bottom right cells of the selection.
+    fn selection(&self) -> (Coord, Coord);
+    /// Shows a message from the script.
+    fn print(&mut self, text: &str);
+}
+
+/// Runs scripts on a sheet of a workbook, sending their messages to
+/// `messages`.
+pub struct WorkbookHost<'a> {
+    pub book: &'a mut Workbook,
+    pub sheet: usize,
+    pub selection: (Coord, Coord),
+    pub messages: Sender<String>,
+}
+
+impl<'a> Host for WorkbookHost<'a> {
+    fn value(&self, coord: Coord) -> Value {
+        self.book.value(self.sheet, coord)
+    }
+
+    fn set(&mut self, coord: Coord, formula: Formula) -> Validity {
+        self.book.set(self.sheet, coord, formula)
+    }
+
+    fn fill(&mut self, from: Coord, to: Coord, direction: FillDirection) {
+        self.book.fill(self.sheet, from, to, direction);
+    }
+
+    fn add_sheet(&mut self, name: &str) -> Result<usize, WorkbookErr> {
+        self.book.add_sheet(name)
+    }
+
+    fn spill_area(&self, coord: Coord) -> Option<(Coord, Coord)> {
+        self.book.spill_areas(self.sheet).into_iter().find(|&(from, _)| from == coord)
+    }
+
+    fn number_locale(&self) -> NumberLocale {
+        self.book.number_locale()
+    }
+
+    fn selection(&self) -> (Coord, Coord) {
+        self.selection
+    }
+
+    fn print(&mut self, text: &str) {
+        let _ = self.messages.send(text.to_string());
+    }
+}
+
+pub fn parse(source: &str) -> Result<Vec<Stmt>, ScriptErr> {
+    // The parser, and running the script, only recurse on parentheses,
+    // calls and blocks; chains of operators and of `else if`s are read into
+    // lists.
+    let mut depth = 0;
+    let mut quoted = false;
+    for c in source.chars() {
+        match c {
+            '"' => { quoted = !quoted; },
+            '\n' => { quoted = false; },
+            '(' | '{' if !quoted => {
+                depth += 1;
+                if depth > MAX_NESTING {
+                    return Err(ScriptErr::TooDeep);
+                }
+            },
+            ')' | '}' if !quoted && depth > 0 => { depth -= 1; },
+            _ => {},
+        }
+    }
+    grammar::script(source).map_err(|e| ScriptErr::Syntax(format!("{}", e)))
+}
+
+/// Runs a parsed script.
+pub fn run(script: &[Stmt], host: &mut Host, limits: Limits) -> Result<(), ScriptErr> {
+    let (Coord(first_col, first_row), Coord(last_col, last_row)) = host.selection();
+    let mut globals = HashMap::new();
+    globals.insert("first_row".to_string(), FormulaAtom::Number(first_row as f64 + 1.0));
+    globals.insert("last_row".to_string(), FormulaAtom::Number(last_row as f64 + 1.0));
+    globals.insert("first_col".to_string(), FormulaAtom::Number(first_col as f64 + 1.0));
+    globals.insert("last_col".to_string(), FormulaAtom::Number(last_col as f64 + 1.0));
+
+    let mut run = Run{
+        host: host,
+        limits: limits,
+        steps: 0,
+        deadline: ::time::precise_time_ns() + limits.millis * 1000000,
+        scopes: vec![globals],
+    };
+    run.block(script)
+}
+
+/// Builds a chain of binary operations with the same precedence.
+fn chain(first: Expr, rest: Vec<(BinOp, Expr)>) -> Expr {
+    if rest.is_empty() { first } else { Expr::Binary(Box::new(first), rest) }
+}
+
+/// Puts unary operators before an expression.
+fn unary(ops: Vec<UnOp>, x: Expr) -> Expr {
+    if ops.is_empty() { x } else { Expr::Unary(ops, Box::new(x)) }
+}
+
+/// A script being run, with the variables of each block it's in.
+struct Run<'a> {
+    host: &'a mut Host,
+    limits: Limits,
+    steps: u64,
+    deadline: u64,
+    scopes: Vec<HashMap<String, FormulaAtom>>,
+}
+
+impl<'a> Run<'a> {
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), ScriptErr> {
+        self.scopes.push(HashMap::new());
+        let ret = self.statements(stmts);
+        self.scopes.pop();
+        ret
+    }
+
+    fn statements(&mut self, stmts: &[Stmt]) -> Result<(), ScriptErr> {
+        for stmt in stmts {
+            try!(self.statement(stmt));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), ScriptErr> {
+        try!(self.step());
+        match *stmt {
+            Stmt::Let(ref name, ref e) => {
+                let x = try!(self.eval(e));
+                self.scopes.last_mut().unwrap().insert(name.clone(), x);
+            },
+            Stmt::Assign(ref name, ref e) => {
+                let x = try!(self.eval(e));
+                match self.scopes.iter_mut().rev().find(|scope| scope.contains_key(name)) {
+                    Some(scope) => { scope.insert(name.clone(), x); },
+                    None => { return Err(ScriptErr::Undefined(name.clone())); },
+                }
+            },
+            Stmt::If(ref branches, ref otherwise) => {
+                let mut taken = otherwise;
+                for &(ref condition, ref body) in branches {
+                    if truthy(&try!(self.eval(condition))) {
+                        taken = body;
+                        break;
+                    }
+                }
+                try!(self.block(taken));
+            },
+            Stmt::While(ref condition, ref body) => {
+                while truthy(&try!(self.eval(condition))) {
+                    try!(self.block(body));
+                }
+            },
+            Stmt::For(ref name, ref from, ref to, ref body) => {
+                let (mut i, to) = (try!(number(&try!(self.eval(from)))), try!(number(&try!(self.eval(to)))));
+                while i < to {
+                    try!(self.step());
+                    let mut scope = HashMap::new();
+                    scope.insert(name.clone(), FormulaAtom::Number(i));
+                    self.scopes.push(scope);
+                    let ret = self.statements(body);
+                    self.scopes.pop();
+                    try!(ret);
+                    i += 1.0;
+                }
+            },
+            Stmt::Expr(ref e) => { try!(self.eval(e)); },
+        }
+        Ok(())
+    }
+
+    /// Counts a step, failing if the script went past its limits.
+    fn step(&mut self) -> Result<(), ScriptErr> {
+        self.charge(1)
+    }
+
+    /// Counts `steps` steps at once, like one for each cell written.
+    fn charge(&mut self, steps: u64) -> Result<(), ScriptErr> {
+        let before = self.steps;
+        self.steps = self.steps.saturating_add(steps);
+        if self.steps > self.limits.steps {
+            return Err(ScriptErr::Steps);
+        }
+        // Reading the clock on every step would slow scripts down.
+        if self.steps / 1000 != before / 1000 && ::time::precise_time_ns() > self.deadline {
+            return Err(ScriptErr::Time);
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, e: &Expr) -> Result<FormulaAtom, ScriptErr> {
+        try!(self.step());
+        match *e {
+            Expr::Atom(ref x) => Ok(x.clone()),
+            Expr::Var(ref name) => match self.scopes.iter().rev().filter_map(|scope| scope.get(name)).next() {
+                Some(x) => Ok(x.clone()),
+                None => Err(ScriptErr::Undefined(name.clone())),
+            },
+            Expr::Unary(ref ops, ref x) => {
+                let mut ret = try!(self.eval(x));
+                for op in ops.iter().rev() {
+                    try!(self.step());
+                    ret = match *op {
+                        UnOp::Neg => FormulaAtom::Number(-try!(number(&ret))),
+                        UnOp::Not => boolean(!truthy(&ret)),
+                    };
+                }
+                Ok(ret)
+            },
+            Expr::Binary(ref first, ref rest) => {
+                let mut ret = try!(self.eval(first));
+                for &(op, ref x) in rest {
+                    let next = match op {
+                        BinOp::And => boolean(truthy(&ret) && truthy(&try!(self.eval(x)))),
+                        BinOp::Or => boolean(truthy(&ret) || truthy(&try!(self.eval(x)))),
+                        op => {
+                            let x = try!(self.eval(x));
+                            try!(binary(op, &ret, &x))
+                        },
+                    };
+                    ret = next;
+                }
+                Ok(ret)
+            },
+            Expr::Call(ref name, ref args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(try!(self.eval(arg)));
+                }
+                self.call(name, &values)
+            },
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[FormulaAtom]) -> Result<FormulaAtom, ScriptErr> {
+        let arity = match name {
+            "value" | "row" | "col" | "trim" | "upper" | "lower" | "len" | "text" | "number" |
+            "is_empty" | "is_number" | "add_sheet" | "print" => 1,
+            "set" | "set_formula" | "cell" | "fill_down" | "fill_right" => 2,
+            _ => { return Err(ScriptErr::Undefined(name.to_string())); },
+        };
+        if args.len() != arity {
+            return Err(ScriptErr::Arity(name.to_string()));
+        }
+
+        Ok(match name {
+            "value" => match self.host.value(try!(coord(&args[0]))) {
+                Ok(x) => *x,
+                Err(e) => { return Err(ScriptErr::Formula(e)); },
+            },
+            // Gives 0 if the cell's validation rejects the value.
+            "set" | "set_formula" => {
+                let formula = if name == "set" {
+                    Formula::Atom(args[1].clone())
+                } else {
+                    let text = try!(string(&args[1]));
+                    try!(parse_formula_in(text, &self.host.number_locale()).map_err(|e| ScriptErr::Syntax(format!("{}", e))))
+                };
+                let at = try!(coord(&args[0]));
+                let validity = self.host.set(at, formula);
+                // Arrays write every cell they spill over.
+                if let Some(area) = self.host.spill_area(at) {
+                    try!(self.charge(cells(area)));
+                }
+                match validity {
+                    Validity::Rejected(_) => boolean(false),
+                    _ => boolean(true),
+                }
+            },
+            "cell" => {
+                let (col, row) = (try!(number(&args[0])), try!(number(&args[1])));
+                if col < 1.0 || row < 1.0 {
+                    return Err(ScriptErr::BadCell(format!("{}, {}", col, row)));
+                }
+                let coord = Coord(col as usize - 1, row as usize - 1);
+                FormulaAtom::String(coord.format_anchored(Anchor(false, false)))
+            },
+            "row" => { let Coord(_, row) = try!(coord(&args[0])); FormulaAtom::Number(row as f64 + 1.0) },
+            "col" => { let Coord(col, _) = try!(coord(&args[0])); FormulaAtom::Number(col as f64 + 1.0) },
+            "fill_down" | "fill_right" => {
+                let direction = if name == "fill_down" { FillDirection::Down } else { FillDirection::Right };
+                let (from, to) = (try!(coord(&args[0])), try!(coord(&args[1])));
+                try!(self.charge(cells(::sheet::corners(from, to))));
+                self.host.fill(from, to, direction);
+                FormulaAtom::Empty
+            },
+            "add_sheet" => match self.host.add_sheet(try!(string(&args[0]))) {
+                Ok(idx) => FormulaAtom::Number(idx as f64 + 1.0),
+                Err(e) => { return Err(ScriptErr::Workbook(e)); },
+            },
+            "print" => {
+                self.host.print(text(&args[0]).as_str());
+                FormulaAtom::Empty
+            },
+            "trim" => FormulaAtom::String(try!(string(&args[0])).trim().to_string()),
+            "upper" => try!(string_atom(try!(string(&args[0])).to_uppercase())),
+            "lower" => try!(string_atom(try!(string(&args[0])).to_lowercase())),
+            "len" => FormulaAtom::Number(try!(string(&args[0])).chars().count() as f64),
+            "text" => FormulaAtom::String(text(&args[0])),
+            // Numbers in strings, like "12.5", become numbers.
+            "number" => match args[0] {
+                FormulaAtom::String(ref s) => match s.trim().parse::<f64>() {
+                    Ok(x) if x.is_finite() => FormulaAtom::Number(x),
+                    _ => { return Err(ScriptErr::Type("Number")); },
+                },
+                ref x => FormulaAtom::Number(try!(number(x))),
+            },
+            "is_empty" => boolean(match args[0] {
+                FormulaAtom::Empty => true,
+                FormulaAtom::String(ref s) => s.len() == 0,
+                _ => false,
+            }),
+            "is_number" => boolean(::conditional::number(&args[0]).is_some()),
+            _ => unreachable!(),
+        })
+    }
+}
+
+fn binary(op: BinOp, a: &FormulaAtom, b: &FormulaAtom) -> Result<FormulaAtom, ScriptErr> {
+    use std::cmp::Ordering;
+
+    let ordering = || compare(a, b).ok_or(ScriptErr::Type("Comparison"));
+    let ret = match op {
+        BinOp::Add => match (a, b) {
+            (&FormulaAtom::String(_), _) | (_, &FormulaAtom::String(_)) => {
+                return string_atom(text(a) + text(b).as_str());
+            },
+            _ => try!(number(a)) + try!(number(b)),
+        },
+        BinOp::Sub => try!(number(a)) - try!(number(b)),
+        BinOp::Mul => try!(number(a)) * try!(number(b)),
+        BinOp::Div => try!(number(a)) / try!(number(b)),
+        BinOp::Mod => try!(number(a)) % try!(number(b)),
+        BinOp::Eq => { return Ok(boolean(a == b || compare(a, b) == Some(Ordering::Equal))); },
+        BinOp::Ne => { return Ok(boolean(!(a == b || compare(a, b) == Some(Ordering::Equal)))); },
+        BinOp::Lt => { return Ok(boolean(try!(ordering()) == Ordering::Less)); },
+        BinOp::Le => { return Ok(boolean(try!(ordering()) != Ordering::Greater)); },
+        BinOp::Gt => { return Ok(boolean(try!(ordering()) == Ordering::Greater)); },
+        BinOp::Ge => { return Ok(boolean(try!(ordering()) != Ordering::Less)); },
+        BinOp::And | BinOp::Or => unreachable!(),
+    };
+    if ret.is_finite() {
+        Ok(FormulaAtom::Number(ret))
+    } else {
+        Err(ScriptErr::Num)
+    }
+}
+
+fn truthy(x: &FormulaAtom) -> bool {
+    match *x {
+        FormulaAtom::Empty => false,
+        FormulaAtom::String(ref s) => s.len() > 0,
+        ref x => ::conditional::number(x).map_or(true, |x| x != 0.0),
+    }
+}
+
+fn boolean(x: bool) -> FormulaAtom {
+    FormulaAtom::Number(if x { 1.0 } else { 0.0 })
+}
+
+fn number(x: &FormulaAtom) -> Result<f64, ScriptErr> {
+    ::conditional::number(x).ok_or(ScriptErr::Type("Number"))
+}
+
+/// A string built by the script, unless it's too long.
+fn string_atom(s: String) -> Result<FormulaAtom, ScriptErr> {
+    if s.len() > MAX_STRING_LEN {
+        return Err(ScriptErr::TooLong);
+    }
+    Ok(FormulaAtom::String(s))
+}
+
+/// How many cells there are in an area.
+fn cells((Coord(col_from, row_from), Coord(col_to, row_to)): (Coord, Coord)) -> u64 {
+    (col_to as u64 - col_from as u64 + 1).saturating_mul(row_to as u64 - row_from as u64 + 1)
+}
+
+fn string(x: &FormulaAtom) -> Result<&str, ScriptErr> {
+    match *x {
+        FormulaAtom::String(ref s) => Ok(s.as_str()),
+        _ => Err(ScriptErr::Type("String")),
+    }
+}
+
+/// A value the way a cell without a format shows it.
+fn text(x: &FormulaAtom) -> String {
+    format_default(x, false).text
+}
+
+/// The cell a string like `B2` names.
+fn coord(x: &FormulaAtom) -> Result<Coord, ScriptErr> {
+    let s = try!(string(x));
+    Coord::parse_anchored(s.trim()).map(|(coord, _)| coord).ok_or(ScriptErr::BadCell(s.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::iter::repeat;
+    use std::sync::mpsc::channel;
+    use sheet::{Coord, FormulaAtom, FormulaErr};
+    use workbook::Workbook;
+
+    fn run_on(book: &mut Workbook, source: &str, limits: Limits) -> Result<(), ScriptErr> {
+        let script = try!(parse(source));
+        let (tx, _) = channel();
+        let mut host = WorkbookHost{book: book, sheet: 0, selection: (Coord(0, 0), Coord(1, 2)), messages: tx};
+        run(&script, &mut host, limits)
+    }
+
+    #[test]
+    fn test_script() {
+        let mut book = Workbook::new();
+        let names = ["  ann ", "Bob", ""];
+        for (row, name) in names.iter().enumerate() {
+            book.set(0, Coord(0, row), Formula::Atom(FormulaAtom::String(name.to_string())));
+            book.set(0, Coord(1, row), Formula::Atom(FormulaAtom::Number(row as f64 * 10.0)));
+        }
+        let source = "
+            # Cleans up the names and adds up the numbers.
+            let total = 0
+            for row in first_row .. last_row + 1 {
+                let name = value(cell(1, row))
+                if is_empty(name) { set(cell(1, row), \"?\") } else { set(cell(1, row), upper(trim(name))) }
+                total = total + value(cell(2, row))
+            }
+            set(\"C1\", total); set(\"C2\", \"n=\" + (last_row - first_row + 1))
+            let i = 0
+            while i < 3 and not (i == 2) {
+                i = i + 1
+            }
+            set_formula(\"C3\", \"add($C$1, \" + i + \")\")
+        ";
+        run_on(&mut book, source, Limits::new()).unwrap();
+        let value = |book: &Workbook, col, row| *book.value(0, Coord(col, row)).ok().unwrap();
+        assert_eq!(FormulaAtom::String("ANN".to_string()), value(&book, 0, 0));
+        assert_eq!(FormulaAtom::String("?".to_string()), value(&book, 0, 2));
+        assert_eq!(FormulaAtom::Number(30.0), value(&book, 2, 0));
+        assert_eq!(FormulaAtom::String("n=3".to_string()), value(&book, 2, 1));
+        assert_eq!(FormulaAtom::Number(32.0), value(&book, 2, 2));
+
+        run_on(&mut book, "fill_down(\"C3\", \"C4\")\nadd_sheet(\"Report\")", Limits::new()).unwrap();
+        assert_eq!(FormulaAtom::Number(32.0), value(&book, 2, 3));
+        assert_eq!(vec!["Sheet1".to_string(), "Report".to_string()], book.sheet_names());
+
+        let (tx, rx) = channel();
+        let mut host = WorkbookHost{book: &mut book, sheet: 0, selection: (Coord(0, 0), Coord(0, 0)), messages: tx};
+        run(&parse("print(\"done\")").unwrap(), &mut host, Limits::new()).unwrap();
+        assert_eq!("done", rx.try_recv().unwrap());
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let mut book = Workbook::new();
+        let mut err = |source: &str, limits: Limits| run_on(&mut book, source, limits).err().unwrap();
+        assert!(match err("let x = ", Limits::new()) { ScriptErr::Syntax(_) => true, _ => false });
+        assert!(match err("x = 1", Limits::new()) { ScriptErr::Undefined(ref x) => x == "x", _ => false });
+        assert!(match err("if 1 { let y = 1 }\ny", Limits::new()) { ScriptErr::Undefined(_) => true, _ => false });
+        assert!(match err("open(\"/etc/passwd\")", Limits::new()) { ScriptErr::Undefined(_) => true, _ => false });
+        assert!(match err("cell(1)", Limits::new()) { ScriptErr::Arity(_) => true, _ => false });
+        assert!(match err("value(\"A0\")", Limits::new()) { ScriptErr::BadCell(_) => true, _ => false });
+        assert!(match err("1 / 0", Limits::new()) { ScriptErr::Num => true, _ => false });
+        assert!(match err("1 < \"a\"", Limits::new()) { ScriptErr::Type(_) => true, _ => false });
+        assert!(match err("set_formula(\"A1\", \"#REF!\")\nvalue(\"A1\")", Limits::new()) {
+            ScriptErr::Formula(FormulaErr::InvalidRef) => true, _ => false });
+        let parens = |n: usize, s: &str| repeat(s).take(n).collect::<String>();
+        assert!(match err(&format!("{}1{}", parens(100, "("), parens(100, ")")), Limits::new()) {
+            ScriptErr::TooDeep => true, _ => false });
+        assert!(match err("let s = \"ab\"\nwhile 1 { s = s + s }", Limits::new()) { ScriptErr::TooLong => true, _ => false });
+        assert!(match err("fill_down(\"A1\", \"B5000\")", Limits{steps: 10000, millis: 60000}) {
+            ScriptErr::Steps => true, _ => false });
+        assert!(match err("set_formula(\"A1\", \"sequence(20000)\")", Limits{steps: 10000, millis: 60000}) {
+            ScriptErr::Steps => true, _ => false });
+        assert!(match err("for i in 0 .. 100000 { }", Limits{steps: 10000, millis: 60000}) {
+            ScriptErr::Steps => true, _ => false });
+        assert!(match err("while 1 { }", Limits{steps: 10000, millis: 60000}) { ScriptErr::Steps => true, _ => false });
+        assert!(match err("while 1 { }", Limits{steps: 1000000, millis: 0}) { ScriptErr::Time => true, _ => false });
+    }
+
+    #[test]
+    fn test_long_chains() {
+        // Chains of operators and of `else if`s don't nest, however long.
+        let mut book = Workbook::new();
+        let n = 100000;
+        let negated = format!("set(\"A1\", {}1)", repeat("- not ").take(n).collect::<String>());
+        let sum = format!("set(\"A2\", 1{})", repeat(" + 1").take(n).collect::<String>());
+        let branches = format!("if 0 {{ }}{} else {{ set(\"A3\", 1) }}",
+                               repeat(" else if 0 { }").take(n).collect::<String>());
+        for source in &[negated, sum, branches] {
+            run_on(&mut book, source, Limits::new()).unwrap();
+        }
+        assert_eq!(FormulaAtom::Number(-1.0), *book.value(0, Coord(0, 0)).ok().unwrap());
+        assert_eq!(FormulaAtom::Number(n as f64 + 1.0), *book.value(0, Coord(0, 1)).ok().unwrap());
+        assert_eq!(FormulaAtom::Number(1.0), *book.value(0, Coord(0, 2)).ok().unwrap());
+    }
+}
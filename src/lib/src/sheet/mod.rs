@@ -1,152 +1,1984 @@
 use ::std::collections::{HashMap, HashSet};
+use ::std::sync::{Arc, Mutex, RwLock};
 use ::std::sync::mpsc::{Sender, Receiver, channel};
+use decimal::{Decimal, Rounding};
+use format::NumberFormat;
+use style::Style;
+use conditional::{ConditionalFormat, Look};
+use validation::{Validation, ValidationRule, ValidationErr, Validity};
+use functions::math::Random;
+use functions::registry::{FunctionRegistry, Registry};
+use deps::{Precedents, Dependents};
 
 pub struct Sheet {
     cells: HashMap<Coord, Formula>,
-    selections: Vec<(Coord, Coord, Sender<(Coord, Value)>)>,
+    /// What the formula of each cell reads.
+    deps: HashMap<Coord, Precedents>,
+    /// The bottom right cells of the arrays the formulas of cells give,
+    /// whether they can spill or not. Behind a lock, as they're worked out
+    /// by evaluating cells, when the cells they're calculated from change.
+    spills: Mutex<HashMap<Coord, Coord>>,
+    formats: HashMap<Coord, NumberFormat>,
+    styles: HashMap<Coord, Style>,
+    conditional_formats: Vec<ConditionalFormat>,
+    validations: Vec<Validation>,
+    /// Behind a lock, as senders can't be shared between the threads that
+    /// evaluate cells, and shared with the subscriptions, which remove
+    /// themselves when dropped. By ID.
+    selections: Arc<Mutex<Vec<(usize, Coord, Coord, Sender<(Coord, Value)>)>>>,
+    next_selection: usize,
+    random: Random,
+    functions: Box<FunctionRegistry + Send>,
+    threads: usize,
+    /// Whether any formula calls a volatile function, once worked out.
+    volatile: Mutex<Option<bool>>,
+    /// The cells that read each cell, once worked out, when the sheet is a
+    /// book on its own.
+    dependents: Mutex<Option<Dependents>>,
+}
+
+/// The values of the cells of a selection, sent when selected and then
+/// whenever they change, until unselected or dropped.
+pub struct Subscription {
+    /// To move or resize the selection with.
+    pub id: usize,
+    pub values: Receiver<(Coord, Value)>,
+    unselect: Box<Fn() + Send>,
+}
+
+impl Subscription {
+    /// A subscription that calls `unselect` to remove its selection when
+    /// dropped.
+    pub fn new(id: usize, values: Receiver<(Coord, Value)>, unselect: Box<Fn() + Send>) -> Subscription {
+        Subscription{id: id, values: values, unselect: unselect}
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        (self.unselect)();
+    }
 }
 
 pub type Value = Result<Box<FormulaAtom>, FormulaErr>;
 
-impl Sheet {
-    pub fn new() -> Self {
-        Sheet{cells: HashMap::new(), selections: vec![]}
+/// How deep lambdas can call each other, or themselves, before giving up.
+pub const MAX_CALL_DEPTH: usize = 100;
+
+/// How many threads cells are evaluated on at once, unless capped with
+/// `set_threads`.
+pub const DEFAULT_THREADS: usize = 4;
+
+/// The fewest cells worth starting another thread for.
+const CELLS_PER_THREAD: usize = 32;
+
+/// Evaluation recurses through references, so threads get as much stack as
+/// a main thread usually has.
+const STACK_SIZE: usize = 8 * 1024 * 1024;
+
+impl Sheet {
+    pub fn new() -> Self {
+        Sheet{
+            cells: HashMap::new(),
+            deps: HashMap::new(),
+            spills: Mutex::new(HashMap::new()),
+            formats: HashMap::new(),
+            styles: HashMap::new(),
+            conditional_formats: vec![],
+            validations: vec![],
+            selections: Arc::new(Mutex::new(vec![])),
+            next_selection: 0,
+            random: Random::from_time(),
+            functions: Box::new(Registry::new()),
+            threads: DEFAULT_THREADS,
+            volatile: Mutex::new(None),
+            dependents: Mutex::new(None),
+        }
+    }
+
+    /// Makes `rand` and `randbetween` draw the same numbers every time the
+    /// sheet is evaluated in the same order, for reproducible results.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    /// Replaces the functions formulas can call besides the built-in ones.
+    pub fn set_functions(&mut self, functions: Box<FunctionRegistry + Send>) {
+        self.functions = functions;
+        *self.volatile.lock().unwrap() = None;
+        self.notify_all();
+    }
+
+    /// Caps how many threads cells are evaluated on at once when many values
+    /// are sent to the selections. With 1 they're evaluated one by one on
+    /// the calling thread.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = ::std::cmp::max(threads, 1);
+    }
+
+    /// Whether any formula in the sheet calls a volatile function.
+    pub fn has_volatile(&self, functions: &FunctionRegistry) -> bool {
+        self.cells.values().any(|f| f.is_volatile(functions))
+    }
+
+    /// Sends the values of the cells whose formulas call volatile functions,
+    /// like `now()`, and of the cells that depend on them to the selections,
+    /// for applications to call when those values may have changed.
+    pub fn refresh_volatile(&self) {
+        let volatile = self.volatile_areas(Context{book: self, sheet: 0, cache: None});
+        let areas: Vec<_> = volatile.into_iter().map(|(from, to)| (0, from, to)).collect();
+        for (_, from, to) in ::deps::update(self, &areas) {
+            self.notify(from, to);
+        }
+    }
+
+    /// The cells whose formulas call volatile functions, with the areas they
+    /// spill over, when this is the sheet `ctx.sheet` of `ctx.book`.
+    pub fn volatile_areas(&self, ctx: Context) -> Vec<(Coord, Coord)> {
+        let mut ret: Vec<(Coord, Coord)> = self.cells.iter().filter(|&(_, f)| f.is_volatile(ctx.book.functions()))
+            .map(|(&coord, _)| (coord, coord)).collect();
+        let spills: Vec<_> = self.spill_areas().into_iter().filter(|&(from, _)| ret.contains(&(from, from))).collect();
+        ret.extend(spills);
+        ret
+    }
+
+    /// Sets the formula of a cell, unless the cell has a validation that
+    /// rejects its value.
+    pub fn set(&mut self, coord: Coord, formula: Formula) -> Validity {
+        let validity = self.put(coord, formula);
+        if let Validity::Rejected(_) = validity {
+            return validity;
+        }
+        for (_, from, to) in ::deps::update(self, &[(0, coord, coord)]) {
+            self.notify(from, to);
+        }
+        validity
+    }
+
+    /// Like `set`, without sending anything to the selections.
+    fn put(&mut self, coord: Coord, formula: Formula) -> Validity {
+        let old = self.swap(coord, formula);
+        let validity = self.validate(coord, Context{book: self, sheet: 0, cache: None});
+        if let Validity::Rejected(_) = validity {
+            self.swap(coord, old);
+        }
+        ::deps::changed(self, (0, coord));
+        validity
+    }
+
+    /// Puts a formula in a cell without validating it nor sending its value
+    /// to the selections, returning the formula that was there.
+    pub fn swap(&mut self, coord: Coord, formula: Formula) -> Formula {
+        *self.volatile.lock().unwrap() = None;
+        let old = if let Formula::Atom(FormulaAtom::Empty) = formula {
+            self.deps.remove(&coord);
+            self.cells.remove(&coord)
+        } else {
+            self.deps.insert(coord, Precedents::of(&formula));
+            self.cells.insert(coord, formula)
+        };
+        old.unwrap_or(Formula::Atom(FormulaAtom::Empty))
+    }
+
+    /// The formula of a cell, if it has one.
+    pub fn formula(&self, coord: Coord) -> Option<&Formula> {
+        self.cells.get(&coord)
+    }
+
+    /// The cells with formulas, row by row.
+    pub fn formulas(&self) -> Vec<(Coord, &Formula)> {
+        let mut ret: Vec<_> = self.cells.iter().map(|(&coord, formula)| (coord, formula)).collect();
+        ret.sort_by(|&(Coord(col_a, row_a), _), &(Coord(col_b, row_b), _)| (row_a, col_a).cmp(&(row_b, col_b)));
+        ret
+    }
+
+    /// What the formula of a cell reads, if it has one.
+    pub fn precedents_of(&self, coord: Coord) -> Option<&Precedents> {
+        self.deps.get(&coord)
+    }
+
+    /// The cells with formulas between `from` and `to`, looking at whichever
+    /// is fewer, the cells of the area or those with formulas.
+    pub fn formula_cells(&self, from: Coord, to: Coord) -> Vec<Coord> {
+        let (Coord(col_from, row_from), Coord(col_to, row_to)) = (from, to);
+        if (col_to - col_from + 1) * (row_to - row_from + 1) <= self.cells.len() {
+            cells_between(from, to).into_iter().filter(|coord| self.cells.contains_key(coord)).collect()
+        } else {
+            self.cells.keys().filter(|&&coord| ::deps::overlap((coord, coord), (from, to))).cloned().collect()
+        }
+    }
+
+    /// What the formulas of the cells read, in no particular order.
+    pub fn precedents(&self) -> Vec<(Coord, &Precedents)> {
+        self.deps.iter().map(|(&coord, precedents)| (coord, precedents)).collect()
+    }
+
+    /// Adds a validation, returning its position and the cells it covers
+    /// whose values already fail it. Where validations overlap, the last one
+    /// added counts.
+    pub fn add_validation(&mut self, validation: Validation) -> (usize, Vec<(Coord, Validity)>) {
+        let (from, to) = (validation.from, validation.to);
+        let idx = self.put_validation(validation);
+        let invalid = self.invalid_cells(from, to, Context{book: self, sheet: 0, cache: None});
+        self.notify(from, to);
+        (idx, invalid)
+    }
+
+    /// Adds a validation without checking the cells it covers nor sending
+    /// them to the selections, returning its position.
+    pub fn put_validation(&mut self, validation: Validation) -> usize {
+        self.validations.push(validation);
+        self.validations.len() - 1
+    }
+
+    /// Removes the validation at position `idx`, returning it, or `None` if
+    /// there isn't one there.
+    pub fn remove_validation(&mut self, idx: usize) -> Option<Validation> {
+        if idx >= self.validations.len() {
+            return None;
+        }
+        let old = self.validations.remove(idx);
+        self.notify(old.from, old.to);
+        Some(old)
+    }
+
+    pub fn validations(&self) -> &[Validation] {
+        &self.validations
+    }
+
+    /// The validation of a cell, if any.
+    pub fn validation(&self, coord: Coord) -> Option<&Validation> {
+        self.validations.iter().rev().find(|v| v.contains(coord))
+    }
+
+    /// Checks the value of a cell against its validation, when this is the
+    /// sheet `ctx.sheet` of `ctx.book`.
+    pub fn validate(&self, coord: Coord, ctx: Context) -> Validity {
+        let validation = match self.validation(coord) {
+            Some(x) => x,
+            None => { return Validity::Valid; },
+        };
+
+        let checked = match self.value_in(coord, ctx) {
+            Ok(ref x) if **x == FormulaAtom::Empty => Ok(()),
+            Ok(x) => match validation.rule {
+                ValidationRule::Custom(ref formula) => {
+                    let (Coord(col_from, row_from), Coord(col, row)) = (validation.from, coord);
+                    let formula = formula.shift(col as isize - col_from as isize, row as isize - row_from as isize);
+                    match self.calc_formula(&formula, ctx) {
+                        Ok(ref x) if ::conditional::number(x).map_or(false, |x| x != 0.0) => Ok(()),
+                        _ => Err(ValidationErr::Failed),
+                    }
+                },
+                _ => validation.check(&x),
+            },
+            Err(_) => Err(ValidationErr::Failed),
+        };
+        validation.validity(checked)
+    }
+
+    /// The cells with formulas between `from` and `to` whose values fail
+    /// their validation, row by row.
+    pub fn invalid_cells(&self, from: Coord, to: Coord, ctx: Context) -> Vec<(Coord, Validity)> {
+        let (Coord(col_from, row_from), Coord(col_to, row_to)) = (from, to);
+        self.formulas().into_iter()
+            .map(|(coord, _)| coord)
+            .filter(|&Coord(col, row)| col_from <= col && col <= col_to && row_from <= row && row <= row_to)
+            .map(|coord| (coord, self.validate(coord, ctx)))
+            .filter(|&(_, ref validity)| *validity != Validity::Valid)
+            .collect()
+    }
+
+    pub fn select(&mut self, from: Coord, to: Coord) -> Subscription {
+        let (tx, rx) = channel();
+        let id = self.next_selection;
+        self.next_selection += 1;
+        self.send(from, to, &tx);
+        self.selections.lock().unwrap().push((id, from, to, tx));
+        let selections = self.selections.clone();
+        Subscription::new(id, rx, Box::new(move || {
+            if let Ok(mut selections) = selections.lock() {
+                selections.retain(|&(other, _, _, _)| other != id);
+            }
+        }))
+    }
+
+    /// Stops sending values to a selection.
+    pub fn unselect(&mut self, id: usize) {
+        self.selections.lock().unwrap().retain(|&(other, _, _, _)| other != id);
+    }
+
+    /// Moves or resizes a selection, like when scrolling, sending only the
+    /// values of the cells that weren't in it before.
+    pub fn move_selection(&mut self, id: usize, from: Coord, to: Coord) {
+        let mut selections = self.selections.lock().unwrap();
+        let connected = match selections.iter_mut().find(|selection| selection.0 == id) {
+            Some(selection) => {
+                let (old_from, old_to) = (selection.1, selection.2);
+                selection.1 = from;
+                selection.2 = to;
+                let entering = cells_between(from, to).into_iter().filter(|&coord| {
+                    !::deps::overlap((coord, coord), (old_from, old_to))
+                }).collect();
+                self.send_cells(entering, &selection.3)
+            },
+            None => { return; },
+        };
+        if !connected {
+            selections.retain(|&(other, _, _, _)| other != id);
+        }
+    }
+
+    /// Gives the cell at `coord` a number format and a style, or removes them
+    /// if `None`, without sending it to the selections.
+    pub fn put_attrs(&mut self, coord: Coord, format: Option<NumberFormat>, style: Option<Style>) {
+        put_attr(&mut self.formats, coord, format);
+        put_attr(&mut self.styles, coord, style);
+    }
+
+    /// Sets the number format of the cells from `from` to `to`, or removes it
+    /// if `None`. Formats only change how values are shown.
+    pub fn set_format(&mut self, from: Coord, to: Coord, format: Option<NumberFormat>) {
+        self.put_format(from, to, format);
+        self.notify(from, to);
+    }
+
+    /// Like `set_format`, without sending the cells to the selections, for
+    /// workbooks, which send them to their own.
+    pub fn put_format(&mut self, from: Coord, to: Coord, format: Option<NumberFormat>) {
+        let Coord(col_from, row_from) = from;
+        let Coord(col_to, row_to) = to;
+        for col in col_from .. col_to+1 {
+            for row in row_from .. row_to+1 {
+                match format {
+                    Some(ref x) => { self.formats.insert(Coord(col, row), x.clone()); },
+                    None => { self.formats.remove(&Coord(col, row)); },
+                }
+            }
+        }
+    }
+
+    pub fn format(&self, coord: Coord) -> Option<&NumberFormat> {
+        self.formats.get(&coord)
+    }
+
+    /// The cells with a number format, row by row.
+    pub fn formats(&self) -> Vec<(Coord, &NumberFormat)> {
+        let mut ret: Vec<_> = self.formats.iter().map(|(&coord, format)| (coord, format)).collect();
+        ret.sort_by(|&(Coord(col_a, row_a), _), &(Coord(col_b, row_b), _)| (row_a, col_a).cmp(&(row_b, col_b)));
+        ret
+    }
+
+    /// Sets the style of the cells from `from` to `to`. `Style::new()`
+    /// removes their styling.
+    pub fn set_style(&mut self, from: Coord, to: Coord, style: Style) {
+        self.update_style(from, to, |x| { *x = style.clone(); });
+    }
+
+    /// Changes the style of each cell from `from` to `to` with `f`, like
+    /// making them bold while keeping the rest of their styles.
+    pub fn update_style<F>(&mut self, from: Coord, to: Coord, f: F)
+        where F: Fn(&mut Style)
+    {
+        let Coord(col_from, row_from) = from;
+        let Coord(col_to, row_to) = to;
+        for col in col_from .. col_to+1 {
+            for row in row_from .. row_to+1 {
+                let mut style = self.styles.remove(&Coord(col, row)).unwrap_or(Style::new());
+                f(&mut style);
+                if style != Style::new() {
+                    self.styles.insert(Coord(col, row), style);
+                }
+            }
+        }
+        self.notify(from, to);
+    }
+
+    pub fn style(&self, coord: Coord) -> Option<&Style> {
+        self.styles.get(&coord)
+    }
+
+    /// The cells with a style, row by row.
+    pub fn styles(&self) -> Vec<(Coord, &Style)> {
+        let mut ret: Vec<_> = self.styles.iter().map(|(&coord, style)| (coord, style)).collect();
+        ret.sort_by(|&(Coord(col_a, row_a), _), &(Coord(col_b, row_b), _)| (row_a, col_a).cmp(&(row_b, col_b)));
+        ret
+    }
+
+    /// Adds a conditional format, returning its position. Later ones are
+    /// applied over earlier ones.
+    pub fn add_conditional_format(&mut self, cf: ConditionalFormat) -> usize {
+        let (from, to) = (cf.from, cf.to);
+        self.conditional_formats.push(cf);
+        self.notify(from, to);
+        self.conditional_formats.len() - 1
+    }
+
+    /// Replaces the conditional format at position `idx`, returning the old
+    /// one, or `None` if there isn't one there.
+    pub fn replace_conditional_format(&mut self, idx: usize, cf: ConditionalFormat) -> Option<ConditionalFormat> {
+        if idx >= self.conditional_formats.len() {
+            return None;
+        }
+        let old = ::std::mem::replace(&mut self.conditional_formats[idx], cf);
+        let (from, to) = (self.conditional_formats[idx].from, self.conditional_formats[idx].to);
+        self.notify(old.from, old.to);
+        self.notify(from, to);
+        Some(old)
+    }
+
+    /// Removes the conditional format at position `idx`, returning it, or
+    /// `None` if there isn't one there.
+    pub fn remove_conditional_format(&mut self, idx: usize) -> Option<ConditionalFormat> {
+        if idx >= self.conditional_formats.len() {
+            return None;
+        }
+        let old = self.conditional_formats.remove(idx);
+        self.notify(old.from, old.to);
+        Some(old)
+    }
+
+    pub fn conditional_formats(&self) -> &[ConditionalFormat] {
+        &self.conditional_formats
+    }
+
+    /// The ranges of cells whose look depends on the value of `coord`
+    /// besides their own, like those with a color scale.
+    pub fn restyled_by(&self, coord: Coord) -> Vec<(Coord, Coord)> {
+        self.conditional_formats.iter().filter(|cf| cf.is_relative() && cf.contains(coord)).map(|cf| {
+            (cf.from, cf.to)
+        }).collect()
+    }
+
+    /// How a cell looks, with its conditional formats applied.
+    pub fn look(&self, coord: Coord) -> Look {
+        self.look_in(coord, Context{book: self, sheet: 0, cache: None})
+    }
+
+    /// Like `look`, when this is the sheet `ctx.sheet` of `ctx.book`.
+    pub fn look_in(&self, coord: Coord, ctx: Context) -> Look {
+        self.looks_in(&[coord], ctx).remove(0)
+    }
+
+    /// Like `look_in` for several cells, evaluating the ranges of the rules
+    /// that depend on them once for all of them.
+    pub fn looks_in(&self, coords: &[Coord], ctx: Context) -> Vec<Look> {
+        let bounds: Vec<(f64, f64)> = self.conditional_formats.iter().map(|cf| {
+            if !cf.is_relative() || !coords.iter().any(|&coord| cf.contains(coord)) {
+                return ::conditional::bounds(&[]);
+            }
+            let cells = self.range_cells(cf.from, cf.to);
+            let numbers: Vec<f64> = self.values_in(&cells, ctx).into_iter().filter_map(|x| match x {
+                Ok(x) => ::conditional::number(&x),
+                Err(_) => None,
+            }).collect();
+            ::conditional::bounds(&numbers)
+        }).collect();
+
+        coords.iter().zip(self.values_in(coords, ctx).into_iter()).map(|(&coord, value)| {
+            let mut look = Look{style: self.styles.get(&coord).cloned().unwrap_or(Style::new()), bar: None};
+            if let Ok(value) = value {
+                for (cf, &bounds) in self.conditional_formats.iter().zip(bounds.iter()) {
+                    if cf.contains(coord) {
+                        cf.apply(&value, bounds, &mut look);
+                    }
+                }
+            }
+            look
+        }).collect()
+    }
+
+    /// Copies the formula in the first row (when filling down) or column (when
+    /// filling right) of the rectangle into the rest of it, shifting relative
+    /// references.
+    pub fn fill(&mut self, from: Coord, to: Coord, direction: FillDirection) {
+        self.fill_series(from, to, 1, direction);
+    }
+
+    /// Extends the series started in the first `seeds` rows (when filling down)
+    /// or columns (when filling right) of the rectangle through the rest of it.
+    ///
+    /// When two or more seeds are all numbers, the series follows their linear
+    /// trend, or in `NumberMode::Decimal` goes on from the last seed by the
+    /// exact step between the last two. Otherwise the seeds are repeated,
+    /// shifting the relative references in their formulas. Number formats and
+    /// styles are repeated either way, except into cells whose validation
+    /// rejects their new formula.
+    ///
+    /// `from` and `to` can be any two opposite corners of the rectangle.
+    pub fn fill_series(&mut self, from: Coord, to: Coord, seeds: usize, direction: FillDirection) {
+        let (from, to) = corners(from, to);
+        let series = self.series(from, to, seeds, direction, self.number_mode());
+        if series.is_empty() {
+            return;
+        }
+        for (coord, formula, format, style) in series {
+            if let Validity::Rejected(_) = self.put(coord, formula) {
+                continue;
+            }
+            self.put_attrs(coord, format, style);
+        }
+        for (_, from, to) in ::deps::update(self, &[(0, from, to)]) {
+            self.notify(from, to);
+        }
+    }
+
+    /// The formulas `fill_series` puts in the cells it fills, along with the
+    /// number formats and styles of their seeds.
+    pub fn series(&self, from: Coord, to: Coord, seeds: usize, direction: FillDirection, mode: NumberMode)
+                  -> Vec<(Coord, Formula, Option<NumberFormat>, Option<Style>)> {
+        let (Coord(col_from, row_from), Coord(col_to, row_to)) = corners(from, to);
+        let (lines, len) = match direction {
+            FillDirection::Down => (col_from .. col_to+1, row_to + 1 - row_from),
+            FillDirection::Right => (row_from .. row_to+1, col_to + 1 - col_from),
+        };
+        let mut ret = vec![];
+        if seeds == 0 || seeds >= len {
+            return ret;
+        }
+
+        for line in lines {
+            let at = |i: usize| match direction {
+                FillDirection::Down => Coord(line, row_from + i),
+                FillDirection::Right => Coord(col_from + i, line),
+            };
+            let seed_formulas: Vec<Formula> = (0 .. seeds).map(|i| {
+                self.cells.get(&at(i)).cloned().unwrap_or(Formula::Atom(FormulaAtom::Empty))
+            }).collect();
+            for (i, formula) in extend_series(&seed_formulas, len, direction, mode).into_iter().enumerate().skip(seeds) {
+                let seed = at(i % seeds);
+                ret.push((at(i), formula, self.formats.get(&seed).cloned(), self.styles.get(&seed).cloned()));
+            }
+        }
+        ret
+    }
+
+    /// Inserts `count` empty rows before row `at`, moving the cells below them
+    /// down. References are rewritten to keep pointing at the same data.
+    pub fn insert_rows(&mut self, at: usize, count: usize) {
+        self.move_lines(LineShift{axis: Axis::Row, at: at, count: count, insert: true});
+    }
+
+    /// Deletes `count` rows starting at row `at`, moving the cells below them
+    /// up. References to deleted cells become `#REF!`.
+    pub fn delete_rows(&mut self, at: usize, count: usize) {
+        self.move_lines(LineShift{axis: Axis::Row, at: at, count: count, insert: false});
+    }
+
+    /// Inserts `count` empty columns before column `at`. See `insert_rows`.
+    pub fn insert_columns(&mut self, at: usize, count: usize) {
+        self.move_lines(LineShift{axis: Axis::Col, at: at, count: count, insert: true});
+    }
+
+    /// Deletes `count` columns starting at column `at`. See `delete_rows`.
+    pub fn delete_columns(&mut self, at: usize, count: usize) {
+        self.move_lines(LineShift{axis: Axis::Col, at: at, count: count, insert: false});
+    }
+
+    /// Inserts or deletes rows or columns. See `insert_rows` and `delete_rows`.
+    pub fn move_lines(&mut self, shift: LineShift) {
+        if shift.count == 0 {
+            return;
+        }
+
+        let cells = ::std::mem::replace(&mut self.cells, HashMap::new());
+        for (coord, formula) in cells {
+            if let Some(coord) = shift.coord(coord) {
+                self.cells.insert(coord, formula.move_lines(&shift));
+            }
+        }
+        self.track_all();
+        move_attrs(&mut self.formats, &shift);
+        move_attrs(&mut self.styles, &shift);
+        let conditional_formats = ::std::mem::replace(&mut self.conditional_formats, vec![]);
+        self.conditional_formats = conditional_formats.into_iter().filter_map(|cf| {
+            shift.range(cf.from, cf.to).map(|(from, to)| ConditionalFormat{from: from, to: to, ..cf})
+        }).collect();
+        let validations = ::std::mem::replace(&mut self.validations, vec![]);
+        self.validations = validations.into_iter().filter_map(|v| {
+            shift.range(v.from, v.to).map(|(from, to)| Validation{from: from, to: to, ..v})
+        }).collect();
+        for selection in self.selections.lock().unwrap().iter_mut() {
+            shift.move_selection(&mut selection.1, &mut selection.2);
+        }
+
+        self.notify_all();
+    }
+
+    /// Sends the current value of the cells from `from` to `to` to the
+    /// selections that include them, dropping the selections whose
+    /// receivers are gone.
+    fn notify(&self, from: Coord, to: Coord) {
+        use ::std::cmp::{min, max};
+
+        let (Coord(col_from, row_from), Coord(col_to, row_to)) = (from, to);
+        self.selections.lock().unwrap().retain(|&(_, Coord(sel_col_from, sel_row_from), Coord(sel_col_to, sel_row_to), ref tx)| {
+            self.send(Coord(max(col_from, sel_col_from), max(row_from, sel_row_from)),
+                      Coord(min(col_to, sel_col_to), min(row_to, sel_row_to)),
+                      tx)
+        });
+    }
+
+    /// Sends the current value of every selected cell to its subscriber,
+    /// after working out again the areas arrays spill over.
+    fn notify_all(&self) {
+        *self.dependents.lock().unwrap() = None;
+        ::deps::refresh_spills(self);
+        self.selections.lock().unwrap().retain(|&(_, from, to, ref tx)| self.send(from, to, tx));
+    }
+
+    /// Whether the receiver is still there.
+    fn send(&self, from: Coord, to: Coord, tx: &Sender<(Coord, Value)>) -> bool {
+        self.send_cells(cells_between(from, to), tx)
+    }
+
+    fn send_cells(&self, coords: Vec<Coord>, tx: &Sender<(Coord, Value)>) -> bool {
+        let values = self.values_in(&coords, Context{book: self, sheet: 0, cache: None});
+        coords.into_iter().zip(values.into_iter()).all(|x| tx.send(x).is_ok())
+    }
+
+    /// Replaces every formula in the sheet by `f` applied to it.
+    pub fn map_formulas<F>(&mut self, f: F)
+        where F: Fn(&Formula) -> Formula
+    {
+        for (_, formula) in self.cells.iter_mut() {
+            *formula = f(formula);
+        }
+        self.track_all();
+    }
+
+    /// Works out again what every formula reads.
+    fn track_all(&mut self) {
+        *self.volatile.lock().unwrap() = None;
+        *self.dependents.lock().unwrap() = None;
+        self.deps = self.cells.iter().map(|(&coord, formula)| (coord, Precedents::of(formula))).collect();
+    }
+
+    pub fn value(&self, coord: Coord) -> Value {
+        self.value_in(coord, Context{book: self, sheet: 0, cache: None})
+    }
+
+    /// The value of a cell of this sheet, when it is the sheet `ctx.sheet` of
+    /// `ctx.book`.
+    pub fn value_in(&self, coord: Coord, ctx: Context) -> Value {
+        self.cell_value(coord, ctx, &mut HashSet::new())
+    }
+
+    /// The values of cells of this sheet, like `value_in` for each, when it
+    /// is the sheet `ctx.sheet` of `ctx.book`. Unless the book is volatile,
+    /// the formulas the cells are calculated from are evaluated first, each
+    /// once, level by level as given by `deps::levels`, on up to
+    /// `ctx.book.threads()` threads at once. Evaluating a cell doesn't change
+    /// anything, so they're the same values as evaluated one by one.
+    pub fn values_in(&self, coords: &[Coord], ctx: Context) -> Vec<Value> {
+        if ctx.book.is_volatile() || ctx.cache.is_some() {
+            return coords.iter().map(|&coord| self.value_in(coord, ctx)).collect();
+        }
+
+        let threads = ctx.book.threads();
+        let cache = Cache::new();
+        let ctx = Context{cache: Some(&cache), ..ctx};
+        let cells: Vec<_> = coords.iter().map(|&coord| (ctx.sheet, coord)).collect();
+        for level in ::deps::levels(ctx.book, &cells) {
+            in_parallel(level.len(), threads, |idx| {
+                let (sheet, coord) = level[idx];
+                ctx.book.sheet_at(sheet).cache_result(coord, Context{sheet: sheet, ..ctx});
+            });
+        }
+        in_parallel(coords.len(), threads, |idx| self.value_in(coords[idx], ctx))
+    }
+
+    /// Evaluates the formula of a cell into `ctx.cache`, as if read by
+    /// another cell.
+    fn cache_result(&self, coord: Coord, ctx: Context) {
+        if let (Some(cache), Some(f)) = (ctx.cache, self.cells.get(&coord)) {
+            let mut visited = HashSet::new();
+            visited.insert(Visit::Cell(ctx.sheet, coord));
+            let result = self.calc_formula_visited(f, ctx, &mut visited);
+            cache.results.write().unwrap().insert((ctx.sheet, coord), Arc::new(result));
+        }
+    }
+
+    /// What the formula of a cell gives, from `ctx.cache` if it's there.
+    /// Not while lambdas are called, as how deep calls already go may change
+    /// it.
+    fn result(&self, coord: Coord, formula: &Formula, ctx: Context, visited: &mut HashSet<Visit>) -> Arc<Value> {
+        if let Some(cache) = ctx.cache {
+            let calling = visited.iter().any(|x| match *x { Visit::Call(_) => true, _ => false });
+            if !calling {
+                if let Some(x) = cache.results.read().unwrap().get(&(ctx.sheet, coord)) {
+                    return x.clone();
+                }
+            }
+        }
+        Arc::new(self.calc_formula_visited(formula, ctx, visited))
+    }
+
+    /// The value of a cell, or of the part of an array spilled over it. A
+    /// cell whose formula gives an array has the top left value of the
+    /// array, unless the array can't spill.
+    fn cell_value(&self, coord: Coord, ctx: Context, visited: &mut HashSet<Visit>) -> Value {
+        match self.cells.get(&coord) {
+            Some(f) => {
+                let result = self.result(coord, f, ctx, visited);
+                let value = match *result {
+                    Ok(ref x) => x,
+                    Err(ref e) => { return Err(e.clone()); },
+                };
+                match **value {
+                    FormulaAtom::Array(ref table) if self.spill_blocked(coord, table) => Err(FormulaErr::Spill),
+                    FormulaAtom::Array(ref table) => table.cells.first().cloned().ok_or(FormulaErr::NA),
+                    _ => Ok(value.clone()),
+                }
+            },
+            None => {
+                let anchor = match self.spilled_from(coord) {
+                    Some(x) => x,
+                    None => { return Ok(Box::new(FormulaAtom::Empty)); },
+                };
+                // An array can't spill over the cells it's calculated from.
+                let mut visited = visited.clone();
+                if !visited.insert(Visit::Cell(ctx.sheet, anchor)) {
+                    return Ok(Box::new(FormulaAtom::Empty));
+                }
+                let value = match self.cells.get(&anchor) {
+                    Some(f) => self.result(anchor, f, ctx, &mut visited),
+                    None => { return Ok(Box::new(FormulaAtom::Empty)); },
+                };
+                let (Coord(col, row), Coord(anchor_col, anchor_row)) = (coord, anchor);
+                match (*value).as_ref().map(|x| &**x) {
+                    Ok(&FormulaAtom::Array(ref table)) if col < anchor_col + table.cols && row < anchor_row + table.rows &&
+                                                          !self.spill_blocked(anchor, table) => {
+                        Ok(Box::new(table.get(col - anchor_col, row - anchor_row).clone()))
+                    },
+                    _ => Ok(Box::new(FormulaAtom::Empty)),
+                }
+            },
+        }
+    }
+
+    /// The cell whose formula gives the array spilled over a cell, if any.
+    fn spilled_from(&self, coord: Coord) -> Option<Coord> {
+        self.spills.lock().unwrap().iter().find(|&(&anchor, &to)| {
+            anchor != coord && ::deps::overlap((coord, coord), (anchor, to))
+        }).map(|(&anchor, _)| anchor)
+    }
+
+    /// The areas that arrays spill over, from the cell whose formula gives
+    /// them to their bottom right cell.
+    pub fn spill_areas(&self) -> Vec<(Coord, Coord)> {
+        let spills: Vec<_> = self.spills.lock().unwrap().iter().map(|(&from, &to)| (from, to)).collect();
+        spills.into_iter().filter(|&(from, to)| !self.area_blocked(from, to)).collect()
+    }
+
+    /// The area a cell's array spills over, or would if nothing were in the
+    /// way, as of the last time it was worked out.
+    pub fn spill_area(&self, anchor: Coord) -> Option<(Coord, Coord)> {
+        self.spills.lock().unwrap().get(&anchor).map(|&to| (anchor, to))
+    }
+
+    /// The areas arrays spill over, or would, that have cells between `from`
+    /// and `to`.
+    pub fn spills_over(&self, from: Coord, to: Coord) -> Vec<(Coord, Coord)> {
+        self.spills.lock().unwrap().iter().map(|(&anchor, &end)| (anchor, end))
+            .filter(|&area| ::deps::overlap(area, (from, to))).collect()
+    }
+
+    /// Works out again the area the array given by the formula of a cell
+    /// spills over, when this is the sheet `ctx.sheet` of `ctx.book`.
+    /// Returns the areas it spilled over before and spills over now if they
+    /// differ.
+    pub fn update_spill(&self, anchor: Coord, ctx: Context) -> Vec<(Coord, Coord)> {
+        let to = match self.cells.get(&anchor) {
+            Some(f) if f.may_spill() => {
+                let mut visited = HashSet::new();
+                visited.insert(Visit::Cell(ctx.sheet, anchor));
+                match self.calc_formula_visited(f, ctx, &mut visited).as_ref().map(|x| &**x) {
+                    Ok(&FormulaAtom::Array(ref table)) if table.cells.len() > 0 => {
+                        let Coord(col, row) = anchor;
+                        Some(Coord(col + table.cols - 1, row + table.rows - 1))
+                    },
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+
+        let mut spills = self.spills.lock().unwrap();
+        let old = match to {
+            Some(to) => spills.insert(anchor, to),
+            None => spills.remove(&anchor),
+        };
+        if old == to {
+            vec![]
+        } else {
+            old.into_iter().chain(to.into_iter()).map(|to| (anchor, to)).collect()
+        }
+    }
+
+    /// The cells whose formulas may give arrays.
+    pub fn spill_anchors(&self) -> Vec<Coord> {
+        self.cells.iter().filter(|&(_, f)| f.may_spill()).map(|(&coord, _)| coord).collect()
+    }
+
+    /// Forgets the areas arrays spill over, before working them all out again.
+    pub fn clear_spills(&self) {
+        self.spills.lock().unwrap().clear();
+    }
+
+    /// Whether other cells with formulas are in the way of an array.
+    fn spill_blocked(&self, Coord(col, row): Coord, table: &Table) -> bool {
+        self.area_blocked(Coord(col, row), Coord(col + table.cols - 1, row + table.rows - 1))
+    }
+
+    /// Whether cells with formulas other than `from` are in the way of an
+    /// array spilling from `from` to `to`, looking at whichever is fewer, the
+    /// cells of the area or those with formulas.
+    fn area_blocked(&self, from: Coord, to: Coord) -> bool {
+        let (Coord(col_from, row_from), Coord(col_to, row_to)) = (from, to);
+        if (col_to - col_from + 1) * (row_to - row_from + 1) <= self.cells.len() {
+            (col_from .. col_to + 1).any(|col| (row_from .. row_to + 1).any(|row| {
+                Coord(col, row) != from && self.cells.contains_key(&Coord(col, row))
+            }))
+        } else {
+            self.cells.keys().any(|&coord| coord != from && ::deps::overlap((coord, coord), (from, to)))
+        }
+    }
+
+    fn calc_formula(&self, formula: &Formula, ctx: Context) -> Value {
+        let mut visited = HashSet::new();
+        self.calc_formula_visited(formula, ctx, &mut visited)
+    }
+
+    fn calc_formula_visited(&self, formula: &Formula, ctx: Context, visited: &mut HashSet<Visit>) -> Value {
+        match *formula {
+            Formula::Atom(ref x) => Ok(Box::new(x.clone())),
+            Formula::Ref(coord, _) => {
+                if !visited.insert(Visit::Cell(ctx.sheet, coord)) {
+                    return Err(FormulaErr::Ref(coord));
+                }
+                self.cell_value(coord, ctx, visited)
+            },
+            Formula::Range(..) => Err(FormulaErr::Type("Value")),
+            Formula::SheetRef(ref name, ref inner) => match ctx.book.sheet_index(name) {
+                Some(idx) => {
+                    let other = Context{sheet: idx, ..ctx};
+                    ctx.book.sheet_at(idx).calc_formula_visited(inner, other, visited)
+                },
+                None => Err(FormulaErr::InvalidRef),
+            },
+            Formula::Name(ref name) => {
+                let target = try!(self.resolve_name(name, ctx, visited));
+                self.calc_formula_visited(target, ctx, visited)
+            },
+            Formula::InvalidRef => Err(FormulaErr::InvalidRef),
+            Formula::Op(ref op, ref args) => {
+                match *op {
+                    FormulaOp::VLookup | FormulaOp::HLookup | FormulaOp::Index | FormulaOp::Match |
+                    FormulaOp::XLookup | FormulaOp::Irr | FormulaOp::XNpv | FormulaOp::SumIf |
+                    FormulaOp::CountIf | FormulaOp::AverageIf | FormulaOp::SumIfs | FormulaOp::CountIfs |
+                    FormulaOp::AverageIfs | FormulaOp::Filter | FormulaOp::Sort | FormulaOp::Unique |
+                    FormulaOp::Sequence | FormulaOp::Transpose => {
+                        let mut tables = Vec::with_capacity(args.len());
+                        for arg in args {
+                            tables.push(try!(self.table(arg, ctx, visited)));
+                        }
+                        return match *op {
+                            FormulaOp::VLookup | FormulaOp::HLookup | FormulaOp::Index | FormulaOp::Match |
+                            FormulaOp::XLookup => ::functions::lookup::call(op, &tables),
+                            _ => {
+                                try!(table_errors(&tables));
+                                match *op {
+                                    FormulaOp::Irr | FormulaOp::XNpv => ::functions::finance::call_tables(op, &tables),
+                                    FormulaOp::Filter | FormulaOp::Sort | FormulaOp::Unique | FormulaOp::Sequence |
+                                    FormulaOp::Transpose => ::functions::array::call(op, &tables),
+                                    _ => ::functions::aggregate::call(op, &tables),
+                                }
+                            },
+                        };
+                    },
+                    FormulaOp::Offset | FormulaOp::Indirect => {
+                        let reference = try!(self.reference(formula, ctx, visited));
+                        return self.calc_formula_visited(&reference, ctx, visited);
+                    },
+                    // if(condition, then, else), evaluating only the branch
+                    // taken. Without `else` a false condition gives an empty
+                    // value.
+                    FormulaOp::If => {
+                        try!(::functions::check_arity(args, 2, 3));
+                        let condition = try!(self.calc_formula_visited(&args[0], ctx, &mut visited.clone()));
+                        let branch = match *condition {
+                            FormulaAtom::Empty => 2,
+                            ref x => if try!(::functions::number(x)) != 0.0 { 1 } else { 2 },
+                        };
+                        return match args.get(branch) {
+                            Some(x) => self.calc_formula_visited(x, ctx, visited),
+                            None => Ok(Box::new(FormulaAtom::Empty)),
+                        };
+                    },
+                    FormulaOp::Let => return self.calc_let(args, ctx, visited),
+                    FormulaOp::Lambda => {
+                        try!(::functions::check_arity(args, 1, ::std::usize::MAX));
+                        let (body, params) = args.split_last().unwrap();
+                        let mut names: Vec<String> = Vec::with_capacity(params.len());
+                        for param in params {
+                            let name = try!(param_name(param));
+                            if names.contains(&name) {
+                                return Err(FormulaErr::Type("Name"));
+                            }
+                            names.push(name);
+                        }
+                        return Ok(Box::new(FormulaAtom::Lambda(names, Box::new(body.clone()))));
+                    },
+                    FormulaOp::Apply => {
+                        try!(::functions::check_arity(args, 1, ::std::usize::MAX));
+                        let callee = try!(self.calc_formula_visited(&args[0], ctx, &mut visited.clone()));
+                        return self.apply(&callee, &args[1..], ctx, visited);
+                    },
+                    // A name in the book that stands for a lambda.
+                    FormulaOp::Custom(ref name) if ctx.book.name(name).is_some() => {
+                        let mut inner = visited.clone();
+                        let target = try!(self.resolve_name(name, ctx, &mut inner));
+                        let callee = try!(self.calc_formula_visited(target, ctx, &mut inner));
+                        return self.apply(&callee, args, ctx, visited);
+                    },
+                    FormulaOp::Add | FormulaOp::Sub | FormulaOp::Mul | FormulaOp::Div if args.len() > 1 => {
+                        return self.arithmetic(op, args, ctx, visited);
+                    },
+                    FormulaOp::Row | FormulaOp::Column | FormulaOp::Rows | FormulaOp::Columns => {
+                        try!(::functions::check_arity(args, 1, 1));
+                        let reference = try!(self.reference(&args[0], ctx, visited));
+                        let (Coord(col_from, row_from), Coord(col_to, row_to)) =
+                            try!(::functions::reference::bounds(&reference));
+                        let x = match *op {
+                            FormulaOp::Row => row_from + 1,
+                            FormulaOp::Column => col_from + 1,
+                            FormulaOp::Rows => row_to - row_from + 1,
+                            _ => col_to - col_from + 1,
+                        };
+                        return Ok(Box::new(FormulaAtom::Number(x as f64)));
+                    },
+                    _ => {},
+                }
+
+                let mut atoms = Vec::with_capacity(args.len());
+                for arg in args {
+                    try!(self.push_arg(arg, ctx, visited, &mut atoms));
+                }
+
+                match *op {
+                    FormulaOp::Add | FormulaOp::Sub | FormulaOp::Mul | FormulaOp::Div => {
+                        self.numeric_op(op, &atoms, ctx.book.number_mode())
+                    },
+                    FormulaOp::Avg => {
+                        if atoms.len() == 0 {
+                            return Err(FormulaErr::Arity(1));
+                        }
+                        if let NumberMode::Decimal{scale, rounding} = ctx.book.number_mode() {
+                            if let Some(decimals) = as_decimals(&atoms) {
+                                let sum = decimals.iter().fold(Decimal::from_integer(0), |acc, x| acc.add(x));
+                                let n = Decimal::from_integer(decimals.len() as i64);
+                                return Ok(Box::new(FormulaAtom::Decimal(sum.div(&n, scale, rounding).unwrap())));
+                            }
+                        }
+                        let (_, kind) = try!(Quantity::of(&atoms[0]));
+                        let mut sum = 0.0;
+                        for a in &atoms {
+                            match try!(Quantity::of(a)) {
+                                (x, k) if k == kind => { sum += x; },
+                                _ => { return Err(FormulaErr::Type("Number")); },
+                            }
+                        }
+                        Ok(Box::new(kind.atom(sum / atoms.len() as f64)))
+                    },
+                    FormulaOp::Today | FormulaOp::Now | FormulaOp::Date | FormulaOp::Year |
+                    FormulaOp::Month | FormulaOp::Day | FormulaOp::Weekday | FormulaOp::DateDif |
+                    FormulaOp::EDate | FormulaOp::EOMonth | FormulaOp::NetworkDays => {
+                        ::functions::date::call(op, &atoms)
+                    },
+                    FormulaOp::Address => ::functions::reference::call(op, &atoms),
+                    FormulaOp::Abs | FormulaOp::Sign | FormulaOp::Int | FormulaOp::Trunc |
+                    FormulaOp::RoundUp | FormulaOp::RoundDown | FormulaOp::Round |
+                    FormulaOp::Floor | FormulaOp::Ceiling | FormulaOp::Mod | FormulaOp::Power |
+                    FormulaOp::Sqrt | FormulaOp::Exp | FormulaOp::Ln | FormulaOp::Log10 |
+                    FormulaOp::Log | FormulaOp::Pi | FormulaOp::Sin | FormulaOp::Cos |
+                    FormulaOp::Tan | FormulaOp::Asin | FormulaOp::Acos | FormulaOp::Atan2 |
+                    FormulaOp::Atan | FormulaOp::RandBetween | FormulaOp::Rand => {
+                        ::functions::math::call(op, &atoms, ctx.book.random())
+                    },
+                    FormulaOp::Pmt | FormulaOp::Fv | FormulaOp::Pv | FormulaOp::Rate | FormulaOp::Npv => {
+                        ::functions::finance::call(op, &atoms)
+                    },
+                    FormulaOp::Custom(ref name) => {
+                        let function = try!(ctx.book.functions().function(name).ok_or(FormulaErr::Name(name.clone())));
+                        try!(::functions::check_arity(&atoms, function.min_args, function.max_args));
+                        (function.call)(&atoms)
+                    },
+                    FormulaOp::VLookup | FormulaOp::HLookup | FormulaOp::Index | FormulaOp::Match |
+                    FormulaOp::XLookup | FormulaOp::Offset | FormulaOp::Indirect | FormulaOp::Row |
+                    FormulaOp::Column | FormulaOp::Rows | FormulaOp::Columns | FormulaOp::Irr |
+                    FormulaOp::XNpv | FormulaOp::SumIf | FormulaOp::CountIf | FormulaOp::AverageIf |
+                    FormulaOp::SumIfs | FormulaOp::CountIfs | FormulaOp::AverageIfs | FormulaOp::Filter |
+                    FormulaOp::Sort | FormulaOp::Unique | FormulaOp::Sequence | FormulaOp::Transpose |
+                    FormulaOp::If | FormulaOp::Let | FormulaOp::Lambda | FormulaOp::Apply => unreachable!(),
+                }
+            }
+        }
+    }
+
+    /// Evaluates an argument of an operation, expanding ranges into the values
+    /// of their cells.
+    fn push_arg(&self, arg: &Formula, ctx: Context, visited: &HashSet<Visit>,
+                atoms: &mut Vec<Box<FormulaAtom>>) -> Result<(), FormulaErr> {
+        match *arg {
+            Formula::Range(from, _, to, _) => {
+                for coord in self.range_cells(from, to) {
+                    let cell = Formula::Ref(coord, Anchor(false, false));
+                    atoms.push(try!(self.calc_formula_visited(&cell, ctx, &mut visited.clone())));
+                }
+            },
+            Formula::SheetRef(ref name, ref inner) => match ctx.book.sheet_index(name) {
+                Some(idx) => {
+                    let other = Context{sheet: idx, ..ctx};
+                    try!(ctx.book.sheet_at(idx).push_arg(inner, other, visited, atoms));
+                },
+                None => { return Err(FormulaErr::InvalidRef); },
+            },
+            Formula::Name(ref name) => {
+                let mut visited = visited.clone();
+                let target = try!(self.resolve_name(name, ctx, &mut visited));
+                try!(self.push_arg(target, ctx, &visited, atoms));
+            },
+            Formula::Op(FormulaOp::Offset, _) | Formula::Op(FormulaOp::Indirect, _) => {
+                let reference = try!(self.reference(arg, ctx, visited));
+                try!(self.push_arg(&reference, ctx, visited, atoms));
+            },
+            _ => {
+                let value = try!(self.calc_formula_visited(arg, ctx, &mut visited.clone()));
+                match *value {
+                    FormulaAtom::Array(ref table) => atoms.extend(table.cells.iter().cloned()),
+                    _ => atoms.push(value.clone()),
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Evaluates an argument into a table. Ranges keep their shape, empty
+    /// cells included, and cells whose values are errors hold them, unless
+    /// they are part of a cycle; anything else is a table with a single value.
+    fn table(&self, arg: &Formula, ctx: Context, visited: &HashSet<Visit>) -> Result<Table, FormulaErr> {
+        self.table_of(arg, ctx, visited).map(|(table, _)| table)
+    }
+
+    /// Like `table`, along with whether the table holds the cells of a range
+    /// rather than a value.
+    fn table_of(&self, arg: &Formula, ctx: Context, visited: &HashSet<Visit>) -> Result<(Table, bool), FormulaErr> {
+        match *arg {
+            Formula::Range(Coord(col_from, row_from), _, Coord(col_to, row_to), _) => {
+                let (cols, rows) = (col_to - col_from + 1, row_to - row_from + 1);
+                let mut cells = Vec::with_capacity(cols * rows);
+                for row in row_from .. row_to+1 {
+                    for col in col_from .. col_to+1 {
+                        let cell = Formula::Ref(Coord(col, row), Anchor(false, false));
+                        cells.push(match self.calc_formula_visited(&cell, ctx, &mut visited.clone()) {
+                            Ok(x) => x,
+                            Err(FormulaErr::Ref(coord)) => { return Err(FormulaErr::Ref(coord)); },
+                            Err(e) => Box::new(FormulaAtom::Error(e)),
+                        });
+                    }
+                }
+                Ok((Table{cols: cols, rows: rows, cells: cells}, true))
+            },
+            Formula::SheetRef(ref name, ref inner) => match ctx.book.sheet_index(name) {
+                Some(idx) => {
+                    let other = Context{sheet: idx, ..ctx};
+                    ctx.book.sheet_at(idx).table_of(inner, other, visited)
+                },
+                None => Err(FormulaErr::InvalidRef),
+            },
+            Formula::Name(ref name) => {
+                let mut visited = visited.clone();
+                let target = try!(self.resolve_name(name, ctx, &mut visited));
+                self.table_of(target, ctx, &visited)
+            },
+            Formula::Op(FormulaOp::Offset, _) | Formula::Op(FormulaOp::Indirect, _) => {
+                let reference = try!(self.reference(arg, ctx, visited));
+                self.table_of(&reference, ctx, visited)
+            },
+            _ => {
+                let value = try!(self.calc_formula_visited(arg, ctx, &mut visited.clone()));
+                match *value {
+                    FormulaAtom::Array(ref table) => Ok((table.clone(), false)),
+                    _ => Ok((Table{cols: 1, rows: 1, cells: vec![value.clone()]}, false)),
+                }
+            },
+        }
+    }
+
+    /// Resolves an argument that stands for cells into a `Ref` or `Range`,
+    /// wrapped in a `SheetRef` if it's in another sheet. References built by
+    /// `offset` and `indirect` are resolved anew on every evaluation, and
+    /// reading them goes through the usual cycle checks.
+    fn reference(&self, arg: &Formula, ctx: Context, visited: &HashSet<Visit>) -> Result<Formula, FormulaErr> {
+        match *arg {
+            Formula::Ref(..) | Formula::Range(..) | Formula::SheetRef(..) => Ok(arg.clone()),
+            Formula::Name(ref name) => {
+                let mut visited = visited.clone();
+                let target = try!(self.resolve_name(name, ctx, &mut visited));
+                self.reference(target, ctx, &visited)
+            },
+            Formula::InvalidRef => Err(FormulaErr::InvalidRef),
+            Formula::Op(FormulaOp::Offset, ref args) => {
+                try!(::functions::check_arity(args, 3, 5));
+                let base = try!(self.reference(&args[0], ctx, visited));
+                let mut atoms = Vec::with_capacity(args.len() - 1);
+                for arg in &args[1..] {
+                    try!(self.push_arg(arg, ctx, visited, &mut atoms));
+                }
+                ::functions::reference::offset(&base, &atoms)
+            },
+            Formula::Op(FormulaOp::Indirect, ref args) => {
+                try!(::functions::check_arity(args, 1, 1));
+                let text = try!(self.calc_formula_visited(&args[0], ctx, &mut visited.clone()));
+                ::functions::reference::indirect(try!(::functions::string(&text)))
+            },
+            _ => Err(FormulaErr::Type("Reference")),
+        }
+    }
+
+    /// Evaluates `let(name, value, ..., body)`, where each name can be used
+    /// in the values after it and in the body.
+    fn calc_let(&self, args: &[Formula], ctx: Context, visited: &mut HashSet<Visit>) -> Value {
+        try!(::functions::check_arity(args, 3, ::std::usize::MAX));
+        if args.len() % 2 == 0 {
+            return Err(::functions::arity(args.len() + 1));
+        }
+        let mut rest = args.to_vec();
+        while rest.len() > 1 {
+            let name = try!(param_name(&rest[0]));
+            let value = try!(self.binding(&rest[1], ctx, visited));
+            rest = bind_let(&rest[2..], &name, &value);
+        }
+        self.calc_formula_visited(&rest[0], ctx, visited)
+    }
+
+    /// Calls a lambda with arguments, which are evaluated here.
+    fn apply(&self, callee: &FormulaAtom, args: &[Formula], ctx: Context, visited: &HashSet<Visit>) -> Value {
+        let (params, body) = match *callee {
+            FormulaAtom::Lambda(ref params, ref body) => (params, body),
+            _ => { return Err(FormulaErr::Type("Function")); },
+        };
+        if args.len() != params.len() {
+            return Err(::functions::arity(params.len()));
+        }
+
+        let depth = (0 .. MAX_CALL_DEPTH).find(|&n| !visited.contains(&Visit::Call(n)));
+        let mut inner = visited.clone();
+        match depth {
+            Some(n) => { inner.insert(Visit::Call(n)); },
+            None => { return Err(FormulaErr::CallDepth); },
+        }
+        let mut body = (**body).clone();
+        for (param, arg) in params.iter().zip(args.iter()) {
+            let value = try!(self.binding(arg, ctx, visited));
+            body = body.bind(param, &value);
+        }
+        self.calc_formula_visited(&body, ctx, &mut inner)
+    }
+
+    /// What a name bound by `let` or by calling a lambda stands for.
+    /// References stay references, so functions still see ranges; anything
+    /// else is evaluated once.
+    fn binding(&self, formula: &Formula, ctx: Context, visited: &HashSet<Visit>) -> Result<Formula, FormulaErr> {
+        match *formula {
+            Formula::Ref(..) | Formula::Range(..) | Formula::SheetRef(..) => { return Ok(formula.clone()); },
+            Formula::Name(_) | Formula::Op(FormulaOp::Offset, _) | Formula::Op(FormulaOp::Indirect, _) => {
+                if let Ok(reference) = self.reference(formula, ctx, visited) {
+                    return Ok(reference);
+                }
+            },
+            _ => {},
+        }
+        let value = try!(self.calc_formula_visited(formula, ctx, &mut visited.clone()));
+        Ok(Formula::Atom(*value))
+    }
+
+    /// The formula a name in the book stands for.
+    fn resolve_name<'a>(&self, name: &str, ctx: Context<'a>, visited: &mut HashSet<Visit>) -> Result<&'a Formula, FormulaErr> {
+        if !visited.insert(Visit::Name(name.to_string())) {
+            return Err(FormulaErr::NameCycle(name.to_string()));
+        }
+        match ctx.book.name(name) {
+            Some(target) => Ok(target),
+            None => Err(FormulaErr::Name(name.to_string())),
+        }
+    }
+
+    /// The non-empty cells inside a range, row by row, counting those that
+    /// arrays spill over.
+    fn range_cells(&self, from: Coord, to: Coord) -> Vec<Coord> {
+        let Coord(col_from, row_from) = from;
+        let Coord(col_to, row_to) = to;
+        let inside = |&Coord(col, row): &Coord| col >= col_from && col <= col_to && row >= row_from && row <= row_to;
+        let mut ret: Vec<Coord> = self.cells.keys().cloned().filter(&inside).collect();
+        for (Coord(anchor_col, anchor_row), Coord(end_col, end_row)) in self.spill_areas() {
+            for row in anchor_row .. end_row + 1 {
+                for col in anchor_col .. end_col + 1 {
+                    if inside(&Coord(col, row)) && (col, row) != (anchor_col, anchor_row) {
+                        ret.push(Coord(col, row));
+                    }
+                }
+            }
+        }
+        ret.sort_by(|&Coord(c1, r1), &Coord(c2, r2)| (r1, c1).cmp(&(r2, c2)));
+        ret.dedup();
+        ret
+    }
+
+    /// An arithmetic operation with more than one argument. Arrays go cell
+    /// by cell, and so do ranges that all have the same shape. Otherwise the
+    /// cells of ranges are added up with the rest, like `add(A1:A3, 1)`.
+    fn arithmetic(&self, op: &FormulaOp, args: &[Formula], ctx: Context, visited: &HashSet<Visit>) -> Value {
+        let mut tables = Vec::with_capacity(args.len());
+        let mut ranges = Vec::with_capacity(args.len());
+        for arg in args {
+            let (table, range) = try!(self.table_of(arg, ctx, visited));
+            tables.push(table);
+            ranges.push(range);
+        }
+        try!(table_errors(&tables));
+
+        let (cols, rows) = (tables[0].cols, tables[0].rows);
+        let arrays = tables.iter().zip(ranges.iter()).any(|(t, &range)| !range && t.cells.len() > 1);
+        let matching = ranges.iter().all(|&range| range) && cols * rows > 1 &&
+            tables.iter().all(|t| t.cols == cols && t.rows == rows);
+        if arrays || matching {
+            return self.elementwise_op(op, &tables, ctx.book.number_mode());
+        }
+        let mut atoms = vec![];
+        for (table, range) in tables.into_iter().zip(ranges.into_iter()) {
+            atoms.extend(table.cells.into_iter().filter(|x| !range || match **x {
+                FormulaAtom::Empty => false,
+                _ => true,
+            }));
+        }
+        self.numeric_op(op, &atoms, ctx.book.number_mode())
+    }
+
+    /// Applies an arithmetic operation element by element, giving an array
+    /// unless every argument has a single cell. Arguments with a single row
+    /// or column are repeated to the size of the others, and empty cells
+    /// count as zero.
+    fn elementwise_op(&self, op: &FormulaOp, tables: &[Table], mode: NumberMode) -> Value {
+        if tables.iter().all(|t| t.cols == 1 && t.rows == 1) {
+            let atoms = tables.iter().map(|t| t.cells[0].clone()).collect();
+            return self.numeric_op(op, &atoms, mode);
+        }
+
+        let cols = tables.iter().map(|t| t.cols).max().unwrap();
+        let rows = tables.iter().map(|t| t.rows).max().unwrap();
+        if tables.iter().any(|t| (t.cols != 1 && t.cols != cols) || (t.rows != 1 && t.rows != rows)) {
+            return Err(FormulaErr::Type("Range"));
+        }
+
+        let mut cells = Vec::with_capacity(cols * rows);
+        for row in 0 .. rows {
+            for col in 0 .. cols {
+                let atoms = tables.iter().map(|t| {
+                    match *t.get(if t.cols == 1 { 0 } else { col }, if t.rows == 1 { 0 } else { row }) {
+                        FormulaAtom::Empty => Box::new(FormulaAtom::Number(0.0)),
+                        ref x => Box::new(x.clone()),
+                    }
+                }).collect();
+                cells.push(try!(self.numeric_op(op, &atoms, mode)));
+            }
+        }
+        Ok(Box::new(FormulaAtom::Array(Table{cols: cols, rows: rows, cells: cells})))
+    }
+
+    /// Folds the arguments with an arithmetic operation, keeping track of
+    /// dates and durations: a date plus a number of days is a date, the
+    /// difference of two dates is a duration, and so on.
+    ///
+    /// In `NumberMode::Decimal`, plain numbers are added, subtracted and
+    /// multiplied exactly. Dates and durations are always floats.
+    fn numeric_op(&self, op: &FormulaOp, atoms: &Vec<Box<FormulaAtom>>, mode: NumberMode) -> Value {
+        use self::Quantity::{Number, Date, Duration};
+
+        if atoms.len() == 0 {
+            return Err(FormulaErr::Arity(1));
+        }
+
+        if let NumberMode::Decimal{scale, rounding} = mode {
+            if let Some(decimals) = as_decimals(atoms) {
+                let mut ret = decimals[0].clone();
+                for x in &decimals[1..] {
+                    ret = match *op {
+                        FormulaOp::Add => ret.add(x),
+                        FormulaOp::Sub => ret.sub(x),
+                        FormulaOp::Mul => ret.mul(x),
+                        FormulaOp::Div => match ret.div(x, scale, rounding) {
+                            Some(x) => x,
+                            None => { return Err(FormulaErr::Num); },
+                        },
+                        _ => unreachable!(),
+                    };
+                }
+                return Ok(Box::new(FormulaAtom::Decimal(ret)));
+            }
+        }
+
+        let (mut ret, mut kind) = try!(Quantity::of(&atoms[0]));
+        for a in &atoms[1..] {
+            let (x, x_kind) = try!(Quantity::of(a));
+            kind = match (op, kind, x_kind) {
+                (_, Number, Number) => Number,
+                (&FormulaOp::Add, Date, Number) | (&FormulaOp::Add, Number, Date) |
+                (&FormulaOp::Add, Date, Duration) | (&FormulaOp::Add, Duration, Date) |
+                (&FormulaOp::Sub, Date, Number) | (&FormulaOp::Sub, Date, Duration) => Date,
+                (&FormulaOp::Add, Duration, _) | (&FormulaOp::Add, _, Duration) |
+                (&FormulaOp::Sub, Duration, _) | (&FormulaOp::Sub, Number, Duration) |
+                (&FormulaOp::Sub, Date, Date) |
+                (&FormulaOp::Mul, Duration, Number) | (&FormulaOp::Mul, Number, Duration) |
+                (&FormulaOp::Div, Duration, Number) => Duration,
+                (&FormulaOp::Div, Duration, Duration) => Number,
+                _ => { return Err(FormulaErr::Type("Number")); },
+            };
+            ret = match *op {
+                FormulaOp::Add => ret + x,
+                FormulaOp::Sub => ret - x,
+                FormulaOp::Mul => ret * x,
+                FormulaOp::Div => ret / x,
+                _ => unreachable!(),
+            };
+        }
+        Ok(Box::new(kind.atom(ret)))
+    }
+}
+
+/// Gives the cell at `coord` a format or style, or removes it if `None`.
+fn put_attr<T>(attrs: &mut HashMap<Coord, T>, coord: Coord, attr: Option<T>) {
+    match attr {
+        Some(x) => { attrs.insert(coord, x); },
+        None => { attrs.remove(&coord); },
+    }
+}
+
+/// Moves the formats or styles of cells along with their rows or columns.
+fn move_attrs<T>(attrs: &mut HashMap<Coord, T>, shift: &LineShift) {
+    let old = ::std::mem::replace(attrs, HashMap::new());
+    for (coord, x) in old {
+        if let Some(coord) = shift.coord(coord) {
+            attrs.insert(coord, x);
+        }
+    }
+}
+
+/// Fails with the first error in the tables, as only lookups look past the
+/// errors in the ranges they are given.
+fn table_errors(tables: &[Table]) -> Result<(), FormulaErr> {
+    for table in tables {
+        for cell in &table.cells {
+            if let FormulaAtom::Error(ref e) = **cell {
+                return Err(e.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The atoms as decimals, if they are all plain numbers. Floats are converted
+/// with `Decimal::from_f64`.
+fn as_decimals(atoms: &[Box<FormulaAtom>]) -> Option<Vec<Decimal>> {
+    let mut ret = Vec::with_capacity(atoms.len());
+    for a in atoms {
+        ret.push(match **a {
+            FormulaAtom::Number(x) => match Decimal::from_f64(x) {
+                Some(x) => x,
+                None => { return None; },
+            },
+            FormulaAtom::Decimal(ref x) => x.clone(),
+            _ => { return None; },
+        });
+    }
+    Some(ret)
+}
+
+/// The kinds of atoms arithmetic works with.
+#[derive(Clone, Copy, PartialEq)]
+enum Quantity {
+    Number,
+    Date,
+    Duration,
+}
+
+impl Quantity {
+    fn of(atom: &FormulaAtom) -> Result<(f64, Quantity), FormulaErr> {
+        match *atom {
+            FormulaAtom::Number(x) => Ok((x, Quantity::Number)),
+            FormulaAtom::Decimal(ref x) => Ok((x.to_f64(), Quantity::Number)),
+            FormulaAtom::Date(x) => Ok((x, Quantity::Date)),
+            FormulaAtom::Duration(x) => Ok((x, Quantity::Duration)),
+            _ => Err(FormulaErr::Type("Number")),
+        }
+    }
+
+    fn atom(&self, x: f64) -> FormulaAtom {
+        match *self {
+            Quantity::Number => FormulaAtom::Number(x),
+            Quantity::Date => FormulaAtom::Date(x),
+            Quantity::Duration => FormulaAtom::Duration(x),
+        }
+    }
+}
+
+/// A set of sheets that can reference each other's cells.
+pub trait Book: Sync {
+    fn sheet_at(&self, idx: usize) -> &Sheet;
+    fn sheet_index(&self, name: &str) -> Option<usize>;
+    /// What a `Formula::Name` stands for.
+    fn name(&self, name: &str) -> Option<&Formula>;
+    /// What `rand` and `randbetween` draw from.
+    fn random(&self) -> &Random;
+    /// The functions formulas can call besides the built-in ones.
+    fn functions(&self) -> &FunctionRegistry;
+    fn sheet_count(&self) -> usize;
+    /// The cells that read each cell, kept by `deps::update` once worked
+    /// out.
+    fn dependents(&self) -> &Mutex<Option<Dependents>>;
+
+    fn number_mode(&self) -> NumberMode {
+        NumberMode::Float
+    }
+
+    /// Whether any formula calls a volatile function. Their cells are
+    /// evaluated one by one, each time they're read, as what they give can
+    /// depend on the order, like the numbers `rand` draws.
+    fn is_volatile(&self) -> bool {
+        false
+    }
+
+    /// How many threads cells can be evaluated on at once, 1 for volatile
+    /// books.
+    fn threads(&self) -> usize {
+        1
+    }
+}
+
+/// How a book does arithmetic with numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumberMode {
+    /// Floating point numbers, fast but inexact: `add(0.1, 0.2)` is
+    /// `0.30000000000000004`.
+    Float,
+    /// Exact decimals. Quotients are rounded to `scale` decimal places.
+    /// Functions that only work with floats get the nearest float, and
+    /// floats they return are turned back into decimals when they meet one.
+    Decimal{scale: u32, rounding: Rounding},
+}
+
+/// A sheet on its own is a book without names, so references to other sheets
+/// can't be resolved.
+impl Book for Sheet {
+    fn sheet_at(&self, _: usize) -> &Sheet {
+        self
+    }
+
+    fn sheet_index(&self, _: &str) -> Option<usize> {
+        None
+    }
+
+    fn sheet_count(&self) -> usize {
+        1
+    }
+
+    fn name(&self, _: &str) -> Option<&Formula> {
+        None
+    }
+
+    fn random(&self) -> &Random {
+        &self.random
+    }
+
+    fn functions(&self) -> &FunctionRegistry {
+        &*self.functions
+    }
+
+    fn is_volatile(&self) -> bool {
+        let mut volatile = self.volatile.lock().unwrap();
+        if volatile.is_none() {
+            *volatile = Some(self.has_volatile(&*self.functions));
+        }
+        volatile.unwrap()
+    }
+
+    fn threads(&self) -> usize {
+        if self.is_volatile() { 1 } else { self.threads }
+    }
+
+    fn dependents(&self) -> &Mutex<Option<Dependents>> {
+        &self.dependents
+    }
+}
+
+/// `f` of every index up to `len`, worked out on up to `threads` threads at
+/// once, with at least `CELLS_PER_THREAD` indices for each.
+fn in_parallel<T, F>(len: usize, threads: usize, f: F) -> Vec<T>
+    where T: Send, F: Fn(usize) -> T + Sync
+{
+    use ::std::sync::atomic::{AtomicUsize, Ordering};
+
+    let threads = ::std::cmp::min(threads, len / CELLS_PER_THREAD);
+    if threads <= 1 {
+        return (0 .. len).map(|idx| f(idx)).collect();
+    }
+
+    // Every thread, this one included, takes the next index left until
+    // there are none, so that slow cells don't hold up the others.
+    let next = AtomicUsize::new(0);
+    let work = || {
+        let mut done = vec![];
+        loop {
+            let idx = next.fetch_add(1, Ordering::SeqCst);
+            if idx >= len {
+                return done;
+            }
+            done.push((idx, f(idx)));
+        }
+    };
+    let work = &work;
+    let guards: Vec<_> = (1 .. threads).filter_map(|_| {
+        ::std::thread::Builder::new().stack_size(STACK_SIZE).scoped(move || work()).ok()
+    }).collect();
+
+    let mut ret: Vec<Option<T>> = (0 .. len).map(|_| None).collect();
+    for (idx, x) in work().into_iter().chain(guards.into_iter().flat_map(|g| g.join().into_iter())) {
+        ret[idx] = Some(x);
+    }
+    ret.into_iter().map(|x| x.unwrap()).collect()
+}
+
+/// The cells from `from` to `to`, column by column.
+pub fn cells_between(Coord(col_from, row_from): Coord, Coord(col_to, row_to): Coord) -> Vec<Coord> {
+    (col_from .. col_to+1).flat_map(|col| (row_from .. row_to+1).map(move |row| Coord(col, row))).collect()
+}
+
+/// The top left and bottom right corners of the rectangle with the
+/// opposite corners `a` and `b`.
+pub fn corners(Coord(a_col, a_row): Coord, Coord(b_col, b_row): Coord) -> (Coord, Coord) {
+    use ::std::cmp::{min, max};
+
+    (Coord(min(a_col, b_col), min(a_row, b_row)), Coord(max(a_col, b_col), max(a_row, b_row)))
+}
+
+/// The name a `let` or a lambda binds.
+fn param_name(formula: &Formula) -> Result<String, FormulaErr> {
+    match *formula {
+        Formula::Name(ref name) => Ok(name.clone()),
+        _ => Err(FormulaErr::Type("Name")),
+    }
+}
+
+/// Binds a name in the arguments of a `let`: in the values and the body,
+/// but not in the names, and not after the `let` binds the name again.
+fn bind_let(args: &[Formula], name: &str, value: &Formula) -> Vec<Formula> {
+    let mut ret = Vec::with_capacity(args.len());
+    let mut shadowed = false;
+    for (idx, arg) in args.iter().enumerate() {
+        if shadowed || (idx % 2 == 0 && idx + 1 < args.len()) {
+            ret.push(arg.clone());
+        } else {
+            ret.push(arg.bind(name, value));
+        }
+        if idx % 2 == 1 && args[idx - 1] == Formula::Name(name.to_string()) {
+            shadowed = true;
+        }
+    }
+    ret
+}
+
+/// Something already being evaluated, to detect cycles.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Visit {
+    Cell(usize, Coord),
+    Name(String),
+    /// A lambda call, `n` calls deep.
+    Call(usize),
+}
+
+/// Where a formula is being evaluated: the sheet at `sheet` in `book`.
+#[derive(Clone, Copy)]
+pub struct Context<'a> {
+    pub book: &'a Book,
+    pub sheet: usize,
+    /// What formulas already gave, when many cells are evaluated at once.
+    pub cache: Option<&'a Cache>,
+}
+
+/// What the formulas of cells give, by sheet and cell, worked out once for
+/// every cell of a pass of `Sheet::values_in` that reads them.
+pub struct Cache {
+    results: RwLock<HashMap<(usize, Coord), Arc<Value>>>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache{results: RwLock::new(HashMap::new())}
     }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Formula {
+    Atom(FormulaAtom),
+    Ref(Coord, Anchor),
+    /// A rectangle of cells, from its top-left to its bottom-right corner.
+    Range(Coord, Anchor, Coord, Anchor),
+    /// A `Ref` or `Range` in another sheet of the book, like `Sheet2!B3`.
+    SheetRef(String, Box<Formula>),
+    /// A name defined in the book, like `TaxRate`.
+    Name(String),
+    /// A reference that points outside the grid, written `#REF!`.
+    InvalidRef,
+    Op(FormulaOp, Vec<Formula>),
+}
 
-    pub fn set(&mut self, coord: Coord, formula: Formula) {
-        if let Formula::Atom(FormulaAtom::Empty) = formula {
-            self.cells.remove(&coord);
+impl Formula {
+    /// Builds a `Formula::Range` from any two opposite corners.
+    pub fn range(a: Coord, a_anchor: Anchor, b: Coord, b_anchor: Anchor) -> Formula {
+        let (Coord(a_col, a_row), Anchor(a_col_abs, a_row_abs)) = (a, a_anchor);
+        let (Coord(b_col, b_row), Anchor(b_col_abs, b_row_abs)) = (b, b_anchor);
+        let (col_from, col_to) = if a_col <= b_col {
+            ((a_col, a_col_abs), (b_col, b_col_abs))
+        } else {
+            ((b_col, b_col_abs), (a_col, a_col_abs))
+        };
+        let (row_from, row_to) = if a_row <= b_row {
+            ((a_row, a_row_abs), (b_row, b_row_abs))
         } else {
-            self.cells.insert(coord, formula);
+            ((b_row, b_row_abs), (a_row, a_row_abs))
         };
+        Formula::Range(
+            Coord(col_from.0, row_from.0), Anchor(col_from.1, row_from.1),
+            Coord(col_to.0, row_to.0), Anchor(col_to.1, row_to.1))
+    }
 
-        let Coord(col, row) = coord;
-        for &(Coord(col_from, row_from), Coord(col_to, row_to), ref tx) in &self.selections {
-            if col >= col_from && col <= col_to && row >= row_from && row <= row_to {
-                if let Err(_) = tx.send((coord, self.value(coord))) {
-                    // TODO: Should remove here.
+    /// Returns a copy of this formula as if it were moved `dc` columns and `dr`
+    /// rows away. Only the relative parts of references move; a reference that
+    /// would end up outside the grid becomes `Formula::InvalidRef`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sheets_lib::sheet::*;
+    ///
+    /// let f = Formula::Ref(Coord(1, 1), Anchor(true, false));
+    /// assert_eq!(Formula::Ref(Coord(1, 3), Anchor(true, false)), f.shift(2, 2));
+    /// assert_eq!(Formula::InvalidRef, f.shift(0, -2));
+    /// ```
+    pub fn shift(&self, dc: isize, dr: isize) -> Formula {
+        match *self {
+            Formula::Ref(Coord(col, row), anchor) => {
+                let Anchor(col_abs, row_abs) = anchor;
+                let col = if col_abs { Some(col) } else { shift_index(col, dc) };
+                let row = if row_abs { Some(row) } else { shift_index(row, dr) };
+                match (col, row) {
+                    (Some(col), Some(row)) => Formula::Ref(Coord(col, row), anchor),
+                    _ => Formula::InvalidRef,
                 }
-            }
+            },
+            Formula::Range(from, from_anchor, to, to_anchor) => {
+                match (Formula::Ref(from, from_anchor).shift(dc, dr), Formula::Ref(to, to_anchor).shift(dc, dr)) {
+                    (Formula::Ref(from, from_anchor), Formula::Ref(to, to_anchor)) => {
+                        Formula::Range(from, from_anchor, to, to_anchor)
+                    },
+                    _ => Formula::InvalidRef,
+                }
+            },
+            Formula::SheetRef(ref name, ref inner) => match inner.shift(dc, dr) {
+                Formula::InvalidRef => Formula::InvalidRef,
+                x => Formula::SheetRef(name.clone(), Box::new(x)),
+            },
+            Formula::Op(ref op, ref args) => {
+                Formula::Op(op.clone(), args.iter().map(|x| x.shift(dc, dr)).collect())
+            },
+            ref x => x.clone(),
         }
     }
 
-    pub fn select(&mut self, from: Coord, to: Coord) -> Receiver<(Coord, Value)> {
-        let (tx, rx) = channel();
-
-        let Coord(col_from, row_from) = from;
-        let Coord(col_to, row_to) = to;
-
-        for col in col_from .. col_to+1 {
-            for row in row_from .. row_to+1 {
-                tx.send((Coord(col, row), self.value(Coord(col, row)))).unwrap();
-            }
+    /// Whether the formula can give an array, which spills over the cells
+    /// next to its own: array literals, functions like `sort`, arithmetic
+    /// with more than one argument when some of them have more than one cell,
+    /// and the `let`s and lambda calls that give any of those.
+    pub fn may_spill(&self) -> bool {
+        match *self {
+            Formula::Atom(FormulaAtom::Array(_)) => true,
+            Formula::Op(FormulaOp::Filter, _) | Formula::Op(FormulaOp::Sort, _) | Formula::Op(FormulaOp::Unique, _) |
+            Formula::Op(FormulaOp::Sequence, _) | Formula::Op(FormulaOp::Transpose, _) => true,
+            // A `let` gives what its body does with the values it binds, and
+            // a call to a lambda what its body does with the arguments.
+            Formula::Op(FormulaOp::Let, ref args) => {
+                let mut rest = args.to_vec();
+                while rest.len() > 1 {
+                    let name = match param_name(&rest[0]) {
+                        Ok(x) => x,
+                        Err(_) => { return false; },
+                    };
+                    rest = bind_let(&rest[2..], &name, &rest[1]);
+                }
+                rest.first().map_or(false, |x| x.may_spill())
+            },
+            Formula::Op(FormulaOp::Apply, ref args) => {
+                let (callee, args) = match args.split_first() {
+                    Some(x) => x,
+                    None => { return false; },
+                };
+                let (params, body): (Vec<String>, Formula) = match *callee {
+                    Formula::Op(FormulaOp::Lambda, ref lambda) => match lambda.split_last() {
+                        Some((body, params)) => {
+                            (params.iter().filter_map(|x| param_name(x).ok()).collect(), (*body).clone())
+                        },
+                        None => { return false; },
+                    },
+                    Formula::Atom(FormulaAtom::Lambda(ref params, ref body)) => (params.clone(), (**body).clone()),
+                    // A lambda called through a name could be any lambda.
+                    _ => { return true; },
+                };
+                params.iter().zip(args.iter()).fold(body, |body, (param, arg)| body.bind(param, arg)).may_spill()
+            },
+            // Their results aren't known until they are evaluated.
+            Formula::Op(FormulaOp::Custom(_), _) => true,
+            Formula::Op(FormulaOp::Add, ref args) | Formula::Op(FormulaOp::Sub, ref args) |
+            Formula::Op(FormulaOp::Mul, ref args) | Formula::Op(FormulaOp::Div, ref args) if args.len() > 1 => {
+                args.iter().any(|x| match *x {
+                    Formula::Range(..) | Formula::SheetRef(..) | Formula::Name(_) |
+                    Formula::Op(FormulaOp::Offset, _) | Formula::Op(FormulaOp::Indirect, _) => true,
+                    ref x => x.may_spill(),
+                })
+            },
+            _ => false,
         }
+    }
 
-        self.selections.push((from, to, tx));
-
-        rx
+    /// Replaces a name bound by `let` or by calling a lambda with what it
+    /// stands for, except where an inner `let` or lambda binds the same name
+    /// again. Calls to the name become calls to `value`.
+    pub fn bind(&self, name: &str, value: &Formula) -> Formula {
+        match *self {
+            Formula::Name(ref x) if x == name => value.clone(),
+            Formula::Op(FormulaOp::Custom(ref x), ref args) if x == name => {
+                let mut args: Vec<Formula> = args.iter().map(|x| x.bind(name, value)).collect();
+                args.insert(0, value.clone());
+                Formula::Op(FormulaOp::Apply, args)
+            },
+            Formula::Op(FormulaOp::Let, ref args) => Formula::Op(FormulaOp::Let, bind_let(args, name, value)),
+            Formula::Op(FormulaOp::Lambda, ref args) if args.split_last().map_or(false, |(_, params)| {
+                params.contains(&Formula::Name(name.to_string()))
+            }) => {
+                self.clone()
+            },
+            Formula::Op(ref op, ref args) => {
+                Formula::Op(op.clone(), args.iter().map(|x| x.bind(name, value)).collect())
+            },
+            ref x => x.clone(),
+        }
     }
 
-    pub fn value(&self, coord: Coord) -> Value {
-        match self.cells.get(&coord) {
-            Some(x) => self.calc_formula(x),
-            _ => Ok(Box::new(FormulaAtom::Empty)),
+    /// Whether the formula calls a function that can give a different result
+    /// every time, like `now()` or `rand()`.
+    pub fn is_volatile(&self, functions: &FunctionRegistry) -> bool {
+        match *self {
+            Formula::Op(ref op, ref args) => {
+                let volatile = match *op {
+                    FormulaOp::Today | FormulaOp::Now | FormulaOp::Rand | FormulaOp::RandBetween => true,
+                    FormulaOp::Custom(ref name) => functions.function(name).map_or(false, |f| f.volatile),
+                    _ => false,
+                };
+                volatile || args.iter().any(|x| x.is_volatile(functions))
+            },
+            _ => false,
         }
     }
 
-    fn calc_formula(&self, formula: &Formula) -> Value {
-        let mut visited = HashSet::new();
-        self.calc_formula_visited(formula, &mut visited)
+    /// Rebuilds the formula bottom up, replacing the parts for which `f`
+    /// returns something.
+    pub fn map<F>(&self, f: &F) -> Formula
+        where F: Fn(&Formula) -> Option<Formula>
+    {
+        if let Some(x) = f(self) {
+            return x;
+        }
+        match *self {
+            Formula::Op(ref op, ref args) => {
+                Formula::Op(op.clone(), args.iter().map(|x| x.map(f)).collect())
+            },
+            ref x => x.clone(),
+        }
     }
 
-    fn calc_formula_visited(&self, formula: &Formula, visited: &mut HashSet<Coord>) -> Value {
-        match *formula {
-            Formula::Atom(ref x) => Ok(Box::new(x.clone())),
-            Formula::Ref(coord) => {
-                if visited.contains(&coord) {
-                    return Err(FormulaErr::Ref(coord));
-                }
-                visited.insert(coord);
-                match self.cells.get(&coord) {
-                    Some(f) => self.calc_formula_visited(f, visited),
-                    None => Ok(Box::new(FormulaAtom::Empty)),
-                }
+    /// Rewrites references after rows or columns were inserted or deleted, so
+    /// they keep pointing at the same data. Unlike `shift`, this moves
+    /// absolute references too. References to other sheets are left alone.
+    pub fn move_lines(&self, shift: &LineShift) -> Formula {
+        match *self {
+            Formula::Ref(coord, anchor) => match shift.coord(coord) {
+                Some(coord) => Formula::Ref(coord, anchor),
+                None => Formula::InvalidRef,
+            },
+            Formula::Range(from, from_anchor, to, to_anchor) => match shift.range(from, to) {
+                Some((from, to)) => Formula::Range(from, from_anchor, to, to_anchor),
+                None => Formula::InvalidRef,
             },
             Formula::Op(ref op, ref args) => {
-                let mut atoms = Vec::with_capacity(args.len());
-                for arg in args {
-                    match self.calc_formula_visited(&arg, &mut visited.clone()) {
-                        Ok(x) => { atoms.push(x); }
-                        Err(x) => { return Err(x); },
-                    }
-                }
+                Formula::Op(op.clone(), args.iter().map(|x| x.move_lines(shift)).collect())
+            },
+            ref x => x.clone(),
+        }
+    }
+}
 
-                match *op {
-                    FormulaOp::Add => {
-                        use ::std::ops::Add;
-                        self.numeric_op(Add::add, &atoms)
-                    },
-                    FormulaOp::Sub => {
-                        use ::std::ops::Sub;
-                        self.numeric_op(Sub::sub, &atoms)
-                    },
-                    FormulaOp::Mul => {
-                        use ::std::ops::Mul;
-                        self.numeric_op(Mul::mul, &atoms)
-                    },
-                    FormulaOp::Div => {
-                        use ::std::ops::Div;
-                        self.numeric_op(Div::div, &atoms)
-                    },
-                    FormulaOp::Avg => {
-                        use ::std::ops::Add;
-                        match self.numeric_op(Add::add, &atoms) {
-                            Ok(b) => {
-                                if let FormulaAtom::Number(ref x) = *b {
-                                    Ok(Box::new(FormulaAtom::Number(x / atoms.len() as f64)))
-                                } else {
-                                    unreachable!()
-                                }
-                            },
-                            Err(x) => Err(x)
-                        }
-                    },
-                }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FillDirection {
+    Down,
+    Right,
+}
+
+/// Extends `seeds` to `len` formulas. See `Sheet::fill_series`.
+fn extend_series(seeds: &[Formula], len: usize, direction: FillDirection, mode: NumberMode) -> Vec<Formula> {
+    let decimals: Vec<Decimal> = seeds.iter().filter_map(|f| match *f {
+        Formula::Atom(FormulaAtom::Number(x)) if mode != NumberMode::Float => Decimal::from_f64(x),
+        Formula::Atom(FormulaAtom::Decimal(ref x)) => Some(x.clone()),
+        _ => None,
+    }).collect();
+
+    if seeds.len() > 1 && decimals.len() == seeds.len() {
+        let last = &decimals[decimals.len() - 1];
+        let step = last.sub(&decimals[decimals.len() - 2]);
+        return (0 .. len).map(|i| {
+            if i < seeds.len() {
+                seeds[i].clone()
+            } else {
+                let steps = Decimal::from_integer((i + 1 - seeds.len()) as i64);
+                Formula::Atom(FormulaAtom::Decimal(last.add(&step.mul(&steps))))
+            }
+        }).collect();
+    }
+
+    let numbers: Vec<f64> = seeds.iter().filter_map(|f| match *f {
+        Formula::Atom(FormulaAtom::Number(x)) => Some(x),
+        Formula::Atom(FormulaAtom::Decimal(ref x)) => Some(x.to_f64()),
+        _ => None,
+    }).collect();
+
+    if seeds.len() > 1 && numbers.len() == seeds.len() {
+        let (intercept, slope) = linear_trend(&numbers);
+        return (0 .. len).map(|i| {
+            if i < seeds.len() {
+                seeds[i].clone()
+            } else {
+                Formula::Atom(FormulaAtom::Number(intercept + slope * i as f64))
             }
+        }).collect();
+    }
+
+    (0 .. len).map(|i| {
+        let seed = i % seeds.len();
+        let offset = (i - seed) as isize;
+        match direction {
+            FillDirection::Down => seeds[seed].shift(0, offset),
+            FillDirection::Right => seeds[seed].shift(offset, 0),
         }
+    }).collect()
+}
+
+/// The least squares line through `(0, ys[0]), (1, ys[1])...`, as
+/// `(intercept, slope)`.
+fn linear_trend(ys: &[f64]) -> (f64, f64) {
+    let n = ys.len() as f64;
+    let x_mean = (n - 1.0) / 2.0;
+    let y_mean = ys.iter().fold(0.0, |acc, y| acc + y) / n;
+    let (mut num, mut den) = (0.0, 0.0);
+    for (x, y) in ys.iter().enumerate() {
+        let dx = x as f64 - x_mean;
+        num += dx * (y - y_mean);
+        den += dx * dx;
     }
+    let slope = num / den;
+    (y_mean - slope * x_mean, slope)
+}
 
-    fn numeric_op<F>(&self, f: F, atoms: &Vec<Box<FormulaAtom>>) -> Value
-        where F: Fn(f64, f64) -> f64
-    {
-        if atoms.len() == 0 {
-            return Err(FormulaErr::Arity(1));
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Axis {
+    Col,
+    Row,
+}
+
+/// The insertion or deletion of `count` rows or columns at `at`.
+#[derive(Debug, Clone, Copy)]
+pub struct LineShift {
+    pub axis: Axis,
+    pub at: usize,
+    pub count: usize,
+    pub insert: bool,
+}
+
+impl LineShift {
+    /// Where a row or column index ends up; `None` if it was deleted.
+    fn index(&self, idx: usize) -> Option<usize> {
+        if idx < self.at {
+            Some(idx)
+        } else if self.insert {
+            Some(idx + self.count)
+        } else if idx >= self.at + self.count {
+            Some(idx - self.count)
+        } else {
+            None
+        }
+    }
+
+    /// Where a span of rows or columns ends up. It grows or shrinks with the
+    /// lines inserted or deleted inside of it; `None` if it was deleted
+    /// completely.
+    fn span(&self, from: usize, to: usize) -> Option<(usize, usize)> {
+        match (self.index(from), self.index(to)) {
+            (Some(from), Some(to)) => Some((from, to)),
+            (None, Some(to)) => Some((self.at, to)),
+            (Some(from), None) => Some((from, self.at - 1)),
+            (None, None) => None,
         }
+    }
 
-        let mut first = true;
-        let mut ret = 0.0;
-        for a in atoms {
-            match **a {
-                FormulaAtom::Number(x) => { 
-                    if first {
-                        ret = x;
-                        first = false;
-                    } else {
-                        ret = f(ret, x);
-                    }
-                },
-                _ => { return Err(FormulaErr::Type("Number")) }
-            }
+    fn coord(&self, Coord(col, row): Coord) -> Option<Coord> {
+        match self.axis {
+            Axis::Col => self.index(col).map(|col| Coord(col, row)),
+            Axis::Row => self.index(row).map(|row| Coord(col, row)),
+        }
+    }
+
+    fn range(&self, Coord(col_from, row_from): Coord, Coord(col_to, row_to): Coord) -> Option<(Coord, Coord)> {
+        match self.axis {
+            Axis::Col => self.span(col_from, col_to).map(|(from, to)| {
+                (Coord(from, row_from), Coord(to, row_to))
+            }),
+            Axis::Row => self.span(row_from, row_to).map(|(from, to)| {
+                (Coord(col_from, from), Coord(col_to, to))
+            }),
+        }
+    }
+
+    /// Moves a selection along with the cells in it. A selection whose cells
+    /// were all deleted stays where it was.
+    pub fn move_selection(&self, from: &mut Coord, to: &mut Coord) {
+        if let Some((new_from, new_to)) = self.range(*from, *to) {
+            *from = new_from;
+            *to = new_to;
         }
-        Ok(Box::new(FormulaAtom::Number(ret)))
     }
 }
 
-#[derive(Clone, PartialEq, Debug)]
-pub enum Formula {
-    Atom(FormulaAtom),
-    Ref(Coord),
-    Op(FormulaOp, Vec<Formula>),
+fn shift_index(idx: usize, delta: isize) -> Option<usize> {
+    let ret = idx as isize + delta;
+    if ret < 0 { None } else { Some(ret as usize) }
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -154,6 +1986,37 @@ pub enum FormulaAtom {
     Empty,
     String(String),
     Number(f64),
+    /// A date and time as a serial number. See the `date` module.
+    Date(f64),
+    /// A number of days, which can have a fractional part.
+    Duration(f64),
+    /// An exact number. See `NumberMode::Decimal`.
+    Decimal(Decimal),
+    /// A function made with `lambda`, with the names of its parameters and
+    /// its body, where the names it uses from an enclosing `let` or lambda are
+    /// already replaced.
+    Lambda(Vec<String>, Box<Formula>),
+    /// Several values, like `{1, 2; 3, 4}`. A cell whose formula gives an
+    /// array spills it over the cells to its right and below.
+    Array(Table),
+    /// The value of a cell in a table that is an error. Only lookups see
+    /// these, and give the error if it's what they find.
+    Error(FormulaErr),
+}
+
+/// A rectangle of values, like the cells of a range.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Table {
+    pub cols: usize,
+    pub rows: usize,
+    /// Row by row.
+    pub cells: Vec<Box<FormulaAtom>>,
+}
+
+impl Table {
+    pub fn get(&self, col: usize, row: usize) -> &FormulaAtom {
+        &self.cells[row * self.cols + col]
+    }
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -163,13 +2026,105 @@ pub enum FormulaOp {
     Mul,
     Div,
     Avg,
+    Today,
+    Now,
+    Date,
+    Year,
+    Month,
+    Day,
+    Weekday,
+    DateDif,
+    EDate,
+    EOMonth,
+    NetworkDays,
+    VLookup,
+    HLookup,
+    Index,
+    Match,
+    XLookup,
+    Offset,
+    Indirect,
+    Row,
+    Column,
+    Rows,
+    Columns,
+    Address,
+    Abs,
+    Sign,
+    Int,
+    Trunc,
+    RoundUp,
+    RoundDown,
+    Round,
+    Floor,
+    Ceiling,
+    Mod,
+    Power,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Log,
+    Pi,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan2,
+    Atan,
+    RandBetween,
+    Rand,
+    Pmt,
+    Fv,
+    Pv,
+    Rate,
+    Npv,
+    Irr,
+    XNpv,
+    SumIf,
+    CountIf,
+    AverageIf,
+    SumIfs,
+    CountIfs,
+    AverageIfs,
+    Filter,
+    Sort,
+    Unique,
+    Sequence,
+    Transpose,
+    If,
+    Let,
+    Lambda,
+    /// Calls the lambda in the first argument with the rest, as in
+    /// `lambda(x, mul(x, 2))(3)`.
+    Apply,
+    /// A name in the book that stands for a lambda, or else a function from
+    /// the book's `FunctionRegistry`.
+    Custom(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum FormulaErr {
     Ref(Coord),
     Type(&'static str),
     Arity(u8),
+    InvalidRef,
+    /// A result that can't be calculated, like a date difference with the
+    /// dates reversed. Shown as `#NUM!`.
+    Num,
+    /// A name that isn't defined, shown as `#NAME?`.
+    Name(String),
+    /// A name that ends up standing for itself.
+    NameCycle(String),
+    /// A value that isn't there, like a lookup that finds nothing. Shown as
+    /// `#N/A`.
+    NA,
+    /// An array that can't spill because other cells with formulas are in
+    /// the way. Shown as `#SPILL!`.
+    Spill,
+    /// Lambdas calling each other more than `MAX_CALL_DEPTH` deep.
+    CallDepth,
 }
 
 /// The position of a cell in a spreadsheet. The cell at A1 has `Coord(0, 0)`.
@@ -178,27 +2133,56 @@ pub struct Coord(pub usize, pub usize);
 
 impl Coord {
     pub fn parse(s: &str) -> Option<Self> {
-        let idx = match s.find(|c: char| c.is_numeric()) {
+        Coord::parse_anchored(s).map(|(coord, _)| coord)
+    }
+
+    /// Parses a reference that may have `$` markers before the column and/or
+    /// the row, like `$A$1`, `A$1` or `$A1`.
+    pub fn parse_anchored(s: &str) -> Option<(Self, Anchor)> {
+        let col_abs = s.starts_with("$");
+        let s = if col_abs { &s[1..] } else { s };
+
+        let idx = match s.find(|c: char| c.is_numeric() || c == '$') {
             None => { return None; },
             Some(x) => x,
         };
 
         let (l, r) = (&s[0..idx], &s[idx..]);
+        let row_abs = r.starts_with("$");
+        let r = if row_abs { &r[1..] } else { r };
+
+        if l.len() == 0 || !l.bytes().all(|b| b >= 'A' as u8 && b <= 'Z' as u8) {
+            return None;
+        }
 
         match r.parse().ok() {
-            None => None,
-            Some(x) => Some(natural_to_numeric(l, x)),
+            None | Some(0) => None,
+            Some(x) => Some((natural_to_numeric(l, x), Anchor(col_abs, row_abs))),
         }
     }
 
     pub fn format_natural(&self) -> String {
+        self.format_anchored(Anchor(false, false))
+    }
+
+    pub fn format_anchored(&self, anchor: Anchor) -> String {
         let Coord(col, row) = *self;
-        let mut ret = numeric_col_to_natural(col);
+        let Anchor(col_abs, row_abs) = anchor;
+        let mut ret = if col_abs { "$".to_string() } else { "".to_string() };
+        ret.push_str(numeric_col_to_natural(col).as_str());
+        if row_abs {
+            ret.push_str("$");
+        }
         ret.push_str(format!("{}", row + 1).as_str());
         ret
     }
 }
 
+/// Which parts of a reference are absolute, as `Anchor(col, row)`. `$A1` has
+/// `Anchor(true, false)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Anchor(pub bool, pub bool);
+
 /// The position of a cell in a spreadsheet in a "natural" representation.
 /// The cell at A1 has `Coord("A1", 1)`.
 #[derive(Debug, PartialEq,  Eq)]
@@ -239,23 +2223,23 @@ pub fn natural_col_to_numeric(col: &str) -> usize {
 }
 
 pub fn numeric_col_to_natural(col: usize) -> String {
-    // TODO: This is only for two letters.
-    let mut ret = "".to_string();
-    let c = col + 1;
-    let i = c / 27;
-    let rem = col - (i * 26);
-    if i > 0 {
-        ret.push((i as u8 + 'A' as u8) as char);
-    }
-    if rem > 0 {
-        ret.push((rem as u8 + 'A' as u8) as char);
+    let mut letters = vec![];
+    let mut c = col + 1;
+    while c > 0 {
+        letters.push(((c - 1) % 26) as u8 + 'A' as u8);
+        c = (c - 1) / 26;
     }
-    ret
+    letters.reverse();
+    String::from_utf8(letters).unwrap()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use format::NumberFormat;
+    use style::{Style, Rgb};
+    use conditional::{ConditionalFormat, Rule, Condition, CompareOp, StylePatch};
+    use validation::{Validation, ValidationRule, ValidationMode, ValidationErr, Validity};
 
     #[test]
     fn test_natural_to_numeric() {
@@ -270,6 +2254,456 @@ mod test {
         assert_eq!(Coord(702, 9), natural_to_numeric("AAA", 10));
     }
 
+    #[test]
+    fn test_numeric_col_to_natural() {
+        assert_eq!("A", numeric_col_to_natural(0));
+        assert_eq!("Z", numeric_col_to_natural(25));
+        assert_eq!("AA", numeric_col_to_natural(26));
+        assert_eq!("AZ", numeric_col_to_natural(26 + 25));
+        assert_eq!("BA", numeric_col_to_natural(26 + 26));
+        assert_eq!("ZZ", numeric_col_to_natural(701));
+        assert_eq!("AAA", numeric_col_to_natural(702));
+    }
+
+    #[test]
+    fn test_parse_anchored() {
+        assert_eq!(Some((Coord(0, 0), Anchor(false, false))), Coord::parse_anchored("A1"));
+        assert_eq!(Some((Coord(0, 0), Anchor(true, true))), Coord::parse_anchored("$A$1"));
+        assert_eq!(Some((Coord(1, 9), Anchor(false, true))), Coord::parse_anchored("B$10"));
+        assert_eq!(Some((Coord(26, 2), Anchor(true, false))), Coord::parse_anchored("$AA3"));
+        assert_eq!(None, Coord::parse_anchored("$1"));
+        assert_eq!(None, Coord::parse_anchored("A0"));
+        assert_eq!(None, Coord::parse_anchored("a1"));
+        assert_eq!(None, Coord::parse_anchored("TaxRate"));
+        assert_eq!("$AA3", Coord(26, 2).format_anchored(Anchor(true, false)));
+        assert_eq!("B$10", Coord(1, 9).format_anchored(Anchor(false, true)));
+    }
+
+    #[test]
+    fn test_shift() {
+        let f = Formula::Op(FormulaOp::Add, vec![
+            Formula::Ref(Coord(1, 1), Anchor(false, false)),
+            Formula::Ref(Coord(1, 1), Anchor(true, true)),
+            Formula::Ref(Coord(1, 1), Anchor(false, true)),
+            Formula::Atom(FormulaAtom::Number(1.0)),
+        ]);
+        assert_eq!(Formula::Op(FormulaOp::Add, vec![
+            Formula::Ref(Coord(3, 0), Anchor(false, false)),
+            Formula::Ref(Coord(1, 1), Anchor(true, true)),
+            Formula::Ref(Coord(3, 1), Anchor(false, true)),
+            Formula::Atom(FormulaAtom::Number(1.0)),
+        ]), f.shift(2, -1));
+        assert_eq!(Formula::Op(FormulaOp::Add, vec![
+            Formula::InvalidRef,
+            Formula::Ref(Coord(1, 1), Anchor(true, true)),
+            Formula::InvalidRef,
+            Formula::Atom(FormulaAtom::Number(1.0)),
+        ]), f.shift(-2, 0));
+    }
+
+    #[test]
+    fn test_sheet_invalid_ref() {
+        let mut sheet = Sheet::new();
+        sheet.set(Coord(0, 0), Formula::InvalidRef);
+        if let Err(FormulaErr::InvalidRef) = sheet.value(Coord(0, 0)) {
+        } else { panic!(); };
+    }
+
+    fn number(sheet: &Sheet, coord: Coord) -> f64 {
+        if let FormulaAtom::Number(x) = *sheet.value(coord).ok().unwrap() {
+            x
+        } else { panic!(); }
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut sheet = Sheet::new();
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        sheet.set(Coord(0, 1), Formula::Atom(FormulaAtom::Number(2.0)));
+        sheet.set(Coord(1, 0), Formula::Op(FormulaOp::Mul, vec![
+            Formula::Ref(Coord(0, 0), Anchor(false, false)),
+            Formula::Ref(Coord(3, 0), Anchor(true, true)),
+        ]));
+        sheet.set(Coord(3, 0), Formula::Atom(FormulaAtom::Number(10.0)));
+
+        sheet.fill(Coord(1, 0), Coord(1, 2), FillDirection::Down);
+        assert_eq!(Some(&Formula::Op(FormulaOp::Mul, vec![
+            Formula::Ref(Coord(0, 2), Anchor(false, false)),
+            Formula::Ref(Coord(3, 0), Anchor(true, true)),
+        ])), sheet.cells.get(&Coord(1, 2)));
+        assert_eq!(20.0, number(&sheet, Coord(1, 1)));
+
+        sheet.fill(Coord(0, 0), Coord(2, 1), FillDirection::Right);
+        assert_eq!(1.0, number(&sheet, Coord(2, 0)));
+        assert_eq!(2.0, number(&sheet, Coord(2, 1)));
+
+        // Corners the other way around fill the same rectangle.
+        sheet.fill(Coord(0, 4), Coord(0, 1), FillDirection::Down);
+        assert_eq!(2.0, number(&sheet, Coord(0, 4)));
+    }
+
+    #[test]
+    fn test_formats() {
+        let money = NumberFormat::parse("#,##0.00").unwrap();
+        let mut sheet = Sheet::new();
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(1234.5)));
+        sheet.set_format(Coord(0, 0), Coord(0, 1), Some(money.clone()));
+        assert_eq!(Some(&money), sheet.format(Coord(0, 1)));
+
+        sheet.fill(Coord(0, 0), Coord(2, 0), FillDirection::Right);
+        assert_eq!(Some(&money), sheet.format(Coord(2, 0)));
+
+        sheet.delete_rows(0, 1);
+        assert_eq!(Some(&money), sheet.format(Coord(0, 0)));
+        assert_eq!(None, sheet.format(Coord(2, 0)));
+
+        sheet.set_format(Coord(0, 0), Coord(0, 0), None);
+        assert_eq!(None, sheet.format(Coord(0, 0)));
+
+        // A cell that rejects its new formula keeps its format too.
+        sheet.add_validation(Validation{from: Coord(3, 2), to: Coord(3, 2),
+            rule: ValidationRule::NumberBetween(0.0, 1.0), mode: ValidationMode::Reject});
+        sheet.set(Coord(3, 0), Formula::Atom(FormulaAtom::Number(5.0)));
+        sheet.set_format(Coord(3, 0), Coord(3, 0), Some(money.clone()));
+        sheet.fill(Coord(3, 0), Coord(3, 2), FillDirection::Down);
+        assert_eq!(Some(&money), sheet.format(Coord(3, 1)));
+        assert_eq!(FormulaAtom::Empty, *sheet.value(Coord(3, 2)).ok().unwrap());
+        assert_eq!(None, sheet.format(Coord(3, 2)));
+    }
+
+    #[test]
+    fn test_styles() {
+        let mut sheet = Sheet::new();
+        sheet.update_style(Coord(0, 0), Coord(1, 1), |x| { x.bold = true; });
+        sheet.update_style(Coord(1, 1), Coord(2, 2), |x| { x.background = Some(Rgb(255, 255, 0)); });
+        assert!(sheet.style(Coord(0, 0)).unwrap().bold);
+        let both = sheet.style(Coord(1, 1)).unwrap().clone();
+        assert!(both.bold && both.background == Some(Rgb(255, 255, 0)));
+        assert!(!sheet.style(Coord(2, 2)).unwrap().bold);
+
+        sheet.insert_columns(0, 1);
+        assert_eq!(None, sheet.style(Coord(0, 0)));
+        assert!(sheet.style(Coord(1, 0)).unwrap().bold);
+
+        sheet.update_style(Coord(1, 0), Coord(1, 0), |x| { x.bold = false; });
+        assert_eq!(None, sheet.style(Coord(1, 0)));
+        sheet.set_style(Coord(0, 0), Coord(5, 5), Style::new());
+        assert_eq!(None, sheet.style(Coord(2, 1)));
+    }
+
+    #[test]
+    fn test_conditional_formats() {
+        let mut sheet = Sheet::new();
+        let selection = sheet.select(Coord(0, 0), Coord(0, 2));
+        let rx = &selection.values;
+        sheet.update_style(Coord(0, 0), Coord(0, 2), |x| { x.bold = true; });
+        sheet.add_conditional_format(ConditionalFormat{from: Coord(0, 0), to: Coord(0, 2), rule: Rule::Highlight(
+            Condition::Compare(CompareOp::Less, FormulaAtom::Number(0.0)),
+            StylePatch{color: Some(Rgb(255, 0, 0)), ..StylePatch::new()})});
+        sheet.add_conditional_format(ConditionalFormat{from: Coord(0, 0), to: Coord(0, 2), rule: Rule::DataBar(Rgb(0, 0, 255))});
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(-2.0)));
+        sheet.set(Coord(0, 1), Formula::Atom(FormulaAtom::Number(2.0)));
+
+        let look = sheet.look(Coord(0, 0));
+        assert!(look.style.bold);
+        assert_eq!(Some(Rgb(255, 0, 0)), look.style.color);
+        assert_eq!(Some((0.0, Rgb(0, 0, 255))), look.bar);
+        assert_eq!(None, sheet.look(Coord(0, 1)).style.color);
+        assert_eq!(Some((1.0, Rgb(0, 0, 255))), sheet.look(Coord(0, 1)).bar);
+
+        // Setting a cell sends the whole range of the data bar again.
+        while let Ok(_) = rx.try_recv() {}
+        sheet.set(Coord(0, 2), Formula::Atom(FormulaAtom::Number(6.0)));
+        let mut sent = 0;
+        while let Ok(_) = rx.try_recv() {
+            sent += 1;
+        }
+        assert_eq!(4, sent);
+        assert_eq!(Some((0.5, Rgb(0, 0, 255))), sheet.look(Coord(0, 1)).bar);
+
+        let looks = sheet.looks_in(&[Coord(0, 0), Coord(0, 1), Coord(0, 2)], Context{book: &sheet, sheet: 0, cache: None});
+        assert_eq!(vec![Some((0.0, Rgb(0, 0, 255))), Some((0.5, Rgb(0, 0, 255))), Some((1.0, Rgb(0, 0, 255)))],
+                   looks.into_iter().map(|x| x.bar).collect::<Vec<_>>());
+
+        assert_eq!(None, sheet.remove_conditional_format(2));
+        let bar = ConditionalFormat{from: Coord(0, 0), to: Coord(0, 2), rule: Rule::DataBar(Rgb(0, 255, 0))};
+        assert_eq!(None, sheet.replace_conditional_format(2, bar.clone()));
+        assert!(sheet.replace_conditional_format(1, bar).is_some());
+        assert_eq!(Some((0.5, Rgb(0, 255, 0))), sheet.look(Coord(0, 1)).bar);
+
+        sheet.delete_rows(0, 3);
+        assert_eq!(0, sheet.conditional_formats().len());
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut sheet = Sheet::new();
+        sheet.add_validation(Validation{from: Coord(0, 0), to: Coord(0, 9),
+            rule: ValidationRule::NumberBetween(0.0, 10.0), mode: ValidationMode::Reject});
+        // Column B has to be more than the cell to its left.
+        sheet.add_validation(Validation{from: Coord(1, 0), to: Coord(1, 9),
+            rule: ValidationRule::Custom(Formula::Op(FormulaOp::Sub, vec![
+                Formula::Ref(Coord(1, 0), Anchor(false, false)),
+                Formula::Ref(Coord(0, 0), Anchor(false, false)),
+            ])), mode: ValidationMode::Warn});
+
+        assert_eq!(Validity::Valid, sheet.set(Coord(0, 1), Formula::Atom(FormulaAtom::Number(5.0))));
+        assert_eq!(Validity::Rejected(ValidationErr::OutOfRange),
+                   sheet.set(Coord(0, 1), Formula::Atom(FormulaAtom::Number(11.0))));
+        assert_eq!(5.0, number(&sheet, Coord(0, 1)));
+        assert_eq!(Validity::Valid, sheet.set(Coord(0, 1), Formula::Atom(FormulaAtom::Empty)));
+
+        sheet.set(Coord(0, 2), Formula::Atom(FormulaAtom::Number(3.0)));
+        assert_eq!(Validity::Valid, sheet.set(Coord(1, 2), Formula::Atom(FormulaAtom::Number(4.0))));
+        assert_eq!(Validity::Warned(ValidationErr::Failed),
+                   sheet.set(Coord(1, 2), Formula::Atom(FormulaAtom::Number(3.0))));
+        assert_eq!(3.0, number(&sheet, Coord(1, 2)));
+
+        sheet.insert_rows(0, 1);
+        assert!(sheet.validation(Coord(0, 0)).is_none());
+        assert_eq!(Coord(0, 10), sheet.validation(Coord(0, 10)).unwrap().to);
+
+        // Values already there are checked against a new validation.
+        sheet.set(Coord(2, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        sheet.set(Coord(2, 1), Formula::Atom(FormulaAtom::Number(7.0)));
+        let (idx, invalid) = sheet.add_validation(Validation{from: Coord(2, 0), to: Coord(2, 9),
+            rule: ValidationRule::NumberBetween(0.0, 5.0), mode: ValidationMode::Warn});
+        assert_eq!(2, idx);
+        assert_eq!(vec![(Coord(2, 1), Validity::Warned(ValidationErr::OutOfRange))], invalid);
+        assert!(sheet.remove_validation(3).is_none());
+        assert_eq!(Coord(2, 9), sheet.remove_validation(2).unwrap().to);
+        assert!(sheet.validation(Coord(2, 1)).is_none());
+    }
+
+    #[test]
+    fn test_fill_series() {
+        let mut sheet = Sheet::new();
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(0.5)));
+        sheet.set(Coord(0, 1), Formula::Atom(FormulaAtom::Number(1.0)));
+        sheet.set(Coord(1, 0), Formula::Atom(FormulaAtom::String("a".to_string())));
+        sheet.set(Coord(1, 1), Formula::Atom(FormulaAtom::String("b".to_string())));
+
+        sheet.fill_series(Coord(0, 0), Coord(1, 4), 2, FillDirection::Down);
+        assert_eq!(1.5, number(&sheet, Coord(0, 2)));
+        assert_eq!(2.5, number(&sheet, Coord(0, 4)));
+        assert_eq!(FormulaAtom::String("a".to_string()), *sheet.value(Coord(1, 2)).ok().unwrap());
+        assert_eq!(FormulaAtom::String("b".to_string()), *sheet.value(Coord(1, 3)).ok().unwrap());
+        assert_eq!(FormulaAtom::String("a".to_string()), *sheet.value(Coord(1, 4)).ok().unwrap());
+
+        sheet.set(Coord(2, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        sheet.set(Coord(3, 0), Formula::Atom(FormulaAtom::Number(2.0)));
+        sheet.set(Coord(4, 0), Formula::Atom(FormulaAtom::Number(3.0)));
+        sheet.fill_series(Coord(2, 0), Coord(6, 0), 3, FillDirection::Right);
+        assert_eq!(4.0, number(&sheet, Coord(5, 0)));
+        assert_eq!(5.0, number(&sheet, Coord(6, 0)));
+    }
+
+    #[test]
+    fn test_insert_rows() {
+        let mut sheet = Sheet::new();
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        sheet.set(Coord(0, 1), Formula::Atom(FormulaAtom::Number(2.0)));
+        sheet.set(Coord(0, 2), Formula::Atom(FormulaAtom::Number(4.0)));
+        sheet.set(Coord(1, 0), Formula::Op(FormulaOp::Add, vec![
+            Formula::Range(Coord(0, 0), Anchor(false, false), Coord(0, 2), Anchor(false, false)),
+        ]));
+        sheet.set(Coord(1, 1), Formula::Ref(Coord(0, 2), Anchor(true, true)));
+
+        sheet.insert_rows(1, 2);
+        assert_eq!(1.0, number(&sheet, Coord(0, 0)));
+        assert_eq!(FormulaAtom::Empty, *sheet.value(Coord(0, 1)).ok().unwrap());
+        assert_eq!(2.0, number(&sheet, Coord(0, 3)));
+        assert_eq!(4.0, number(&sheet, Coord(0, 4)));
+        assert_eq!(7.0, number(&sheet, Coord(1, 0)));
+        assert_eq!(4.0, number(&sheet, Coord(1, 3)));
+        assert_eq!(Some(&Formula::Ref(Coord(0, 4), Anchor(true, true))), sheet.cells.get(&Coord(1, 3)));
+
+        sheet.set(Coord(0, 2), Formula::Atom(FormulaAtom::Number(8.0)));
+        assert_eq!(15.0, number(&sheet, Coord(1, 0)));
+    }
+
+    #[test]
+    fn test_delete_rows() {
+        let mut sheet = Sheet::new();
+        for row in 0 .. 5 {
+            sheet.set(Coord(0, row), Formula::Atom(FormulaAtom::Number(row as f64)));
+        }
+        sheet.set(Coord(1, 0), Formula::Op(FormulaOp::Add, vec![
+            Formula::Range(Coord(0, 1), Anchor(false, false), Coord(0, 4), Anchor(false, false)),
+        ]));
+        sheet.set(Coord(1, 1), Formula::Ref(Coord(0, 2), Anchor(false, false)));
+        sheet.set(Coord(1, 4), Formula::Ref(Coord(0, 4), Anchor(false, false)));
+
+        sheet.delete_rows(1, 2);
+        assert_eq!(3.0, number(&sheet, Coord(0, 1)));
+        assert_eq!(7.0, number(&sheet, Coord(1, 0)));
+        assert_eq!(4.0, number(&sheet, Coord(1, 2)));
+        assert_eq!(None, sheet.cells.get(&Coord(1, 1)));
+
+        sheet.set(Coord(2, 0), Formula::Ref(Coord(0, 2), Anchor(false, false)));
+        sheet.delete_rows(2, 1);
+        if let Err(FormulaErr::InvalidRef) = sheet.value(Coord(2, 0)) {
+        } else { panic!(); };
+        sheet.delete_rows(1, 1);
+        assert_eq!(Some(&Formula::Op(FormulaOp::Add, vec![Formula::InvalidRef])), sheet.cells.get(&Coord(1, 0)));
+    }
+
+    #[test]
+    fn test_insert_delete_columns() {
+        let mut sheet = Sheet::new();
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        sheet.set(Coord(2, 0), Formula::Atom(FormulaAtom::Number(2.0)));
+        sheet.set(Coord(3, 0), Formula::Op(FormulaOp::Add, vec![
+            Formula::Range(Coord(0, 0), Anchor(false, false), Coord(2, 0), Anchor(false, false)),
+        ]));
+
+        sheet.insert_columns(1, 1);
+        assert_eq!(3.0, number(&sheet, Coord(4, 0)));
+        assert_eq!(Some(&Formula::Op(FormulaOp::Add, vec![
+            Formula::Range(Coord(0, 0), Anchor(false, false), Coord(3, 0), Anchor(false, false)),
+        ])), sheet.cells.get(&Coord(4, 0)));
+
+        sheet.delete_columns(0, 1);
+        assert_eq!(2.0, number(&sheet, Coord(3, 0)));
+    }
+
+    #[test]
+    fn test_insert_rows_notifies() {
+        let mut sheet = Sheet::new();
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        let selection = sheet.select(Coord(0, 0), Coord(0, 1));
+        let rx = &selection.values;
+        assert_eq!(Coord(0, 0), rx.recv().unwrap().0);
+        assert_eq!(Coord(0, 1), rx.recv().unwrap().0);
+
+        // The selection moves down with its cells.
+        sheet.insert_rows(0, 1);
+        let (coord, value) = rx.recv().unwrap();
+        assert_eq!(Coord(0, 1), coord);
+        assert_eq!(FormulaAtom::Number(1.0), *value.ok().unwrap());
+        let (coord, value) = rx.recv().unwrap();
+        assert_eq!(Coord(0, 2), coord);
+        assert_eq!(FormulaAtom::Empty, *value.ok().unwrap());
+
+        sheet.set(Coord(0, 2), Formula::Atom(FormulaAtom::Number(2.0)));
+        assert_eq!(Coord(0, 2), rx.recv().unwrap().0);
+        sheet.delete_rows(0, 2);
+        let (coord, value) = rx.recv().unwrap();
+        assert_eq!(Coord(0, 0), coord);
+        assert_eq!(FormulaAtom::Number(2.0), *value.ok().unwrap());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        fn received(subscription: &Subscription) -> Vec<Coord> {
+            let mut coords = vec![];
+            while let Ok((coord, _)) = subscription.values.try_recv() {
+                coords.push(coord);
+            }
+            coords
+        }
+
+        let mut sheet = Sheet::new();
+        let first = sheet.select(Coord(0, 0), Coord(1, 1));
+        let second = sheet.select(Coord(0, 0), Coord(0, 0));
+        assert!(first.id != second.id);
+        assert_eq!(4, received(&first).len());
+        assert_eq!(1, received(&second).len());
+
+        // Scrolling down a row only sends the row coming into view.
+        sheet.move_selection(first.id, Coord(0, 1), Coord(1, 2));
+        assert_eq!(vec![Coord(0, 2), Coord(1, 2)], received(&first));
+        sheet.move_selection(first.id, Coord(0, 0), Coord(2, 2));
+        assert_eq!(vec![Coord(0, 0), Coord(1, 0), Coord(2, 0), Coord(2, 1), Coord(2, 2)],
+                   received(&first));
+
+        sheet.unselect(second.id);
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        assert_eq!(1, received(&first).len());
+        assert_eq!(0, received(&second).len());
+
+        // Dropped subscriptions remove their selection.
+        assert_eq!(1, sheet.selections.lock().unwrap().len());
+        drop(first);
+        assert!(sheet.selections.lock().unwrap().is_empty());
+        drop(second);
+    }
+
+    #[test]
+    fn test_dependents_sent() {
+        fn received(subscription: &Subscription) -> Vec<(usize, f64)> {
+            let mut ret = vec![];
+            while let Ok((Coord(col, _), value)) = subscription.values.try_recv() {
+                ret.push((col, ::functions::number(&value.ok().unwrap()).unwrap()));
+            }
+            ret.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+            ret
+        }
+
+        let mut sheet = Sheet::new();
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        sheet.set(Coord(1, 0), Formula::Op(FormulaOp::Add, vec![
+            Formula::Ref(Coord(0, 0), Anchor(false, false)),
+            Formula::Atom(FormulaAtom::Number(1.0)),
+        ]));
+        // D1 reads A1 through B1, and E1 looks in a range with A1 in it.
+        sheet.set(Coord(3, 0), Formula::Op(FormulaOp::Add, vec![Formula::Ref(Coord(1, 0), Anchor(false, false))]));
+        sheet.set(Coord(4, 0), Formula::Op(FormulaOp::Index, vec![
+            Formula::Range(Coord(0, 0), Anchor(false, false), Coord(1, 0), Anchor(false, false)),
+            Formula::Atom(FormulaAtom::Number(1.0)),
+        ]));
+        let selection = sheet.select(Coord(3, 0), Coord(4, 0));
+        assert_eq!(vec![(3, 2.0), (4, 1.0)], received(&selection));
+
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(5.0)));
+        assert_eq!(vec![(3, 6.0), (4, 5.0)], received(&selection));
+    }
+
+    #[test]
+    fn test_spills_sent() {
+        fn received(subscription: &Subscription) -> Vec<(usize, usize, Option<f64>)> {
+            let mut ret = vec![];
+            while let Ok((Coord(col, row), value)) = subscription.values.try_recv() {
+                ret.push((col, row, value.ok().and_then(|x| ::functions::number(&x).ok())));
+            }
+            ret.sort_by(|&(col_a, row_a, _), &(col_b, row_b, _)| (col_a, row_a).cmp(&(col_b, row_b)));
+            ret.dedup();
+            ret
+        }
+
+        let mut sheet = Sheet::new();
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(2.0)));
+        // B1 spills as many numbers as A1 says, and C1 reads the second.
+        sheet.set(Coord(1, 0), Formula::Op(FormulaOp::Sequence, vec![Formula::Ref(Coord(0, 0), Anchor(false, false))]));
+        sheet.set(Coord(2, 0), Formula::Op(FormulaOp::Add, vec![
+            Formula::Ref(Coord(1, 1), Anchor(false, false)),
+            Formula::Atom(FormulaAtom::Number(10.0)),
+        ]));
+        assert_eq!(vec![(Coord(1, 0), Coord(1, 1))], sheet.spill_areas());
+        let selection = sheet.select(Coord(1, 0), Coord(2, 2));
+        received(&selection);
+
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(3.0)));
+        assert_eq!(vec![(Coord(1, 0), Coord(1, 2))], sheet.spill_areas());
+        assert_eq!(vec![(1, 0, Some(1.0)), (1, 1, Some(2.0)), (1, 2, Some(3.0)), (2, 0, Some(12.0))],
+                   received(&selection));
+
+        // Cells the array shrinks off are empty again, which C1 can't add.
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        assert_eq!(vec![(1, 0, Some(1.0)), (1, 1, None), (1, 2, None), (2, 0, None)], received(&selection));
+
+        // A formula in the way blocks the array, and clearing it lets it
+        // spill again.
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(2.0)));
+        received(&selection);
+        sheet.set(Coord(1, 1), Formula::Atom(FormulaAtom::Number(7.0)));
+        assert!(sheet.spill_areas().is_empty());
+        assert_eq!(vec![(1, 0, None), (1, 1, Some(7.0)), (2, 0, Some(17.0))], received(&selection));
+        sheet.set(Coord(1, 1), Formula::Atom(FormulaAtom::Empty));
+        assert_eq!(vec![(1, 0, Some(1.0)), (1, 1, Some(2.0)), (2, 0, Some(12.0))], received(&selection));
+    }
+
     #[test]
     #[should_panic]
     fn test_bad_natural_col() {
@@ -304,8 +2738,8 @@ mod test {
             assert_eq!("test", *x);
         } else { panic!(); };
 
-        sheet.set(Coord(3, 4), Formula::Ref(Coord(2, 3)));
-        sheet.set(Coord(5, 6), Formula::Ref(Coord(3, 4)));
+        sheet.set(Coord(3, 4), Formula::Ref(Coord(2, 3), Anchor(false, false)));
+        sheet.set(Coord(5, 6), Formula::Ref(Coord(3, 4), Anchor(false, false)));
         if let FormulaAtom::String(ref x) = *sheet.value(Coord(5, 6)).ok().unwrap() {
             assert_eq!("test", *x);
         } else { panic!(); };
@@ -314,7 +2748,7 @@ mod test {
     #[test]
     fn test_sheet_cycle() {
         let mut sheet = Sheet::new();
-        sheet.set(Coord(0, 0), Formula::Ref(Coord(0, 0)));
+        sheet.set(Coord(0, 0), Formula::Ref(Coord(0, 0), Anchor(false, false)));
         if let Err(FormulaErr::Ref(x)) = sheet.value(Coord(0, 0)) {
             assert_eq!(Coord(0, 0), x);
         } else { panic!(); };
@@ -323,10 +2757,10 @@ mod test {
     #[test]
     fn test_sheet_long_cycle() {
         let mut sheet = Sheet::new();
-        sheet.set(Coord(0, 0), Formula::Ref(Coord(0, 1)));
-        sheet.set(Coord(0, 1), Formula::Ref(Coord(0, 2)));
-        sheet.set(Coord(0, 2), Formula::Ref(Coord(0, 3)));
-        sheet.set(Coord(0, 3), Formula::Ref(Coord(0, 1)));
+        sheet.set(Coord(0, 0), Formula::Ref(Coord(0, 1), Anchor(false, false)));
+        sheet.set(Coord(0, 1), Formula::Ref(Coord(0, 2), Anchor(false, false)));
+        sheet.set(Coord(0, 2), Formula::Ref(Coord(0, 3), Anchor(false, false)));
+        sheet.set(Coord(0, 3), Formula::Ref(Coord(0, 1), Anchor(false, false)));
         if let Err(FormulaErr::Ref(x)) = sheet.value(Coord(0, 0)) {
             assert_eq!(Coord(0, 1), x);
         } else { panic!(); };
@@ -341,8 +2775,8 @@ mod test {
             FormulaOp::Add,
             vec![
                 Formula::Atom(FormulaAtom::Number(4.0)),
-                Formula::Ref(Coord(0, 0)),
-                Formula::Ref(Coord(0, 1)),
+                Formula::Ref(Coord(0, 0), Anchor(false, false)),
+                Formula::Ref(Coord(0, 1), Anchor(false, false)),
             ]));
 
         assert_eq!(FormulaAtom::Number(9.0), *sheet.value(Coord(0, 2)).ok().unwrap());
@@ -352,13 +2786,13 @@ mod test {
     fn test_sheet_add_cycle() {
         let mut sheet = Sheet::new();
         sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Number(2.0)));
-        sheet.set(Coord(0, 1), Formula::Ref(Coord(0, 1)));
+        sheet.set(Coord(0, 1), Formula::Ref(Coord(0, 1), Anchor(false, false)));
         sheet.set(Coord(0, 2), Formula::Op(
             FormulaOp::Add,
             vec![
                 Formula::Atom(FormulaAtom::Number(4.0)),
-                Formula::Ref(Coord(0, 0)),
-                Formula::Ref(Coord(0, 1)),
+                Formula::Ref(Coord(0, 0), Anchor(false, false)),
+                Formula::Ref(Coord(0, 1), Anchor(false, false)),
             ]));
 
         if let Err(FormulaErr::Ref(x)) = sheet.value(Coord(0, 2)) {
@@ -375,8 +2809,8 @@ mod test {
             FormulaOp::Add,
             vec![
                 Formula::Atom(FormulaAtom::Number(4.0)),
-                Formula::Ref(Coord(0, 0)),
-                Formula::Ref(Coord(0, 1)),
+                Formula::Ref(Coord(0, 0), Anchor(false, false)),
+                Formula::Ref(Coord(0, 1), Anchor(false, false)),
             ]));
 
         if let Err(FormulaErr::Type(x)) = sheet.value(Coord(0, 2)) {
@@ -384,6 +2818,36 @@ mod test {
         } else { panic!(); };
     }
 
+    #[test]
+    fn test_sheet_date_arithmetic() {
+        let mut sheet = Sheet::new();
+        sheet.set(Coord(0, 0), Formula::Atom(FormulaAtom::Date(45306.0)));
+        sheet.set(Coord(0, 1), Formula::Atom(FormulaAtom::Date(45310.5)));
+        sheet.set(Coord(0, 2), Formula::Atom(FormulaAtom::Duration(0.25)));
+        let a = || Formula::Ref(Coord(0, 0), Anchor(false, false));
+        let b = || Formula::Ref(Coord(0, 1), Anchor(false, false));
+        let c = || Formula::Ref(Coord(0, 2), Anchor(false, false));
+        let n = |x| Formula::Atom(FormulaAtom::Number(x));
+
+        sheet.set(Coord(1, 0), Formula::Op(FormulaOp::Add, vec![a(), n(2.0), c()]));
+        assert_eq!(FormulaAtom::Date(45308.25), *sheet.value(Coord(1, 0)).ok().unwrap());
+        sheet.set(Coord(1, 1), Formula::Op(FormulaOp::Sub, vec![b(), a()]));
+        assert_eq!(FormulaAtom::Duration(4.5), *sheet.value(Coord(1, 1)).ok().unwrap());
+        sheet.set(Coord(1, 2), Formula::Op(FormulaOp::Mul, vec![c(), n(4.0)]));
+        assert_eq!(FormulaAtom::Duration(1.0), *sheet.value(Coord(1, 2)).ok().unwrap());
+        sheet.set(Coord(1, 3), Formula::Op(FormulaOp::Div, vec![c(), c()]));
+        assert_eq!(FormulaAtom::Number(1.0), *sheet.value(Coord(1, 3)).ok().unwrap());
+        sheet.set(Coord(1, 4), Formula::Op(FormulaOp::Avg, vec![a(), b()]));
+        assert_eq!(FormulaAtom::Date(45308.25), *sheet.value(Coord(1, 4)).ok().unwrap());
+
+        sheet.set(Coord(1, 5), Formula::Op(FormulaOp::Add, vec![a(), b()]));
+        if let Err(FormulaErr::Type(_)) = sheet.value(Coord(1, 5)) {
+        } else { panic!(); };
+        sheet.set(Coord(1, 6), Formula::Op(FormulaOp::Avg, vec![a(), n(1.0)]));
+        if let Err(FormulaErr::Type(_)) = sheet.value(Coord(1, 6)) {
+        } else { panic!(); };
+    }
+
     #[test]
     fn test_sheet_avg() {
         let mut sheet = Sheet::new();
@@ -393,8 +2857,8 @@ mod test {
             FormulaOp::Avg,
             vec![
                 Formula::Atom(FormulaAtom::Number(10.0)),
-                Formula::Ref(Coord(0, 0)),
-                Formula::Ref(Coord(0, 1)),
+                Formula::Ref(Coord(0, 0), Anchor(false, false)),
+                Formula::Ref(Coord(0, 1), Anchor(false, false)),
             ]));
 
         assert_eq!(FormulaAtom::Number(5.0), *sheet.value(Coord(0, 2)).ok().unwrap());
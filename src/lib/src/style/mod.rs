@@ -0,0 +1,114 @@
+//! How cells look: fonts, colors, alignment and borders.
+
+/// A color, as red, green and blue components.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    /// Parses a color written as `#RRGGBB`.
+    pub fn parse_hex(s: &str) -> Option<Rgb> {
+        if s.len() != 7 || !s.starts_with("#") || !s[1..].chars().all(|c| c.is_digit(16)) {
+            return None;
+        }
+        let component = |i: usize| u8::from_str_radix(&s[i..i+2], 16).ok();
+        match (component(1), component(3), component(5)) {
+            (Some(r), Some(g), Some(b)) => Some(Rgb(r, g, b)),
+            _ => None,
+        }
+    }
+
+    pub fn to_hex(&self) -> String {
+        let Rgb(r, g, b) = *self;
+        format!("#{:02X}{:02X}{:02X}", r, g, b)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HAlign {
+    /// Text to the left and numbers to the right.
+    General,
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VAlign {
+    Top,
+    Middle,
+    Bottom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Border {
+    /// In pixels.
+    pub width: u8,
+    pub color: Rgb,
+}
+
+/// The borders of a cell, `None` where it has none.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Borders {
+    pub top: Option<Border>,
+    pub bottom: Option<Border>,
+    pub left: Option<Border>,
+    pub right: Option<Border>,
+}
+
+impl Borders {
+    pub fn none() -> Borders {
+        Borders{top: None, bottom: None, left: None, right: None}
+    }
+
+    pub fn all(border: Border) -> Borders {
+        Borders{top: Some(border), bottom: Some(border), left: Some(border), right: Some(border)}
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style {
+    pub bold: bool,
+    pub italic: bool,
+    /// In points; `None` for the default size.
+    pub font_size: Option<u32>,
+    /// The color of the text; `None` for the default one.
+    pub color: Option<Rgb>,
+    pub background: Option<Rgb>,
+    pub h_align: HAlign,
+    pub v_align: VAlign,
+    pub borders: Borders,
+    /// Whether long text breaks into lines instead of overflowing the cell.
+    pub wrap: bool,
+}
+
+impl Style {
+    /// The style of cells that haven't been styled.
+    pub fn new() -> Style {
+        Style{
+            bold: false,
+            italic: false,
+            font_size: None,
+            color: None,
+            background: None,
+            h_align: HAlign::General,
+            v_align: VAlign::Bottom,
+            borders: Borders::none(),
+            wrap: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(Some(Rgb(255, 0, 0x80)), Rgb::parse_hex("#FF0080"));
+        assert_eq!(Some(Rgb(10, 11, 12)), Rgb::parse_hex("#0a0b0c"));
+        assert_eq!(None, Rgb::parse_hex("FF0080"));
+        assert_eq!(None, Rgb::parse_hex("#FF00GG"));
+        assert_eq!(None, Rgb::parse_hex("#1é234"));
+        assert_eq!("#0A0B0C", Rgb(10, 11, 12).to_hex());
+    }
+}
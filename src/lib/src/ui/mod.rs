@@ -2,9 +2,15 @@ use ::glutin_window::{GlutinWindow};
 use ::conrod;
 use ::opengl_graphics::glyph_cache::GlyphCache;
 use ::opengl_graphics::{OpenGL, GlGraphics};
-use ::sheet::{Coord, Value, Formula};
-use ::std::sync::mpsc::{sync_channel, Receiver, SyncSender};
-use ::std::sync::Mutex;
+use ::sheet::{Coord, Value, Formula, FormulaAtom, FillDirection};
+use ::format::{Formatted, Color};
+use ::style::{Rgb, Borders, Border, HAlign, VAlign};
+use ::conditional::Look;
+use ::std::collections::VecDeque;
+use ::std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use ::handle::{WorkbookHandle, CellLook};
+use ::workbook::WorkbookErr;
+use ::parser::{NumberLocale, parse_formula_in};
 
 const WINDOW_WIDTH: u32 = 800;
 const WINDOW_HEIGHT: u32 = 600;
@@ -14,8 +20,36 @@ const GRID_COLUMNS: usize = WINDOW_WIDTH as usize / CELL_WIDTH - 1;
 const GRID_ROWS: usize = WINDOW_HEIGHT as usize / CELL_HEIGHT - 1;
 const NUM_CELLS: usize = GRID_COLUMNS * GRID_ROWS;
 const TEXTBOX_ID: usize = NUM_CELLS + 1;
+const FILL_HANDLE_ID: usize = TEXTBOX_ID + 1;
+const FILL_HANDLE_SIZE: f64 = 8.0;
+/// Each line of a cell's text is a label, drawn twice if bold.
+const CELL_LABEL_ID: usize = FILL_HANDLE_ID + NUM_CELLS;
+const MAX_LINES: usize = 4;
+const CELL_BAR_ID: usize = CELL_LABEL_ID + NUM_CELLS * MAX_LINES * 2;
+const CHOICES_ID: usize = CELL_BAR_ID + NUM_CELLS;
+const STATUS_ID: usize = CHOICES_ID + 1;
+const ADD_TAB_ID: usize = STATUS_ID + 1;
+const TAB_ID: usize = ADD_TAB_ID + 1;
+const TAB_WIDTH: f64 = 100.0;
+const TAB_BAR_HEIGHT: f64 = CELL_HEIGHT as f64;
+const STATUS_WIDTH: f64 = 300.0;
+/// Whether strings in cells are shown quoted, the way they are typed.
+const QUOTE_STRINGS: bool = false;
+/// Between the text and the sides of a cell.
+const LABEL_PADDING: f64 = 2.0;
 
-struct CellGrid(Vec<Option<String>>);
+/// What is shown in a cell.
+struct GridCell {
+    formatted: Formatted,
+    look: Look,
+    /// Whether the value is a number, which goes to the right unless the
+    /// style says otherwise.
+    number: bool,
+    /// The values allowed by a list validation, if the cell has one.
+    choices: Vec<FormulaAtom>,
+}
+
+struct CellGrid(Vec<Option<GridCell>>);
 
 impl CellGrid {
     fn new() -> Self {
@@ -28,21 +62,19 @@ impl CellGrid {
 
     fn get_str(&self, col: usize, row: usize) -> &str {
         match self[Coord(col, row)] {
-            Some(ref x) => x.as_str(),
+            Some(ref x) => x.formatted.text.as_str(),
             None => ""
         }
     }
 
-    fn set<'a, 'b>(&'a mut self, coord: Coord, val: &'b str) {
-        self[coord] = match val {
-            "" => None,
-            x => Some(x.to_string()),
-        }
+    fn set(&mut self, coord: Coord, val: GridCell) {
+        let plain = val.look.style == ::style::Style::new() && val.look.bar.is_none() && val.choices.is_empty();
+        self[coord] = if val.formatted.text.len() == 0 && plain { None } else { Some(val) };
     }
 }
 
 impl ::std::ops::Index<Coord> for CellGrid {
-    type Output = Option<String>;
+    type Output = Option<GridCell>;
 
     fn index<'a>(&'a self, Coord(col, row): Coord) -> &'a Self::Output {
         let &CellGrid(ref m) = self;
@@ -60,54 +92,47 @@ impl ::std::ops::IndexMut<Coord> for CellGrid {
 struct State {
     editing: Option<Coord>,
     editing_text: String,
+    /// The cell whose fill handle was picked, waiting for the last cell to
+    /// fill.
+    filling: Option<Coord>,
+    /// The values to pick from for the cell being edited, if it has a list
+    /// validation.
+    choices: Vec<FormulaAtom>,
+    choice_labels: Vec<String>,
+    chosen: Option<usize>,
+    /// The sheet on display, and the names of all of them.
+    sheet: usize,
+    tabs: Vec<String>,
+    /// The last error or warning, shown next to the tabs.
+    status: String,
+    /// How the user types numbers.
+    locale: NumberLocale,
 }
 
-pub fn run<F>(sheet_select: &mut F, event_stream: SyncSender<UIEvent>)
-    where F: Send + FnMut(Coord, Coord) -> Receiver<(Coord, Value)>
-{
+/// Shows the workbook, getting the values of the cells on display and their
+/// formats, looks and allowed values from `book`, and sending what the user
+/// does to `event_stream`. Errors and warnings sent to `messages` are shown
+/// to the user.
+pub fn run(book: WorkbookHandle, event_stream: Sender<UIEvent>, messages: Receiver<String>) {
     use event::*;
+    use input::{Button, Key};
+
+    let mut grid = CellGrid::new();
+    let mut view = View::new(&book, 0);
+    let (events_sender, events_recv) = channel();
+    let mut adding: VecDeque<Receiver<Result<usize, WorkbookErr>>> = VecDeque::new();
 
-    let grid = Mutex::new(CellGrid::new());
-    let grid_ref = &grid;
-    let (events_sender, events_recv) = sync_channel(0);
-
-    let guard = ::std::thread::scoped(move|| {
-        let sheet_selection = sheet_select(Coord(0, 0), Coord(GRID_COLUMNS-1, GRID_ROWS-1));
-
-        let mut running = true;
-        while running {
-            select! {
-                x = events_recv.recv() => {
-                    match x {
-                        Ok(x) => if let Err(_) = event_stream.send(x) {
-                            running = false;
-                        },
-                        Err(_) => { running = false; },
-                    }  
-                },
-                x = sheet_selection.recv() => {
-                    match x {
-                        Ok((Coord(col, row), value)) => {
-                            let mut g = grid_ref.lock().unwrap();
-                            match value {
-                                Ok(v) => {
-                                    g.set(Coord(col, row), ::parser::format_formula(&Formula::Atom(*v)).as_str());
-                                },
-                                Err(x) => {
-                                    g.set(Coord(col, row), format!("<E:{:?}>", x).as_str())
-                                }
-                            }
-                        },
-                        Err(_) => { running = false; },
-                    }
-                }
-            };
-        };
-    });
-        
     let mut state = State{
         editing: None,
         editing_text: "".to_string(),
+        filling: None,
+        choices: vec![],
+        choice_labels: vec![],
+        chosen: None,
+        sheet: 0,
+        tabs: book.sheet_names().recv().unwrap_or(vec![]),
+        status: "".to_string(),
+        locale: book.number_locale().recv().unwrap_or(NumberLocale::english()),
     };
     let opengl = OpenGL::_3_2;
     let window = make_window(opengl);
@@ -117,14 +142,108 @@ pub fn run<F>(sheet_select: &mut F, event_stream: SyncSender<UIEvent>)
     let event_iter = window.events().ups(180).max_fps(60);
     for event in event_iter {
         ui.handle_event(&event);
+        if let Some(Button::Keyboard(Key::F5)) = event.press_args() {
+            // Runs on the cell being edited, or else on the cells shown.
+            let (from, to) = match state.editing {
+                Some(coord) => (coord, coord),
+                None => (Coord(0, 0), Coord(GRID_COLUMNS-1, GRID_ROWS-1)),
+            };
+            event_stream.send(UIEvent::RunScript(state.sheet, from, to)).unwrap();
+        }
+        if let Some(_) = event.update_args() {
+            while let Ok(x) = events_recv.try_recv() {
+                match x {
+                    // Sent to the book from here, so that the sheet is added
+                    // before the view that shows it selects its cells.
+                    UIEvent::AddSheet(name) => adding.push_back(book.add_sheet(name.as_str())),
+                    UIEvent::ShowSheet(idx) => {
+                        book.unselect(view.selection);
+                        grid = CellGrid::new();
+                        view = View::new(&book, idx);
+                    },
+                    x => event_stream.send(x).unwrap(),
+                }
+            }
+            while let Some(Ok(added)) = adding.front().map(|x| x.try_recv()) {
+                adding.pop_front();
+                if let Err(x) = added {
+                    state.status = format!("Can't add the sheet: {:?}", x);
+                    state.tabs = book.sheet_names().recv().unwrap_or(vec![]);
+                    state.sheet = 0;
+                    book.unselect(view.selection);
+                    grid = CellGrid::new();
+                    view = View::new(&book, 0);
+                }
+            }
+            while let Ok(x) = messages.try_recv() {
+                state.status = x;
+            }
+            view.update(&book, &mut grid);
+        }
         if let Some(args) = event.render_args() {
             gl.draw(args.viewport(), |_, gl| {
-                draw_ui(gl, &mut ui, &*grid.lock().unwrap(), &mut state, &events_sender);
+                draw_ui(gl, &mut ui, &grid, &mut state, &events_sender);
             });
         }
     }
+}
 
-    guard.join();
+/// The cells of the sheet on display, with the values received for them
+/// that wait for their looks.
+struct View {
+    sheet: usize,
+    selection: usize,
+    values: Receiver<(Coord, Value)>,
+    pending: VecDeque<(Vec<(Coord, Value)>, Receiver<Result<Vec<CellLook>, WorkbookErr>>)>,
+}
+
+impl View {
+    fn new(book: &WorkbookHandle, sheet: usize) -> View {
+        let (selection, values) = book.select_fixed(sheet, Coord(0, 0), Coord(GRID_COLUMNS-1, GRID_ROWS-1));
+        View{sheet: sheet, selection: selection, values: values, pending: VecDeque::new()}
+    }
+
+    /// Asks for the looks of the cells whose values changed, all at once,
+    /// and shows the cells whose looks arrived, which they do in order.
+    fn update(&mut self, book: &WorkbookHandle, grid: &mut CellGrid) {
+        let mut changed = vec![];
+        while let Ok(x) = self.values.try_recv() {
+            changed.push(x);
+        }
+        if !changed.is_empty() {
+            let looks = book.looks(self.sheet, changed.iter().map(|&(coord, _)| coord).collect());
+            self.pending.push_back((changed, looks));
+        }
+        loop {
+            let looks = match self.pending.front().map(|&(_, ref looks)| looks.try_recv()) {
+                Some(Ok(Ok(x))) => x,
+                // For a sheet that was removed.
+                Some(Ok(Err(_))) | Some(Err(TryRecvError::Disconnected)) => {
+                    self.pending.pop_front();
+                    continue;
+                },
+                _ => break,
+            };
+            let (changed, _) = self.pending.pop_front().unwrap();
+            for ((coord, value), look) in changed.into_iter().zip(looks.into_iter()) {
+                grid.set(coord, grid_cell(value, look));
+            }
+        }
+    }
+}
+
+fn grid_cell(value: Value, (format, look, choices): CellLook) -> GridCell {
+    let (formatted, number) = match value {
+        Ok(v) => {
+            let number = match *v {
+                FormulaAtom::Empty | FormulaAtom::String(_) => false,
+                _ => true,
+            };
+            (::format::format_atom(&*v, format.as_ref(), QUOTE_STRINGS), number)
+        },
+        Err(x) => (Formatted{text: format!("<E:{:?}>", x), color: None}, false),
+    };
+    GridCell{formatted: formatted, look: look, number: number, choices: choices}
 }
 
 fn make_window(opengl: OpenGL) -> GlutinWindow {
@@ -151,50 +270,283 @@ fn make_ui<'a>() -> conrod::Ui<GlyphCache<'a>> {
     conrod::Ui::new(glyph_cache, theme)
 }
 
-fn draw_ui<'a>(gl: &mut GlGraphics, ui: &mut conrod::Ui<GlyphCache<'a>>, grid: &CellGrid, state: &mut State, events: &SyncSender<UIEvent>) {
-    use conrod::{Background, Colorable, WidgetMatrix, Button, Labelable, Positionable,
-        Sizeable, Widget, TextBox};
+fn draw_ui<'a>(gl: &mut GlGraphics, ui: &mut conrod::Ui<GlyphCache<'a>>, grid: &CellGrid, state: &mut State, events: &Sender<UIEvent>) {
+    use conrod::{Background, Colorable, Frameable, WidgetMatrix, Button, Label, Positionable,
+        Sizeable, Widget, TextBox, DropDownList};
     
     Background::new().rgb(1.0, 1.0, 1.0).draw(ui, gl);
 
+    let sheet = state.sheet;
+    let enabled = if let Some(_) = state.editing { false } else { true };
+
     WidgetMatrix::new(GRID_COLUMNS, GRID_ROWS)
-    .xy(0.0, 0.0)
-    .dimensions(WINDOW_WIDTH as f64, WINDOW_HEIGHT as f64)
+    .xy(0.0, TAB_BAR_HEIGHT / 2.0)
+    .dimensions(WINDOW_WIDTH as f64, WINDOW_HEIGHT as f64 - TAB_BAR_HEIGHT)
     .each_widget(ui, |ui, num, col, row, pos, dim| {
-        let &mut State{ref mut editing, ref mut editing_text} = state;
-        Button::new()
-            .label(grid.get_str(col, row))
-            .point(pos)
-            .dim(dim)
+        let &mut State{ref mut editing, ref mut editing_text, ref mut filling, ref mut choices,
+            ref mut choice_labels, ref mut chosen, ..} = state;
+        let cell = grid[Coord(col, row)].as_ref();
+        let style = cell.map(|x| x.look.style.clone()).unwrap_or(::style::Style::new());
+
+        let mut button = Button::new().point(pos).dim(dim);
+        if let Some(background) = style.background {
+            button = button.color(rgb_color(background));
+        }
+        // Conrod frames all the sides of a button alike, so the widest border
+        // is drawn all around the cell.
+        if let Some(border) = widest_border(&style.borders) {
+            button = button.frame(border.width as f64).frame_color(rgb_color(border.color));
+        }
+        button
             .react(|| {
-                *editing = Some(Coord(col, row));
-                *editing_text = grid.get_str(col, row).to_string();
+                match filling.take() {
+                    Some(source) => if let Some(event) = fill_event(sheet, source, Coord(col, row)) {
+                        events.send(event).unwrap();
+                    },
+                    None => {
+                        *editing = Some(Coord(col, row));
+                        *editing_text = grid.get_str(col, row).to_string();
+                        *choices = cell.map(|x| x.choices.clone()).unwrap_or(vec![]);
+                        *choice_labels = choices.iter().map(|x| {
+                            ::format::format_atom(x, None, QUOTE_STRINGS).text
+                        }).collect();
+                        *chosen = None;
+                    },
+                }
             })
-            .enabled(if let Some(_) = state.editing { false } else { true })
+            .enabled(enabled)
             .set(num, ui);
+
+        if let Some((length, color)) = cell.and_then(|x| x.look.bar) {
+            let width = (dim[0] - 4.0) * length;
+            Button::new()
+                .point([pos[0] - (dim[0] - 4.0) / 2.0 + width / 2.0, pos[1]])
+                .dim([width, dim[1] / 2.0])
+                .color(rgb_color(color))
+                .enabled(false)
+                .set(CELL_BAR_ID + num, ui);
+        }
+
+        // The UI has a single font, so bold text is drawn twice, a pixel
+        // apart, and italic text looks upright.
+        if let Some(cell) = cell {
+            let color = match (cell.formatted.color, style.color) {
+                (Some(color), _) => format_color(color),
+                (None, Some(color)) => rgb_color(color),
+                (None, None) => format_color(Color::Black),
+            };
+            let font_size = style.font_size.unwrap_or(ui.theme.font_size_medium);
+            let line_height = font_size as f64 * 1.25;
+            let text_width = dim[0] - 2.0 * LABEL_PADDING;
+            let mut lines = if style.wrap {
+                wrap_lines(cell.formatted.text.as_str(), text_width, |s| ui.glyph_cache.width(font_size, s))
+            } else {
+                vec![cell.formatted.text.clone()]
+            };
+            let fitting = ::std::cmp::max((dim[1] / line_height) as usize, 1);
+            lines.truncate(::std::cmp::min(fitting, MAX_LINES));
+
+            let h_align = match style.h_align {
+                HAlign::General => if cell.number { HAlign::Right } else { HAlign::Left },
+                x => x,
+            };
+            // Where the first line goes, with the others below it.
+            let block_height = lines.len() as f64 * line_height;
+            let top = match style.v_align {
+                VAlign::Top => pos[1] + dim[1] / 2.0 - LABEL_PADDING,
+                VAlign::Middle => pos[1] + block_height / 2.0,
+                VAlign::Bottom => pos[1] - dim[1] / 2.0 + LABEL_PADDING + block_height,
+            };
+            for (i, line) in lines.iter().enumerate() {
+                let width = ui.glyph_cache.width(font_size, line.as_str());
+                let x = match h_align {
+                    HAlign::Left => pos[0] - dim[0] / 2.0 + LABEL_PADDING + width / 2.0,
+                    HAlign::Right => pos[0] + dim[0] / 2.0 - LABEL_PADDING - width / 2.0,
+                    _ => pos[0],
+                };
+                let y = top - (i as f64 + 0.5) * line_height;
+                let id = CELL_LABEL_ID + (num * MAX_LINES + i) * 2;
+                Label::new(line.as_str()).font_size(font_size).color(color).point([x, y]).set(id, ui);
+                if style.bold {
+                    Label::new(line.as_str()).font_size(font_size).color(color).point([x + 1.0, y]).set(id + 1, ui);
+                }
+            }
+        }
+
+        // The fill handle sits at the bottom right corner of each cell.
+        let handle_pos = [pos[0] + (dim[0] - FILL_HANDLE_SIZE) / 2.0,
+                          pos[1] - (dim[1] - FILL_HANDLE_SIZE) / 2.0];
+        let picked = *filling == Some(Coord(col, row));
+        Button::new()
+            .point(handle_pos)
+            .dim([FILL_HANDLE_SIZE, FILL_HANDLE_SIZE])
+            .rgb(if picked { 0.9 } else { 0.2 }, 0.4, 0.8)
+            .react(|| {
+                *filling = if picked { None } else { Some(Coord(col, row)) };
+            })
+            .enabled(enabled)
+            .set(FILL_HANDLE_ID + num, ui);
     });
 
+    draw_tabs(ui, state, enabled, events);
+
+    Label::new(state.status.as_str())
+        .xy((WINDOW_WIDTH as f64 - STATUS_WIDTH) / 2.0, (TAB_BAR_HEIGHT - WINDOW_HEIGHT as f64) / 2.0)
+        .font_size(ui.theme.font_size_small)
+        .color(format_color(Color::Red))
+        .set(STATUS_ID, ui);
+
     if let Some(coord) = state.editing {
-        let &mut State{ref mut editing, ref mut editing_text} = state;
+        let &mut State{ref mut editing, ref mut editing_text, ref mut choices, ref mut choice_labels,
+            ref mut chosen, ref mut status, locale, ..} = state;
         TextBox::new(editing_text)
             .middle()
             .width(500.0).height(100.0)
             .react(|s: &mut String| {
                 println!("REACT {}", s);
-                match ::parser::parse_formula(s.as_str()) {
+                match parse_formula_in(s.as_str(), &locale) {
                     Ok(f) => {
-                        events.send(UIEvent::EditCell(coord, Box::new(f))).unwrap();
+                        events.send(UIEvent::EditCell(sheet, coord, Box::new(f))).unwrap();
                         *editing = None;
+                        status.clear();
                     },
-                    Err(x) => println!("{}", x),
+                    Err(x) => { *status = format!("{}", x); },
                 }
             })
             .set(TEXTBOX_ID, ui);
+
+        // Cells with a list validation also get a list to pick a value from,
+        // right below the text box.
+        if editing.is_some() && !choices.is_empty() {
+            DropDownList::new(choice_labels, chosen)
+                .xy(0.0, -75.0)
+                .width(500.0).height(CELL_HEIGHT as f64)
+                .react(|chosen: &mut Option<usize>, idx: usize, _: &str| {
+                    *chosen = Some(idx);
+                    let f = Formula::Atom(choices[idx].clone());
+                    events.send(UIEvent::EditCell(sheet, coord, Box::new(f))).unwrap();
+                    *editing = None;
+                })
+                .set(CHOICES_ID, ui);
+        }
     }
 
     ui.draw(gl);
 }
 
+/// Breaks `text` into lines no wider than `width`, at spaces, as long as
+/// words fit. `measure` gives the width of some text.
+fn wrap_lines<F>(text: &str, width: f64, measure: F) -> Vec<String>
+    where F: Fn(&str) -> f64
+{
+    let mut lines = vec![];
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let longer = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+            if line.is_empty() || measure(longer.as_str()) <= width {
+                line = longer;
+            } else {
+                lines.push(::std::mem::replace(&mut line, word.to_string()));
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn format_color(color: Color) -> conrod::color::Color {
+    use conrod::color::rgb;
+
+    match color {
+        Color::Black => rgb(0.0, 0.0, 0.0),
+        Color::Blue => rgb(0.0, 0.0, 1.0),
+        Color::Cyan => rgb(0.0, 1.0, 1.0),
+        Color::Green => rgb(0.0, 0.5, 0.0),
+        Color::Magenta => rgb(1.0, 0.0, 1.0),
+        Color::Red => rgb(1.0, 0.0, 0.0),
+        Color::White => rgb(1.0, 1.0, 1.0),
+        Color::Yellow => rgb(1.0, 1.0, 0.0),
+    }
+}
+
+fn rgb_color(Rgb(r, g, b): Rgb) -> conrod::color::Color {
+    conrod::color::rgb(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+}
+
+fn widest_border(borders: &Borders) -> Option<Border> {
+    let sides = [borders.top, borders.bottom, borders.left, borders.right];
+    sides.iter().filter_map(|x| *x).fold(None, |acc: Option<Border>, x| match acc {
+        Some(ref widest) if widest.width >= x.width => acc,
+        _ => Some(x),
+    })
+}
+
+/// Draws a tab for each sheet at the bottom of the window, and a last one to
+/// add a new sheet.
+fn draw_tabs<'a>(ui: &mut conrod::Ui<GlyphCache<'a>>, state: &mut State, enabled: bool, events: &Sender<UIEvent>) {
+    use conrod::{Colorable, Button, Labelable, Positionable, Sizeable, Widget};
+
+    let tab_x = |i: usize| (TAB_WIDTH - WINDOW_WIDTH as f64) / 2.0 + i as f64 * TAB_WIDTH;
+    let tab_y = (TAB_BAR_HEIGHT - WINDOW_HEIGHT as f64) / 2.0;
+    let &mut State{ref mut sheet, ref mut tabs, ..} = state;
+
+    for i in 0 .. tabs.len() {
+        let shade = if *sheet == i { 1.0 } else { 0.8 };
+        Button::new()
+            .label(tabs[i].as_str())
+            .xy(tab_x(i), tab_y)
+            .width(TAB_WIDTH).height(TAB_BAR_HEIGHT)
+            .rgb(shade, shade, shade)
+            .react(|| {
+                *sheet = i;
+                events.send(UIEvent::ShowSheet(i)).unwrap();
+            })
+            .enabled(enabled)
+            .set(TAB_ID + i, ui);
+    }
+
+    Button::new()
+        .label("+")
+        .xy(tab_x(tabs.len()), tab_y)
+        .width(TAB_WIDTH).height(TAB_BAR_HEIGHT)
+        .react(|| {
+            let mut n = tabs.len() + 1;
+            while tabs.contains(&format!("Sheet{}", n)) {
+                n += 1;
+            }
+            let name = format!("Sheet{}", n);
+            events.send(UIEvent::AddSheet(name.clone())).unwrap();
+            tabs.push(name);
+            *sheet = tabs.len() - 1;
+            events.send(UIEvent::ShowSheet(*sheet)).unwrap();
+        })
+        .enabled(enabled)
+        .set(ADD_TAB_ID, ui);
+}
+
+/// Fills from the `source` cell to `target`, if it is right below or to the
+/// right of it.
+fn fill_event(sheet: usize, source: Coord, target: Coord) -> Option<UIEvent> {
+    let (Coord(source_col, source_row), Coord(target_col, target_row)) = (source, target);
+    if source_col == target_col && target_row > source_row {
+        Some(UIEvent::Fill(sheet, source, target, FillDirection::Down))
+    } else if source_row == target_row && target_col > source_col {
+        Some(UIEvent::Fill(sheet, source, target, FillDirection::Right))
+    } else {
+        None
+    }
+}
+
+/// What the user did, for the sheet at the given position in the workbook.
 pub enum UIEvent {
-    EditCell(Coord, Box<Formula>),
+    EditCell(usize, Coord, Box<Formula>),
+    Fill(usize, Coord, Coord, FillDirection),
+    /// Handled by the UI itself, which adds the sheet to the book.
+    AddSheet(String),
+    /// Handled by the UI itself, which starts showing that sheet.
+    ShowSheet(usize),
+    /// Runs the script on the selected cells, from the top left to the bottom
+    /// right one. Sent when F5 is pressed.
+    RunScript(usize, Coord, Coord),
 }
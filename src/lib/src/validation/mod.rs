@@ -0,0 +1,151 @@
+//! Data validation, which limits what can be typed into the cells of a
+//! range.
+
+use sheet::{Coord, Formula, FormulaAtom};
+use conditional::{compare, number};
+
+/// A rule for the cells from `from` to `to`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validation {
+    pub from: Coord,
+    pub to: Coord,
+    pub rule: ValidationRule,
+    pub mode: ValidationMode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationRule {
+    /// A number from the first to the second, both included.
+    NumberBetween(f64, f64),
+    /// One of the values, with strings compared ignoring case.
+    List(Vec<FormulaAtom>),
+    /// A date from the first to the second, both included, as serial
+    /// numbers.
+    DateBetween(f64, f64),
+    /// A string with from the first to the second characters.
+    TextLength(usize, usize),
+    /// A formula that must give a number other than zero. It's written for
+    /// the top left cell of the range; relative references move for the
+    /// other cells.
+    Custom(Formula),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValidationMode {
+    /// Invalid values aren't set.
+    Reject,
+    /// Invalid values are set anyway, and reported.
+    Warn,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationErr {
+    NotANumber,
+    NotADate,
+    NotAString,
+    /// A number, a date or a length out of the allowed range.
+    OutOfRange,
+    NotInList,
+    /// The custom formula gave zero, or an error.
+    Failed,
+}
+
+/// What happened when setting a cell with a validation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Validity {
+    Valid,
+    /// Set anyway, as the validation only warns.
+    Warned(ValidationErr),
+    /// Not set.
+    Rejected(ValidationErr),
+}
+
+impl Validation {
+    pub fn contains(&self, Coord(col, row): Coord) -> bool {
+        let (Coord(col_from, row_from), Coord(col_to, row_to)) = (self.from, self.to);
+        col >= col_from && col <= col_to && row >= row_from && row <= row_to
+    }
+
+    /// Checks a value against any rule but `Custom`, for which the sheet has
+    /// to evaluate the formula first. Empty cells are always valid.
+    pub fn check(&self, value: &FormulaAtom) -> Result<(), ValidationErr> {
+        if *value == FormulaAtom::Empty {
+            return Ok(());
+        }
+
+        match self.rule {
+            ValidationRule::NumberBetween(low, high) => match *value {
+                FormulaAtom::Number(_) | FormulaAtom::Decimal(_) => {
+                    between(number(value).unwrap(), low, high)
+                },
+                _ => Err(ValidationErr::NotANumber),
+            },
+            ValidationRule::DateBetween(low, high) => match *value {
+                FormulaAtom::Date(x) => between(x, low, high),
+                _ => Err(ValidationErr::NotADate),
+            },
+            ValidationRule::TextLength(low, high) => match *value {
+                FormulaAtom::String(ref x) => {
+                    let len = x.chars().count();
+                    if len >= low && len <= high { Ok(()) } else { Err(ValidationErr::OutOfRange) }
+                },
+                _ => Err(ValidationErr::NotAString),
+            },
+            ValidationRule::List(ref values) => {
+                let found = values.iter().any(|x| compare(value, x) == Some(::std::cmp::Ordering::Equal));
+                if found { Ok(()) } else { Err(ValidationErr::NotInList) }
+            },
+            ValidationRule::Custom(_) => Ok(()),
+        }
+    }
+
+    /// Turns the result of a check into what to do with the value.
+    pub fn validity(&self, checked: Result<(), ValidationErr>) -> Validity {
+        match (checked, self.mode) {
+            (Ok(()), _) => Validity::Valid,
+            (Err(x), ValidationMode::Warn) => Validity::Warned(x),
+            (Err(x), ValidationMode::Reject) => Validity::Rejected(x),
+        }
+    }
+}
+
+fn between(x: f64, low: f64, high: f64) -> Result<(), ValidationErr> {
+    if x >= low && x <= high { Ok(()) } else { Err(ValidationErr::OutOfRange) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sheet::{Coord, FormulaAtom};
+
+    fn validation(rule: ValidationRule) -> Validation {
+        Validation{from: Coord(0, 0), to: Coord(0, 0), rule: rule, mode: ValidationMode::Reject}
+    }
+
+    #[test]
+    fn test_check() {
+        let string = |x: &str| FormulaAtom::String(x.to_string());
+        let numbers = validation(ValidationRule::NumberBetween(1.0, 10.0));
+        assert_eq!(Ok(()), numbers.check(&FormulaAtom::Number(10.0)));
+        assert_eq!(Ok(()), numbers.check(&FormulaAtom::Empty));
+        assert_eq!(Err(ValidationErr::OutOfRange), numbers.check(&FormulaAtom::Number(0.5)));
+        assert_eq!(Err(ValidationErr::NotANumber), numbers.check(&string("5")));
+
+        let dates = validation(ValidationRule::DateBetween(45292.0, 45657.0));
+        assert_eq!(Ok(()), dates.check(&FormulaAtom::Date(45306.5)));
+        assert_eq!(Err(ValidationErr::NotADate), dates.check(&FormulaAtom::Number(45306.0)));
+
+        let list = validation(ValidationRule::List(vec![string("Yes"), string("No"), FormulaAtom::Number(1.0)]));
+        assert_eq!(Ok(()), list.check(&string("yes")));
+        assert_eq!(Ok(()), list.check(&FormulaAtom::Number(1.0)));
+        assert_eq!(Err(ValidationErr::NotInList), list.check(&string("Maybe")));
+
+        let length = validation(ValidationRule::TextLength(2, 3));
+        assert_eq!(Ok(()), length.check(&string("añá")));
+        assert_eq!(Err(ValidationErr::OutOfRange), length.check(&string("a")));
+
+        let warn = Validation{mode: ValidationMode::Warn, ..length.clone()};
+        assert_eq!(Validity::Warned(ValidationErr::NotAString), warn.validity(warn.check(&FormulaAtom::Number(1.0))));
+        assert_eq!(Validity::Rejected(ValidationErr::OutOfRange), length.validity(length.check(&string("abcd"))));
+    }
+}
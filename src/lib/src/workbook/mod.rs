@@ -0,0 +1,906 @@
+use ::std::collections::HashMap;
+use ::std::sync::{Arc, Mutex};
+use ::std::sync::mpsc::{Sender, channel};
+use sheet::{Sheet, Book, Context, Coord, Formula, Value, FillDirection, LineShift, Axis, NumberMode, Subscription,
+            DEFAULT_THREADS, cells_between, corners};
+use format::NumberFormat;
+use style::Style;
+use conditional::{ConditionalFormat, Look};
+use validation::{Validation, Validity};
+use functions::math::Random;
+use functions::registry::{FunctionRegistry, Registry};
+use parser::{NumberLocale, ParseError, parse_formula_with};
+use deps::Dependents;
+
+/// A list of named sheets whose formulas can reference each other's cells,
+/// like `Sheet2!B3`.
+pub struct Workbook {
+    sheets: Vec<(String, Sheet)>,
+    names: HashMap<String, Formula>,
+    /// Behind a lock, as senders can't be shared between the threads that
+    /// evaluate cells, and shared with the subscriptions, which remove
+    /// themselves when dropped. By ID, with the index of the sheet, and
+    /// whether it stays where it is when lines are inserted or deleted.
+    selections: Arc<Mutex<Vec<(usize, usize, Coord, Coord, bool, Sender<(Coord, Value)>)>>>,
+    next_selection: usize,
+    number_mode: NumberMode,
+    number_locale: NumberLocale,
+    random: Random,
+    functions: Box<FunctionRegistry + Send>,
+    threads: usize,
+    /// Whether any formula or name calls a volatile function, once worked
+    /// out.
+    volatile: Mutex<Option<bool>>,
+    /// The cells that read each cell, once worked out.
+    dependents: Mutex<Option<Dependents>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WorkbookErr {
+    /// Sheet names can't be empty nor have `'` or `!` in them.
+    BadName(String),
+    /// Defined names must be identifiers that don't look like a reference.
+    BadDefinedName(String),
+    DuplicateName(String),
+    NoSuchSheet(usize),
+    /// A validation or conditional format that isn't there, by position.
+    NoSuchRule(usize),
+    /// A workbook always keeps at least one sheet.
+    LastSheet,
+}
+
+impl Workbook {
+    /// A workbook with a single empty sheet, called `Sheet1`.
+    pub fn new() -> Self {
+        Workbook{
+            sheets: vec![("Sheet1".to_string(), Sheet::new())],
+            names: HashMap::new(),
+            selections: Arc::new(Mutex::new(vec![])),
+            next_selection: 0,
+            number_mode: NumberMode::Float,
+            number_locale: NumberLocale::english(),
+            random: Random::from_time(),
+            functions: Box::new(Registry::new()),
+            threads: DEFAULT_THREADS,
+            volatile: Mutex::new(None),
+            dependents: Mutex::new(None),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sheets.len()
+    }
+
+    pub fn sheet_names(&self) -> Vec<String> {
+        self.sheets.iter().map(|&(ref name, _)| name.clone()).collect()
+    }
+
+    pub fn sheet(&self, idx: usize) -> &Sheet {
+        &self.sheets[idx].1
+    }
+
+    /// Adds an empty sheet after the last one, returning its position.
+    pub fn add_sheet(&mut self, name: &str) -> Result<usize, WorkbookErr> {
+        try!(self.check_name(name, None));
+        self.sheets.push((name.to_string(), Sheet::new()));
+        // References to a sheet by this name now read it.
+        *self.dependents.lock().unwrap() = None;
+        Ok(self.sheets.len() - 1)
+    }
+
+    /// Renames a sheet, rewriting the references to it.
+    pub fn rename_sheet(&mut self, idx: usize, name: &str) -> Result<(), WorkbookErr> {
+        try!(self.check_idx(idx));
+        try!(self.check_name(name, Some(idx)));
+
+        let old = ::std::mem::replace(&mut self.sheets[idx].0, name.to_string());
+        self.map_sheet_refs(&old, |inner| {
+            Formula::SheetRef(name.to_string(), Box::new(inner.clone()))
+        });
+        *self.dependents.lock().unwrap() = None;
+        Ok(())
+    }
+
+    /// Deletes a sheet. References to it become `#REF!`.
+    pub fn delete_sheet(&mut self, idx: usize) -> Result<(), WorkbookErr> {
+        try!(self.check_idx(idx));
+        if self.sheets.len() == 1 {
+            return Err(WorkbookErr::LastSheet);
+        }
+
+        let (name, _) = self.sheets.remove(idx);
+        self.map_sheet_refs(&name, |_| Formula::InvalidRef);
+
+        {
+            let mut selections = self.selections.lock().unwrap();
+            selections.retain(|&(_, sheet, _, _, _, _)| sheet != idx);
+            for selection in selections.iter_mut() {
+                if selection.1 > idx {
+                    selection.1 -= 1;
+                }
+            }
+        }
+
+        self.notify_all();
+        Ok(())
+    }
+
+    /// Moves the sheet at `from` so that it ends up at position `to`.
+    pub fn move_sheet(&mut self, from: usize, to: usize) -> Result<(), WorkbookErr> {
+        try!(self.check_idx(from));
+        try!(self.check_idx(to));
+
+        let sheet = self.sheets.remove(from);
+        self.sheets.insert(to, sheet);
+        *self.dependents.lock().unwrap() = None;
+
+        for selection in self.selections.lock().unwrap().iter_mut() {
+            let idx = selection.1;
+            selection.1 = if idx == from {
+                to
+            } else if from < to && idx > from && idx <= to {
+                idx - 1
+            } else if to < from && idx >= to && idx < from {
+                idx + 1
+            } else {
+                idx
+            };
+        }
+        Ok(())
+    }
+
+    /// Defines, or redefines, a name that formulas can use instead of
+    /// `target`. It's usually a reference to a cell or a range, like
+    /// `Sheet1!$C$17:$C$230`, or a constant.
+    ///
+    /// References in `target` without a sheet are to the first sheet, and
+    /// are kept as such, so that they follow it when it's renamed or lines
+    /// are inserted or deleted in it.
+    pub fn define_name(&mut self, name: &str, target: Formula) -> Result<(), WorkbookErr> {
+        let valid = name.chars().enumerate().all(|(i, c)| match c {
+            'A' ... 'Z' | 'a' ... 'z' | '_' => true,
+            '0' ... '9' | '.' => i > 0,
+            _ => false,
+        });
+        if !valid || name.len() == 0 || Coord::parse_anchored(name).is_some() {
+            return Err(WorkbookErr::BadDefinedName(name.to_string()));
+        }
+
+        let first = self.sheets[0].0.clone();
+        let target = target.map(&|f| match *f {
+            Formula::Ref(..) | Formula::Range(..) => Some(Formula::SheetRef(first.clone(), Box::new(f.clone()))),
+            _ => None,
+        });
+        self.names.insert(name.to_string(), target);
+        self.notify_all();
+        Ok(())
+    }
+
+    pub fn undefine_name(&mut self, name: &str) {
+        if let Some(_) = self.names.remove(name) {
+            self.notify_all();
+        }
+    }
+
+    /// The defined names and what they stand for, sorted by name.
+    pub fn names(&self) -> Vec<(&str, &Formula)> {
+        let mut ret: Vec<_> = self.names.iter().map(|(name, formula)| (name.as_str(), formula)).collect();
+        ret.sort_by(|&(a, _), &(b, _)| a.cmp(b));
+        ret
+    }
+
+    /// Takes the sheets and names of `other`, keeping the settings, functions
+    /// and selections of this workbook, but for the selections of sheets
+    /// `other` doesn't have.
+    pub fn replace_contents(&mut self, other: Workbook) {
+        self.sheets = other.sheets;
+        self.names = other.names;
+        let len = self.sheets.len();
+        self.selections.lock().unwrap().retain(|&(_, sheet, _, _, _, _)| sheet < len);
+        self.notify_all();
+    }
+
+    pub fn number_mode(&self) -> NumberMode {
+        self.number_mode
+    }
+
+    /// Switches between float and exact decimal arithmetic, recalculating
+    /// everything.
+    pub fn set_number_mode(&mut self, mode: NumberMode) {
+        self.number_mode = mode;
+        self.notify_all();
+    }
+
+    pub fn number_locale(&self) -> NumberLocale {
+        self.number_locale
+    }
+
+    /// How numbers typed on their own in a cell are read by
+    /// `parse_formula`.
+    pub fn set_number_locale(&mut self, locale: NumberLocale) {
+        self.number_locale = locale;
+    }
+
+    /// Reads a formula typed by the user, with numbers on their own in the
+    /// workbook's locale.
+    pub fn parse_formula(&self, s: &str) -> Result<Formula, ParseError> {
+        parse_formula_with(s, &self.number_locale, &*self.functions)
+    }
+
+    /// See `Sheet::seed_random`.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    /// See `Sheet::set_threads`.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = ::std::cmp::max(threads, 1);
+    }
+
+    /// See `Sheet::set_functions`.
+    pub fn set_functions(&mut self, functions: Box<FunctionRegistry + Send>) {
+        self.functions = functions;
+        self.notify_all();
+    }
+
+    /// See `Sheet::refresh_volatile`.
+    pub fn refresh_volatile(&self) {
+        let mut areas = vec![];
+        for sheet in 0 .. self.sheets.len() {
+            let volatile = self.sheets[sheet].1.volatile_areas(Context{book: self, sheet: sheet, cache: None});
+            areas.extend(volatile.into_iter().map(|(from, to)| (sheet, from, to)));
+        }
+        for (sheet, from, to) in ::deps::update(self, &areas) {
+            self.notify(sheet, from, to);
+        }
+    }
+
+    /// See `Sheet::set`.
+    pub fn set(&mut self, sheet: usize, coord: Coord, formula: Formula) -> Validity {
+        let validity = self.put(sheet, coord, formula);
+        if let Validity::Rejected(_) = validity {
+            return validity;
+        }
+        for (sheet, from, to) in ::deps::update(self, &[(sheet, coord, coord)]) {
+            self.notify(sheet, from, to);
+        }
+        validity
+    }
+
+    /// Like `set`, without sending anything to the selections.
+    fn put(&mut self, sheet: usize, coord: Coord, formula: Formula) -> Validity {
+        *self.volatile.lock().unwrap() = None;
+        let old = self.sheets[sheet].1.swap(coord, formula);
+        let validity = self.sheets[sheet].1.validate(coord, Context{book: self, sheet: sheet, cache: None});
+        if let Validity::Rejected(_) = validity {
+            self.sheets[sheet].1.swap(coord, old);
+        }
+        ::deps::changed(self, (sheet, coord));
+        validity
+    }
+
+    /// See `Sheet::add_validation`.
+    pub fn add_validation(&mut self, sheet: usize, validation: Validation) -> Result<(usize, Vec<(Coord, Validity)>), WorkbookErr> {
+        if sheet >= self.sheets.len() {
+            return Err(WorkbookErr::NoSuchSheet(sheet));
+        }
+        let (from, to) = (validation.from, validation.to);
+        let idx = self.sheets[sheet].1.put_validation(validation);
+        let invalid = self.sheets[sheet].1.invalid_cells(from, to, Context{book: self, sheet: sheet, cache: None});
+        self.notify(sheet, from, to);
+        Ok((idx, invalid))
+    }
+
+    /// See `Sheet::remove_validation`.
+    pub fn remove_validation(&mut self, sheet: usize, idx: usize) -> Result<(), WorkbookErr> {
+        if sheet >= self.sheets.len() {
+            return Err(WorkbookErr::NoSuchSheet(sheet));
+        }
+        let old = try!(self.sheets[sheet].1.remove_validation(idx).ok_or(WorkbookErr::NoSuchRule(idx)));
+        self.notify(sheet, old.from, old.to);
+        Ok(())
+    }
+
+    pub fn validations(&self, sheet: usize) -> &[Validation] {
+        self.sheets[sheet].1.validations()
+    }
+
+    pub fn validation(&self, sheet: usize, coord: Coord) -> Option<&Validation> {
+        self.sheets[sheet].1.validation(coord)
+    }
+
+    pub fn formula(&self, sheet: usize, coord: Coord) -> Option<&Formula> {
+        self.sheets[sheet].1.formula(coord)
+    }
+
+    pub fn value(&self, sheet: usize, coord: Coord) -> Value {
+        self.sheets[sheet].1.value_in(coord, Context{book: self, sheet: sheet, cache: None})
+    }
+
+    /// See `Sheet::set_format`.
+    pub fn set_format(&mut self, sheet: usize, from: Coord, to: Coord, format: Option<NumberFormat>) {
+        self.sheets[sheet].1.put_format(from, to, format);
+        self.notify(sheet, from, to);
+    }
+
+    pub fn format(&self, sheet: usize, coord: Coord) -> Option<&NumberFormat> {
+        self.sheets[sheet].1.format(coord)
+    }
+
+    /// See `Sheet::set_style`.
+    pub fn set_style(&mut self, sheet: usize, from: Coord, to: Coord, style: Style) {
+        self.sheets[sheet].1.set_style(from, to, style);
+        self.notify(sheet, from, to);
+    }
+
+    /// See `Sheet::update_style`.
+    pub fn update_style<F>(&mut self, sheet: usize, from: Coord, to: Coord, f: F)
+        where F: Fn(&mut Style)
+    {
+        self.sheets[sheet].1.update_style(from, to, f);
+        self.notify(sheet, from, to);
+    }
+
+    pub fn style(&self, sheet: usize, coord: Coord) -> Option<&Style> {
+        self.sheets[sheet].1.style(coord)
+    }
+
+    /// See `Sheet::add_conditional_format`.
+    pub fn add_conditional_format(&mut self, sheet: usize, cf: ConditionalFormat) -> usize {
+        let (from, to) = (cf.from, cf.to);
+        let idx = self.sheets[sheet].1.add_conditional_format(cf);
+        self.notify(sheet, from, to);
+        idx
+    }
+
+    /// See `Sheet::replace_conditional_format`.
+    pub fn replace_conditional_format(&mut self, sheet: usize, idx: usize, cf: ConditionalFormat) -> Result<(), WorkbookErr> {
+        if sheet >= self.sheets.len() {
+            return Err(WorkbookErr::NoSuchSheet(sheet));
+        }
+        let (from, to) = (cf.from, cf.to);
+        let old = try!(self.sheets[sheet].1.replace_conditional_format(idx, cf).ok_or(WorkbookErr::NoSuchRule(idx)));
+        self.notify(sheet, old.from, old.to);
+        self.notify(sheet, from, to);
+        Ok(())
+    }
+
+    /// See `Sheet::remove_conditional_format`.
+    pub fn remove_conditional_format(&mut self, sheet: usize, idx: usize) -> Result<(), WorkbookErr> {
+        if sheet >= self.sheets.len() {
+            return Err(WorkbookErr::NoSuchSheet(sheet));
+        }
+        let old = try!(self.sheets[sheet].1.remove_conditional_format(idx).ok_or(WorkbookErr::NoSuchRule(idx)));
+        self.notify(sheet, old.from, old.to);
+        Ok(())
+    }
+
+    pub fn conditional_formats(&self, sheet: usize) -> &[ConditionalFormat] {
+        self.sheets[sheet].1.conditional_formats()
+    }
+
+    /// See `Sheet::spill_areas`.
+    pub fn spill_areas(&self, sheet: usize) -> Vec<(Coord, Coord)> {
+        self.sheets[sheet].1.spill_areas()
+    }
+
+    /// See `Sheet::look`.
+    pub fn look(&self, sheet: usize, coord: Coord) -> Look {
+        self.sheets[sheet].1.look_in(coord, Context{book: self, sheet: sheet, cache: None})
+    }
+
+    /// See `Sheet::looks_in`.
+    pub fn looks(&self, sheet: usize, coords: &[Coord]) -> Vec<Look> {
+        self.sheets[sheet].1.looks_in(coords, Context{book: self, sheet: sheet, cache: None})
+    }
+
+    /// Like `Sheet::select`, for a sheet of the workbook.
+    pub fn select(&mut self, sheet: usize, from: Coord, to: Coord) -> Subscription {
+        self.add_selection(sheet, from, to, false)
+    }
+
+    /// Like `select`, for a selection that stays where it is when lines are
+    /// inserted or deleted, like the cells on display.
+    pub fn select_fixed(&mut self, sheet: usize, from: Coord, to: Coord) -> Subscription {
+        self.add_selection(sheet, from, to, true)
+    }
+
+    fn add_selection(&mut self, sheet: usize, from: Coord, to: Coord, fixed: bool) -> Subscription {
+        let (tx, rx) = channel();
+        let id = self.next_selection;
+        self.next_selection += 1;
+        self.send(sheet, from, to, &tx);
+        self.selections.lock().unwrap().push((id, sheet, from, to, fixed, tx));
+        let selections = self.selections.clone();
+        Subscription::new(id, rx, Box::new(move || {
+            if let Ok(mut selections) = selections.lock() {
+                selections.retain(|&(other, _, _, _, _, _)| other != id);
+            }
+        }))
+    }
+
+    /// See `Sheet::unselect`.
+    pub fn unselect(&mut self, id: usize) {
+        self.selections.lock().unwrap().retain(|&(other, _, _, _, _, _)| other != id);
+    }
+
+    /// Like `Sheet::move_selection`, keeping the selection on its sheet.
+    pub fn move_selection(&mut self, id: usize, from: Coord, to: Coord) {
+        let mut selections = self.selections.lock().unwrap();
+        let connected = match selections.iter_mut().find(|selection| selection.0 == id) {
+            Some(selection) => {
+                let (old_from, old_to) = (selection.2, selection.3);
+                selection.2 = from;
+                selection.3 = to;
+                let entering = cells_between(from, to).into_iter().filter(|&coord| {
+                    !::deps::overlap((coord, coord), (old_from, old_to))
+                }).collect();
+                self.send_cells(selection.1, entering, &selection.5)
+            },
+            None => { return; },
+        };
+        if !connected {
+            selections.retain(|&(other, _, _, _, _, _)| other != id);
+        }
+    }
+
+    /// See `Sheet::fill`.
+    pub fn fill(&mut self, sheet: usize, from: Coord, to: Coord, direction: FillDirection) {
+        self.fill_series(sheet, from, to, 1, direction);
+    }
+
+    /// See `Sheet::fill_series`.
+    pub fn fill_series(&mut self, sheet: usize, from: Coord, to: Coord, seeds: usize, direction: FillDirection) {
+        let (from, to) = corners(from, to);
+        let series = self.sheets[sheet].1.series(from, to, seeds, direction, self.number_mode);
+        if series.is_empty() {
+            return;
+        }
+        for (coord, formula, format, style) in series {
+            if let Validity::Rejected(_) = self.put(sheet, coord, formula) {
+                continue;
+            }
+            self.sheets[sheet].1.put_attrs(coord, format, style);
+        }
+        for (sheet, from, to) in ::deps::update(self, &[(sheet, from, to)]) {
+            self.notify(sheet, from, to);
+        }
+    }
+
+    /// See `Sheet::insert_rows`. References from other sheets are rewritten
+    /// too.
+    pub fn insert_rows(&mut self, sheet: usize, at: usize, count: usize) {
+        self.move_lines(sheet, LineShift{axis: Axis::Row, at: at, count: count, insert: true});
+    }
+
+    /// See `Sheet::delete_rows`.
+    pub fn delete_rows(&mut self, sheet: usize, at: usize, count: usize) {
+        self.move_lines(sheet, LineShift{axis: Axis::Row, at: at, count: count, insert: false});
+    }
+
+    /// See `Sheet::insert_columns`.
+    pub fn insert_columns(&mut self, sheet: usize, at: usize, count: usize) {
+        self.move_lines(sheet, LineShift{axis: Axis::Col, at: at, count: count, insert: true});
+    }
+
+    /// See `Sheet::delete_columns`.
+    pub fn delete_columns(&mut self, sheet: usize, at: usize, count: usize) {
+        self.move_lines(sheet, LineShift{axis: Axis::Col, at: at, count: count, insert: false});
+    }
+
+    fn move_lines(&mut self, sheet: usize, shift: LineShift) {
+        self.sheets[sheet].1.move_lines(shift);
+        for selection in self.selections.lock().unwrap().iter_mut().filter(|selection| selection.1 == sheet && !selection.4) {
+            shift.move_selection(&mut selection.2, &mut selection.3);
+        }
+        let name = self.sheets[sheet].0.clone();
+        self.map_sheet_refs(&name, |inner| match inner.move_lines(&shift) {
+            Formula::InvalidRef => Formula::InvalidRef,
+            x => Formula::SheetRef(name.clone(), Box::new(x)),
+        });
+        self.notify_all();
+    }
+
+    /// Replaces every reference to the sheet called `name` in the workbook,
+    /// including the targets of defined names, by `f` applied to the
+    /// reference inside that sheet.
+    fn map_sheet_refs<F>(&mut self, name: &str, f: F)
+        where F: Fn(&Formula) -> Formula
+    {
+        let replace = |formula: &Formula| match *formula {
+            Formula::SheetRef(ref sheet, ref inner) if sheet == name => Some(f(inner)),
+            _ => None,
+        };
+        for &mut (_, ref mut sheet) in self.sheets.iter_mut() {
+            sheet.map_formulas(|formula| formula.map(&replace));
+        }
+        for (_, target) in self.names.iter_mut() {
+            *target = target.map(&replace);
+        }
+    }
+
+    fn check_idx(&self, idx: usize) -> Result<(), WorkbookErr> {
+        if idx < self.sheets.len() { Ok(()) } else { Err(WorkbookErr::NoSuchSheet(idx)) }
+    }
+
+    fn check_name(&self, name: &str, renaming: Option<usize>) -> Result<(), WorkbookErr> {
+        if name.len() == 0 || name.contains("'") || name.contains("!") {
+            return Err(WorkbookErr::BadName(name.to_string()));
+        }
+        for (idx, &(ref other, _)) in self.sheets.iter().enumerate() {
+            if other == name && Some(idx) != renaming {
+                return Err(WorkbookErr::DuplicateName(name.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the values of the cells of `sheet` from `from` to `to` to the
+    /// selections that include them, dropping the selections whose
+    /// receivers are gone.
+    fn notify(&self, sheet: usize, from: Coord, to: Coord) {
+        use ::std::cmp::{min, max};
+
+        let (Coord(col_from, row_from), Coord(col_to, row_to)) = (from, to);
+        self.selections.lock().unwrap().retain(|&(_, idx, Coord(sel_col_from, sel_row_from), Coord(sel_col_to, sel_row_to), _, ref tx)| {
+            idx != sheet || self.send(sheet,
+                                      Coord(max(col_from, sel_col_from), max(row_from, sel_row_from)),
+                                      Coord(min(col_to, sel_col_to), min(row_to, sel_row_to)),
+                                      tx)
+        });
+    }
+
+    /// Sends the values of every selected cell to its selection, after
+    /// working out again the areas arrays spill over.
+    fn notify_all(&self) {
+        *self.volatile.lock().unwrap() = None;
+        *self.dependents.lock().unwrap() = None;
+        ::deps::refresh_spills(self);
+        self.selections.lock().unwrap().retain(|&(_, sheet, from, to, _, ref tx)| self.send(sheet, from, to, tx));
+    }
+
+    /// Whether the receiver is still there.
+    fn send(&self, sheet: usize, from: Coord, to: Coord, tx: &Sender<(Coord, Value)>) -> bool {
+        self.send_cells(sheet, cells_between(from, to), tx)
+    }
+
+    fn send_cells(&self, sheet: usize, coords: Vec<Coord>, tx: &Sender<(Coord, Value)>) -> bool {
+        let values = self.sheets[sheet].1.values_in(&coords, Context{book: self, sheet: sheet, cache: None});
+        coords.into_iter().zip(values.into_iter()).all(|x| tx.send(x).is_ok())
+    }
+}
+
+impl Book for Workbook {
+    fn sheet_at(&self, idx: usize) -> &Sheet {
+        &self.sheets[idx].1
+    }
+
+    fn sheet_index(&self, name: &str) -> Option<usize> {
+        self.sheets.iter().position(|&(ref other, _)| other == name)
+    }
+
+    fn sheet_count(&self) -> usize {
+        self.sheets.len()
+    }
+
+    fn name(&self, name: &str) -> Option<&Formula> {
+        self.names.get(name)
+    }
+
+    fn number_mode(&self) -> NumberMode {
+        self.number_mode
+    }
+
+    fn random(&self) -> &Random {
+        &self.random
+    }
+
+    fn functions(&self) -> &FunctionRegistry {
+        &*self.functions
+    }
+
+    fn is_volatile(&self) -> bool {
+        let mut volatile = self.volatile.lock().unwrap();
+        if volatile.is_none() {
+            let functions = &*self.functions;
+            *volatile = Some(self.sheets.iter().any(|&(_, ref sheet)| sheet.has_volatile(functions)) ||
+                             self.names.values().any(|f| f.is_volatile(functions)));
+        }
+        volatile.unwrap()
+    }
+
+    fn threads(&self) -> usize {
+        if self.is_volatile() { 1 } else { self.threads }
+    }
+
+    fn dependents(&self) -> &Mutex<Option<Dependents>> {
+        &self.dependents
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sheet::{Book, Coord, Anchor, Formula, FormulaAtom, FormulaErr, FormulaOp, NumberMode};
+    use decimal::{Decimal, Rounding};
+    use ::std::sync::mpsc::Receiver;
+
+    fn sheet_ref(name: &str, col: usize, row: usize) -> Formula {
+        Formula::SheetRef(name.to_string(), Box::new(Formula::Ref(Coord(col, row), Anchor(false, false))))
+    }
+
+    #[test]
+    fn test_sheet_ref() {
+        let mut book = Workbook::new();
+        assert_eq!(Ok(1), book.add_sheet("Sheet2"));
+        book.set(1, Coord(1, 2), Formula::Atom(FormulaAtom::Number(2.0)));
+        book.set(1, Coord(1, 3), Formula::Atom(FormulaAtom::Number(4.0)));
+        book.set(0, Coord(0, 0), sheet_ref("Sheet2", 1, 2));
+        book.set(0, Coord(0, 1), Formula::Op(FormulaOp::Add, vec![
+            Formula::SheetRef("Sheet2".to_string(), Box::new(Formula::Range(
+                Coord(1, 0), Anchor(false, false), Coord(1, 5), Anchor(false, false)))),
+        ]));
+        book.set(0, Coord(0, 2), sheet_ref("Sheet3", 0, 0));
+
+        assert_eq!(FormulaAtom::Number(2.0), *book.value(0, Coord(0, 0)).ok().unwrap());
+        assert_eq!(FormulaAtom::Number(6.0), *book.value(0, Coord(0, 1)).ok().unwrap());
+        if let Err(FormulaErr::InvalidRef) = book.value(0, Coord(0, 2)) {
+        } else { panic!(); };
+    }
+
+    #[test]
+    fn test_cross_sheet_cycle() {
+        let mut book = Workbook::new();
+        book.add_sheet("Sheet2").unwrap();
+        book.set(0, Coord(0, 0), sheet_ref("Sheet2", 0, 0));
+        book.set(1, Coord(0, 0), sheet_ref("Sheet1", 0, 0));
+        if let Err(FormulaErr::Ref(x)) = book.value(0, Coord(0, 0)) {
+            assert_eq!(Coord(0, 0), x);
+        } else { panic!(); };
+    }
+
+    #[test]
+    fn test_sheet_names() {
+        let mut book = Workbook::new();
+        assert_eq!(Err(WorkbookErr::DuplicateName("Sheet1".to_string())), book.add_sheet("Sheet1"));
+        assert_eq!(Err(WorkbookErr::BadName("a!b".to_string())), book.add_sheet("a!b"));
+        book.add_sheet("Data").unwrap();
+        book.set(1, Coord(0, 0), Formula::Atom(FormulaAtom::Number(3.0)));
+        book.set(0, Coord(0, 0), sheet_ref("Data", 0, 0));
+
+        book.rename_sheet(1, "Q1 data").unwrap();
+        assert_eq!(vec!["Sheet1".to_string(), "Q1 data".to_string()], book.sheet_names());
+        assert_eq!(FormulaAtom::Number(3.0), *book.value(0, Coord(0, 0)).ok().unwrap());
+
+        book.move_sheet(1, 0).unwrap();
+        assert_eq!(vec!["Q1 data".to_string(), "Sheet1".to_string()], book.sheet_names());
+        assert_eq!(FormulaAtom::Number(3.0), *book.value(1, Coord(0, 0)).ok().unwrap());
+
+        book.delete_sheet(0).unwrap();
+        assert_eq!(1, book.len());
+        if let Err(FormulaErr::InvalidRef) = book.value(0, Coord(0, 0)) {
+        } else { panic!(); };
+        assert_eq!(Err(WorkbookErr::LastSheet), book.delete_sheet(0));
+        assert_eq!(Err(WorkbookErr::NoSuchSheet(3)), book.rename_sheet(3, "x"));
+        assert_eq!(Err(WorkbookErr::NoSuchSheet(3)), book.remove_conditional_format(3, 0));
+        assert_eq!(Err(WorkbookErr::NoSuchRule(0)), book.remove_conditional_format(0, 0));
+    }
+
+    #[test]
+    fn test_add_validation() {
+        use validation::{ValidationRule, ValidationMode, ValidationErr};
+        let mut book = Workbook::new();
+        book.add_sheet("Sheet2").unwrap();
+        book.set(1, Coord(0, 0), Formula::Atom(FormulaAtom::Number(7.0)));
+        book.set(1, Coord(0, 1), Formula::Atom(FormulaAtom::Number(3.0)));
+        let selection = book.select(1, Coord(0, 0), Coord(0, 0));
+        assert_eq!(Coord(0, 0), selection.values.recv().unwrap().0);
+
+        let validation = Validation{from: Coord(0, 0), to: Coord(0, 9),
+            rule: ValidationRule::NumberBetween(0.0, 5.0), mode: ValidationMode::Reject};
+        assert_eq!(Ok((0, vec![(Coord(0, 0), Validity::Rejected(ValidationErr::OutOfRange))])),
+                   book.add_validation(1, validation.clone()));
+        assert_eq!(Coord(0, 0), selection.values.try_recv().unwrap().0);
+        assert_eq!(Err(WorkbookErr::NoSuchSheet(2)), book.add_validation(2, validation));
+
+        assert_eq!(Err(WorkbookErr::NoSuchRule(1)), book.remove_validation(1, 1));
+        assert_eq!(Ok(()), book.remove_validation(1, 0));
+        assert_eq!(Coord(0, 0), selection.values.try_recv().unwrap().0);
+        assert!(book.validations(1).is_empty());
+    }
+
+    #[test]
+    fn test_insert_rows_across_sheets() {
+        let mut book = Workbook::new();
+        book.add_sheet("Sheet2").unwrap();
+        book.set(1, Coord(0, 3), Formula::Atom(FormulaAtom::Number(5.0)));
+        book.set(0, Coord(0, 0), sheet_ref("Sheet2", 0, 3));
+        book.set(0, Coord(0, 3), Formula::Atom(FormulaAtom::Number(7.0)));
+
+        book.insert_rows(1, 1, 2);
+        assert_eq!(FormulaAtom::Number(5.0), *book.value(0, Coord(0, 0)).ok().unwrap());
+        assert_eq!(FormulaAtom::Number(7.0), *book.value(0, Coord(0, 3)).ok().unwrap());
+
+        book.delete_rows(1, 5, 1);
+        if let Err(FormulaErr::InvalidRef) = book.value(0, Coord(0, 0)) {
+        } else { panic!(); };
+    }
+
+    #[test]
+    fn test_fixed_selection() {
+        let mut book = Workbook::new();
+        let moving = book.select(0, Coord(0, 0), Coord(0, 3));
+        let fixed = book.select_fixed(0, Coord(0, 0), Coord(0, 3));
+        let count = |rx: &Receiver<(Coord, Value)>| {
+            let mut ret = 0;
+            while rx.try_recv().is_ok() { ret += 1; }
+            ret
+        };
+        assert_eq!(4, count(&moving.values));
+        assert_eq!(4, count(&fixed.values));
+
+        // Deleting rows shrinks the selection that moves with its cells,
+        // while the fixed one still gets every row it had.
+        book.delete_rows(0, 1, 2);
+        assert_eq!(2, count(&moving.values));
+        assert_eq!(4, count(&fixed.values));
+        book.set(0, Coord(0, 3), Formula::Atom(FormulaAtom::Number(1.0)));
+        assert_eq!(0, count(&moving.values));
+        assert_eq!(Coord(0, 3), fixed.values.try_recv().unwrap().0);
+    }
+
+    #[test]
+    fn test_names() {
+        let mut book = Workbook::new();
+        book.add_sheet("Data").unwrap();
+        for row in 0 .. 3 {
+            book.set(1, Coord(2, row), Formula::Atom(FormulaAtom::Number(row as f64 + 1.0)));
+        }
+        book.define_name("TaxRate", Formula::Atom(FormulaAtom::Number(0.5))).unwrap();
+        book.define_name("Sales", Formula::SheetRef("Data".to_string(), Box::new(Formula::Range(
+            Coord(2, 0), Anchor(true, true), Coord(2, 2), Anchor(true, true))))).unwrap();
+        book.define_name("First", sheet_ref("Data", 2, 0)).unwrap();
+
+        book.set(0, Coord(0, 0), Formula::Op(FormulaOp::Mul, vec![
+            Formula::Op(FormulaOp::Add, vec![Formula::Name("Sales".to_string())]),
+            Formula::Name("TaxRate".to_string()),
+        ]));
+        book.set(0, Coord(0, 1), Formula::Name("First".to_string()));
+        book.set(0, Coord(0, 2), Formula::Name("Nope".to_string()));
+        assert_eq!(FormulaAtom::Number(3.0), *book.value(0, Coord(0, 0)).ok().unwrap());
+        assert_eq!(FormulaAtom::Number(1.0), *book.value(0, Coord(0, 1)).ok().unwrap());
+        if let Err(FormulaErr::Name(x)) = book.value(0, Coord(0, 2)) {
+            assert_eq!("Nope", x);
+        } else { panic!(); };
+
+        book.insert_rows(1, 0, 1);
+        book.set(1, Coord(2, 0), Formula::Atom(FormulaAtom::Number(10.0)));
+        assert_eq!(FormulaAtom::Number(3.0), *book.value(0, Coord(0, 0)).ok().unwrap());
+        assert_eq!(FormulaAtom::Number(1.0), *book.value(0, Coord(0, 1)).ok().unwrap());
+
+        book.delete_rows(1, 1, 1);
+        if let Err(FormulaErr::InvalidRef) = book.value(0, Coord(0, 1)) {
+        } else { panic!(); };
+
+        // Names of cells in the first sheet move with them too.
+        book.set(0, Coord(3, 0), Formula::Atom(FormulaAtom::Number(5.0)));
+        book.define_name("Local", Formula::Range(Coord(3, 0), Anchor(true, true), Coord(3, 1), Anchor(true, true))).unwrap();
+        book.set(1, Coord(0, 0), Formula::Op(FormulaOp::Add, vec![Formula::Name("Local".to_string())]));
+        book.insert_rows(0, 0, 2);
+        assert_eq!(Some(&Formula::SheetRef("Sheet1".to_string(), Box::new(Formula::Range(
+            Coord(3, 2), Anchor(true, true), Coord(3, 3), Anchor(true, true))))), book.name("Local"));
+        assert_eq!(FormulaAtom::Number(5.0), *book.value(1, Coord(0, 0)).ok().unwrap());
+        book.delete_columns(0, 3, 1);
+        if let Err(FormulaErr::InvalidRef) = book.value(1, Coord(0, 0)) {
+        } else { panic!(); };
+
+        assert_eq!(Err(WorkbookErr::BadDefinedName("B2".to_string())), book.define_name("B2", sheet_ref("Data", 0, 0)));
+        assert_eq!(Err(WorkbookErr::BadDefinedName("1x".to_string())), book.define_name("1x", sheet_ref("Data", 0, 0)));
+    }
+
+    #[test]
+    fn test_name_cycle() {
+        let mut book = Workbook::new();
+        book.define_name("A", Formula::Name("B".to_string())).unwrap();
+        book.define_name("B", Formula::Name("A".to_string())).unwrap();
+        book.set(0, Coord(0, 0), Formula::Name("A".to_string()));
+        if let Err(FormulaErr::NameCycle(x)) = book.value(0, Coord(0, 0)) {
+            assert_eq!("A", x);
+        } else { panic!(); };
+    }
+
+    #[test]
+    fn test_select() {
+        let mut book = Workbook::new();
+        book.add_sheet("Sheet2").unwrap();
+        book.set(1, Coord(0, 0), Formula::Atom(FormulaAtom::Number(1.0)));
+        book.set(0, Coord(0, 0), sheet_ref("Sheet2", 0, 0));
+
+        let selection = book.select(0, Coord(0, 0), Coord(0, 0));
+        let (coord, value) = selection.values.recv().unwrap();
+        assert_eq!(Coord(0, 0), coord);
+        assert_eq!(FormulaAtom::Number(1.0), *value.ok().unwrap());
+
+        book.move_sheet(0, 1).unwrap();
+        book.set(1, Coord(0, 0), Formula::Atom(FormulaAtom::Number(2.0)));
+        assert_eq!(FormulaAtom::Number(2.0), *selection.values.recv().unwrap().1.ok().unwrap());
+
+        // Dropping the subscription removes the selection.
+        drop(selection);
+        assert!(book.selections.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decimal_mode() {
+        let number = |x| Formula::Atom(FormulaAtom::Number(x));
+        let mut book = Workbook::new();
+        book.set(0, Coord(0, 0), Formula::Op(FormulaOp::Add, vec![number(0.1), number(0.2)]));
+        book.set(0, Coord(0, 1), Formula::Op(FormulaOp::Div, vec![number(2.0), number(3.0)]));
+        book.set(0, Coord(0, 2), Formula::Op(FormulaOp::Div, vec![number(1.0), number(0.0)]));
+        book.set(0, Coord(0, 3), Formula::Op(FormulaOp::Avg, vec![number(0.1), number(0.2), number(0.4)]));
+        let selection = book.select(0, Coord(0, 0), Coord(0, 0));
+        let rx = &selection.values;
+        assert_eq!(FormulaAtom::Number(0.1 + 0.2), *rx.recv().unwrap().1.ok().unwrap());
+
+        book.set_number_mode(NumberMode::Decimal{scale: 4, rounding: Rounding::HalfUp});
+        let decimal = |s| FormulaAtom::Decimal(Decimal::parse(s).unwrap());
+        assert_eq!(decimal("0.3"), *rx.recv().unwrap().1.ok().unwrap());
+        assert_eq!(decimal("0.3"), *book.value(0, Coord(0, 0)).ok().unwrap());
+        assert_eq!(decimal("0.6667"), *book.value(0, Coord(0, 1)).ok().unwrap());
+        assert!(match book.value(0, Coord(0, 2)) { Err(FormulaErr::Num) => true, _ => false });
+        assert_eq!(decimal("0.2333"), *book.value(0, Coord(0, 3)).ok().unwrap());
+
+        // Floats returned by other functions meet decimals as decimals.
+        book.set(0, Coord(1, 0), Formula::Op(FormulaOp::Year, vec![Formula::Atom(FormulaAtom::Date(45306.0))]));
+        book.set(0, Coord(1, 1), Formula::Op(FormulaOp::Sub, vec![
+            Formula::Ref(Coord(1, 0), Anchor(false, false)), Formula::Ref(Coord(0, 0), Anchor(false, false))]));
+        assert_eq!(decimal("2023.7"), *book.value(0, Coord(1, 1)).ok().unwrap());
+
+        // Series of numbers go on by exact steps.
+        book.set(0, Coord(2, 0), number(0.1));
+        book.set(0, Coord(2, 1), number(0.2));
+        book.fill_series(0, Coord(2, 0), Coord(2, 3), 2, FillDirection::Down);
+        assert_eq!(decimal("0.3"), *book.value(0, Coord(2, 2)).ok().unwrap());
+        assert_eq!(decimal("0.4"), *book.value(0, Coord(2, 3)).ok().unwrap());
+    }
+
+    #[test]
+    fn test_number_locale() {
+        use parser::NumberLocale;
+        let mut book = Workbook::new();
+        let number = |x| Ok(Formula::Atom(FormulaAtom::Number(x)));
+        assert_eq!(number(1234.5), book.parse_formula("1,234.5"));
+        book.set_number_locale(NumberLocale::decimal_comma());
+        assert_eq!(number(1234.5), book.parse_formula("1.234,5"));
+        assert_eq!(number(0.5), book.parse_formula("0,5"));
+        // Formulas still use `,` between arguments.
+        assert_eq!(Ok(Formula::Op(FormulaOp::Add, vec![Formula::Atom(FormulaAtom::Number(1.5)),
+                                                        Formula::Atom(FormulaAtom::Number(2.0))])),
+                   book.parse_formula("add(1.5, 2)"));
+    }
+
+    #[test]
+    fn test_named_lambdas() {
+        use parser::parse_formula;
+
+        let mut book = Workbook::new();
+        let parse = |x: &str| parse_formula(x).ok().unwrap();
+        book.define_name("Hyp", parse("lambda(a, b, sqrt(add(mul(a, a), mul(b, b))))")).unwrap();
+        book.define_name("Fact", parse("lambda(n, if(n, mul(n, Fact(sub(n, 1))), 1))")).unwrap();
+        book.define_name("Forever", parse("lambda(n, Forever(n))")).unwrap();
+        book.define_name("Rate", parse("0.5")).unwrap();
+        book.set(0, Coord(0, 0), parse("3"));
+
+        let mut value = |formula: &str| {
+            book.set(0, Coord(1, 0), parse(formula));
+            book.value(0, Coord(1, 0))
+        };
+        assert_eq!(FormulaAtom::Number(5.0), *value("Hyp(A1, 4)").ok().unwrap());
+        assert_eq!(FormulaAtom::Number(120.0), *value("Fact(5)").ok().unwrap());
+        assert_eq!(FormulaAtom::Number(2.0), *value("let(Hyp, lambda(x, div(x, 2)), Hyp(4))").ok().unwrap());
+        assert!(match value("Forever(1)") { Err(FormulaErr::CallDepth) => true, _ => false });
+        assert!(match value("Hyp(1)") { Err(FormulaErr::Arity(2)) => true, _ => false });
+        assert!(match value("Rate(1)") { Err(FormulaErr::Type(_)) => true, _ => false });
+        assert!(match value("Nope(1)") { Err(FormulaErr::Name(ref x)) => x == "Nope", _ => false });
+    }
+}
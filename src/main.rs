@@ -2,31 +2,87 @@
 
 extern crate sheets_lib;
 
-use ::std::sync::mpsc::sync_channel;
-use ::std::sync::Mutex;
+use ::std::sync::mpsc::{channel, Sender};
+use sheets_lib::handle::WorkbookHandle;
+use sheets_lib::script;
+use sheets_lib::sheet::Coord;
+use sheets_lib::workbook::Workbook;
 
 fn main() {
-    let sheet = Mutex::new(sheets_lib::sheet::Sheet::new());
-    let sheet_ref = &sheet;
+    let book = WorkbookHandle::new(Workbook::new());
+    let events_book = book.clone();
+    let (message_send, message_recv) = channel();
+
+    // A script given on the command line runs once on A1 at the start, and
+    // then on the selection whenever F5 is pressed.
+    let script = ::std::env::args().nth(1).and_then(|path| load_script(&path, &message_send));
+    if let Some(ref script) = script {
+        run_script(&book, script, 0, Coord(0, 0), Coord(0, 0), &message_send);
+    }
+
+    let (event_send, event_recv) = channel();
+    let events_messages = message_send.clone();
 
-    let (event_send, event_recv) = sync_channel(0);
-    
     let guard = ::std::thread::scoped(move|| {
-        use sheets_lib::ui::UIEvent::{EditCell};
-        let mut running = true;
-        while running {
-            match event_recv.recv() {
-                Ok(EditCell(coord, formula)) => {
-                    sheet_ref.lock().unwrap().set(coord, *formula);
+        use sheets_lib::ui::UIEvent::{EditCell, Fill, AddSheet, ShowSheet, RunScript};
+        use sheets_lib::validation::Validity;
+        let book = events_book;
+        for event in event_recv.iter() {
+            match event {
+                EditCell(sheet, coord, formula) => {
+                    match book.set(sheet, coord, *formula).recv() {
+                        Ok(Ok(Validity::Warned(x))) => { let _ = events_messages.send(format!("Warning: {:?}", x)); },
+                        Ok(Ok(Validity::Rejected(x))) => { let _ = events_messages.send(format!("Rejected: {:?}", x)); },
+                        Ok(Err(x)) => { let _ = events_messages.send(format!("Error: {:?}", x)); },
+                        _ => {},
+                    }
+                },
+                Fill(sheet, from, to, direction) => {
+                    book.fill(sheet, from, to, direction);
+                },
+                AddSheet(_) | ShowSheet(_) => {},
+                RunScript(sheet, from, to) => if let Some(ref script) = script {
+                    run_script(&book, script, sheet, from, to, &events_messages);
                 },
-                Err(_) => { running = false },
             };
         }
     });
 
-    sheets_lib::ui::run(&mut |from, to| {
-        sheet.lock().unwrap().select(from, to)
-    }, event_send);
+    sheets_lib::ui::run(book, event_send, message_recv);
 
     guard.join();
 }
+
+/// Reads and parses a script, sending what went wrong to `messages` if it
+/// can't.
+fn load_script(path: &str, messages: &Sender<String>) -> Option<Vec<script::Stmt>> {
+    use std::io::Read;
+
+    let mut source = String::new();
+    if let Err(x) = ::std::fs::File::open(path).and_then(|mut f| f.read_to_string(&mut source)) {
+        let _ = messages.send(format!("Can't read {}: {}", path, x));
+        return None;
+    }
+    match script::parse(source.as_str()) {
+        Ok(x) => Some(x),
+        Err(x) => {
+            let _ = messages.send(format!("Can't parse {}: {:?}", path, x));
+            None
+        },
+    }
+}
+
+/// Runs the script on the workbook's thread, without waiting for it, sending
+/// what it prints, and what went wrong if it fails, to `messages`.
+fn run_script(book: &WorkbookHandle, script: &[script::Stmt], sheet: usize, from: Coord, to: Coord,
+              messages: &Sender<String>) {
+    let script = script.to_vec();
+    let messages = messages.clone();
+    book.with(move |book| {
+        let mut host = script::WorkbookHost{book: book, sheet: sheet, selection: (from, to),
+                                            messages: messages.clone()};
+        if let Err(x) = script::run(&script, &mut host, script::Limits::new()) {
+            let _ = messages.send(format!("Script error: {:?}", x));
+        }
+    });
+}